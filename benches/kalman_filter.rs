@@ -33,6 +33,29 @@ fn ekf(b: &mut Criterion) {
     b.bench_function("ekf", |b| b.iter(|| ekf.update_estimate(&u, &z, dt)));
 }
 
+/// Same filter with the models inlined instead of called through a vtable
+fn ekf_static(b: &mut Criterion) {
+    let q = Matrix4::<f64>::from_diagonal(&Vector4::new(0.1, 0.1, deg2rad(1.0), 1.0));
+    let r = nalgebra::Matrix2::identity();
+    let initial_state = GaussianState {
+        x: Vector4::<f64>::new(0., 0., 0., 0.),
+        cov: Matrix4::<f64>::identity(),
+    };
+    let mut ekf = ExtendedKalmanFilter::<f64, Const<4>, Const<2>, Const<2>, _, _>::with_models(
+        q,
+        r,
+        SimpleProblemMeasurementModel {},
+        SimpleProblemMotionModel {},
+        initial_state,
+    );
+
+    let dt = 0.1;
+    let u: Vector2<f64> = Default::default();
+    let z: Vector2<f64> = Default::default();
+
+    b.bench_function("ekf_static", |b| b.iter(|| ekf.update_estimate(&u, &z, dt)));
+}
+
 fn ukf(b: &mut Criterion) {
     // setup ukf
     let dt = 0.1;
@@ -59,5 +82,5 @@ fn ukf(b: &mut Criterion) {
     b.bench_function("ukf", |b| b.iter(|| ukf.update_estimate(&u, &z, dt)));
 }
 
-criterion_group!(benches, ekf, ukf);
+criterion_group!(benches, ekf, ekf_static, ukf);
 criterion_main!(benches);
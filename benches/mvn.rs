@@ -0,0 +1,51 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use nalgebra::{DMatrix, DVector, Dyn};
+extern crate robotics;
+use robotics::utils::mvn::MultiVariateNormal;
+
+/// Gaussian of dimension `dim` with a correlated covariance
+fn mvn(dim: usize) -> MultiVariateNormal<f64, Dyn> {
+    let a = DMatrix::from_fn(dim, dim, |i, j| 1.0 / (1.0 + i as f64 + j as f64));
+    let cov = &a * a.transpose() + DMatrix::identity(dim, dim);
+    MultiVariateNormal::new(&DVector::zeros(dim), &cov).unwrap()
+}
+
+fn mvn_pdf(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mvn_pdf");
+    for dim in [2, 3, 6, 15] {
+        let mvn = mvn(dim);
+        let x = DVector::from_fn(dim, |i, _| 0.1 * i as f64);
+        group.bench_with_input(BenchmarkId::new("pdf", dim), &dim, |b, _| {
+            b.iter(|| mvn.pdf(&x))
+        });
+        group.bench_with_input(BenchmarkId::new("log_pdf", dim), &dim, |b, _| {
+            b.iter(|| mvn.log_pdf(&x))
+        });
+    }
+    group.finish();
+}
+
+/// Log densities of 1000 particules one at a time and, with the `simd` feature, in batches
+fn mvn_log_pdf_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mvn_log_pdf_batch");
+    for dim in [2, 3] {
+        let a = DMatrix::<f32>::from_fn(dim, dim, |i, j| 1.0 / (1.0 + i as f32 + j as f32));
+        let cov = &a * a.transpose() + DMatrix::identity(dim, dim);
+        let mvn = MultiVariateNormal::new(&DVector::zeros(dim), &cov).unwrap();
+        let xs: Vec<DVector<f32>> = (0..1000)
+            .map(|k| DVector::from_fn(dim, |i, _| ((k * dim + i) % 17) as f32 * 0.1))
+            .collect();
+        group.bench_with_input(BenchmarkId::new("scalar", dim), &dim, |b, _| {
+            b.iter(|| xs.iter().map(|x| mvn.log_pdf(x)).collect::<Vec<f32>>())
+        });
+        #[cfg(feature = "simd")]
+        group.bench_with_input(BenchmarkId::new("simd", dim), &dim, |b, _| {
+            b.iter(|| robotics::utils::simd::log_pdf_batch(&mvn, &xs))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, mvn_pdf, mvn_log_pdf_batch);
+criterion_main!(benches);
@@ -0,0 +1,229 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use nalgebra::{Const, Matrix2, Matrix3, Matrix4, OMatrix, Vector2, Vector3, Vector4};
+use rustc_hash::FxHashMap;
+extern crate robotics;
+use robotics::localization::{
+    BayesianFilter, BayesianFilterKnownCorrespondences, Parallelism, ParticleFilter,
+    ParticleFilterKnownCorrespondences, ParticleFilterVariant, ResamplingScheme,
+};
+use robotics::models::measurement::{
+    MeasurementModel, RangeBearingMeasurementModel, SimpleProblemMeasurementModel,
+};
+use robotics::models::motion::{MotionModel, SimpleProblemMotionModel, Velocity};
+use robotics::utils::deg2rad;
+use robotics::utils::state::GaussianState;
+
+fn particle_filter(
+    num_particules: usize,
+    resampling_scheme: ResamplingScheme,
+) -> ParticleFilter<
+    f64,
+    Const<4>,
+    Const<2>,
+    Const<2>,
+    Box<SimpleProblemMeasurementModel>,
+    Box<SimpleProblemMotionModel>,
+> {
+    let r = Matrix4::<f64>::from_diagonal(&Vector4::new(0.1, 0.1, deg2rad(1.0), 1.0));
+    let q = Matrix2::identity();
+    let initial_state = GaussianState {
+        x: Vector4::<f64>::new(0., 0., 0., 0.),
+        cov: Matrix4::<f64>::identity(),
+    };
+    let mut pf = ParticleFilter::with_models(
+        r,
+        q,
+        SimpleProblemMeasurementModel::new(),
+        SimpleProblemMotionModel::new(),
+        initial_state.clone(),
+        num_particules,
+        resampling_scheme,
+    );
+    pf.set_seed(0, &initial_state);
+    pf
+}
+
+/// Cost of an update against the number of particules, sequential and on the rayon thread pool
+fn pf_particules(c: &mut Criterion) {
+    let u = Vector2::new(1.0, 0.1);
+    let z = Vector2::new(0.1, 0.0);
+    let mut group = c.benchmark_group("pf_particules");
+    for num_particules in [100, 1_000, 10_000] {
+        for (name, parallelism) in [
+            ("sequential", Parallelism::Sequential),
+            ("rayon", Parallelism::Rayon),
+        ] {
+            let mut pf = particle_filter(num_particules, ResamplingScheme::default());
+            pf.set_parallelism(parallelism);
+            group.bench_with_input(
+                BenchmarkId::new(name, num_particules),
+                &num_particules,
+                |b, _| b.iter(|| pf.update_estimate(&u, &z, 0.1)),
+            );
+        }
+    }
+    group.finish();
+}
+
+fn pf_resampling(c: &mut Criterion) {
+    let u = Vector2::new(1.0, 0.1);
+    let z = Vector2::new(0.1, 0.0);
+    let mut group = c.benchmark_group("pf_resampling");
+    for scheme in [
+        ResamplingScheme::IID,
+        ResamplingScheme::Stratified,
+        ResamplingScheme::Systematic,
+    ] {
+        let mut pf = particle_filter(1_000, scheme);
+        group.bench_function(format!("{scheme:?}"), |b| {
+            b.iter(|| pf.update_estimate(&u, &z, 0.1))
+        });
+    }
+    group.finish();
+}
+
+fn pf_variants(c: &mut Criterion) {
+    let u = Vector2::new(1.0, 0.1);
+    let z = Vector2::new(0.1, 0.0);
+    let mut group = c.benchmark_group("pf_variants");
+    for variant in [
+        ParticleFilterVariant::Bootstrap,
+        ParticleFilterVariant::Auxiliary,
+        ParticleFilterVariant::Regularized,
+    ] {
+        let mut pf = particle_filter(1_000, ResamplingScheme::default());
+        pf.set_variant(variant);
+        group.bench_function(format!("{variant:?}"), |b| {
+            b.iter(|| pf.update_estimate(&u, &z, 0.1))
+        });
+    }
+    group.finish();
+}
+
+/// Cost of an update of the landmark particle filter against the number of landmarks observed,
+/// the weights have one factor per landmark like the maps of a FastSLAM
+fn pf_landmarks(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pf_landmarks");
+    for num_landmarks in [10, 100, 1_000] {
+        let landmarks: FxHashMap<u32, Vector3<f64>> = (0..num_landmarks)
+            .map(|i| {
+                let angle = i as f64 * 0.1;
+                (i, Vector3::new(10.0 * angle.cos(), 10.0 * angle.sin(), 0.0))
+            })
+            .collect();
+        let measurements: Vec<(u32, Vector2<f64>)> = landmarks
+            .iter()
+            .map(|(id, landmark)| {
+                (
+                    *id,
+                    Vector2::new(landmark.xy().norm(), landmark.y.atan2(landmark.x)),
+                )
+            })
+            .collect();
+        let initial_state = GaussianState {
+            x: Vector3::zeros(),
+            cov: Matrix3::identity() * 0.01,
+        };
+        let mut pf = ParticleFilterKnownCorrespondences::new(
+            initial_state.cov,
+            Matrix2::from_diagonal(&Vector2::new(0.1, 0.2)),
+            landmarks,
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.1; 6]),
+            initial_state.clone(),
+            300,
+        );
+        pf.set_seed(0, &initial_state);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_landmarks),
+            &num_landmarks,
+            |b, _| {
+                b.iter(|| {
+                    pf.update_estimate(
+                        Some(Vector2::new(1.0, 0.1)),
+                        Some(measurements.clone()),
+                        0.1,
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// f32 position driven by the velocity input, the noise is added by the filter
+struct Integrator;
+
+impl MotionModel<f32, Const<2>, Const<2>, Const<2>> for Integrator {
+    fn prediction(&self, x: &Vector2<f32>, u: &Vector2<f32>, dt: f32) -> Vector2<f32> {
+        x + u * dt
+    }
+    fn jacobian_wrt_state(&self, _x: &Vector2<f32>, _u: &Vector2<f32>, _dt: f32) -> Matrix2<f32> {
+        Matrix2::identity()
+    }
+    fn jacobian_wrt_input(&self, _x: &Vector2<f32>, _u: &Vector2<f32>, dt: f32) -> Matrix2<f32> {
+        Matrix2::identity() * dt
+    }
+    fn cov_noise_control_space(&self, _u: &Vector2<f32>) -> Matrix2<f32> {
+        Matrix2::zeros()
+    }
+    fn sample(&self, x: &Vector2<f32>, u: &Vector2<f32>, dt: f32) -> Vector2<f32> {
+        self.prediction(x, u, dt)
+    }
+}
+
+/// f32 measurement of the position
+struct Position;
+
+impl MeasurementModel<f32, Const<2>, Const<2>> for Position {
+    fn prediction(&self, x: &Vector2<f32>, _landmark: Option<&Vector2<f32>>) -> Vector2<f32> {
+        *x
+    }
+    fn jacobian(
+        &self,
+        _x: &Vector2<f32>,
+        _landmark: Option<&Vector2<f32>>,
+    ) -> OMatrix<f32, Const<2>, Const<2>> {
+        Matrix2::identity()
+    }
+}
+
+/// Update of 50k f32 particules, the log densities are computed in batches when the bench is
+/// run with `--features simd`
+fn pf_f32(c: &mut Criterion) {
+    let initial_state = GaussianState {
+        x: Vector2::<f32>::zeros(),
+        cov: Matrix2::identity(),
+    };
+    let mut pf = ParticleFilter::<f32, Const<2>, Const<2>, Const<2>, _, _>::with_models(
+        Matrix2::identity() * 0.1,
+        Matrix2::identity() * 0.5,
+        Position,
+        Integrator,
+        initial_state.clone(),
+        50_000,
+        ResamplingScheme::default(),
+    );
+    pf.set_seed(0, &initial_state);
+    let (u, z) = (Vector2::new(1.0, 0.5), Vector2::new(0.1, 0.05));
+    let name = if cfg!(feature = "simd") {
+        "simd"
+    } else {
+        "scalar"
+    };
+    let mut group = c.benchmark_group("pf_f32");
+    group.sample_size(20);
+    group.bench_function(name, |b| b.iter(|| pf.update_estimate(&u, &z, 0.1)));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    pf_particules,
+    pf_resampling,
+    pf_variants,
+    pf_landmarks,
+    pf_f32
+);
+criterion_main!(benches);
@@ -0,0 +1,123 @@
+use nalgebra::{Const, Matrix2, Matrix3, Vector2, Vector3};
+use rustc_hash::FxHashMap;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+extern crate robotics;
+use robotics::control::path_tracking::PurePursuit;
+use robotics::localization::{
+    BayesianFilterKnownCorrespondences, ParticleFilterKnownCorrespondences,
+};
+use robotics::models::measurement::{MeasurementModel, RangeBearingMeasurementModel};
+use robotics::models::motion::{MotionModel, Velocity};
+use robotics::planning::trajectory::{as_path, cubic_spline_trajectory, TrajectoryLimits};
+use robotics::utils::latency::LatencyMonitor;
+use robotics::utils::state::GaussianState;
+
+/// Runs the estimate / plan / control loop at 20 Hz and prints the latency report as CSV.
+///
+/// cargo run --release --example full_loop_latency -- [iterations] [load threads]
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = std::env::args().skip(1);
+    let iterations: usize = args.next().map_or(Ok(400), |a| a.parse())?;
+    let load_threads: usize = args.next().map_or(Ok(0), |a| a.parse())?;
+    let period = Duration::from_millis(50);
+    let dt = period.as_secs_f64();
+
+    // busy threads competing with the loop for the cores
+    let running = Arc::new(AtomicBool::new(true));
+    let load: Vec<_> = (0..load_threads)
+        .map(|_| {
+            let running = running.clone();
+            std::thread::spawn(move || {
+                let mut x = 0u64;
+                while running.load(Ordering::Relaxed) {
+                    x = x.wrapping_mul(6364136223846793005).wrapping_add(1);
+                }
+                x
+            })
+        })
+        .collect();
+
+    let landmarks: FxHashMap<u32, Vector3<f64>> = (0..20)
+        .map(|i| {
+            let a = i as f64 / 20.0 * std::f64::consts::TAU;
+            (i, Vector3::new(10.0 * a.cos(), 10.0 * a.sin(), 0.0))
+        })
+        .collect();
+    let motion_model = Velocity::new([0.1; 6]);
+    let measurement_model = RangeBearingMeasurementModel::new();
+    let mut filter = ParticleFilterKnownCorrespondences::new(
+        Matrix3::identity() * 0.01,
+        Matrix2::identity() * 0.01,
+        landmarks.clone(),
+        RangeBearingMeasurementModel::new(),
+        Velocity::new([0.1; 6]),
+        GaussianState {
+            x: Vector3::zeros(),
+            cov: Matrix3::identity() * 0.01,
+        },
+        500,
+    );
+    let waypoints: Vec<Vector2<f64>> = (0..=8)
+        .map(|i| {
+            let a = i as f64 / 8.0 * std::f64::consts::TAU;
+            Vector2::new(5.0 * a.sin(), 5.0 - 5.0 * a.cos())
+        })
+        .collect();
+    let limits = TrajectoryLimits {
+        max_velocity: 1.0,
+        max_acceleration: 0.5,
+        max_lateral_acceleration: 0.5,
+    };
+    let controller = PurePursuit::default();
+
+    let mut monitor = LatencyMonitor::new();
+    monitor.set_budget("estimate", Duration::from_millis(20));
+    monitor.set_budget("plan", Duration::from_millis(20));
+    monitor.set_budget("control", Duration::from_millis(1));
+    monitor.set_budget("period", period + Duration::from_millis(5));
+
+    let mut pose = Vector3::zeros();
+    let mut u = Vector2::zeros();
+    let mut path = Vec::new();
+    let mut next = Instant::now();
+    for i in 0..iterations {
+        monitor.tick();
+        pose = motion_model.prediction(&pose, &u, dt);
+        let measurements: Vec<(u32, Vector2<f64>)> = landmarks
+            .iter()
+            .map(|(id, lm)| (*id, measurement_model.prediction(&pose, Some(lm))))
+            .collect();
+
+        let estimate: GaussianState<f64, Const<3>> = monitor.measure("estimate", || {
+            filter.update_estimate(Some(u), Some(measurements), dt);
+            filter.gaussian_estimate()
+        });
+        if i % 20 == 0 {
+            path = monitor.measure("plan", || {
+                cubic_spline_trajectory(&waypoints, &limits, 0.1)
+                    .map_or(Vec::new(), |t| as_path(&t))
+            });
+        }
+        let command = monitor.measure("control", || controller.control(&estimate, u[0], &path));
+        u = command.map_or(Vector2::zeros(), |c| c.unicycle(controller.wheelbase));
+
+        next += period;
+        if let Some(wait) = next.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    running.store(false, Ordering::Relaxed);
+    for thread in load {
+        thread.join().unwrap();
+    }
+    monitor.write_csv(std::io::stdout())?;
+    if !monitor.within_budgets() {
+        eprintln!("some modules went over their budget");
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+use nalgebra::{Matrix3, Rotation3, SMatrix, SVector, Vector3};
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use rand_distr::{Distribution, Normal};
+use std::error::Error;
+
+extern crate robotics;
+use robotics::geo::{Geodetic, GnssFix, LocalFrame};
+use robotics::localization::{FusedLocalizer, FusedLocalizerConfig, ImuSample, StateMask};
+use robotics::models::imu::NavState;
+use robotics::utils::frames::STANDARD_GRAVITY;
+
+const IMU_RATE: usize = 100;
+const ODOMETRY_PERIOD: usize = 5;
+const GNSS_PERIOD: usize = 100;
+
+/// Noisy vector with the standard deviation `std` on each axis
+fn noisy(v: Vector3<f64>, std: f64, rng: &mut StdRng) -> Vector3<f64> {
+    let normal = Normal::new(0.0, std).unwrap();
+    v + Vector3::from_fn(|_, _| normal.sample(rng))
+}
+
+/// Outdoor robot driving circles with an IMU at 100 Hz, the wheel odometry at 20 Hz and a GNSS
+/// receiver at 1 Hz which loses the fix for 20 s
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut rng = StdRng::seed_from_u64(7);
+    let (v, w) = (1.5, 0.1);
+    let dt = 1.0 / IMU_RATE as f64;
+    let gyro_bias = Vector3::new(0.002, -0.001, 0.005);
+    let accel_bias = Vector3::new(0.05, -0.03, 0.0);
+    let outage = 60.0..80.0;
+
+    let config = FusedLocalizerConfig {
+        planar: true,
+        // the wheels do not measure the lateral slip
+        odometry_mask: StateMask::VELOCITY_X,
+        gnss_mask: StateMask::POSITION_X | StateMask::POSITION_Y,
+        ..FusedLocalizerConfig::default()
+    };
+    let mut initial_cov = SMatrix::<f64, 15, 15>::from_diagonal(&SVector::from_element(1e-2));
+    initial_cov.fixed_view_mut::<3, 3>(9, 9).fill_diagonal(1e-4);
+    let initial_state = NavState {
+        rotation: Rotation3::identity(),
+        velocity: Vector3::new(v, 0.0, 0.0),
+        position: Vector3::zeros(),
+    };
+    let mut localizer = FusedLocalizer::new(config, initial_state, initial_cov);
+    let origin = Geodetic::new(46.52, 6.57, 400.0);
+    let frame = LocalFrame::new(origin);
+    localizer.set_origin(origin);
+
+    let mut errors = Vec::new();
+    for k in 1..=IMU_RATE * 120 {
+        let t = k as f64 * dt;
+        let truth = Vector3::new((w * t).sin(), 1.0 - (w * t).cos(), 0.0) * (v / w);
+
+        let sample = ImuSample {
+            gyro: noisy(Vector3::new(0.0, 0.0, w) + gyro_bias, 1e-3, &mut rng),
+            accel: noisy(
+                Vector3::new(0.0, v * w, STANDARD_GRAVITY) + accel_bias,
+                1e-2,
+                &mut rng,
+            ),
+            mag: None,
+            dt,
+        };
+        localizer.predict(&sample);
+        if k % ODOMETRY_PERIOD == 0 {
+            let velocity = noisy(Vector3::new(v, 0.0, 0.0), 0.02, &mut rng);
+            localizer.update_odometry(&velocity, &Matrix3::from_diagonal_element(4e-4));
+        }
+        if k % GNSS_PERIOD == 0 && !outage.contains(&t) {
+            let position = frame.from_enu(&noisy(truth, 0.8, &mut rng));
+            localizer.update_gnss(&GnssFix::with_accuracy(t, position, 0.8, 1.5));
+        }
+        if k % (10 * IMU_RATE) == 0 {
+            let error = (localizer.state().position - truth).xy().norm();
+            errors.push(error * error);
+            println!(
+                "t = {t:5.1} s: error {error:.2} m, std {:.2} m{}",
+                localizer
+                    .pose_2d()
+                    .cov
+                    .fixed_view::<2, 2>(0, 0)
+                    .trace()
+                    .sqrt(),
+                if outage.contains(&t) { " (outage)" } else { "" }
+            );
+        }
+    }
+    println!(
+        "rmse {:.2} m, gyroscope bias {:.4} rad/s (true {:.4})",
+        (errors.iter().sum::<f64>() / errors.len() as f64).sqrt(),
+        localizer.gyro_bias().z,
+        gyro_bias.z
+    );
+    Ok(())
+}
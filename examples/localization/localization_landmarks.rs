@@ -4,7 +4,7 @@ use rustc_hash::FxHashMap;
 use std::error::Error;
 
 extern crate robotics;
-use robotics::data::utias::UtiasDataset;
+use robotics::datasets::utias::UtiasDataset;
 use robotics::localization::BayesianFilterKnownCorrespondences;
 use robotics::localization::{
     ExtendedKalmanFilterKnownCorrespondences, ParticleFilterKnownCorrespondences,
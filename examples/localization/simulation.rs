@@ -0,0 +1,114 @@
+use nalgebra::{Const, Matrix2, Matrix3, Vector2, Vector3};
+use std::error::Error;
+
+extern crate robotics;
+use robotics::datasets::evaluation::evaluate;
+use robotics::datasets::event::{sort_events, Event};
+use robotics::localization::{
+    BayesianFilterKnownCorrespondences, ExtendedKalmanFilterKnownCorrespondences,
+    ParticleFilterKnownCorrespondences,
+};
+use robotics::models::measurement::RangeBearingMeasurementModel;
+use robotics::models::motion::Velocity;
+use robotics::pipeline::{FilterStage, Stage};
+use robotics::sim::{Obstacle, SimConfig, Simulator, World};
+use robotics::utils::metrics::{is_consistent, nees};
+use robotics::utils::state::GaussianState;
+
+type Filter = Box<dyn BayesianFilterKnownCorrespondences<f64, Const<3>, Const<2>, Const<2>>>;
+
+/// Runs the EKF and the particle filter on the same simulated figure eight inside a ring of
+/// landmarks, and compares their estimates with the ground truth
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut world = World::landmark_ring(12, Vector2::zeros(), 12.0);
+    world.obstacles.push(Obstacle {
+        center: Vector2::new(0.0, 0.0),
+        radius: 1.0,
+    });
+    let config = SimConfig {
+        half_fov: std::f64::consts::FRAC_PI_2,
+        measurement_period: 5,
+        seed: 42,
+        ..SimConfig::default()
+    };
+    let (v_std, w_std) = (config.odometry_noise.x, config.odometry_noise.y);
+    let q = Matrix2::from_diagonal(&config.measurement_noise.map(|s| s * s));
+    let landmarks = world.landmark_map();
+
+    let initial_pose = Vector3::new(0.0, -6.0, 0.0);
+    let mut sim = Simulator::new(world, config, initial_pose);
+    // one lap to the left then one to the right
+    let lap = (2.0 * std::f64::consts::PI / 0.2 / sim.config.dt).round() as usize;
+    let commands: Vec<Vector2<f64>> = (0..2 * lap)
+        .map(|i| Vector2::new(1.0, if i < lap { 0.2 } else { -0.2 }))
+        .collect();
+    let mut events = sim.run(&commands);
+    sort_events(&mut events);
+    let groundtruth: Vec<(f64, Vector3<f64>)> = sim.truth.clone();
+    println!(
+        "{} steps, {} beams hit the obstacle at the end",
+        commands.len(),
+        sim.scan().to_points().len()
+    );
+
+    let initial_state = GaussianState {
+        x: initial_pose,
+        cov: Matrix3::from_diagonal(&Vector3::new(0.1, 0.1, 0.01)),
+    };
+    let motion_noise = [v_std * v_std, 0.0, w_std * w_std, 0.0, 0.0, 0.0];
+    let ekf: Filter = Box::new(ExtendedKalmanFilterKnownCorrespondences::new(
+        q,
+        landmarks.clone(),
+        RangeBearingMeasurementModel::new(),
+        Velocity::new(motion_noise),
+        initial_state.clone(),
+    ));
+    let pf: Filter = Box::new(ParticleFilterKnownCorrespondences::new(
+        initial_state.cov,
+        q,
+        landmarks,
+        RangeBearingMeasurementModel::new(),
+        Velocity::new(motion_noise),
+        initial_state,
+        500,
+    ));
+
+    for (name, filter) in [("EKF", ekf), ("PF", pf)] {
+        let mut stage = FilterStage::new(filter);
+        let mut estimates = Vec::new();
+        let mut nees_values = Vec::new();
+        for event in events.iter().cloned() {
+            let at_measurement = matches!(event.event, Event::Measurements(_));
+            let Some(estimate) = stage.process(event) else {
+                continue;
+            };
+            estimates.push((estimate.time, estimate.state.x.xy()));
+            if at_measurement {
+                let mut truth = groundtruth
+                    .iter()
+                    .min_by(|a, b| {
+                        (a.0 - estimate.time)
+                            .abs()
+                            .total_cmp(&(b.0 - estimate.time).abs())
+                    })
+                    .unwrap()
+                    .1;
+                // heading of the truth on the same side of +-pi as the estimate
+                let heading_error = truth.z - estimate.state.x.z;
+                truth.z = estimate.state.x.z + heading_error.sin().atan2(heading_error.cos());
+                nees_values.extend(nees(&estimate.state, &truth));
+            }
+        }
+        let groundtruth_xy: Vec<(f64, Vector2<f64>)> =
+            groundtruth.iter().map(|(t, p)| (*t, p.xy())).collect();
+        let error = evaluate(&estimates, &groundtruth_xy, 0.01).unwrap();
+        let average_nees = nees_values.iter().sum::<f64>() / nees_values.len() as f64;
+        println!(
+            "{name}: rmse {:.3} m, ate {:.3} m, average NEES {average_nees:.2} (consistent: {})",
+            error.rmse,
+            error.ate,
+            is_consistent(&nees_values, 3, 0.95)
+        );
+    }
+    Ok(())
+}
@@ -1,4 +1,6 @@
-use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+
+use crate::models::motion::MotionModel;
 
 pub trait LinearModel<'a, T: RealField, S: Dim, U: Dim>
 where
@@ -17,6 +19,51 @@ where
     }
 }
 
+/// Solution P of the Discrete time Algebraic Riccati Equation (DARE)
+///
+/// P = A^T P A - A^T P B (R + B^T P B)^-1 B^T P A + Q
+///
+/// by fixed point iterations from P = Q, None if R + B^T P B is not invertible
+pub fn dare<T: RealField + Copy, S: Dim, U: Dim>(
+    a: &OMatrix<T, S, S>,
+    b: &OMatrix<T, S, U>,
+    q: &OMatrix<T, S, S>,
+    r: &OMatrix<T, U, U>,
+    max_iter: usize,
+    epsilon: T,
+) -> Option<OMatrix<T, S, S>>
+where
+    DefaultAllocator:
+        Allocator<T, S, S> + Allocator<T, S, U> + Allocator<T, U, S> + Allocator<T, U, U>,
+{
+    let at = a.transpose();
+    let bt = b.transpose();
+    let mut p = q.clone();
+    for _ in 0..max_iter {
+        let pn = &at * &p * a - &at * &p * b * (r + &bt * &p * b).try_inverse()? * &bt * &p * a + q;
+        if (&pn - &p).abs().max() < epsilon {
+            break;
+        }
+        p = pn;
+    }
+    Some(p)
+}
+
+/// Gain K = (R + B^T P B)^-1 B^T P A of the control u = -K x
+pub fn lqr_gain<T: RealField + Copy, S: Dim, U: Dim>(
+    a: &OMatrix<T, S, S>,
+    b: &OMatrix<T, S, U>,
+    p: &OMatrix<T, S, S>,
+    r: &OMatrix<T, U, U>,
+) -> Option<OMatrix<T, U, S>>
+where
+    DefaultAllocator:
+        Allocator<T, S, S> + Allocator<T, S, U> + Allocator<T, U, S> + Allocator<T, U, U>,
+{
+    let bt = b.transpose();
+    Some((r + &bt * p * b).try_inverse()? * bt * p * a)
+}
+
 pub fn lqr<'a, T: RealField + Copy, S: Dim, U: Dim>(
     x: &OVector<T, S>,
     dt: T,
@@ -33,26 +80,307 @@ where
         + Allocator<T, U, U>,
 {
     let a = linear_model.a(dt);
-    let at = a.transpose();
     let b = linear_model.b(dt);
-    let bt = b.transpose();
-    let q = linear_model.q();
-    let r = linear_model.r();
+    let p = dare(
+        &a,
+        &b,
+        linear_model.q(),
+        linear_model.r(),
+        max_iter,
+        epsilon,
+    )?;
+    let k = lqr_gain(&a, &b, &p, linear_model.r())?;
 
-    // Discrete time Algebraic Riccati Equation (DARE)
-    let mut p = linear_model.q().clone();
-    for _ in 0..max_iter {
-        let pn =
-            &at * &p * &a - &at * &p * &b * (r + &bt * &p * &b).try_inverse()? * &bt * &p * &a + q;
-        if (&pn - &p).abs().max() < epsilon {
-            // println!("done {i}");
+    // LQR control
+    Some(-k * x)
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IlqrConfig<T> {
+    pub max_iterations: usize,
+    /// Stops when the relative decrease of the cost is below
+    pub tolerance: T,
+    /// Initial Levenberg-Marquardt regularization added to Quu
+    pub regularization: T,
+}
+
+impl<T: RealField> Default for IlqrConfig<T> {
+    fn default() -> Self {
+        IlqrConfig {
+            max_iterations: 100,
+            tolerance: T::from_f64(1e-6).unwrap(),
+            regularization: T::from_f64(1e-6).unwrap(),
+        }
+    }
+}
+
+/// Optimal trajectory, `states` has one more element than `controls`. The feedback
+/// u = controls[k] + gains[k] (x - states[k]) tracks it
+pub struct IlqrSolution<T: RealField, S: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, U, S>,
+{
+    pub states: Vec<OVector<T, S>>,
+    pub controls: Vec<OVector<T, U>>,
+    pub gains: Vec<OMatrix<T, U, S>>,
+    pub cost: T,
+    pub iterations: usize,
+}
+
+/// Quadratic cost of iLQR, sum 1/2 (x - x_goal)^T Q (x - x_goal) + 1/2 u^T R u over the horizon
+/// plus 1/2 (x_N - x_goal)^T Q_final (x_N - x_goal)
+pub struct QuadraticCost<T: RealField, S: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, U, U>,
+{
+    pub x_goal: OVector<T, S>,
+    pub q: OMatrix<T, S, S>,
+    pub r: OMatrix<T, U, U>,
+    pub q_final: OMatrix<T, S, S>,
+}
+
+impl<T: RealField + Copy, S: Dim, U: Dim> QuadraticCost<T, S, U>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, S, S> + Allocator<T, U, U>,
+{
+    pub fn evaluate(&self, states: &[OVector<T, S>], controls: &[OVector<T, U>]) -> T {
+        let half = T::from_f64(0.5).unwrap();
+        let running = states.iter().zip(controls).fold(T::zero(), |acc, (x, u)| {
+            let dx = x - &self.x_goal;
+            acc + (dx.dot(&(&self.q * &dx)) + u.dot(&(&self.r * u))) * half
+        });
+        let dx = states.last().unwrap() - &self.x_goal;
+        running + dx.dot(&(&self.q_final * &dx)) * half
+    }
+}
+
+fn rollout<T: RealField + Copy, S: Dim, Z: Dim, U: Dim>(
+    model: &dyn MotionModel<T, S, Z, U>,
+    x0: &OVector<T, S>,
+    controls: &[OVector<T, U>],
+    dt: T,
+) -> Vec<OVector<T, S>>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, S, S>
+        + Allocator<T, U, U>
+        + Allocator<T, S, U>
+        + Allocator<T, Z, S>,
+{
+    let mut states = Vec::with_capacity(controls.len() + 1);
+    states.push(x0.clone());
+    for u in controls {
+        let x = model.prediction(states.last().unwrap(), u, dt);
+        states.push(x);
+    }
+    states
+}
+
+/// Iterative LQR over the horizon of `initial_controls`, the dynamics are linearized with the
+/// jacobians of the motion model around the current trajectory. None if the first rollout
+/// diverges or if the horizon is empty
+///
+/// Source : Synthesis and Stabilization of Complex Behaviors through Online Trajectory
+/// Optimization, Tassa et al. 2012
+pub fn ilqr<T: RealField + Copy, S: Dim, Z: Dim, U: Dim>(
+    model: &dyn MotionModel<T, S, Z, U>,
+    x0: &OVector<T, S>,
+    initial_controls: Vec<OVector<T, U>>,
+    cost: &QuadraticCost<T, S, U>,
+    dt: T,
+    config: &IlqrConfig<T>,
+) -> Option<IlqrSolution<T, S, U>>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, S, S>
+        + Allocator<T, U, U>
+        + Allocator<T, S, U>
+        + Allocator<T, U, S>
+        + Allocator<T, Z, S>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, U>,
+{
+    if initial_controls.is_empty() {
+        return None;
+    }
+    let mut controls = initial_controls;
+    let mut states = rollout(model, x0, &controls, dt);
+    let mut j = cost.evaluate(&states, &controls);
+    if !j.is_finite() {
+        return None;
+    }
+    let (s_dim, u_dim) = (x0.shape_generic().0, controls[0].shape_generic().0);
+    let mut mu = config.regularization;
+    let ten = T::from_f64(10.0).unwrap();
+    let mut gains = Vec::new();
+    let mut iterations = 0;
+
+    while iterations < config.max_iterations {
+        iterations += 1;
+        // backward pass
+        let n = controls.len();
+        let mut v_x = &cost.q_final * (&states[n] - &cost.x_goal);
+        let mut v_xx = cost.q_final.clone();
+        let mut feedforward = vec![OMatrix::zeros_generic(u_dim, Const::<1>); n];
+        gains = vec![OMatrix::zeros_generic(u_dim, s_dim); n];
+        let mut regularized = true;
+        for k in (0..n).rev() {
+            let a = model.jacobian_wrt_state(&states[k], &controls[k], dt);
+            let b = model.jacobian_wrt_input(&states[k], &controls[k], dt);
+            let at = a.transpose();
+            let bt = b.transpose();
+            let q_x = &cost.q * (&states[k] - &cost.x_goal) + &at * &v_x;
+            let q_u = &cost.r * &controls[k] + &bt * &v_x;
+            let q_xx = &cost.q + &at * &v_xx * &a;
+            let q_ux = &bt * &v_xx * &a;
+            let q_uu = &cost.r + &bt * &v_xx * &b;
+            let q_uu_reg = &q_uu + OMatrix::identity_generic(u_dim, u_dim) * mu;
+            let Some(q_uu_inv) = q_uu_reg.cholesky().map(|c| c.inverse()) else {
+                regularized = false;
+                break;
+            };
+            let kff = -&q_uu_inv * &q_u;
+            let kfb = -&q_uu_inv * &q_ux;
+            let kfb_t = kfb.transpose();
+            v_x = q_x + &kfb_t * &q_uu * &kff + &kfb_t * &q_u + q_ux.transpose() * &kff;
+            v_xx = q_xx + &kfb_t * &q_uu * &kfb + &kfb_t * &q_ux + q_ux.transpose() * &kfb;
+            v_xx = (&v_xx + v_xx.transpose()) * T::from_f64(0.5).unwrap();
+            feedforward[k] = kff;
+            gains[k] = kfb;
+        }
+        if !regularized {
+            mu *= ten;
+            continue;
+        }
+
+        // forward pass with a backtracking line search
+        let mut alpha = T::one();
+        let mut accepted = None;
+        for _ in 0..10 {
+            let mut new_states = vec![x0.clone()];
+            let mut new_controls = Vec::with_capacity(n);
+            for k in 0..n {
+                let u = &controls[k]
+                    + &feedforward[k] * alpha
+                    + &gains[k] * (&new_states[k] - &states[k]);
+                new_states.push(model.prediction(&new_states[k], &u, dt));
+                new_controls.push(u);
+            }
+            let new_j = cost.evaluate(&new_states, &new_controls);
+            if new_j.is_finite() && new_j < j {
+                accepted = Some((new_states, new_controls, new_j));
+                break;
+            }
+            alpha *= T::from_f64(0.5).unwrap();
+        }
+        let Some((new_states, new_controls, new_j)) = accepted else {
+            mu *= ten;
+            if mu > T::from_f64(1e10).unwrap() {
+                break;
+            }
+            continue;
+        };
+        let decrease = (j - new_j) / j.abs().max(T::default_epsilon());
+        states = new_states;
+        controls = new_controls;
+        j = new_j;
+        mu = (mu / ten).max(config.regularization);
+        if decrease < config.tolerance {
             break;
         }
-        p = pn;
     }
-    // LQR gain
-    let k = (r + &bt * &p * b).try_inverse()? * bt * &p * a;
 
-    // LQR control
-    Some(-k * x)
+    Some(IlqrSolution {
+        states,
+        controls,
+        gains,
+        cost: j,
+        iterations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Const, Matrix1, Matrix2, Matrix2x1, Matrix3, Matrix3x2, Vector2, Vector3};
+
+    #[test]
+    fn dare_double_integrator() {
+        let dt = 0.1;
+        let a = Matrix2::new(1.0, dt, 0.0, 1.0);
+        let b = Matrix2x1::new(0.5 * dt * dt, dt);
+        let q = Matrix2::identity();
+        let r = Matrix1::new(1.0);
+        let p = dare(&a, &b, &q, &r, 10000, 1e-12).unwrap();
+        let residual = a.transpose() * p * a
+            - a.transpose()
+                * p
+                * b
+                * (r + b.transpose() * p * b).try_inverse().unwrap()
+                * b.transpose()
+                * p
+                * a
+            + q
+            - p;
+        assert!(residual.abs().max() < 1e-9);
+        let k = lqr_gain(&a, &b, &p, &r).unwrap();
+        // the closed loop is stable
+        let eigenvalues = (a - b * k).complex_eigenvalues();
+        assert!(eigenvalues.iter().all(|e| e.norm() < 1.0));
+    }
+
+    /// Unicycle (x, y, theta) with the input (v, w)
+    struct Unicycle;
+
+    impl MotionModel<f64, Const<3>, Const<2>, Const<2>> for Unicycle {
+        fn prediction(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Vector3<f64> {
+            x + Vector3::new(u[0] * x[2].cos(), u[0] * x[2].sin(), u[1]) * dt
+        }
+        fn jacobian_wrt_state(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Matrix3<f64> {
+            #[rustfmt::skip]
+            let j = Matrix3::new(
+                1.0, 0.0, -u[0] * x[2].sin() * dt,
+                0.0, 1.0, u[0] * x[2].cos() * dt,
+                0.0, 0.0, 1.0,
+            );
+            j
+        }
+        fn jacobian_wrt_input(
+            &self,
+            x: &Vector3<f64>,
+            _u: &Vector2<f64>,
+            dt: f64,
+        ) -> Matrix3x2<f64> {
+            Matrix3x2::new(x[2].cos() * dt, 0.0, x[2].sin() * dt, 0.0, 0.0, dt)
+        }
+        fn cov_noise_control_space(&self, _u: &Vector2<f64>) -> Matrix2<f64> {
+            Matrix2::identity()
+        }
+        fn sample(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Vector3<f64> {
+            self.prediction(x, u, dt)
+        }
+    }
+
+    #[test]
+    fn ilqr_parks_a_unicycle() {
+        let cost = QuadraticCost {
+            x_goal: Vector3::new(2.0, 1.0, 0.0),
+            q: Matrix3::zeros(),
+            r: Matrix2::identity() * 0.01,
+            q_final: Matrix3::identity() * 100.0,
+        };
+        let x0 = Vector3::zeros();
+        let controls = vec![Vector2::new(0.1, 0.0); 50];
+        let initial_cost = cost.evaluate(&rollout(&Unicycle, &x0, &controls, 0.1), &controls);
+        let solution = ilqr(&Unicycle, &x0, controls, &cost, 0.1, &IlqrConfig::default()).unwrap();
+        assert!(solution.cost < initial_cost);
+        assert_eq!(51, solution.states.len());
+        approx::assert_abs_diff_eq!(
+            cost.x_goal,
+            *solution.states.last().unwrap(),
+            epsilon = 0.05
+        );
+    }
 }
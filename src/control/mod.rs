@@ -1 +1,4 @@
 pub mod lqr;
+pub mod mpc;
+pub mod path_tracking;
+pub mod teach_and_repeat;
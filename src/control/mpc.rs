@@ -0,0 +1,278 @@
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+
+use crate::models::motion::MotionModel;
+
+/// S : State Size, U: Input Size
+pub struct MpcConfig<T: RealField, S: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, S, S> + Allocator<T, U, U>,
+{
+    pub horizon: usize,
+    pub dt: T,
+    /// Weight of the tracking error on the horizon
+    pub q: OMatrix<T, S, S>,
+    /// Weight of the inputs
+    pub r: OMatrix<T, U, U>,
+    /// Weight of the tracking error at the end of the horizon
+    pub q_final: OMatrix<T, S, S>,
+    pub u_min: OVector<T, U>,
+    pub u_max: OVector<T, U>,
+    /// Projected gradient iterations per call to `control`
+    pub max_iterations: usize,
+    /// Stops when the relative decrease of the cost is below
+    pub tolerance: T,
+}
+
+/// Nonlinear MPC by single shooting, the motion model is rolled out over the horizon and the
+/// quadratic tracking cost is minimized over the inputs with a projected gradient inside the
+/// input bounds. The gradient comes from the adjoint of the jacobians of the model. The solution
+/// is shifted to warm start the next call
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct Mpc<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, S, S> + Allocator<T, U, U>,
+{
+    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    pub config: MpcConfig<T, S, U>,
+    controls: Vec<OVector<T, U>>,
+    states: Vec<OVector<T, S>>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> Mpc<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, S, S>
+        + Allocator<T, U, U>
+        + Allocator<T, S, U>
+        + Allocator<T, U, S>
+        + Allocator<T, Z, S>,
+{
+    /// The horizon should be at least one step
+    pub fn new(
+        motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+        config: MpcConfig<T, S, U>,
+    ) -> Mpc<T, S, Z, U> {
+        assert!(
+            config.horizon > 0,
+            "the horizon should be at least one step"
+        );
+        let u0 = (&config.u_min + &config.u_max) * T::from_f64(0.5).unwrap();
+        Mpc {
+            motion_model,
+            controls: vec![u0; config.horizon],
+            states: Vec::new(),
+            config,
+        }
+    }
+
+    /// States predicted by the last call to `control`, from the current state
+    pub fn predicted_states(&self) -> &[OVector<T, S>] {
+        &self.states
+    }
+
+    /// Inputs planned by the last call to `control`
+    pub fn planned_controls(&self) -> &[OVector<T, U>] {
+        &self.controls
+    }
+
+    fn rollout(&self, x: &OVector<T, S>, controls: &[OVector<T, U>]) -> Vec<OVector<T, S>> {
+        let mut states = Vec::with_capacity(controls.len() + 1);
+        states.push(x.clone());
+        for u in controls {
+            let next = self
+                .motion_model
+                .prediction(states.last().unwrap(), u, self.config.dt);
+            states.push(next);
+        }
+        states
+    }
+
+    fn cost(
+        &self,
+        states: &[OVector<T, S>],
+        controls: &[OVector<T, U>],
+        reference: &[OVector<T, S>],
+    ) -> T {
+        let c = &self.config;
+        let mut j = T::zero();
+        for (k, u) in controls.iter().enumerate() {
+            let dx = &states[k] - reference_at(reference, k);
+            j += dx.dot(&(&c.q * &dx)) + u.dot(&(&c.r * u));
+        }
+        let n = controls.len();
+        let dx = &states[n] - reference_at(reference, n);
+        j + dx.dot(&(&c.q_final * &dx))
+    }
+
+    fn project(&self, u: OVector<T, U>) -> OVector<T, U> {
+        u.zip_zip_map(&self.config.u_min, &self.config.u_max, |u, lo, hi| {
+            u.max(lo).min(hi)
+        })
+    }
+
+    /// First input of the optimal sequence from the state `x`. `reference` is the state to reach
+    /// at each step of the horizon, its last element is repeated if it is shorter than
+    /// `horizon + 1`, so a single goal can be given, but it can't be empty
+    pub fn control(&mut self, x: &OVector<T, S>, reference: &[OVector<T, S>]) -> OVector<T, U> {
+        assert!(
+            !reference.is_empty(),
+            "the reference should have at least one state"
+        );
+        let c = &self.config;
+        let dt = c.dt;
+        let two = T::from_f64(2.0).unwrap();
+        let mut controls = std::mem::take(&mut self.controls);
+        let mut states = self.rollout(x, &controls);
+        let mut j = self.cost(&states, &controls, reference);
+        let mut step = T::one();
+
+        for _ in 0..c.max_iterations {
+            // adjoint: lambda_k = dJ/dx_k
+            let n = controls.len();
+            let mut lambda = (&c.q_final * (&states[n] - reference_at(reference, n))) * two;
+            let mut grads: Vec<OVector<T, U>> = Vec::with_capacity(n);
+            for k in (0..n).rev() {
+                let a = self
+                    .motion_model
+                    .jacobian_wrt_state(&states[k], &controls[k], dt);
+                let b = self
+                    .motion_model
+                    .jacobian_wrt_input(&states[k], &controls[k], dt);
+                grads.push((&c.r * &controls[k]) * two + b.transpose() * &lambda);
+                let dx = &states[k] - reference_at(reference, k);
+                lambda = (&c.q * dx) * two + a.transpose() * lambda;
+            }
+            grads.reverse();
+
+            // backtracking on the projected step
+            let mut accepted = None;
+            for _ in 0..20 {
+                let candidate: Vec<OVector<T, U>> = controls
+                    .iter()
+                    .zip(&grads)
+                    .map(|(u, g)| self.project(u - g * step))
+                    .collect();
+                let candidate_states = self.rollout(x, &candidate);
+                let candidate_j = self.cost(&candidate_states, &candidate, reference);
+                if candidate_j < j {
+                    accepted = Some((candidate, candidate_states, candidate_j));
+                    break;
+                }
+                step /= two;
+            }
+            let Some((new_controls, new_states, new_j)) = accepted else {
+                break;
+            };
+            let decrease = (j - new_j) / j.abs().max(T::default_epsilon());
+            controls = new_controls;
+            states = new_states;
+            j = new_j;
+            step *= two;
+            if decrease < c.tolerance {
+                break;
+            }
+        }
+
+        let u = controls[0].clone();
+        self.states = states;
+        // warm start of the next call
+        controls.rotate_left(1);
+        if let Some(last) = controls.len().checked_sub(2).map(|i| controls[i].clone()) {
+            *controls.last_mut().unwrap() = last;
+        }
+        self.controls = controls;
+        u
+    }
+}
+
+fn reference_at<T: RealField, S: Dim>(reference: &[OVector<T, S>], k: usize) -> &OVector<T, S>
+where
+    DefaultAllocator: Allocator<T, S>,
+{
+    &reference[k.min(reference.len() - 1)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Const, Matrix1, Matrix2, Matrix2x1, Vector1, Vector2};
+
+    /// Position and velocity driven by the acceleration
+    struct DoubleIntegrator;
+
+    impl MotionModel<f64, Const<2>, Const<1>, Const<1>> for DoubleIntegrator {
+        fn prediction(&self, x: &Vector2<f64>, u: &Vector1<f64>, dt: f64) -> Vector2<f64> {
+            self.jacobian_wrt_state(x, u, dt) * x + self.jacobian_wrt_input(x, u, dt) * u
+        }
+        fn jacobian_wrt_state(
+            &self,
+            _x: &Vector2<f64>,
+            _u: &Vector1<f64>,
+            dt: f64,
+        ) -> Matrix2<f64> {
+            Matrix2::new(1.0, dt, 0.0, 1.0)
+        }
+        fn jacobian_wrt_input(
+            &self,
+            _x: &Vector2<f64>,
+            _u: &Vector1<f64>,
+            dt: f64,
+        ) -> Matrix2x1<f64> {
+            Matrix2x1::new(0.5 * dt * dt, dt)
+        }
+        fn cov_noise_control_space(&self, _u: &Vector1<f64>) -> Matrix1<f64> {
+            Matrix1::identity()
+        }
+        fn sample(&self, x: &Vector2<f64>, u: &Vector1<f64>, dt: f64) -> Vector2<f64> {
+            self.prediction(x, u, dt)
+        }
+    }
+
+    fn config(horizon: usize) -> MpcConfig<f64, Const<2>, Const<1>> {
+        MpcConfig {
+            horizon,
+            dt: 0.1,
+            q: Matrix2::new(1.0, 0.0, 0.0, 0.1),
+            r: Matrix1::new(0.01),
+            q_final: Matrix2::identity() * 10.0,
+            u_min: Vector1::new(-1.0),
+            u_max: Vector1::new(1.0),
+            max_iterations: 100,
+            tolerance: 1e-9,
+        }
+    }
+
+    #[test]
+    fn mpc_reaches_the_goal_within_the_bounds() {
+        let mut mpc = Mpc::new(Box::new(DoubleIntegrator), config(20));
+        let goal = [Vector2::new(5.0, 0.0)];
+        let mut x = Vector2::zeros();
+
+        let u = mpc.control(&x, &goal);
+        // far from the goal, full acceleration
+        approx::assert_abs_diff_eq!(1.0, u[0], epsilon = 1e-9);
+        assert_eq!(21, mpc.predicted_states().len());
+
+        for _ in 0..150 {
+            let u = mpc.control(&x, &goal);
+            assert!(u[0].abs() <= 1.0);
+            x = DoubleIntegrator.prediction(&x, &u, 0.1);
+        }
+        approx::assert_abs_diff_eq!(goal[0], x, epsilon = 0.05);
+    }
+
+    #[test]
+    #[should_panic(expected = "the horizon should be at least one step")]
+    fn empty_horizon() {
+        Mpc::new(Box::new(DoubleIntegrator), config(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "the reference should have at least one state")]
+    fn empty_reference() {
+        let mut mpc = Mpc::new(Box::new(DoubleIntegrator), config(20));
+        mpc.control(&Vector2::zeros(), &[]);
+    }
+}
@@ -0,0 +1,253 @@
+use nalgebra::{Const, Vector2};
+
+use crate::utils::state::GaussianState;
+
+/// Command of a car like robot, the steering angle of the front wheels [rad] and the speed [m/s]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SteeringCommand {
+    pub steering: f64,
+    pub velocity: f64,
+}
+
+impl SteeringCommand {
+    /// (v, w) input of the motion models of the crate, w = v tan(steering) / wheelbase
+    pub fn unicycle(&self, wheelbase: f64) -> Vector2<f64> {
+        Vector2::new(
+            self.velocity,
+            self.velocity * self.steering.tan() / wheelbase,
+        )
+    }
+}
+
+/// Closest point of the path to `p`, with the index of its segment
+fn nearest(path: &[Vector2<f64>], p: &Vector2<f64>) -> (usize, Vector2<f64>) {
+    if path.len() == 1 {
+        return (0, path[0]);
+    }
+    path.windows(2)
+        .enumerate()
+        .map(|(i, w)| {
+            let d = w[1] - w[0];
+            let t = ((p - w[0]).dot(&d) / d.norm_squared().max(f64::EPSILON)).clamp(0.0, 1.0);
+            (i, w[0] + d * t)
+        })
+        .min_by(|a, b| (a.1 - p).norm().total_cmp(&(b.1 - p).norm()))
+        .unwrap()
+}
+
+/// Remaining length of the path from the point `q` of the segment `i`
+fn remaining(path: &[Vector2<f64>], i: usize, q: &Vector2<f64>) -> f64 {
+    let tail: f64 = path[i + 1..].windows(2).map(|w| (w[1] - w[0]).norm()).sum();
+    path.get(i + 1).map_or(0.0, |next| (next - q).norm()) + tail
+}
+
+fn normalize_angle(a: f64) -> f64 {
+    f64::atan2(a.sin(), a.cos())
+}
+
+/// Speed command slowing down linearly over the last `stopping_distance` of the path
+fn velocity(target_speed: f64, stopping_distance: f64, remaining: f64) -> f64 {
+    if stopping_distance <= 0.0 {
+        return target_speed;
+    }
+    target_speed * (remaining / stopping_distance).min(1.0)
+}
+
+/// Pure pursuit, steers the rear axle on the arc going through the point of the path at the
+/// lookahead distance ld = lookahead_gain * v + min_lookahead
+///
+/// Source : Implementation of the Pure Pursuit Path Tracking Algorithm, Coulter 1992
+#[derive(Debug, Clone, Copy)]
+pub struct PurePursuit {
+    pub lookahead_gain: f64,
+    pub min_lookahead: f64,
+    pub wheelbase: f64,
+    pub target_speed: f64,
+    pub stopping_distance: f64,
+    pub max_steering: f64,
+}
+
+impl Default for PurePursuit {
+    fn default() -> Self {
+        PurePursuit {
+            lookahead_gain: 0.5,
+            min_lookahead: 1.0,
+            wheelbase: 2.5,
+            target_speed: 2.0,
+            stopping_distance: 2.0,
+            max_steering: 0.6,
+        }
+    }
+}
+
+impl PurePursuit {
+    /// `estimate` is the pose (x, y, theta) of the rear axle and `speed` the current speed,
+    /// None if the path is empty
+    pub fn control(
+        &self,
+        estimate: &GaussianState<f64, Const<3>>,
+        speed: f64,
+        path: &[Vector2<f64>],
+    ) -> Option<SteeringCommand> {
+        if path.is_empty() {
+            return None;
+        }
+        let position = estimate.x.xy();
+        let theta = estimate.x[2];
+        let lookahead = self.lookahead_gain * speed.abs() + self.min_lookahead;
+
+        let (i, q) = nearest(path, &position);
+        let remaining = remaining(path, i, &q);
+        let target = path[i + 1..]
+            .iter()
+            .find(|p| (*p - position).norm() >= lookahead)
+            .unwrap_or(path.last().unwrap());
+
+        let alpha =
+            normalize_angle(f64::atan2(target.y - position.y, target.x - position.x) - theta);
+        let steering = f64::atan2(2.0 * self.wheelbase * alpha.sin(), lookahead);
+        Some(SteeringCommand {
+            steering: steering.clamp(-self.max_steering, self.max_steering),
+            velocity: velocity(self.target_speed, self.stopping_distance, remaining),
+        })
+    }
+}
+
+/// Stanley, corrects the heading error and the cross track error e of the front axle, positive
+/// on the left of the path, steering = heading_error - atan(k e / (softening + v))
+///
+/// Source : Stanley: The Robot that Won the DARPA Grand Challenge, Thrun et al. 2006
+#[derive(Debug, Clone, Copy)]
+pub struct Stanley {
+    /// k, gain of the cross track error
+    pub gain: f64,
+    /// Keeps the gain bounded at low speed [m/s]
+    pub softening: f64,
+    pub wheelbase: f64,
+    pub target_speed: f64,
+    pub stopping_distance: f64,
+    pub max_steering: f64,
+}
+
+impl Default for Stanley {
+    fn default() -> Self {
+        Stanley {
+            gain: 1.0,
+            softening: 1.0,
+            wheelbase: 2.5,
+            target_speed: 2.0,
+            stopping_distance: 2.0,
+            max_steering: 0.6,
+        }
+    }
+}
+
+impl Stanley {
+    /// `estimate` is the pose (x, y, theta) of the rear axle and `speed` the current speed,
+    /// None if the path has less than 2 waypoints
+    pub fn control(
+        &self,
+        estimate: &GaussianState<f64, Const<3>>,
+        speed: f64,
+        path: &[Vector2<f64>],
+    ) -> Option<SteeringCommand> {
+        if path.len() < 2 {
+            return None;
+        }
+        let theta = estimate.x[2];
+        let front = estimate.x.xy() + Vector2::new(theta.cos(), theta.sin()) * self.wheelbase;
+
+        let (i, q) = nearest(path, &front);
+        let direction = path[i + 1] - path[i];
+        let heading_error = normalize_angle(f64::atan2(direction.y, direction.x) - theta);
+        // positive when the front axle is on the left of the path
+        let offset = front - q;
+        let cross_track = (direction.x * offset.y - direction.y * offset.x) / direction.norm();
+
+        let steering =
+            heading_error - f64::atan2(self.gain * cross_track, self.softening + speed.abs());
+        Some(SteeringCommand {
+            steering: steering.clamp(-self.max_steering, self.max_steering),
+            velocity: velocity(
+                self.target_speed,
+                self.stopping_distance,
+                remaining(path, i, &q),
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix3, Vector3};
+
+    fn state(x: f64, y: f64, theta: f64) -> GaussianState<f64, Const<3>> {
+        GaussianState {
+            x: Vector3::new(x, y, theta),
+            cov: Matrix3::identity() * 0.01,
+        }
+    }
+
+    /// Kinematic bicycle from the rear axle
+    fn step(pose: &mut GaussianState<f64, Const<3>>, command: &SteeringCommand, wheelbase: f64) {
+        let dt = 0.05;
+        let u = command.unicycle(wheelbase);
+        pose.x += Vector3::new(
+            u[0] * pose.x[2].cos() * dt,
+            u[0] * pose.x[2].sin() * dt,
+            u[1] * dt,
+        );
+    }
+
+    #[test]
+    fn steer_towards_a_straight_path() {
+        let path: Vec<Vector2<f64>> = (0..=40).map(|i| Vector2::new(i as f64, 0.0)).collect();
+        let pose = state(0.0, 1.0, 0.0);
+        let pure_pursuit = PurePursuit::default();
+        let stanley = Stanley::default();
+        // on the left of the path, the robot turns right
+        assert!(pure_pursuit.control(&pose, 1.0, &path).unwrap().steering < 0.0);
+        assert!(stanley.control(&pose, 1.0, &path).unwrap().steering < 0.0);
+        let end = state(40.0, 0.0, 0.0);
+        assert_eq!(
+            0.0,
+            pure_pursuit.control(&end, 0.0, &path).unwrap().velocity
+        );
+        assert!(Stanley::default().control(&pose, 1.0, &path[..1]).is_none());
+    }
+
+    #[test]
+    fn track_a_curve() {
+        let path: Vec<Vector2<f64>> = (0..=100)
+            .map(|i| {
+                let x = i as f64 * 0.5;
+                Vector2::new(x, 3.0 * (x / 8.0).sin())
+            })
+            .collect();
+        let pure_pursuit = PurePursuit::default();
+        let stanley = Stanley::default();
+        let mut a = state(0.0, -1.0, 0.3);
+        let mut b = a.clone();
+        let mut speed_a = 0.0;
+        let mut speed_b = 0.0;
+        for _ in 0..600 {
+            let command = pure_pursuit.control(&a, speed_a, &path).unwrap();
+            step(&mut a, &command, pure_pursuit.wheelbase);
+            speed_a = command.velocity;
+            let command = stanley.control(&b, speed_b, &path).unwrap();
+            step(&mut b, &command, stanley.wheelbase);
+            speed_b = command.velocity;
+            for pose in [&a, &b] {
+                let (_, q) = nearest(&path, &pose.x.xy());
+                assert!((q - pose.x.xy()).norm() < 1.5);
+            }
+        }
+        let (_, q) = nearest(&path, &a.x.xy());
+        assert!((q - a.x.xy()).norm() < 0.3);
+        assert!(a.x.x > 40.0);
+        let front = b.x.xy() + Vector2::new(b.x[2].cos(), b.x[2].sin()) * stanley.wheelbase;
+        let (_, q) = nearest(&path, &front);
+        assert!((q - front).norm() < 0.3);
+    }
+}
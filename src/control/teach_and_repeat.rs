@@ -0,0 +1,308 @@
+use nalgebra::{Isometry2, Matrix3, Vector2, Vector3};
+
+use crate::control::path_tracking::{PurePursuit, SteeringCommand};
+use crate::perception::scan_matching::{Icp, IcpConfig, ScanMatcher};
+use crate::utils::state::GaussianState;
+
+/// Pose of the robot during the teach pass and its scan, in the robot frame
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde-serialize",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct Keyframe {
+    pub pose: Isometry2<f64>,
+    pub scan: Vec<Vector2<f64>>,
+}
+
+/// Path driven during the teach pass, the poses are in the odometry frame of the teach pass
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(
+    feature = "serde-serialize",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct TaughtPath {
+    pub keyframes: Vec<Keyframe>,
+}
+
+#[cfg(feature = "serde-serialize")]
+impl TaughtPath {
+    /// Writes the path as JSON
+    pub fn save<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn std::error::Error>> {
+        crate::utils::persistence::save(writer, self)
+    }
+
+    /// Reads a path written by `save`
+    pub fn load<R: std::io::Read>(reader: R) -> Result<TaughtPath, Box<dyn std::error::Error>> {
+        crate::utils::persistence::load(reader)
+    }
+}
+
+/// Records a keyframe each time the robot moved `keyframe_distance` [m] or turned
+/// `keyframe_rotation` [rad] since the last one
+#[derive(Debug, Clone)]
+pub struct Teacher {
+    pub keyframe_distance: f64,
+    pub keyframe_rotation: f64,
+    path: TaughtPath,
+}
+
+impl Teacher {
+    pub fn new(keyframe_distance: f64, keyframe_rotation: f64) -> Teacher {
+        Teacher {
+            keyframe_distance,
+            keyframe_rotation,
+            path: TaughtPath::default(),
+        }
+    }
+
+    /// Odometry pose and scan, returns true if a keyframe was added
+    pub fn record(&mut self, pose: &Isometry2<f64>, scan: &[Vector2<f64>]) -> bool {
+        if let Some(last) = self.path.keyframes.last() {
+            let delta = last.pose.inverse() * pose;
+            if delta.translation.vector.norm() < self.keyframe_distance
+                && delta.rotation.angle().abs() < self.keyframe_rotation
+            {
+                return false;
+            }
+        }
+        self.path.keyframes.push(Keyframe {
+            pose: *pose,
+            scan: scan.to_vec(),
+        });
+        true
+    }
+
+    /// Ends the teach pass, the last pose should be recorded before
+    pub fn finish(self) -> TaughtPath {
+        self.path
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RepeatConfig {
+    /// Configuration of the ICP of `Repeater::new`
+    pub icp: IcpConfig,
+    /// The scan matches below this fitness are ignored, the robot relies on its odometry
+    pub min_fitness: f64,
+    /// Number of keyframes ahead of the current one searched for the closest
+    pub search_window: usize,
+    /// Distance [m] to the last keyframe at which the repeat is finished
+    pub goal_tolerance: f64,
+    pub controller: PurePursuit,
+}
+
+impl Default for RepeatConfig {
+    fn default() -> Self {
+        RepeatConfig {
+            icp: IcpConfig::default(),
+            min_fitness: 0.7,
+            search_window: 5,
+            goal_tolerance: 0.3,
+            controller: PurePursuit::default(),
+        }
+    }
+}
+
+/// Repeats a taught path. The robot is localized relative to the closest keyframe by matching
+/// its scan against the taught one, so no globally consistent map is needed, and the odometry
+/// bridges the failed matches. The taught poses are tracked with pure pursuit
+pub struct Repeater {
+    pub config: RepeatConfig,
+    path: TaughtPath,
+    /// One per keyframe, its scan is the target
+    matchers: Vec<Box<dyn ScanMatcher + Send>>,
+    /// Pose of the odometry frame of the repeat in the frame of the taught path
+    correction: Isometry2<f64>,
+    current: usize,
+    localized: bool,
+}
+
+impl Repeater {
+    /// Matches the scans with ICP, the robot is assumed to start near the first keyframe
+    pub fn new(path: TaughtPath, config: RepeatConfig) -> Repeater {
+        let icp = config.icp;
+        Repeater::with_matcher(path, config, || Icp::new(icp))
+    }
+
+    /// Matches the scans with the matchers made by `matcher`, e.g. NDT
+    pub fn with_matcher<M: ScanMatcher + Send + 'static>(
+        path: TaughtPath,
+        config: RepeatConfig,
+        matcher: impl Fn() -> M,
+    ) -> Repeater {
+        let matchers = path
+            .keyframes
+            .iter()
+            .map(|k| {
+                let mut m = matcher();
+                m.set_target(&k.scan);
+                Box::new(m) as Box<dyn ScanMatcher + Send>
+            })
+            .collect();
+        Repeater {
+            config,
+            path,
+            matchers,
+            correction: Isometry2::identity(),
+            current: 0,
+            localized: false,
+        }
+    }
+
+    /// Sets the pose of the odometry frame in the frame of the taught path, when the start pose
+    /// is known
+    pub fn set_correction(&mut self, correction: Isometry2<f64>) {
+        self.correction = correction;
+    }
+
+    /// Index of the closest keyframe
+    pub fn current_keyframe(&self) -> usize {
+        self.current
+    }
+
+    /// True if the last scan matched a keyframe
+    pub fn is_localized(&self) -> bool {
+        self.localized
+    }
+
+    /// Pose in the frame of the taught path from the odometry pose
+    pub fn pose(&self, odometry: &Isometry2<f64>) -> Isometry2<f64> {
+        self.correction * odometry
+    }
+
+    pub fn is_finished(&self, odometry: &Isometry2<f64>) -> bool {
+        self.path.keyframes.last().is_none_or(|last| {
+            (self.pose(odometry).translation.vector - last.pose.translation.vector).norm()
+                <= self.config.goal_tolerance
+        })
+    }
+
+    /// Command from the odometry pose, the current scan and speed, None once the path is finished
+    pub fn update(
+        &mut self,
+        odometry: &Isometry2<f64>,
+        scan: &[Vector2<f64>],
+        speed: f64,
+    ) -> Option<SteeringCommand> {
+        if self.is_finished(odometry) {
+            return None;
+        }
+        let predicted = self.pose(odometry);
+        let end = (self.current + self.config.search_window + 1).min(self.path.keyframes.len());
+        self.current = (self.current..end)
+            .min_by(|a, b| {
+                let distance = |i: usize| {
+                    (self.path.keyframes[i].pose.translation.vector - predicted.translation.vector)
+                        .norm()
+                };
+                distance(*a).total_cmp(&distance(*b))
+            })
+            .unwrap_or(self.current);
+
+        let keyframe = &self.path.keyframes[self.current];
+        let initial = keyframe.pose.inverse() * predicted;
+        let matched = self.matchers[self.current]
+            .match_scan(scan, &initial)
+            .filter(|result| result.fitness >= self.config.min_fitness);
+        self.localized = matched.is_some();
+        if let Some(result) = matched {
+            self.correction = keyframe.pose * result.transform * odometry.inverse();
+        }
+
+        let pose = self.pose(odometry);
+        let estimate = GaussianState {
+            x: Vector3::new(
+                pose.translation.x,
+                pose.translation.y,
+                pose.rotation.angle(),
+            ),
+            cov: Matrix3::identity(),
+        };
+        let remaining: Vec<Vector2<f64>> = self.path.keyframes[self.current..]
+            .iter()
+            .map(|k| k.pose.translation.vector)
+            .collect();
+        self.config.controller.control(&estimate, speed, &remaining)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Point2;
+
+    /// Walls of a corridor with pillars, every 5 cm
+    fn world() -> Vec<Vector2<f64>> {
+        let mut points = Vec::new();
+        for i in 0..=400 {
+            let x = -2.0 + i as f64 * 0.05;
+            points.push(Vector2::new(x, -2.0));
+            points.push(Vector2::new(x, 4.0));
+        }
+        for i in 0..=120 {
+            let y = -2.0 + i as f64 * 0.05;
+            points.push(Vector2::new(-2.0, y));
+            points.push(Vector2::new(18.0, y));
+        }
+        for pillar in [3.0, 8.0, 13.0] {
+            for i in 0..10 {
+                points.push(Vector2::new(pillar + i as f64 * 0.05, -1.0));
+                points.push(Vector2::new(pillar, -1.0 + i as f64 * 0.05));
+            }
+        }
+        points
+    }
+
+    fn scan(world: &[Vector2<f64>], pose: &Isometry2<f64>) -> Vec<Vector2<f64>> {
+        world
+            .iter()
+            .filter(|p| (*p - pose.translation.vector).norm() < 8.0)
+            .map(|p| (pose.inverse() * Point2::from(*p)).coords)
+            .collect()
+    }
+
+    fn drive(pose: &Isometry2<f64>, u: &Vector2<f64>, dt: f64) -> Isometry2<f64> {
+        pose * Isometry2::new(Vector2::new(u[0] * dt, 0.0), u[1] * dt)
+    }
+
+    #[test]
+    fn repeat_a_taught_path() {
+        let world = world();
+        let dt = 0.1;
+
+        // teach: an arc driven with the odometry starting at the identity
+        let mut teacher = Teacher::new(0.5, 0.3);
+        let mut pose = Isometry2::identity();
+        for _ in 0..120 {
+            teacher.record(&pose, &scan(&world, &pose));
+            pose = drive(&pose, &Vector2::new(1.0, 0.03), dt);
+        }
+        teacher.record(&pose, &scan(&world, &pose));
+        let path = teacher.finish();
+        let goal = path.keyframes.last().unwrap().pose.translation.vector;
+        assert!(path.keyframes.len() > 20);
+
+        // repeat: the robot starts 20 cm off, its odometry starts again at the identity
+        let mut repeater = Repeater::new(path, RepeatConfig::default());
+        let start = Isometry2::new(Vector2::new(0.1, 0.2), 0.05);
+        let mut truth = start;
+        let mut odometry = Isometry2::identity();
+        let mut speed = 0.0;
+        for _ in 0..400 {
+            let Some(command) = repeater.update(&odometry, &scan(&world, &truth), speed) else {
+                break;
+            };
+            let u = command.unicycle(repeater.config.controller.wheelbase);
+            truth = drive(&truth, &u, dt);
+            // the wheels slip
+            odometry = drive(&odometry, &Vector2::new(u[0] * 1.05, u[1]), dt);
+            speed = command.velocity;
+            assert!(repeater.is_localized());
+            let estimated = repeater.pose(&odometry);
+            assert!((estimated.translation.vector - truth.translation.vector).norm() < 0.2);
+        }
+        assert!(repeater.is_finished(&odometry));
+        assert!((truth.translation.vector - goal).norm() < 0.5);
+    }
+}
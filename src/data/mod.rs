@@ -1,2 +0,0 @@
-pub mod slam_course;
-pub mod utias;
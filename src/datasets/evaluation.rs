@@ -0,0 +1,107 @@
+use nalgebra::{Isometry2, Point2, Vector2};
+
+use crate::utils::metrics::align_se2;
+
+/// Statistics of the position error of an estimated trajectory
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryError {
+    /// Number of estimates matched with a ground truth position
+    pub matched: usize,
+    /// Absolute trajectory error, RMSE after the SE2 alignment of the estimate on the ground truth
+    pub ate: f64,
+    pub mean: f64,
+    pub max: f64,
+    /// RMSE without alignment, for filters sharing the frame of the ground truth
+    pub rmse: f64,
+}
+
+pub fn rmse(errors: &[f64]) -> f64 {
+    if errors.is_empty() {
+        return 0.0;
+    }
+    (errors.iter().map(|e| e * e).sum::<f64>() / errors.len() as f64).sqrt()
+}
+
+/// Matches each estimate with the ground truth position closest in time, at most `max_dt` away.
+/// Both trajectories are (time, position) sorted by time
+pub fn associate(
+    estimates: &[(f64, Vector2<f64>)],
+    groundtruth: &[(f64, Vector2<f64>)],
+    max_dt: f64,
+) -> Vec<(Vector2<f64>, Vector2<f64>)> {
+    let mut pairs = Vec::new();
+    if groundtruth.is_empty() {
+        return pairs;
+    }
+    for (time, estimate) in estimates {
+        let i = groundtruth.partition_point(|(t, _)| t < time);
+        let closest = [i.saturating_sub(1), i.min(groundtruth.len() - 1)]
+            .into_iter()
+            .min_by(|a, b| {
+                (groundtruth[*a].0 - time)
+                    .abs()
+                    .partial_cmp(&(groundtruth[*b].0 - time).abs())
+                    .unwrap()
+            })
+            .unwrap();
+        if (groundtruth[closest].0 - time).abs() <= max_dt {
+            pairs.push((*estimate, groundtruth[closest].1));
+        }
+    }
+    pairs
+}
+
+/// None if no estimate can be matched with the ground truth
+pub fn evaluate(
+    estimates: &[(f64, Vector2<f64>)],
+    groundtruth: &[(f64, Vector2<f64>)],
+    max_dt: f64,
+) -> Option<TrajectoryError> {
+    let pairs = associate(estimates, groundtruth, max_dt);
+    if pairs.is_empty() {
+        return None;
+    }
+    let (source, target): (Vec<Vector2<f64>>, Vec<Vector2<f64>>) = pairs.iter().copied().unzip();
+    let alignment = align_se2(&source, &target);
+
+    let aligned: Vec<f64> = pairs
+        .iter()
+        .map(|(e, t)| ((alignment * Point2::from(*e)).coords - t).norm())
+        .collect();
+    let unaligned: Vec<f64> = pairs.iter().map(|(e, t)| (e - t).norm()).collect();
+    Some(TrajectoryError {
+        matched: pairs.len(),
+        ate: rmse(&aligned),
+        mean: aligned.iter().sum::<f64>() / aligned.len() as f64,
+        max: aligned.iter().copied().fold(0.0, f64::max),
+        rmse: rmse(&unaligned),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ate_of_a_rigidly_moved_trajectory() {
+        let groundtruth: Vec<(f64, Vector2<f64>)> = (0..50)
+            .map(|i| {
+                let t = i as f64 * 0.1;
+                (t, Vector2::new(t.cos() * 3.0, t.sin() * 2.0 + t))
+            })
+            .collect();
+        let transform = Isometry2::new(Vector2::new(1.0, -2.0), 0.7);
+        // slightly shifted timestamps, the estimates are matched to the closest ground truth
+        let estimates: Vec<(f64, Vector2<f64>)> = groundtruth
+            .iter()
+            .map(|(t, p)| (t + 0.01, (transform * Point2::from(*p)).coords))
+            .collect();
+
+        let error = evaluate(&estimates, &groundtruth, 0.05).unwrap();
+        assert_eq!(50, error.matched);
+        approx::assert_abs_diff_eq!(0.0, error.ate, epsilon = 1e-9);
+        assert!(error.rmse > 1.0);
+
+        assert!(evaluate(&estimates, &groundtruth[..0], 0.05).is_none());
+    }
+}
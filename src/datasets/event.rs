@@ -0,0 +1,37 @@
+use nalgebra::Vector2;
+
+/// Dataset record, independent of the dataset format
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    /// Control input (v, w)
+    Control(Vector2<f64>),
+    /// [range, bearing] measurements taken at the same time, with the landmark id if known
+    Measurements(Vec<(Option<u32>, Vector2<f64>)>),
+    /// Reference position, with the heading if the dataset provides it
+    GroundTruth {
+        xy: Vector2<f64>,
+        heading: Option<f64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimedEvent {
+    pub time: f64,
+    pub event: Event,
+}
+
+/// Sorts the events by time, at the same time the controls come first,
+/// then the ground truth and finally the measurements
+pub fn sort_events(events: &mut [TimedEvent]) {
+    let rank = |e: &Event| match e {
+        Event::Control(_) => 0,
+        Event::GroundTruth { .. } => 1,
+        Event::Measurements(_) => 2,
+    };
+    events.sort_by(|a, b| {
+        a.time
+            .partial_cmp(&b.time)
+            .unwrap()
+            .then(rank(&a.event).cmp(&rank(&b.event)))
+    });
+}
@@ -0,0 +1,5 @@
+pub mod evaluation;
+pub mod event;
+pub mod slam_course;
+pub mod utias;
+pub mod victoria_park;
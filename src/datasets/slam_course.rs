@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+#[cfg(feature = "viz")]
 use plotters::prelude::*;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
@@ -130,6 +131,7 @@ impl<'a> Iterator for SlamCourseDatasetIteratorRef<'a> {
 /// - the landmarks in the map (black +'s)
 /// - current robot pose (red)
 /// - observations made at this time step (line between robot and landmark)
+#[cfg(feature = "viz")]
 fn plot(dataset: &SlamCourseDataset) -> Result<(), Box<dyn Error>> {
     std::fs::create_dir_all("./img")?;
     let filename = "./img/slam_course.png";
@@ -160,7 +162,7 @@ fn plot(dataset: &SlamCourseDataset) -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[cfg(test)]
+#[cfg(all(test, feature = "viz"))]
 mod tests {
     use super::*;
 
@@ -1,8 +1,11 @@
 use csv;
+use nalgebra::Vector2;
 use rustc_hash::FxHashMap;
 use serde::{Deserialize, Serialize};
 use std::error::Error;
 
+use crate::datasets::event::{sort_events, Event, TimedEvent};
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct RangeBearing {
     pub time: f64,
@@ -222,6 +225,38 @@ impl UtiasDataset {
             odometry,
         })
     }
+
+    /// All the records in time order, the measurements sharing a timestamp are grouped
+    pub fn events(&self) -> impl Iterator<Item = TimedEvent> {
+        let mut events: Vec<TimedEvent> = self
+            .odometry
+            .iter()
+            .map(|od| TimedEvent {
+                time: od.time,
+                event: Event::Control(Vector2::new(od.forward_velocity, od.angular_velocity)),
+            })
+            .chain(self.groundtruth.iter().map(|p| TimedEvent {
+                time: p.time,
+                event: Event::GroundTruth {
+                    xy: Vector2::new(p.x, p.y),
+                    heading: Some(p.orientation),
+                },
+            }))
+            .collect();
+        for group in self.measurements.chunk_by(|a, b| a.time == b.time) {
+            events.push(TimedEvent {
+                time: group[0].time,
+                event: Event::Measurements(
+                    group
+                        .iter()
+                        .map(|rb| (Some(rb.subject_nb), Vector2::new(rb.range, rb.bearing)))
+                        .collect(),
+                ),
+            });
+        }
+        sort_events(&mut events);
+        events.into_iter()
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,354 @@
+use nalgebra::Vector2;
+use std::error::Error;
+use std::f64::consts::FRAC_PI_2;
+use std::fs::read_to_string;
+use std::path::Path;
+
+use crate::datasets::event::{sort_events, Event, TimedEvent};
+use crate::models::kinematics::Ackermann;
+
+/// Beams of a scan, from the right of the vehicle to its left
+pub const LASER_BEAMS: usize = 361;
+/// Angle between two beams [rad]
+pub const LASER_RESOLUTION: f64 = std::f64::consts::PI / 360.0;
+
+/// Dead reckoning record, the speed is measured by the encoder of the left rear wheel
+#[derive(Debug, Clone, PartialEq)]
+pub struct Odometry {
+    pub time: f64,
+    /// Speed of the left rear wheel [m/s]
+    pub speed: f64,
+    /// Steering angle [rad]
+    pub steering: f64,
+}
+
+/// Tree detected in the laser scan, in the rear axle frame, the correspondences are unknown
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeBearing {
+    pub time: f64,
+    pub range: f64,
+    pub bearing: f64,
+}
+
+/// GPS fix, used as ground truth
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gps {
+    pub time: f64,
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Tree in the laser frame, the bearing is 0 straight ahead
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tree {
+    /// Range of the trunk center [m]
+    pub range: f64,
+    pub bearing: f64,
+    pub diameter: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TreeDetectionConfig {
+    /// Beyond this range the returns are too sparse to find trunks [m]
+    pub max_range: f64,
+    /// Consecutive beams farther apart than this belong to different objects [m]
+    pub max_jump: f64,
+    pub min_diameter: f64,
+    pub max_diameter: f64,
+}
+
+impl Default for TreeDetectionConfig {
+    fn default() -> Self {
+        TreeDetectionConfig {
+            max_range: 30.0,
+            max_jump: 1.0,
+            min_diameter: 0.05,
+            max_diameter: 1.5,
+        }
+    }
+}
+
+/// Trunks in a scan of `LASER_BEAMS` ranges: runs of consecutive beams in front of both of
+/// their neighbours, with a plausible width
+pub fn detect_trees(ranges: &[f64], config: &TreeDetectionConfig) -> Vec<Tree> {
+    let angle = |i: usize| i as f64 * LASER_RESOLUTION - FRAC_PI_2;
+    let mut trees = Vec::new();
+    let mut start = 0;
+    for end in 1..=ranges.len() {
+        if end < ranges.len() && (ranges[end] - ranges[end - 1]).abs() <= config.max_jump {
+            continue;
+        }
+        let (first, segment) = (start, &ranges[start..end]);
+        start = end;
+        if segment.iter().any(|r| *r >= config.max_range)
+            || (first > 0 && ranges[first - 1] < segment[0])
+            || (end < ranges.len() && ranges[end] < segment[segment.len() - 1])
+        {
+            continue;
+        }
+        let closest = segment.iter().copied().fold(f64::INFINITY, f64::min);
+        let diameter = segment.len() as f64 * LASER_RESOLUTION * closest;
+        if diameter < config.min_diameter || config.max_diameter < diameter {
+            continue;
+        }
+        trees.push(Tree {
+            range: closest + diameter / 2.0,
+            bearing: angle(first) + (segment.len() - 1) as f64 * LASER_RESOLUTION / 2.0,
+            diameter,
+        });
+    }
+    trees
+}
+
+/// Geometry of the Victoria Park truck, the reference point is the middle of the rear axle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vehicle {
+    pub kinematics: Ackermann,
+    /// Lateral distance from the middle of the rear axle to the left rear wheel
+    pub encoder_offset: f64,
+    /// Position of the laser, x forward and y to the left
+    pub laser: Vector2<f64>,
+}
+
+impl Default for Vehicle {
+    fn default() -> Self {
+        Vehicle {
+            kinematics: Ackermann {
+                wheelbase: 2.83,
+                track_width: 1.52,
+            },
+            encoder_offset: 0.76,
+            laser: Vector2::new(3.78, 0.5),
+        }
+    }
+}
+
+impl Vehicle {
+    /// (v, w) of the rear axle, the left rear wheel is on the inside of the left turns
+    pub fn control(&self, odometry: &Odometry) -> Vector2<f64> {
+        let ratio = 1.0 - odometry.steering.tan() * self.encoder_offset / self.kinematics.wheelbase;
+        self.kinematics
+            .forward(odometry.speed / ratio, odometry.steering)
+    }
+
+    /// [range, bearing] of a tree seen from the middle of the rear axle
+    pub fn measurement(&self, tree: &Tree) -> Vector2<f64> {
+        let p = self.laser + Vector2::new(tree.bearing.cos(), tree.bearing.sin()) * tree.range;
+        Vector2::new(p.norm(), p.y.atan2(p.x))
+    }
+}
+
+/// Rows of whitespace separated numbers with `fields` columns, empty lines are ignored
+fn rows(content: &str, file: &str, fields: usize) -> Result<Vec<Vec<f64>>, Box<dyn Error>> {
+    let mut rows = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let row = line
+            .split_whitespace()
+            .map(|x| x.parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| format!("{file} line {}: {e}", i + 1))?;
+        if row.len() != fields {
+            return Err(format!(
+                "{file} line {}: expected {fields} fields, found {}",
+                i + 1,
+                row.len()
+            )
+            .into());
+        }
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// Victoria Park dataset, from the text exports of the original Matlab files :
+///
+/// ```text
+/// aa3_dr.txt    time speed steering
+/// aa3_lsr2.txt  time range_0 ... range_360
+/// aa3_gpsx.txt  time x y
+/// ```
+///
+/// with the time in milliseconds, the speed of the left rear wheel in m/s, the steering angle
+/// in radians and the ranges and positions in meters. The beams go from the right of the
+/// vehicle to its left by half a degree and the trees are extracted from the scans
+///
+/// Source : Optimization of the simultaneous localization and map-building algorithm for
+/// real-time implementation, Guivant & Nebot, 2001
+pub struct VictoriaParkDataset {
+    pub vehicle: Vehicle,
+    pub odometry: Vec<Odometry>,
+    pub measurements: Vec<RangeBearing>,
+    pub gps: Vec<Gps>,
+}
+
+impl VictoriaParkDataset {
+    /// Reads aa3_dr.txt, aa3_lsr2.txt and aa3_gpsx.txt in `dir`
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<VictoriaParkDataset, Box<dyn Error>> {
+        let read = |file: &str| {
+            let path = dir.as_ref().join(file);
+            read_to_string(&path).map_err(|e| format!("{}: {e}", path.display()))
+        };
+        VictoriaParkDataset::parse(
+            &read("aa3_dr.txt")?,
+            &read("aa3_lsr2.txt")?,
+            &read("aa3_gpsx.txt")?,
+            Vehicle::default(),
+            &TreeDetectionConfig::default(),
+        )
+    }
+
+    pub fn parse(
+        dead_reckoning: &str,
+        laser: &str,
+        gps: &str,
+        vehicle: Vehicle,
+        config: &TreeDetectionConfig,
+    ) -> Result<VictoriaParkDataset, Box<dyn Error>> {
+        let odometry: Vec<Odometry> = rows(dead_reckoning, "aa3_dr", 3)?
+            .iter()
+            .map(|row| Odometry {
+                time: row[0] / 1000.0,
+                speed: row[1],
+                steering: row[2],
+            })
+            .collect();
+        let mut measurements = Vec::new();
+        for row in rows(laser, "aa3_lsr2", 1 + LASER_BEAMS)? {
+            let time = row[0] / 1000.0;
+            measurements.extend(detect_trees(&row[1..], config).iter().map(|tree| {
+                let z = vehicle.measurement(tree);
+                RangeBearing {
+                    time,
+                    range: z.x,
+                    bearing: z.y,
+                }
+            }));
+        }
+        let gps: Vec<Gps> = rows(gps, "aa3_gpsx", 3)?
+            .iter()
+            .map(|row| Gps {
+                time: row[0] / 1000.0,
+                x: row[1],
+                y: row[2],
+            })
+            .collect();
+
+        Ok(VictoriaParkDataset {
+            vehicle,
+            odometry,
+            measurements,
+            gps,
+        })
+    }
+
+    /// All the records in time order, the trees of a scan are grouped, the controls are the
+    /// (v, w) of the rear axle
+    pub fn events(&self) -> impl Iterator<Item = TimedEvent> {
+        let mut events: Vec<TimedEvent> = self
+            .odometry
+            .iter()
+            .map(|od| TimedEvent {
+                time: od.time,
+                event: Event::Control(self.vehicle.control(od)),
+            })
+            .chain(self.gps.iter().map(|g| TimedEvent {
+                time: g.time,
+                event: Event::GroundTruth {
+                    xy: Vector2::new(g.x, g.y),
+                    heading: None,
+                },
+            }))
+            .collect();
+        for group in self.measurements.chunk_by(|a, b| a.time == b.time) {
+            events.push(TimedEvent {
+                time: group[0].time,
+                event: Event::Measurements(
+                    group
+                        .iter()
+                        .map(|rb| (None, Vector2::new(rb.range, rb.bearing)))
+                        .collect(),
+                ),
+            });
+        }
+        sort_events(&mut events);
+        events.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_abs_diff_eq;
+
+    /// Nothing in range but a trunk of 5 beams at 5m straight ahead
+    fn scan() -> Vec<f64> {
+        let mut ranges = vec![81.91; LASER_BEAMS];
+        ranges[178..183].fill(5.0);
+        ranges
+    }
+
+    #[test]
+    fn tree_straight_ahead() {
+        let trees = detect_trees(&scan(), &TreeDetectionConfig::default());
+        assert_eq!(1, trees.len());
+        let diameter = 5.0 * LASER_RESOLUTION * 5.0;
+        assert_abs_diff_eq!(diameter, trees[0].diameter, epsilon = 1e-12);
+        assert_abs_diff_eq!(5.0 + diameter / 2.0, trees[0].range, epsilon = 1e-12);
+        assert_abs_diff_eq!(0.0, trees[0].bearing, epsilon = 1e-12);
+
+        // a wall is too wide to be a trunk
+        let mut ranges = scan();
+        ranges[100..200].fill(5.0);
+        assert!(detect_trees(&ranges, &TreeDetectionConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn parse_and_merge() {
+        let dead_reckoning = "0 2.0 0.1\n500 2.0 0.0\n";
+        let laser = format!(
+            "500 {}\n",
+            scan()
+                .iter()
+                .map(|r| r.to_string())
+                .collect::<Vec<String>>()
+                .join(" ")
+        );
+        let gps = "500 1.0 2.0\n";
+        let vehicle = Vehicle::default();
+        let dataset = VictoriaParkDataset::parse(
+            dead_reckoning,
+            &laser,
+            gps,
+            vehicle,
+            &TreeDetectionConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(2, dataset.odometry.len());
+        assert_eq!(1, dataset.measurements.len());
+        let z = &dataset.measurements[0];
+        assert_eq!(0.5, z.time);
+        let tree_x = 3.78 + 5.0 + 2.5 * LASER_RESOLUTION * 5.0;
+        assert_abs_diff_eq!(tree_x.hypot(0.5), z.range, epsilon = 1e-12);
+        assert_abs_diff_eq!(0.5f64.atan2(tree_x), z.bearing, epsilon = 1e-12);
+
+        let events: Vec<TimedEvent> = dataset.events().collect();
+        assert_eq!(4, events.len());
+        let v = 2.0 / (1.0 - 0.1f64.tan() * 0.76 / 2.83);
+        let Event::Control(u) = &events[0].event else {
+            panic!("expected a control, got {:?}", events[0]);
+        };
+        assert_abs_diff_eq!(
+            Vector2::new(v, v * 0.1f64.tan() / 2.83),
+            *u,
+            epsilon = 1e-12
+        );
+        assert!(matches!(events[1].event, Event::Control(_)));
+        assert!(matches!(events[2].event, Event::GroundTruth { .. }));
+        assert!(matches!(&events[3].event, Event::Measurements(z) if z.len() == 1));
+
+        assert!(VictoriaParkDataset::parse("0 1.0", "", "", vehicle, &Default::default()).is_err());
+    }
+}
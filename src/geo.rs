@@ -0,0 +1,410 @@
+use nalgebra::{
+    allocator::Allocator, Const, DefaultAllocator, Dim, Matrix2, Matrix3, OMatrix, OVector,
+    Vector2, Vector3,
+};
+
+use crate::models::measurement::MeasurementModel;
+use crate::utils::frames::{enu_to_ned, ned_to_enu};
+
+/// WGS-84 semi-major axis [m]
+pub const WGS84_A: f64 = 6_378_137.0;
+/// WGS-84 flattening
+pub const WGS84_F: f64 = 1.0 / 298.257_223_563;
+/// Square of the first eccentricity
+const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+const UTM_K0: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500_000.0;
+const UTM_FALSE_NORTHING_SOUTH: f64 = 10_000_000.0;
+
+/// Position on the WGS-84 ellipsoid, as given by the GNSS receivers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geodetic {
+    /// [deg], > 0 in the north
+    pub latitude: f64,
+    /// [deg], > 0 in the east
+    pub longitude: f64,
+    /// Height above the ellipsoid [m]
+    pub altitude: f64,
+}
+
+impl Geodetic {
+    pub fn new(latitude: f64, longitude: f64, altitude: f64) -> Geodetic {
+        Geodetic {
+            latitude,
+            longitude,
+            altitude,
+        }
+    }
+
+    /// Earth centered earth fixed coordinates [m]
+    pub fn to_ecef(&self) -> Vector3<f64> {
+        let (lat, lon) = (self.latitude.to_radians(), self.longitude.to_radians());
+        let n = prime_vertical_radius(lat);
+        Vector3::new(
+            (n + self.altitude) * lat.cos() * lon.cos(),
+            (n + self.altitude) * lat.cos() * lon.sin(),
+            (n * (1.0 - WGS84_E2) + self.altitude) * lat.sin(),
+        )
+    }
+
+    /// From the earth centered earth fixed coordinates, iterated to below the micrometer
+    pub fn from_ecef(ecef: &Vector3<f64>) -> Geodetic {
+        let p = ecef.xy().norm();
+        let lon = ecef.y.atan2(ecef.x);
+        let mut lat = ecef.z.atan2(p * (1.0 - WGS84_E2));
+        let mut altitude = 0.0;
+        for _ in 0..10 {
+            let n = prime_vertical_radius(lat);
+            // valid at the poles too, unlike p / cos(lat) - n
+            altitude = p * lat.cos() + ecef.z * lat.sin() - WGS84_A * WGS84_A / n;
+            lat = ecef.z.atan2(p * (1.0 - WGS84_E2 * n / (n + altitude)));
+        }
+        Geodetic::new(lat.to_degrees(), lon.to_degrees(), altitude)
+    }
+}
+
+fn prime_vertical_radius(lat: f64) -> f64 {
+    WGS84_A / (1.0 - WGS84_E2 * lat.sin().powi(2)).sqrt()
+}
+
+/// Local tangent plane at `origin`, for the positions within a few kilometers of it
+#[derive(Debug, Clone)]
+pub struct LocalFrame {
+    pub origin: Geodetic,
+    origin_ecef: Vector3<f64>,
+    /// Rows are the east, north and up axes in ECEF
+    ecef_to_enu: Matrix3<f64>,
+}
+
+impl LocalFrame {
+    pub fn new(origin: Geodetic) -> LocalFrame {
+        let (lat, lon) = (origin.latitude.to_radians(), origin.longitude.to_radians());
+        let (sin_lat, cos_lat) = lat.sin_cos();
+        let (sin_lon, cos_lon) = lon.sin_cos();
+        #[rustfmt::skip]
+        let ecef_to_enu = Matrix3::new(
+            -sin_lon, cos_lon, 0.,
+            -sin_lat * cos_lon, -sin_lat * sin_lon, cos_lat,
+            cos_lat * cos_lon, cos_lat * sin_lon, sin_lat,
+        );
+        LocalFrame {
+            origin,
+            origin_ecef: origin.to_ecef(),
+            ecef_to_enu,
+        }
+    }
+
+    /// East North Up coordinates of `position` [m]
+    pub fn to_enu(&self, position: &Geodetic) -> Vector3<f64> {
+        self.ecef_to_enu * (position.to_ecef() - self.origin_ecef)
+    }
+
+    pub fn from_enu(&self, enu: &Vector3<f64>) -> Geodetic {
+        Geodetic::from_ecef(&(self.origin_ecef + self.ecef_to_enu.transpose() * enu))
+    }
+
+    /// North East Down coordinates of `position` [m]
+    pub fn to_ned(&self, position: &Geodetic) -> Vector3<f64> {
+        enu_to_ned(&self.to_enu(position))
+    }
+
+    pub fn from_ned(&self, ned: &Vector3<f64>) -> Geodetic {
+        self.from_enu(&ned_to_enu(ned))
+    }
+}
+
+/// Universal Transverse Mercator coordinates, the exceptions of the zones around Norway and
+/// Svalbard are not handled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Utm {
+    /// 1 to 60
+    pub zone: u8,
+    pub north: bool,
+    /// [m]
+    pub easting: f64,
+    /// [m]
+    pub northing: f64,
+}
+
+/// Coefficients of the series of Krüger in the third flattening n, to the fourth order
+///
+/// Source : Transverse Mercator with an accuracy of a few nanometers, Karney 2011
+struct KrugerSeries {
+    /// Radius of the rectifying sphere scaled by the scale factor of the central meridian
+    scale: f64,
+    alpha: [f64; 4],
+    beta: [f64; 4],
+    delta: [f64; 4],
+}
+
+fn kruger_series() -> KrugerSeries {
+    let n = WGS84_F / (2.0 - WGS84_F);
+    let (n2, n3, n4) = (n * n, n * n * n, n * n * n * n);
+    KrugerSeries {
+        scale: UTM_K0 * WGS84_A / (1.0 + n) * (1.0 + n2 / 4.0 + n4 / 64.0),
+        alpha: [
+            n / 2.0 - 2.0 * n2 / 3.0 + 5.0 * n3 / 16.0 + 41.0 * n4 / 180.0,
+            13.0 * n2 / 48.0 - 3.0 * n3 / 5.0 + 557.0 * n4 / 1440.0,
+            61.0 * n3 / 240.0 - 103.0 * n4 / 140.0,
+            49561.0 * n4 / 161280.0,
+        ],
+        beta: [
+            n / 2.0 - 2.0 * n2 / 3.0 + 37.0 * n3 / 96.0 - n4 / 360.0,
+            n2 / 48.0 + n3 / 15.0 - 437.0 * n4 / 1440.0,
+            17.0 * n3 / 480.0 - 37.0 * n4 / 840.0,
+            4397.0 * n4 / 161280.0,
+        ],
+        delta: [
+            2.0 * n - 2.0 * n2 / 3.0 - 2.0 * n3 + 116.0 * n4 / 45.0,
+            7.0 * n2 / 3.0 - 8.0 * n3 / 5.0 - 227.0 * n4 / 45.0,
+            56.0 * n3 / 15.0 - 136.0 * n4 / 35.0,
+            4279.0 * n4 / 630.0,
+        ],
+    }
+}
+
+fn central_meridian(zone: u8) -> f64 {
+    (zone as f64 * 6.0 - 183.0).to_radians()
+}
+
+impl Utm {
+    /// Coordinates in the zone of `position`
+    pub fn from_geodetic(position: &Geodetic) -> Utm {
+        let zone = (((position.longitude + 180.0) / 6.0).floor() as i64).rem_euclid(60) as u8 + 1;
+        Utm::from_geodetic_in_zone(position, zone)
+    }
+
+    /// Coordinates in the given zone, e.g. to stay in the zone of the origin of a map crossing
+    /// the border of two zones
+    pub fn from_geodetic_in_zone(position: &Geodetic, zone: u8) -> Utm {
+        let series = kruger_series();
+        let n = WGS84_F / (2.0 - WGS84_F);
+        let e = 2.0 * n.sqrt() / (1.0 + n);
+        let lat = position.latitude.to_radians();
+        let dlon = position.longitude.to_radians() - central_meridian(zone);
+
+        // conformal latitude
+        let t = (lat.sin().atanh() - e * (e * lat.sin()).atanh()).sinh();
+        let xi = t.atan2(dlon.cos());
+        let eta = (dlon.sin() / (1.0 + t * t).sqrt()).atanh();
+        let (mut easting, mut northing) = (eta, xi);
+        for (j, alpha) in series.alpha.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            easting += alpha * (k * xi).cos() * (k * eta).sinh();
+            northing += alpha * (k * xi).sin() * (k * eta).cosh();
+        }
+        let north = position.latitude >= 0.0;
+        Utm {
+            zone,
+            north,
+            easting: UTM_FALSE_EASTING + series.scale * easting,
+            northing: if north { 0.0 } else { UTM_FALSE_NORTHING_SOUTH } + series.scale * northing,
+        }
+    }
+
+    /// Latitude and longitude, the altitude is 0
+    pub fn to_geodetic(&self) -> Geodetic {
+        let series = kruger_series();
+        let false_northing = if self.north {
+            0.0
+        } else {
+            UTM_FALSE_NORTHING_SOUTH
+        };
+        let xi = (self.northing - false_northing) / series.scale;
+        let eta = (self.easting - UTM_FALSE_EASTING) / series.scale;
+        let (mut xi_prime, mut eta_prime) = (xi, eta);
+        for (j, beta) in series.beta.iter().enumerate() {
+            let k = 2.0 * (j + 1) as f64;
+            xi_prime -= beta * (k * xi).sin() * (k * eta).cosh();
+            eta_prime -= beta * (k * xi).cos() * (k * eta).sinh();
+        }
+        let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+        let lat = series
+            .delta
+            .iter()
+            .enumerate()
+            .fold(chi, |lat, (j, delta)| {
+                lat + delta * (2.0 * (j + 1) as f64 * chi).sin()
+            });
+        let lon = central_meridian(self.zone) + eta_prime.sinh().atan2(xi_prime.cos());
+        Geodetic::new(lat.to_degrees(), lon.to_degrees(), 0.0)
+    }
+}
+
+/// Position fix of a GNSS receiver with its covariance in the local ENU frame [m^2]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GnssFix {
+    pub time: f64,
+    pub position: Geodetic,
+    pub covariance: Matrix3<f64>,
+}
+
+impl GnssFix {
+    /// Fix with the standard deviations reported by the receiver, e.g. from the HDOP/VDOP
+    /// multiplied by the range error
+    pub fn with_accuracy(time: f64, position: Geodetic, horizontal: f64, vertical: f64) -> GnssFix {
+        GnssFix {
+            time,
+            position,
+            covariance: Matrix3::from_diagonal(&Vector3::new(
+                horizontal * horizontal,
+                horizontal * horizontal,
+                vertical * vertical,
+            )),
+        }
+    }
+
+    /// Horizontal (east, north) position in `frame` and its covariance, the measurement and the
+    /// measurement noise of `GnssPositionModel`
+    pub fn to_local(&self, frame: &LocalFrame) -> (Vector2<f64>, Matrix2<f64>) {
+        (
+            frame.to_enu(&self.position).xy(),
+            self.covariance.fixed_view::<2, 2>(0, 0).into_owned(),
+        )
+    }
+}
+
+/// Measurement = [east, north] of the antenna in a `LocalFrame`, the state starts with
+/// [x, y, theta] in the same frame. The antenna is at `lever_arm` in the robot frame, the
+/// heading is only read when it is not at the origin
+pub struct GnssPositionModel {
+    pub lever_arm: Vector2<f64>,
+}
+
+impl GnssPositionModel {
+    pub fn new(lever_arm: Vector2<f64>) -> Box<GnssPositionModel> {
+        Box::new(GnssPositionModel { lever_arm })
+    }
+}
+
+impl<S: Dim> MeasurementModel<f64, S, Const<2>> for GnssPositionModel
+where
+    DefaultAllocator: Allocator<f64, S> + Allocator<f64, S, S> + Allocator<f64, Const<2>, S>,
+{
+    fn prediction(&self, x: &OVector<f64, S>, _landmark: Option<&OVector<f64, S>>) -> Vector2<f64> {
+        let position = Vector2::new(x[0], x[1]);
+        if self.lever_arm == Vector2::zeros() {
+            return position;
+        }
+        let (sin, cos) = x[2].sin_cos();
+        position
+            + Vector2::new(
+                cos * self.lever_arm.x - sin * self.lever_arm.y,
+                sin * self.lever_arm.x + cos * self.lever_arm.y,
+            )
+    }
+
+    fn jacobian(
+        &self,
+        x: &OVector<f64, S>,
+        _landmark: Option<&OVector<f64, S>>,
+    ) -> OMatrix<f64, Const<2>, S> {
+        let mut jac = OMatrix::zeros_generic(Const::<2>, x.shape_generic().0);
+        jac[(0, 0)] = 1.;
+        jac[(1, 1)] = 1.;
+        if self.lever_arm != Vector2::zeros() {
+            let (sin, cos) = x[2].sin_cos();
+            jac[(0, 2)] = -sin * self.lever_arm.x - cos * self.lever_arm.y;
+            jac[(1, 2)] = cos * self.lever_arm.x - sin * self.lever_arm.y;
+        }
+        jac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn ecef_round_trip() {
+        let equator = Geodetic::new(0.0, 0.0, 0.0).to_ecef();
+        assert_relative_eq!(equator, Vector3::new(WGS84_A, 0.0, 0.0), epsilon = 1e-6);
+        let pole = Geodetic::new(90.0, 0.0, 0.0).to_ecef();
+        assert_relative_eq!(pole.z, 6_356_752.314_245, epsilon = 1e-5);
+        let pole = Geodetic::from_ecef(&pole);
+        assert_relative_eq!(pole.latitude, 90.0, epsilon = 1e-9);
+        assert_relative_eq!(pole.altitude, 0.0, epsilon = 1e-6);
+
+        let position = Geodetic::new(-33.8568, 151.2153, 42.0);
+        let back = Geodetic::from_ecef(&position.to_ecef());
+        assert_relative_eq!(back.latitude, position.latitude, epsilon = 1e-10);
+        assert_relative_eq!(back.longitude, position.longitude, epsilon = 1e-10);
+        assert_relative_eq!(back.altitude, position.altitude, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn local_frame() {
+        let origin = Geodetic::new(48.8583, 2.2945, 35.0);
+        let frame = LocalFrame::new(origin);
+        assert_relative_eq!(frame.to_enu(&origin), Vector3::zeros(), epsilon = 1e-9);
+
+        // one arc second north is ~30.9 m at this latitude
+        let north = Geodetic::new(origin.latitude + 1.0 / 3600.0, origin.longitude, 35.0);
+        let enu = frame.to_enu(&north);
+        assert!(enu.x.abs() < 1e-6 && (enu.y - 30.9).abs() < 0.1 && enu.z.abs() < 1e-3);
+        assert_relative_eq!(frame.to_ned(&north), enu_to_ned(&enu));
+
+        let enu = Vector3::new(120.0, -45.0, 3.0);
+        assert_relative_eq!(frame.to_enu(&frame.from_enu(&enu)), enu, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn utm() {
+        // on the central meridian of the zone 31 at the equator
+        let utm = Utm::from_geodetic(&Geodetic::new(0.0, 3.0, 0.0));
+        assert_eq!((utm.zone, utm.north), (31, true));
+        assert_relative_eq!(utm.easting, 500_000.0, epsilon = 1e-6);
+        assert_relative_eq!(utm.northing, 0.0, epsilon = 1e-6);
+        // Eiffel tower, 31U 448252 5411944
+        let utm = Utm::from_geodetic(&Geodetic::new(48.8583, 2.2945, 0.0));
+        assert_eq!((utm.zone, utm.north), (31, true));
+        assert_relative_eq!(utm.easting, 448_252.0, epsilon = 0.5);
+        assert_relative_eq!(utm.northing, 5_411_944.0, epsilon = 0.5);
+
+        for position in [
+            Geodetic::new(48.8583, 2.2945, 0.0),
+            Geodetic::new(-33.8568, 151.2153, 0.0),
+            Geodetic::new(64.1, -21.9, 0.0),
+        ] {
+            let utm = Utm::from_geodetic(&position);
+            assert_eq!(utm.north, position.latitude >= 0.0);
+            let back = utm.to_geodetic();
+            assert_relative_eq!(back.latitude, position.latitude, epsilon = 1e-9);
+            assert_relative_eq!(back.longitude, position.longitude, epsilon = 1e-9);
+        }
+
+        // the distances match the local frame nearby, up to the scale factor
+        let origin = Geodetic::new(45.0, 9.0, 0.0);
+        let east = Geodetic::new(45.0, 9.01, 0.0);
+        let frame = LocalFrame::new(origin);
+        let (a, b) = (Utm::from_geodetic(&origin), Utm::from_geodetic(&east));
+        let utm_distance =
+            ((b.easting - a.easting).powi(2) + (b.northing - a.northing).powi(2)).sqrt();
+        assert_relative_eq!(
+            utm_distance,
+            frame.to_enu(&east).norm() * UTM_K0,
+            epsilon = 0.05
+        );
+    }
+
+    #[test]
+    fn gnss_model_with_lever_arm() {
+        let model = GnssPositionModel::new(Vector2::new(0.5, 0.2));
+        let x = Vector3::new(1.0, 2.0, 0.7);
+        let z = model.prediction(&x, None);
+        let jac = model.jacobian(&x, None);
+        let h = 1e-6;
+        let dx = Vector3::new(0.0, 0.0, h);
+        let numerical =
+            (model.prediction(&(x + dx), None) - model.prediction(&(x - dx), None)) / (2.0 * h);
+        assert_relative_eq!(jac.column(2).into_owned(), numerical, epsilon = 1e-8);
+        assert_relative_eq!((z - x.xy()).norm(), model.lever_arm.norm(), epsilon = 1e-12);
+
+        let fix = GnssFix::with_accuracy(0.0, Geodetic::new(45.0, 9.0, 0.0), 2.0, 5.0);
+        let (position, covariance) = fix.to_local(&LocalFrame::new(fix.position));
+        assert_relative_eq!(position, Vector2::zeros(), epsilon = 1e-9);
+        assert_eq!(covariance, Matrix2::identity() * 4.0);
+    }
+}
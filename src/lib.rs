@@ -1,6 +1,15 @@
 pub mod control;
-pub mod data;
+pub mod datasets;
+#[deprecated(note = "renamed to `datasets`")]
+pub use datasets as data;
+pub mod geo;
 pub mod localization;
 pub mod mapping;
 pub mod models;
+pub mod perception;
+pub mod pipeline;
+pub mod planning;
+#[cfg(feature = "ros2")]
+pub mod ros2;
+pub mod sim;
 pub mod utils;
@@ -0,0 +1,330 @@
+use nalgebra::{Matrix3, Quaternion, Rotation3, UnitQuaternion, Vector3, Vector4};
+
+/// Sample of an IMU in the body frame, the gyroscope [rad/s], the accelerometer (any unit, only
+/// its direction is used) and the optional magnetometer (any unit). `dt` [s] is the time since
+/// the previous sample
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    pub gyro: Vector3<f64>,
+    pub accel: Vector3<f64>,
+    pub mag: Option<Vector3<f64>>,
+    pub dt: f64,
+}
+
+/// Attitude and heading reference system. The orientation rotates the body frame into the world
+/// frame which is z up, with x along the horizontal magnetic field when there is a magnetometer
+/// (north west up). Without magnetometer the heading is only integrated from the gyroscope.
+/// The first sample initializes the orientation from the accelerometer and magnetometer
+pub trait AttitudeFilter {
+    fn update(&mut self, sample: &ImuSample);
+    fn orientation(&self) -> UnitQuaternion<f64>;
+    /// Estimated bias of the gyroscope, removed from its samples
+    fn gyro_bias(&self) -> Vector3<f64>;
+}
+
+/// Orientation measured by the accelerometer and `horizontal`, a vector of the body frame along
+/// the world x axis once projected on the horizontal plane
+fn measured_orientation(
+    accel: &Vector3<f64>,
+    horizontal: &Vector3<f64>,
+) -> Option<UnitQuaternion<f64>> {
+    let up = accel.try_normalize(f64::EPSILON)?;
+    let west = up.cross(horizontal).try_normalize(f64::EPSILON)?;
+    let north = west.cross(&up);
+    // the columns are the world axes in the body frame
+    let world_to_body = Matrix3::from_columns(&[north, west, up]);
+    Some(UnitQuaternion::from_rotation_matrix(
+        &Rotation3::from_matrix_unchecked(world_to_body.transpose()),
+    ))
+}
+
+fn initial_orientation(sample: &ImuSample) -> UnitQuaternion<f64> {
+    let horizontal = sample.mag.unwrap_or_else(Vector3::x);
+    measured_orientation(&sample.accel, &horizontal)
+        .or_else(|| measured_orientation(&sample.accel, &Vector3::y()))
+        .unwrap_or_else(UnitQuaternion::identity)
+}
+
+/// q + q ⊗ (0, w) dt / 2 normalized
+fn integrate(q: &UnitQuaternion<f64>, w: &Vector3<f64>, dt: f64) -> UnitQuaternion<f64> {
+    let dq = q.quaternion() * Quaternion::from_imag(*w) * 0.5;
+    UnitQuaternion::from_quaternion(q.quaternion() + dq * dt)
+}
+
+/// Integrates the gyroscope and slerps towards the orientation measured by the accelerometer
+/// and magnetometer by `gain` per second. The correction is integrated into the gyroscope bias
+/// with `bias_gain`
+#[derive(Debug, Clone)]
+pub struct ComplementaryFilter {
+    pub gain: f64,
+    pub bias_gain: f64,
+    orientation: Option<UnitQuaternion<f64>>,
+    bias: Vector3<f64>,
+}
+
+impl ComplementaryFilter {
+    pub fn new(gain: f64, bias_gain: f64) -> ComplementaryFilter {
+        ComplementaryFilter {
+            gain,
+            bias_gain,
+            orientation: None,
+            bias: Vector3::zeros(),
+        }
+    }
+}
+
+impl AttitudeFilter for ComplementaryFilter {
+    fn update(&mut self, sample: &ImuSample) {
+        let Some(q) = self.orientation else {
+            self.orientation = Some(initial_orientation(sample));
+            return;
+        };
+        let predicted = integrate(&q, &(sample.gyro - self.bias), sample.dt);
+        let horizontal = sample
+            .mag
+            .unwrap_or_else(|| predicted.inverse() * Vector3::x());
+        let Some(measured) = measured_orientation(&sample.accel, &horizontal) else {
+            self.orientation = Some(predicted);
+            return;
+        };
+        // rotation from the prediction to the measurement, in the body frame
+        let error = (predicted.inverse() * measured).scaled_axis();
+        let alpha = (self.gain * sample.dt).min(1.0);
+        self.orientation = Some(predicted * UnitQuaternion::from_scaled_axis(error * alpha));
+        self.bias -= error * self.bias_gain * sample.dt;
+    }
+
+    fn orientation(&self) -> UnitQuaternion<f64> {
+        self.orientation.unwrap_or_else(UnitQuaternion::identity)
+    }
+
+    fn gyro_bias(&self) -> Vector3<f64> {
+        self.bias
+    }
+}
+
+/// Gradient descent of the error between the measured and predicted directions of the gravity
+/// and magnetic field, its step `beta` [rad/s] is about the gyroscope noise. The bias is
+/// integrated from the gradient with `zeta`
+///
+/// Source : Madgwick, An efficient orientation filter for inertial and inertial/magnetic
+/// sensor arrays, 2010
+#[derive(Debug, Clone)]
+pub struct Madgwick {
+    pub beta: f64,
+    pub zeta: f64,
+    orientation: Option<UnitQuaternion<f64>>,
+    bias: Vector3<f64>,
+}
+
+impl Madgwick {
+    pub fn new(beta: f64, zeta: f64) -> Madgwick {
+        Madgwick {
+            beta,
+            zeta,
+            orientation: None,
+            bias: Vector3::zeros(),
+        }
+    }
+
+    /// Normalized gradient of the objective function at `q`, (w, x, y, z)
+    fn gradient(
+        q: &UnitQuaternion<f64>,
+        accel: &Vector3<f64>,
+        mag: Option<&Vector3<f64>>,
+    ) -> Option<Vector4<f64>> {
+        let a = accel.try_normalize(f64::EPSILON)?;
+        let (q0, q1, q2, q3) = (q.w, q.i, q.j, q.k);
+        let f = Vector3::new(
+            2.0 * (q1 * q3 - q0 * q2) - a.x,
+            2.0 * (q0 * q1 + q2 * q3) - a.y,
+            2.0 * (0.5 - q1 * q1 - q2 * q2) - a.z,
+        );
+        #[rustfmt::skip]
+        let j = nalgebra::Matrix3x4::new(
+            -2.0 * q2, 2.0 * q3, -2.0 * q0, 2.0 * q1,
+            2.0 * q1, 2.0 * q0, 2.0 * q3, 2.0 * q2,
+            0.0, -4.0 * q1, -4.0 * q2, 0.0,
+        );
+        let mut gradient = j.transpose() * f;
+
+        if let Some(m) = mag.and_then(|m| m.try_normalize(f64::EPSILON)) {
+            // reference field in the world frame, in the plane x z
+            let h = q * m;
+            let (bx, bz) = (h.x.hypot(h.y), h.z);
+            let f = Vector3::new(
+                2.0 * bx * (0.5 - q2 * q2 - q3 * q3) + 2.0 * bz * (q1 * q3 - q0 * q2) - m.x,
+                2.0 * bx * (q1 * q2 - q0 * q3) + 2.0 * bz * (q0 * q1 + q2 * q3) - m.y,
+                2.0 * bx * (q0 * q2 + q1 * q3) + 2.0 * bz * (0.5 - q1 * q1 - q2 * q2) - m.z,
+            );
+            #[rustfmt::skip]
+            let j = nalgebra::Matrix3x4::new(
+                -2.0 * bz * q2, 2.0 * bz * q3, -4.0 * bx * q2 - 2.0 * bz * q0, -4.0 * bx * q3 + 2.0 * bz * q1,
+                -2.0 * bx * q3 + 2.0 * bz * q1, 2.0 * bx * q2 + 2.0 * bz * q0, 2.0 * bx * q1 + 2.0 * bz * q3, -2.0 * bx * q0 + 2.0 * bz * q2,
+                2.0 * bx * q2, 2.0 * bx * q3 - 4.0 * bz * q1, 2.0 * bx * q0 - 4.0 * bz * q2, 2.0 * bx * q1,
+            );
+            gradient += j.transpose() * f;
+        }
+        Some(gradient.try_normalize(f64::EPSILON).unwrap_or_default())
+    }
+}
+
+impl AttitudeFilter for Madgwick {
+    fn update(&mut self, sample: &ImuSample) {
+        let Some(q) = self.orientation else {
+            self.orientation = Some(initial_orientation(sample));
+            return;
+        };
+        let gradient = Madgwick::gradient(&q, &sample.accel, sample.mag.as_ref());
+        if let Some(g) = gradient {
+            // angular rate error of the gradient step, in the body frame
+            let step = Quaternion::new(g[0], g[1], g[2], g[3]);
+            let error = (q.quaternion().conjugate() * step).imag() * 2.0;
+            self.bias += error * self.zeta * sample.dt;
+        }
+        let w = sample.gyro - self.bias;
+        let mut dq = q.quaternion() * Quaternion::from_imag(w) * 0.5;
+        if let Some(g) = gradient {
+            dq -= Quaternion::new(g[0], g[1], g[2], g[3]) * self.beta;
+        }
+        self.orientation = Some(UnitQuaternion::from_quaternion(
+            q.quaternion() + dq * sample.dt,
+        ));
+    }
+
+    fn orientation(&self) -> UnitQuaternion<f64> {
+        self.orientation.unwrap_or_else(UnitQuaternion::identity)
+    }
+
+    fn gyro_bias(&self) -> Vector3<f64> {
+        self.bias
+    }
+}
+
+/// Nonlinear complementary filter on SO(3), the cross products of the measured and predicted
+/// directions of the gravity and magnetic field drive a PI correction of the angular rate, the
+/// integral term is the gyroscope bias
+///
+/// Source : Mahony, Hamel & Pflimlin, Nonlinear complementary filters on the special
+/// orthogonal group, 2008
+#[derive(Debug, Clone)]
+pub struct Mahony {
+    pub kp: f64,
+    pub ki: f64,
+    orientation: Option<UnitQuaternion<f64>>,
+    bias: Vector3<f64>,
+}
+
+impl Mahony {
+    pub fn new(kp: f64, ki: f64) -> Mahony {
+        Mahony {
+            kp,
+            ki,
+            orientation: None,
+            bias: Vector3::zeros(),
+        }
+    }
+}
+
+impl AttitudeFilter for Mahony {
+    fn update(&mut self, sample: &ImuSample) {
+        let Some(q) = self.orientation else {
+            self.orientation = Some(initial_orientation(sample));
+            return;
+        };
+        let mut error = Vector3::zeros();
+        if let Some(a) = sample.accel.try_normalize(f64::EPSILON) {
+            let up = q.inverse() * Vector3::z();
+            error += a.cross(&up);
+        }
+        if let Some(m) = sample.mag.and_then(|m| m.try_normalize(f64::EPSILON)) {
+            let h = q * m;
+            let b = Vector3::new(h.x.hypot(h.y), 0.0, h.z);
+            error += m.cross(&(q.inverse() * b));
+        }
+        self.bias -= error * self.ki * sample.dt;
+        let w = sample.gyro - self.bias + error * self.kp;
+        self.orientation = Some(integrate(&q, &w, sample.dt));
+    }
+
+    fn orientation(&self) -> UnitQuaternion<f64> {
+        self.orientation.unwrap_or_else(UnitQuaternion::identity)
+    }
+
+    fn gyro_bias(&self) -> Vector3<f64> {
+        self.bias
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Samples of an IMU with the orientation `truth` turning at `rate` in the world frame
+    fn samples(
+        truth: UnitQuaternion<f64>,
+        rate: Vector3<f64>,
+        bias: Vector3<f64>,
+        with_mag: bool,
+        n: usize,
+    ) -> (Vec<ImuSample>, UnitQuaternion<f64>) {
+        let dt = 0.01;
+        let field = Vector3::new(0.5, 0.0, -0.8);
+        let mut q = truth;
+        let mut samples = Vec::with_capacity(n);
+        for _ in 0..n {
+            samples.push(ImuSample {
+                gyro: q.inverse() * rate + bias,
+                accel: q.inverse() * Vector3::new(0.0, 0.0, 9.81),
+                mag: with_mag.then(|| q.inverse() * field),
+                dt,
+            });
+            q = UnitQuaternion::from_scaled_axis(rate * dt) * q;
+        }
+        (samples, q)
+    }
+
+    fn filters() -> Vec<Box<dyn AttitudeFilter>> {
+        vec![
+            Box::new(ComplementaryFilter::new(1.0, 0.3)),
+            Box::new(Madgwick::new(0.1, 0.02)),
+            Box::new(Mahony::new(1.0, 0.1)),
+        ]
+    }
+
+    #[test]
+    fn converge_to_the_orientation_and_the_bias() {
+        let truth = UnitQuaternion::from_euler_angles(0.3, -0.2, 1.0);
+        let bias = Vector3::new(0.01, -0.02, 0.015);
+        let (samples, _) = samples(truth, Vector3::zeros(), bias, true, 12000);
+        for mut filter in filters() {
+            assert_eq!(UnitQuaternion::identity(), filter.orientation());
+            for sample in &samples {
+                filter.update(sample);
+            }
+            assert!(filter.orientation().angle_to(&truth) < 0.01);
+            approx::assert_abs_diff_eq!(bias, filter.gyro_bias(), epsilon = 2e-3);
+        }
+    }
+
+    #[test]
+    fn track_the_tilt_while_turning() {
+        let truth = UnitQuaternion::from_euler_angles(-0.2, 0.1, 0.0);
+        let (samples, end) = samples(
+            truth,
+            Vector3::new(0.0, 0.0, 0.5),
+            Vector3::zeros(),
+            false,
+            2000,
+        );
+        for mut filter in filters() {
+            for sample in &samples {
+                filter.update(sample);
+            }
+            let up = filter.orientation().inverse() * Vector3::z();
+            let true_up = end.inverse() * Vector3::z();
+            assert!(up.angle(&true_up) < 0.01);
+            assert!(filter.orientation().angle_to(&end) < 0.05);
+        }
+    }
+}
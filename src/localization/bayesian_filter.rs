@@ -2,6 +2,10 @@ use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OVector, RealField};
 
 use crate::utils::state::GaussianState;
 
+/// Common interface of the filters (EKF, UKF, PF, ...) so they can be swapped without
+/// changing the estimation loop, either through generics or `Box<dyn BayesianFilter<..>>`
+///
+/// S : State Size, Z: Observation Size, U: Input Size
 pub trait BayesianFilter<T: RealField, S: Dim, Z: Dim, U: Dim>
 where
     DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, Z> + Allocator<T, S, S>,
@@ -11,6 +15,10 @@ where
     fn gaussian_estimate(&self) -> GaussianState<T, S>;
 }
 
+/// Same as `BayesianFilter` for the filters using a map of known landmarks, the prediction
+/// and the correction steps are optional so asynchronous odometry and measurements can be fed
+///
+/// S : State Size, Z: Observation Size, U: Input Size
 pub trait BayesianFilterKnownCorrespondences<T: RealField, S: Dim, Z: Dim, U: Dim>
 where
     DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, Z> + Allocator<T, S, S>,
@@ -24,3 +32,38 @@ where
 
     fn gaussian_estimate(&self) -> GaussianState<T, S>;
 }
+
+// Boxed filters can be passed to code generic over the traits
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, F> BayesianFilter<T, S, Z, U> for Box<F>
+where
+    F: BayesianFilter<T, S, Z, U> + ?Sized,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, Z> + Allocator<T, S, S>,
+{
+    fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
+        (**self).update_estimate(u, z, dt)
+    }
+
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        (**self).gaussian_estimate()
+    }
+}
+
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, F> BayesianFilterKnownCorrespondences<T, S, Z, U>
+    for Box<F>
+where
+    F: BayesianFilterKnownCorrespondences<T, S, Z, U> + ?Sized,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, Z> + Allocator<T, S, S>,
+{
+    fn update_estimate(
+        &mut self,
+        control: Option<OVector<T, U>>,
+        measurements: Option<Vec<(u32, OVector<T, Z>)>>,
+        dt: T,
+    ) {
+        (**self).update_estimate(control, measurements, dt)
+    }
+
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        (**self).gaussian_estimate()
+    }
+}
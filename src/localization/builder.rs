@@ -0,0 +1,576 @@
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use rand::distributions::Distribution;
+use rand_distr::{Standard, StandardNormal};
+
+use crate::localization::{
+    CovarianceUpdate, ExtendedKalmanFilter, Parallelism, ParticleFilter, ParticleFilterVariant,
+    ResamplingScheme,
+};
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::mvn;
+use crate::utils::state::GaussianState;
+
+const DEFAULT_NUM_PARTICULES: usize = 1000;
+
+/// The models of the built particle filter are `Sync` so it can be set to `Parallelism::Rayon`
+type SyncMeasurementModel<T, S, Z> = Box<dyn MeasurementModel<T, S, Z> + Send + Sync>;
+type SyncMotionModel<T, S, Z, U> = Box<dyn MotionModel<T, S, Z, U> + Send + Sync>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuilderError {
+    MissingField(&'static str),
+    DimensionMismatch {
+        name: &'static str,
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    CovarianceNotPositiveSemiDefinite(&'static str),
+    NoParticules,
+}
+
+impl std::error::Error for BuilderError {}
+
+impl std::fmt::Display for BuilderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BuilderError::MissingField(name) => write!(f, "missing field `{name}`"),
+            BuilderError::DimensionMismatch {
+                name,
+                expected,
+                found,
+            } => write!(f, "`{name}` should be {expected:?} but is {found:?}"),
+            BuilderError::CovarianceNotPositiveSemiDefinite(name) => {
+                write!(f, "`{name}` is not positive semi-definite")
+            }
+            BuilderError::NoParticules => write!(f, "the number of particules must be > 0"),
+        }
+    }
+}
+
+/// Same rule as `MultiVariateNormal::new`, symmetric and positive semi-definite
+fn check_covariance<T: RealField, D: Dim>(
+    name: &'static str,
+    cov: &OMatrix<T, D, D>,
+    dim: usize,
+) -> Result<(), BuilderError>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    if cov.shape() != (dim, dim) {
+        return Err(BuilderError::DimensionMismatch {
+            name,
+            expected: (dim, dim),
+            found: cov.shape(),
+        });
+    }
+    if !mvn::is_semi_definite(cov) {
+        return Err(BuilderError::CovarianceNotPositiveSemiDefinite(name));
+    }
+    Ok(())
+}
+
+/// Named setters for `ParticleFilter::new`, the noises are validated in `build` without
+/// evaluating the models. The measurement noise should be square, of size Z when it is known
+/// at compile time. The particules are spread around the initial state with the motion noise,
+/// the covariance of the initial state is not used
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct ParticleFilterBuilder<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    motion_noise: Option<OMatrix<T, S, S>>,
+    measurement_noise: Option<OMatrix<T, Z, Z>>,
+    measurement_model: Option<SyncMeasurementModel<T, S, Z>>,
+    motion_model: Option<SyncMotionModel<T, S, Z, U>>,
+    initial_state: Option<GaussianState<T, S>>,
+    num_particules: usize,
+    resampling_scheme: ResamplingScheme,
+    variant: ParticleFilterVariant,
+    parallelism: Parallelism,
+    seed: Option<u64>,
+}
+
+impl<T: RealField, S: Dim, Z: Dim, U: Dim> Default for ParticleFilterBuilder<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    fn default() -> Self {
+        ParticleFilterBuilder {
+            motion_noise: None,
+            measurement_noise: None,
+            measurement_model: None,
+            motion_model: None,
+            initial_state: None,
+            num_particules: DEFAULT_NUM_PARTICULES,
+            resampling_scheme: ResamplingScheme::default(),
+            variant: ParticleFilterVariant::default(),
+            parallelism: Parallelism::default(),
+            seed: None,
+        }
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> ParticleFilterBuilder<T, S, Z, U>
+where
+    StandardNormal: Distribution<T>,
+    Standard: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process noise added to each particule after the prediction (R)
+    pub fn motion_noise(mut self, r: OMatrix<T, S, S>) -> Self {
+        self.motion_noise = Some(r);
+        self
+    }
+
+    /// Measurement noise used to weight the particules (Q)
+    pub fn measurement_noise(mut self, q: OMatrix<T, Z, Z>) -> Self {
+        self.measurement_noise = Some(q);
+        self
+    }
+
+    pub fn measurement_model(mut self, measurement_model: SyncMeasurementModel<T, S, Z>) -> Self {
+        self.measurement_model = Some(measurement_model);
+        self
+    }
+
+    pub fn motion_model(mut self, motion_model: SyncMotionModel<T, S, Z, U>) -> Self {
+        self.motion_model = Some(motion_model);
+        self
+    }
+
+    pub fn initial_state(mut self, initial_state: GaussianState<T, S>) -> Self {
+        self.initial_state = Some(initial_state);
+        self
+    }
+
+    /// Defaults to 1000
+    pub fn num_particules(mut self, num_particules: usize) -> Self {
+        self.num_particules = num_particules;
+        self
+    }
+
+    /// Defaults to `ResamplingScheme::Systematic`
+    pub fn resampling_scheme(mut self, resampling_scheme: ResamplingScheme) -> Self {
+        self.resampling_scheme = resampling_scheme;
+        self
+    }
+
+    /// Defaults to `ParticleFilterVariant::Bootstrap`
+    pub fn variant(mut self, variant: ParticleFilterVariant) -> Self {
+        self.variant = variant;
+        self
+    }
+
+    /// Defaults to `Parallelism::Sequential`
+    pub fn parallelism(mut self, parallelism: Parallelism) -> Self {
+        self.parallelism = parallelism;
+        self
+    }
+
+    /// Defaults to a random seed
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn build(
+        self,
+    ) -> Result<
+        ParticleFilter<T, S, Z, U, SyncMeasurementModel<T, S, Z>, SyncMotionModel<T, S, Z, U>>,
+        BuilderError,
+    >
+    where
+        OVector<T, S>: Send + Sync,
+        OVector<T, Z>: Send,
+    {
+        let initial_state = self
+            .initial_state
+            .ok_or(BuilderError::MissingField("initial_state"))?;
+        let r = self
+            .motion_noise
+            .ok_or(BuilderError::MissingField("motion_noise"))?;
+        let q = self
+            .measurement_noise
+            .ok_or(BuilderError::MissingField("measurement_noise"))?;
+        let measurement_model = self
+            .measurement_model
+            .ok_or(BuilderError::MissingField("measurement_model"))?;
+        let motion_model = self
+            .motion_model
+            .ok_or(BuilderError::MissingField("motion_model"))?;
+
+        let dim = initial_state.x.nrows();
+        check_covariance("motion_noise", &r, dim)?;
+        check_covariance(
+            "measurement_noise",
+            &q,
+            Z::try_to_usize().unwrap_or(q.nrows()),
+        )?;
+        if self.num_particules == 0 {
+            return Err(BuilderError::NoParticules);
+        }
+
+        let mut pf = ParticleFilter::with_models(
+            r,
+            q,
+            measurement_model,
+            motion_model,
+            initial_state.clone(),
+            self.num_particules,
+            self.resampling_scheme,
+        );
+        pf.set_variant(self.variant);
+        pf.set_parallelism(self.parallelism);
+        if let Some(seed) = self.seed {
+            pf.set_seed(seed, &initial_state);
+        }
+        Ok(pf)
+    }
+}
+
+/// Named setters for `ExtendedKalmanFilter::new`, the covariances are validated in `build`
+/// without evaluating the models. The measurement noise should be square, of size Z when it
+/// is known at compile time
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct EkfBuilder<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    motion_noise: Option<OMatrix<T, S, S>>,
+    measurement_noise: Option<OMatrix<T, Z, Z>>,
+    measurement_model: Option<Box<dyn MeasurementModel<T, S, Z> + Send>>,
+    motion_model: Option<Box<dyn MotionModel<T, S, Z, U> + Send>>,
+    initial_state: Option<GaussianState<T, S>>,
+    covariance_update: CovarianceUpdate,
+}
+
+impl<T: RealField, S: Dim, Z: Dim, U: Dim> Default for EkfBuilder<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    fn default() -> Self {
+        EkfBuilder {
+            motion_noise: None,
+            measurement_noise: None,
+            measurement_model: None,
+            motion_model: None,
+            initial_state: None,
+            covariance_update: CovarianceUpdate::default(),
+        }
+    }
+}
+
+impl<T: RealField, S: Dim, Z: Dim, U: Dim> EkfBuilder<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>,
+{
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process noise added to the predicted covariance (R)
+    pub fn motion_noise(mut self, r: OMatrix<T, S, S>) -> Self {
+        self.motion_noise = Some(r);
+        self
+    }
+
+    /// Measurement noise (Q)
+    pub fn measurement_noise(mut self, q: OMatrix<T, Z, Z>) -> Self {
+        self.measurement_noise = Some(q);
+        self
+    }
+
+    pub fn measurement_model(
+        mut self,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+    ) -> Self {
+        self.measurement_model = Some(measurement_model);
+        self
+    }
+
+    pub fn motion_model(mut self, motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>) -> Self {
+        self.motion_model = Some(motion_model);
+        self
+    }
+
+    pub fn initial_state(mut self, initial_state: GaussianState<T, S>) -> Self {
+        self.initial_state = Some(initial_state);
+        self
+    }
+
+    pub fn covariance_update(mut self, covariance_update: CovarianceUpdate) -> Self {
+        self.covariance_update = covariance_update;
+        self
+    }
+
+    pub fn build(self) -> Result<ExtendedKalmanFilter<T, S, Z, U>, BuilderError> {
+        let initial_state = self
+            .initial_state
+            .ok_or(BuilderError::MissingField("initial_state"))?;
+        let r = self
+            .motion_noise
+            .ok_or(BuilderError::MissingField("motion_noise"))?;
+        let q = self
+            .measurement_noise
+            .ok_or(BuilderError::MissingField("measurement_noise"))?;
+        let measurement_model = self
+            .measurement_model
+            .ok_or(BuilderError::MissingField("measurement_model"))?;
+        let motion_model = self
+            .motion_model
+            .ok_or(BuilderError::MissingField("motion_model"))?;
+
+        let dim = initial_state.x.nrows();
+        check_covariance("initial_state.cov", &initial_state.cov, dim)?;
+        check_covariance("motion_noise", &r, dim)?;
+        check_covariance(
+            "measurement_noise",
+            &q,
+            Z::try_to_usize().unwrap_or(q.nrows()),
+        )?;
+
+        let mut ekf =
+            ExtendedKalmanFilter::new(r, q, measurement_model, motion_model, initial_state);
+        ekf.set_covariance_update(self.covariance_update);
+        Ok(ekf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::measurement::SimpleProblemMeasurementModel;
+    use crate::models::motion::SimpleProblemMotionModel;
+    use nalgebra::{Matrix2, Matrix4, Matrix4x2, Vector2, Vector4};
+
+    fn initial_state() -> GaussianState<f64, Const<4>> {
+        GaussianState {
+            x: Vector4::new(0., 0., 0., 0.),
+            cov: Matrix4::identity(),
+        }
+    }
+
+    #[test]
+    fn ekf_builder() {
+        let ekf = EkfBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+            .motion_noise(Matrix4::identity())
+            .measurement_noise(Matrix2::identity())
+            .measurement_model(SimpleProblemMeasurementModel::new())
+            .motion_model(SimpleProblemMotionModel::new())
+            .initial_state(initial_state())
+            .build();
+        assert!(ekf.is_ok());
+
+        let ekf = EkfBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+            .motion_noise(Matrix4::identity())
+            .measurement_model(SimpleProblemMeasurementModel::new())
+            .motion_model(SimpleProblemMotionModel::new())
+            .initial_state(initial_state())
+            .build();
+        assert_eq!(
+            Some(BuilderError::MissingField("measurement_noise")),
+            ekf.err()
+        );
+    }
+
+    #[test]
+    fn particle_filter_builder_rejects_bad_covariance() {
+        let pf = ParticleFilterBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+            .motion_noise(-Matrix4::identity())
+            .measurement_noise(Matrix2::identity())
+            .measurement_model(SimpleProblemMeasurementModel::new())
+            .motion_model(SimpleProblemMotionModel::new())
+            .initial_state(initial_state())
+            .build();
+        assert_eq!(
+            Some(BuilderError::CovarianceNotPositiveSemiDefinite(
+                "motion_noise"
+            )),
+            pf.err()
+        );
+
+        let pf = ParticleFilterBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+            .motion_noise(Matrix4::identity())
+            .measurement_noise(Matrix2::identity())
+            .measurement_model(SimpleProblemMeasurementModel::new())
+            .motion_model(SimpleProblemMotionModel::new())
+            .initial_state(initial_state())
+            .build()
+            .unwrap();
+        assert_eq!(DEFAULT_NUM_PARTICULES, pf.particules.len());
+
+        // the covariance of the initial state is not used, the noises may be semi-definite
+        let pf = ParticleFilterBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+            .motion_noise(Matrix4::from_diagonal(&Vector4::new(0.1, 0.1, 0.0, 0.0)))
+            .measurement_noise(Matrix2::identity())
+            .measurement_model(SimpleProblemMeasurementModel::new())
+            .motion_model(SimpleProblemMotionModel::new())
+            .initial_state(GaussianState {
+                x: Vector4::zeros(),
+                cov: Matrix4::zeros(),
+            })
+            .build();
+        assert!(pf.is_ok());
+
+        let pf = ParticleFilterBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+            .motion_noise(Matrix4::identity())
+            .measurement_noise(Matrix2::new(1.0, 0.5, 0.0, 1.0))
+            .measurement_model(SimpleProblemMeasurementModel::new())
+            .motion_model(SimpleProblemMotionModel::new())
+            .initial_state(initial_state())
+            .build();
+        assert_eq!(
+            Some(BuilderError::CovarianceNotPositiveSemiDefinite(
+                "measurement_noise"
+            )),
+            pf.err()
+        );
+    }
+
+    #[test]
+    fn landmark_measurement_model() {
+        use crate::models::measurement::RangeBearingMeasurementModel;
+        use crate::models::motion::Velocity;
+        use nalgebra::{Matrix3, Vector3};
+
+        // the range bearing model needs a landmark, it is not evaluated by the builders
+        let ekf = EkfBuilder::<f64, Const<3>, Const<2>, Const<2>>::new()
+            .motion_noise(Matrix3::identity() * 0.1)
+            .measurement_noise(Matrix2::identity())
+            .measurement_model(RangeBearingMeasurementModel::new())
+            .motion_model(Velocity::new([0.1; 6]))
+            .initial_state(GaussianState {
+                x: Vector3::zeros(),
+                cov: Matrix3::zeros(),
+            })
+            .build();
+        assert!(ekf.is_ok());
+    }
+
+    #[test]
+    fn measurement_noise_is_square() {
+        use nalgebra::{DMatrix, DVector, Dyn};
+
+        /// The first `n` components of the state
+        struct Head(usize);
+
+        impl MeasurementModel<f64, Const<4>, Dyn> for Head {
+            fn prediction(
+                &self,
+                x: &Vector4<f64>,
+                _landmark: Option<&Vector4<f64>>,
+            ) -> DVector<f64> {
+                DVector::from_fn(self.0, |i, _| x[i])
+            }
+            fn jacobian(
+                &self,
+                _x: &Vector4<f64>,
+                _landmark: Option<&Vector4<f64>>,
+            ) -> OMatrix<f64, Dyn, Const<4>> {
+                OMatrix::identity_generic(Dyn(self.0), Const::<4>)
+            }
+        }
+
+        /// The state does not move
+        struct Still;
+
+        impl MotionModel<f64, Const<4>, Dyn, Const<2>> for Still {
+            fn prediction(&self, x: &Vector4<f64>, _u: &Vector2<f64>, _dt: f64) -> Vector4<f64> {
+                *x
+            }
+            fn jacobian_wrt_state(
+                &self,
+                _x: &Vector4<f64>,
+                _u: &Vector2<f64>,
+                _dt: f64,
+            ) -> Matrix4<f64> {
+                Matrix4::identity()
+            }
+            fn jacobian_wrt_input(
+                &self,
+                _x: &Vector4<f64>,
+                _u: &Vector2<f64>,
+                _dt: f64,
+            ) -> Matrix4x2<f64> {
+                Matrix4x2::zeros()
+            }
+            fn cov_noise_control_space(&self, _u: &Vector2<f64>) -> Matrix2<f64> {
+                Matrix2::zeros()
+            }
+            fn sample(&self, x: &Vector4<f64>, _u: &Vector2<f64>, _dt: f64) -> Vector4<f64> {
+                *x
+            }
+        }
+
+        // the size of a dynamic measurement is not known without evaluating the model
+        let build = |rows: usize| {
+            EkfBuilder::<f64, Const<4>, Dyn, Const<2>>::new()
+                .motion_noise(Matrix4::identity())
+                .measurement_noise(DMatrix::identity(rows, 3))
+                .measurement_model(Box::new(Head(3)))
+                .motion_model(Box::new(Still))
+                .initial_state(initial_state())
+                .build()
+        };
+        assert!(build(3).is_ok());
+        assert_eq!(
+            Some(BuilderError::DimensionMismatch {
+                name: "measurement_noise",
+                expected: (2, 2),
+                found: (2, 3),
+            }),
+            build(2).err()
+        );
+    }
+
+    #[test]
+    fn deterministic_particle_filter() {
+        use crate::localization::BayesianFilter;
+
+        let run = |parallelism: Parallelism, threads: usize| {
+            let mut pf = ParticleFilterBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+                .motion_noise(Matrix4::identity() * 0.1)
+                .measurement_noise(Matrix2::identity())
+                .measurement_model(SimpleProblemMeasurementModel::new())
+                .motion_model(SimpleProblemMotionModel::new())
+                .initial_state(initial_state())
+                .num_particules(300)
+                .parallelism(parallelism)
+                .seed(7)
+                .build()
+                .unwrap();
+            let pool = rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap();
+            pool.install(|| {
+                for i in 0..5 {
+                    let u = Vector2::new(1.0, 0.1);
+                    let z = Vector2::new(i as f64, 0.5);
+                    pf.update_estimate(&u, &z, 0.1);
+                }
+            });
+            pf.particules
+        };
+        let sequential = run(Parallelism::Sequential, 1);
+        assert_eq!(sequential, run(Parallelism::Rayon, 1));
+        assert_eq!(sequential, run(Parallelism::Rayon, 4));
+    }
+}
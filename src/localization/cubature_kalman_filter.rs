@@ -0,0 +1,238 @@
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OMatrix, OVector, RealField, U1};
+
+use crate::localization::bayesian_filter::BayesianFilter;
+use crate::localization::diagnostics::{self, FilterEvent, FilterObserver, Observer};
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::state::GaussianState;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+/// Kalman filter propagating the 2n cubature points x ± sqrt(n) L e_i, P = LL^T, of the third
+/// degree spherical-radial rule through the models, all with the weight 1 / 2n. Same as the
+/// UKF with alpha = 1, beta = 0 and kappa = 0, there are no parameters to tune
+///
+/// Source : Cubature Kalman Filters, Arasaratnam & Haykin, 2009
+///
+/// S : State Size, Z: Observation Size, U: Input Size, H: Measurement Model, M: Motion Model
+pub struct CubatureKalmanFilter<
+    T: RealField,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    H = Box<dyn MeasurementModel<T, S, Z> + Send>,
+    M = Box<dyn MotionModel<T, S, Z, U> + Send>,
+> where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    r: OMatrix<T, S, S>,
+    q: OMatrix<T, Z, Z>,
+    measurement_model: H,
+    motion_model: M,
+    state: GaussianState<T, S>,
+    observer: Observer<T>,
+    _input: PhantomData<U>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> CubatureKalmanFilter<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    /// Same arguments as `ExtendedKalmanFilter::new`
+    pub fn new(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+        motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+        initial_state: GaussianState<T, S>,
+    ) -> CubatureKalmanFilter<T, S, Z, U> {
+        CubatureKalmanFilter::with_models(r, q, measurement_model, motion_model, initial_state)
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> CubatureKalmanFilter<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    pub fn with_models(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: H,
+        motion_model: M,
+        initial_state: GaussianState<T, S>,
+    ) -> CubatureKalmanFilter<T, S, Z, U, H, M> {
+        CubatureKalmanFilter {
+            r,
+            q,
+            measurement_model,
+            motion_model,
+            state: initial_state,
+            observer: None,
+            _input: PhantomData,
+        }
+    }
+
+    /// Receives the innovations, the gain norms and the update times
+    pub fn set_observer(&mut self, observer: impl FilterObserver<T> + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// The 2n points x ± sqrt(n) L e_i of a Gaussian
+    pub fn cubature_points(state: &GaussianState<T, S>) -> Vec<OVector<T, S>> {
+        let dim = state.x.nrows();
+        let sqrt_cov = state
+            .cov
+            .clone()
+            .cholesky()
+            .expect("the covariance should be positive definite")
+            .l()
+            * T::from_usize(dim).unwrap().sqrt();
+        (0..dim)
+            .flat_map(|i| {
+                let column = sqrt_cov.column(i);
+                [&state.x + &column, &state.x - &column]
+            })
+            .collect()
+    }
+}
+
+/// Mean and covariance of points with equal weights
+fn moments<T: RealField + Copy, D: Dim>(
+    points: &[OVector<T, D>],
+) -> (OVector<T, D>, OMatrix<T, D, D>)
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D> + Allocator<T, U1, D>,
+{
+    let dim = points[0].shape_generic().0;
+    let weight = T::one() / T::from_usize(points.len()).unwrap();
+    let mean = points
+        .iter()
+        .fold(OMatrix::zeros_generic(dim, U1), |a, b| a + b)
+        * weight;
+    let cov = points
+        .iter()
+        .map(|p| p - &mean)
+        .fold(OMatrix::zeros_generic(dim, dim), |a, dp| {
+            a + &dp * dp.transpose()
+        })
+        * weight;
+    (mean, cov)
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> BayesianFilter<T, S, Z, U>
+    for CubatureKalmanFilter<T, S, Z, U, H, M>
+where
+    H: MeasurementModel<T, S, Z>,
+    M: MotionModel<T, S, Z, U>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, S, Z>
+        + Allocator<T, U1, S>
+        + Allocator<T, U1, Z>,
+{
+    fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
+        let start = Instant::now();
+        // predict
+        let points: Vec<OVector<T, S>> = Self::cubature_points(&self.state)
+            .iter()
+            .map(|x| self.motion_model.prediction(x, u, dt))
+            .collect();
+        let (x_pred, cov_pred) = moments(&points);
+        let prediction = GaussianState {
+            x: x_pred,
+            cov: cov_pred + &self.r,
+        };
+
+        // update
+        let points = Self::cubature_points(&prediction);
+        let z_points: Vec<OVector<T, Z>> = points
+            .iter()
+            .map(|x| self.measurement_model.prediction(x, None))
+            .collect();
+        let (z_pred, cov_z) = moments(&z_points);
+        let cov_z = cov_z + &self.q;
+        let weight = T::one() / T::from_usize(points.len()).unwrap();
+        let cross_cov = points
+            .iter()
+            .zip(z_points.iter())
+            .map(|(x, z_point)| (x - &prediction.x) * (z_point - &z_pred).transpose())
+            .fold(
+                OMatrix::zeros_generic(prediction.x.shape_generic().0, z_pred.shape_generic().0),
+                |a, b| a + b,
+            )
+            * weight;
+
+        let innovation = z - z_pred;
+        let kalman_gain = cross_cov * cov_z.clone().try_inverse().unwrap();
+        self.state = GaussianState {
+            x: &prediction.x + &kalman_gain * &innovation,
+            cov: prediction.cov - &kalman_gain * cov_z * kalman_gain.transpose(),
+        };
+        diagnostics::notify(&mut self.observer, || FilterEvent::Innovation {
+            landmark: None,
+            innovation: innovation.iter().copied().collect(),
+            gain_norm: Some(kalman_gain.norm()),
+        });
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::UpdateTime(start.elapsed())
+        });
+    }
+
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        self.state.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::UnscentedKalmanFilter;
+    use crate::models::measurement::SimpleProblemMeasurementModel;
+    use crate::models::motion::SimpleProblemMotionModel;
+    use nalgebra::{Matrix2, Matrix4, Vector2, Vector4};
+
+    #[test]
+    fn same_estimate_as_the_ukf_without_center_point() {
+        let r = Matrix4::from_diagonal(&Vector4::new(0.1, 0.1, 0.01, 1.0));
+        let q = Matrix2::identity() * 0.5;
+        let initial_state = GaussianState {
+            x: Vector4::zeros(),
+            cov: Matrix4::identity(),
+        };
+        let mut ckf = CubatureKalmanFilter::new(
+            r,
+            q,
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state.clone(),
+        );
+        // lambda = alpha^2 (n + kappa) - n = 0, the center point has no weight
+        let mut ukf = UnscentedKalmanFilter::new(
+            r,
+            q,
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            1.0,
+            0.0,
+            0.0,
+            initial_state,
+        );
+
+        let u = Vector2::new(1.0, 0.1);
+        for i in 0..50 {
+            let t = i as f64 * 0.1;
+            let z = Vector2::new(t.cos() * 3.0, t.sin() * 3.0);
+            ckf.update_estimate(&u, &z, 0.1);
+            ukf.update_estimate(&u, &z, 0.1);
+            let (expected, estimate) = (ukf.gaussian_estimate(), ckf.gaussian_estimate());
+            approx::assert_abs_diff_eq!(expected.x, estimate.x, epsilon = 1e-9);
+            approx::assert_abs_diff_eq!(expected.cov, estimate.cov, epsilon = 1e-9);
+        }
+    }
+}
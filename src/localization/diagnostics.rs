@@ -0,0 +1,166 @@
+use nalgebra::RealField;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+/// Diagnostics reported by a filter during `update_estimate`
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterEvent<T> {
+    /// Measurement minus its prediction, with the landmark for the filters with a map and the
+    /// Frobenius norm of the gain for the Kalman filters
+    Innovation {
+        landmark: Option<u32>,
+        innovation: Vec<T>,
+        gain_norm: Option<T>,
+    },
+    /// Measurement of a landmark missing from the map, it is ignored
+    Rejected { landmark: u32 },
+    /// Effective sample size 1 / sum(w^2) of the particule weights before the resampling
+    EffectiveSampleSize(T),
+    /// The particules were resampled
+    Resampled,
+    /// Duration of the whole update, reported last
+    UpdateTime(Duration),
+}
+
+/// Receives the diagnostics of a filter, e.g. to find why it diverges without adding prints
+/// to the filter. Closures and channel senders are observers, the events are only built while
+/// a filter has one
+pub trait FilterObserver<T>: Send {
+    fn notify(&mut self, event: FilterEvent<T>);
+}
+
+impl<T, F: FnMut(FilterEvent<T>) + Send> FilterObserver<T> for F {
+    fn notify(&mut self, event: FilterEvent<T>) {
+        self(event)
+    }
+}
+
+/// The events are dropped once the receiver is gone
+impl<T: Send> FilterObserver<T> for Sender<FilterEvent<T>> {
+    fn notify(&mut self, event: FilterEvent<T>) {
+        let _ = self.send(event);
+    }
+}
+
+pub(crate) type Observer<T> = Option<Box<dyn FilterObserver<T>>>;
+
+/// Builds the event and sends it only if there is an observer
+pub(crate) fn notify<T>(observer: &mut Observer<T>, event: impl FnOnce() -> FilterEvent<T>) {
+    if let Some(observer) = observer {
+        observer.notify(event());
+    }
+}
+
+/// 1 / sum(w^2) of normalized weights, from 1 when a single particule has all the weight to
+/// the number of particules when the weights are uniform
+pub fn effective_sample_size<T: RealField + Copy>(weights: &[T]) -> T {
+    T::one() / weights.iter().fold(T::zero(), |acc, w| acc + *w * *w)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::{
+        BayesianFilter, BayesianFilterKnownCorrespondences, ExtendedKalmanFilter,
+        ExtendedKalmanFilterKnownCorrespondences, ParticleFilter, ResamplingScheme,
+    };
+    use crate::models::measurement::{RangeBearingMeasurementModel, SimpleProblemMeasurementModel};
+    use crate::models::motion::{SimpleProblemMotionModel, Velocity};
+    use crate::utils::state::GaussianState;
+    use approx::assert_relative_eq;
+    use nalgebra::{Matrix2, Matrix3, Matrix4, Vector2, Vector3, Vector4};
+    use rustc_hash::FxHashMap;
+    use std::sync::mpsc;
+
+    #[test]
+    fn effective_sample_size_bounds() {
+        assert_relative_eq!(effective_sample_size(&[0.25; 4]), 4.0);
+        assert_relative_eq!(effective_sample_size(&[1.0, 0.0, 0.0]), 1.0);
+    }
+
+    #[test]
+    fn kalman_filter_events() {
+        let initial_state = GaussianState {
+            x: Vector4::zeros(),
+            cov: Matrix4::identity(),
+        };
+        let mut ekf = ExtendedKalmanFilter::new(
+            Matrix4::identity() * 0.1,
+            Matrix2::identity(),
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state,
+        );
+        let (sender, receiver) = mpsc::channel();
+        ekf.set_observer(sender);
+        ekf.update_estimate(&Vector2::zeros(), &Vector2::new(1.0, -0.5), 0.1);
+        let events: Vec<FilterEvent<f64>> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 2);
+        let FilterEvent::Innovation {
+            landmark: None,
+            innovation,
+            gain_norm: Some(gain_norm),
+        } = &events[0]
+        else {
+            panic!("expected an innovation, got {:?}", events[0]);
+        };
+        assert_eq!(innovation, &vec![1.0, -0.5]);
+        assert!(*gain_norm > 0.0);
+        assert!(matches!(events[1], FilterEvent::UpdateTime(_)));
+
+        let mut landmarks = FxHashMap::default();
+        landmarks.insert(0, Vector3::new(5.0, 0.0, 0.0));
+        let mut ekf = ExtendedKalmanFilterKnownCorrespondences::new(
+            Matrix2::identity() * 0.01,
+            landmarks,
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.1; 6]),
+            GaussianState {
+                x: Vector3::zeros(),
+                cov: Matrix3::identity() * 0.01,
+            },
+        );
+        let mut rejected = Vec::new();
+        let (sender, receiver) = mpsc::channel();
+        ekf.set_observer(sender);
+        let measurements = vec![(0, Vector2::new(5.0, 0.0)), (7, Vector2::new(1.0, 0.0))];
+        ekf.update_estimate(None, Some(measurements), 0.1);
+        for event in receiver.try_iter() {
+            match event {
+                FilterEvent::Innovation { landmark, .. } => assert_eq!(landmark, Some(0)),
+                FilterEvent::Rejected { landmark } => rejected.push(landmark),
+                _ => (),
+            }
+        }
+        assert_eq!(rejected, vec![7]);
+    }
+
+    #[test]
+    fn particle_filter_events() {
+        let initial_state = GaussianState {
+            x: Vector4::zeros(),
+            cov: Matrix4::identity(),
+        };
+        let mut pf = ParticleFilter::new(
+            Matrix4::identity() * 0.1,
+            Matrix2::identity(),
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state.clone(),
+            100,
+            ResamplingScheme::Systematic,
+        );
+        pf.set_seed(0, &initial_state);
+        let (sender, receiver) = mpsc::channel();
+        pf.set_observer(move |event: FilterEvent<f64>| sender.send(event).unwrap());
+        pf.update_estimate(&Vector2::zeros(), &Vector2::new(1.0, -0.5), 0.1);
+        let events: Vec<FilterEvent<f64>> = receiver.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        let FilterEvent::EffectiveSampleSize(ess) = events[0] else {
+            panic!("expected the effective sample size, got {:?}", events[0]);
+        };
+        assert!(ess > 1.0 && ess <= 100.0);
+        assert_eq!(events[1], FilterEvent::Resampled);
+        assert!(matches!(events[2], FilterEvent::UpdateTime(_)));
+    }
+}
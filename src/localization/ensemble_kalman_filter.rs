@@ -0,0 +1,348 @@
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use rand::distributions::Distribution;
+use rand::RngCore;
+use rand_distr::StandardNormal;
+
+use crate::localization::bayesian_filter::BayesianFilter;
+use crate::localization::diagnostics::{self, FilterEvent, FilterObserver, Observer};
+use crate::localization::particle_filter::{gaussian_estimate, initial_particules};
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::mvn::MultiVariateNormal;
+use crate::utils::rng::Philox;
+use crate::utils::state::GaussianState;
+use std::marker::PhantomData;
+use std::time::Instant;
+
+/// Stochastic ensemble Kalman filter, the members are propagated with the motion noise like
+/// particules and corrected with the gain estimated from the ensemble, each with its own
+/// perturbed measurement. Only the S x Z and Z x Z covariances are formed, so the update
+/// scales with the number of members instead of the square of the state size
+///
+/// Source : The Ensemble Kalman Filter: theoretical formulation and practical implementation,
+/// Evensen, 2003
+///
+/// S : State Size, Z: Observation Size, U: Input Size, H: Measurement Model, M: Motion Model
+pub struct EnsembleKalmanFilter<
+    T: RealField,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    H = Box<dyn MeasurementModel<T, S, Z> + Send>,
+    M = Box<dyn MotionModel<T, S, Z, U> + Send>,
+> where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+{
+    motion_noise: MultiVariateNormal<T, S>,
+    measurement_noise: MultiVariateNormal<T, Z>,
+    q: OMatrix<T, Z, Z>,
+    measurement_model: H,
+    motion_model: M,
+    pub ensemble: Vec<OVector<T, S>>,
+    seed: u64,
+    step: u64,
+    observer: Observer<T>,
+    _input: PhantomData<U>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> EnsembleKalmanFilter<T, S, Z, U>
+where
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    /// Same arguments as `ExtendedKalmanFilter::new`, the members are drawn from `initial_state`.
+    /// The covariances are estimated from the members, there should be at least two of them
+    pub fn new(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+        motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+        initial_state: GaussianState<T, S>,
+        ensemble_size: usize,
+    ) -> EnsembleKalmanFilter<T, S, Z, U> {
+        EnsembleKalmanFilter::with_models(
+            r,
+            q,
+            measurement_model,
+            motion_model,
+            initial_state,
+            ensemble_size,
+        )
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> EnsembleKalmanFilter<T, S, Z, U, H, M>
+where
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    pub fn with_models(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: H,
+        motion_model: M,
+        initial_state: GaussianState<T, S>,
+        ensemble_size: usize,
+    ) -> EnsembleKalmanFilter<T, S, Z, U, H, M> {
+        assert!(
+            ensemble_size >= 2,
+            "the ensemble should have at least two members"
+        );
+        let seed = rand::thread_rng().next_u64();
+        let mvn = MultiVariateNormal::new(&initial_state.x, &initial_state.cov).unwrap();
+        EnsembleKalmanFilter {
+            motion_noise: MultiVariateNormal::zero_mean(&r).unwrap(),
+            measurement_noise: MultiVariateNormal::zero_mean(&q).unwrap(),
+            q,
+            measurement_model,
+            motion_model,
+            ensemble: initial_particules(&mvn, ensemble_size, seed),
+            seed,
+            step: 0,
+            observer: None,
+            _input: PhantomData,
+        }
+    }
+
+    /// The seed is random by default, the members are drawn again from `initial_state` so the
+    /// whole run can be replayed
+    pub fn set_seed(&mut self, seed: u64, initial_state: &GaussianState<T, S>) {
+        let mvn = MultiVariateNormal::new(&initial_state.x, &initial_state.cov).unwrap();
+        self.ensemble = initial_particules(&mvn, self.ensemble.len(), seed);
+        self.seed = seed;
+        self.step = 0;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Receives the innovations of the ensemble mean, the gain norms and the update times
+    pub fn set_observer(&mut self, observer: impl FilterObserver<T> + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> BayesianFilter<T, S, Z, U>
+    for EnsembleKalmanFilter<T, S, Z, U, H, M>
+where
+    H: MeasurementModel<T, S, Z>,
+    M: MotionModel<T, S, Z, U>,
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, S, Z>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
+        let start = Instant::now();
+        self.step += 1;
+        let (seed, step) = (self.seed, self.step);
+        let mut rngs: Vec<Philox> = (0..self.ensemble.len())
+            .map(|i| Philox::for_particule(seed, i as u64, step))
+            .collect();
+
+        // predict
+        self.ensemble = self
+            .ensemble
+            .iter()
+            .zip(rngs.iter_mut())
+            .map(|(x, rng)| {
+                self.motion_model.prediction(x, u, dt) + self.motion_noise.sample_with_rng(rng)
+            })
+            .collect();
+
+        // update, the covariances are estimated with the N - 1 denominator
+        let z_members: Vec<OVector<T, Z>> = self
+            .ensemble
+            .iter()
+            .map(|x| self.measurement_model.prediction(x, None))
+            .collect();
+        let n = T::from_usize(self.ensemble.len()).unwrap();
+        let x_mean = self.ensemble.iter().fold(
+            OMatrix::zeros_generic(self.ensemble[0].shape_generic().0, Const::<1>),
+            |a, b| a + b,
+        ) / n;
+        let z_mean = z_members.iter().fold(
+            OMatrix::zeros_generic(z.shape_generic().0, Const::<1>),
+            |a, b| a + b,
+        ) / n;
+        let (cross_cov, cov_z) = self.ensemble.iter().zip(z_members.iter()).fold(
+            (
+                OMatrix::zeros_generic(x_mean.shape_generic().0, z.shape_generic().0),
+                OMatrix::zeros_generic(z.shape_generic().0, z.shape_generic().0),
+            ),
+            |(cross_cov, cov_z), (x, z_member)| {
+                let dz = z_member - &z_mean;
+                (
+                    cross_cov + (x - &x_mean) * dz.transpose(),
+                    cov_z + &dz * dz.transpose(),
+                )
+            },
+        );
+        let denominator = n - T::one();
+        let cov_z = cov_z / denominator + &self.q;
+        // the members keep their prediction when the innovation covariance is singular
+        let Some(cov_z_inv) = cov_z.try_inverse() else {
+            diagnostics::notify(&mut self.observer, || {
+                FilterEvent::UpdateTime(start.elapsed())
+            });
+            return;
+        };
+        let kalman_gain = cross_cov / denominator * cov_z_inv;
+
+        self.ensemble = self
+            .ensemble
+            .iter()
+            .zip(z_members.iter())
+            .zip(rngs.iter_mut())
+            .map(|((x, z_member), rng)| {
+                let perturbed = z + self.measurement_noise.sample_with_rng(rng);
+                x + &kalman_gain * (perturbed - z_member)
+            })
+            .collect();
+        diagnostics::notify(&mut self.observer, || FilterEvent::Innovation {
+            landmark: None,
+            innovation: (z - &z_mean).iter().copied().collect(),
+            gain_norm: Some(kalman_gain.norm()),
+        });
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::UpdateTime(start.elapsed())
+        });
+    }
+
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        gaussian_estimate(&self.ensemble)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::ExtendedKalmanFilter;
+    use crate::models::measurement::PositionMeasurementModel;
+    use nalgebra::{Matrix2, Vector2};
+
+    /// Position driven by the velocity input
+    struct Integrator;
+
+    impl MotionModel<f64, Const<2>, Const<2>, Const<2>> for Integrator {
+        fn prediction(&self, x: &Vector2<f64>, u: &Vector2<f64>, dt: f64) -> Vector2<f64> {
+            x + u * dt
+        }
+        fn jacobian_wrt_state(
+            &self,
+            _x: &Vector2<f64>,
+            _u: &Vector2<f64>,
+            _dt: f64,
+        ) -> Matrix2<f64> {
+            Matrix2::identity()
+        }
+        fn jacobian_wrt_input(
+            &self,
+            _x: &Vector2<f64>,
+            _u: &Vector2<f64>,
+            dt: f64,
+        ) -> Matrix2<f64> {
+            Matrix2::identity() * dt
+        }
+        fn cov_noise_control_space(&self, _u: &Vector2<f64>) -> Matrix2<f64> {
+            Matrix2::zeros()
+        }
+        fn sample(&self, x: &Vector2<f64>, u: &Vector2<f64>, dt: f64) -> Vector2<f64> {
+            self.prediction(x, u, dt)
+        }
+    }
+
+    #[test]
+    fn linear_problem_matches_the_kalman_filter() {
+        let r = Matrix2::new(0.2, 0.05, 0.05, 0.1);
+        let q = Matrix2::identity() * 0.3;
+        let initial_state = GaussianState {
+            x: Vector2::new(1.0, -1.0),
+            cov: Matrix2::identity(),
+        };
+        let mut ekf = ExtendedKalmanFilter::<f64, Const<2>, Const<2>, Const<2>>::new(
+            r,
+            q,
+            PositionMeasurementModel::new(),
+            Box::new(Integrator),
+            initial_state.clone(),
+        );
+        let mut enkf = EnsembleKalmanFilter::<f64, Const<2>, Const<2>, Const<2>>::new(
+            r,
+            q,
+            PositionMeasurementModel::new(),
+            Box::new(Integrator),
+            initial_state.clone(),
+            5000,
+        );
+        enkf.set_seed(4, &initial_state);
+
+        let u = Vector2::new(1.0, 0.5);
+        for i in 0..10 {
+            let t = i as f64 * 0.1;
+            let z = Vector2::new(1.0 + t, -1.0 + 0.5 * t);
+            ekf.update_estimate(&u, &z, 0.1);
+            enkf.update_estimate(&u, &z, 0.1);
+        }
+        let (expected, estimate) = (ekf.gaussian_estimate(), enkf.gaussian_estimate());
+        approx::assert_abs_diff_eq!(expected.x, estimate.x, epsilon = 0.05);
+        approx::assert_abs_diff_eq!(expected.cov, estimate.cov, epsilon = 0.02);
+    }
+
+    #[test]
+    #[should_panic(expected = "the ensemble should have at least two members")]
+    fn single_member() {
+        EnsembleKalmanFilter::<f64, Const<2>, Const<2>, Const<2>>::new(
+            Matrix2::identity(),
+            Matrix2::identity(),
+            PositionMeasurementModel::new(),
+            Box::new(Integrator),
+            GaussianState {
+                x: Vector2::zeros(),
+                cov: Matrix2::identity(),
+            },
+            1,
+        );
+    }
+
+    #[test]
+    fn singular_innovation_covariance() {
+        // identical members and a measurement noise on a line, the innovation covariance is
+        // singular
+        let initial_state = GaussianState {
+            x: Vector2::new(1.0, -1.0),
+            cov: Matrix2::zeros(),
+        };
+        let mut enkf = EnsembleKalmanFilter::<f64, Const<2>, Const<2>, Const<2>>::new(
+            Matrix2::zeros(),
+            Matrix2::new(1.0, 1.0, 1.0, 1.0),
+            PositionMeasurementModel::new(),
+            Box::new(Integrator),
+            initial_state.clone(),
+            10,
+        );
+        enkf.set_seed(2, &initial_state);
+        enkf.update_estimate(&Vector2::new(1.0, 0.5), &Vector2::new(3.0, 0.0), 0.1);
+        let estimate = enkf.gaussian_estimate();
+        approx::assert_abs_diff_eq!(estimate.x, Vector2::new(1.1, -0.95), epsilon = 1e-12);
+    }
+}
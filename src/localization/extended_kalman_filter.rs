@@ -1,21 +1,76 @@
-use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
 use rustc_hash::FxHashMap;
 
-use crate::localization::{BayesianFilter, BayesianFilterKnownCorrespondences};
+use crate::localization::diagnostics::{self, Observer};
+use crate::localization::{
+    BayesianFilter, BayesianFilterKnownCorrespondences, Belief, FilterEvent, FilterObserver,
+    WarmStartConfig,
+};
 use crate::models::measurement::MeasurementModel;
 use crate::models::motion::MotionModel;
+#[cfg(feature = "serde-serialize")]
+use crate::utils::persistence;
 use crate::utils::state::GaussianState;
+#[cfg(feature = "serde-serialize")]
+use std::error::Error;
+use std::marker::PhantomData;
+use std::time::Instant;
 
-/// S : State Size, Z: Observation Size, U: Input Size
-pub struct ExtendedKalmanFilter<T: RealField, S: Dim, Z: Dim, U: Dim>
+/// How the covariance is corrected by a measurement
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CovarianceUpdate {
+    /// (I - KH)P
+    #[default]
+    Standard,
+    /// (I - KH)P(I - KH)^T + KQK^T, stays symmetric positive definite with rounding errors, e.g.
+    /// in f32, at the cost of more products
+    Joseph,
+}
+
+/// Corrected covariance with the gain `kalman_gain` of the measurement jacobian `h`
+fn corrected_covariance<T: RealField, S: Dim, Z: Dim>(
+    update: CovarianceUpdate,
+    cov: &OMatrix<T, S, S>,
+    kalman_gain: &OMatrix<T, S, Z>,
+    h: &OMatrix<T, Z, S>,
+    q: &OMatrix<T, Z, Z>,
+) -> OMatrix<T, S, S>
 where
+    DefaultAllocator:
+        Allocator<T, S, S> + Allocator<T, S, Z> + Allocator<T, Z, S> + Allocator<T, Z, Z>,
+{
+    let shape = cov.shape_generic();
+    let i_kh = OMatrix::identity_generic(shape.0, shape.1) - kalman_gain * h;
+    match update {
+        CovarianceUpdate::Standard => i_kh * cov,
+        CovarianceUpdate::Joseph => {
+            &i_kh * cov * i_kh.transpose() + kalman_gain * q * kalman_gain.transpose()
+        }
+    }
+}
+
+/// S : State Size, Z: Observation Size, U: Input Size, H: Measurement Model, M: Motion Model
+///
+/// The models are boxed by default, `with_models` takes them by value so their calls are
+/// statically dispatched and can be inlined
+pub struct ExtendedKalmanFilter<
+    T: RealField,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    H = Box<dyn MeasurementModel<T, S, Z> + Send>,
+    M = Box<dyn MotionModel<T, S, Z, U> + Send>,
+> where
     DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
 {
     r: OMatrix<T, S, S>,
     q: OMatrix<T, Z, Z>,
-    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
-    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    measurement_model: H,
+    motion_model: M,
     state: GaussianState<T, S>,
+    covariance_update: CovarianceUpdate,
+    observer: Observer<T>,
+    _input: PhantomData<U>,
 }
 
 impl<T: RealField, S: Dim, Z: Dim, U: Dim> ExtendedKalmanFilter<T, S, Z, U>
@@ -29,19 +84,87 @@ where
         motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
         initial_state: GaussianState<T, S>,
     ) -> ExtendedKalmanFilter<T, S, Z, U> {
+        ExtendedKalmanFilter::with_models(r, q, measurement_model, motion_model, initial_state)
+    }
+}
+
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M> ExtendedKalmanFilter<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    pub fn with_models(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: H,
+        motion_model: M,
+        initial_state: GaussianState<T, S>,
+    ) -> ExtendedKalmanFilter<T, S, Z, U, H, M> {
         ExtendedKalmanFilter {
             r,
             q,
             measurement_model,
             motion_model,
             state: initial_state,
+            covariance_update: CovarianceUpdate::default(),
+            observer: None,
+            _input: PhantomData,
         }
     }
+
+    pub fn set_covariance_update(&mut self, covariance_update: CovarianceUpdate) {
+        self.covariance_update = covariance_update;
+    }
+
+    /// Receives the innovations, the gain norms and the update times
+    pub fn set_observer(&mut self, observer: impl FilterObserver<T> + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Noise of the next measurements, e.g. the covariance reported with each GNSS fix
+    pub fn set_measurement_noise(&mut self, q: OMatrix<T, Z, Z>) {
+        self.q = q;
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> ExtendedKalmanFilter<T, S, Z, U, H, M>
+where
+    DefaultAllocator:
+        Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z> + Allocator<T, Const<1>, S>,
+{
+    /// Belief to save at shutdown
+    pub fn snapshot(&self) -> Belief<T, S> {
+        Belief::Gaussian(self.state.clone())
+    }
+
+    /// Restarts from the belief of a previous session with the inflated covariance
+    pub fn warm_start(&mut self, belief: &Belief<T, S>, config: &WarmStartConfig<T, S>) {
+        self.state = belief.inflated_gaussian(config);
+    }
+}
+
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M> ExtendedKalmanFilter<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+    GaussianState<T, S>: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the state estimate as JSON, the models and noises are not saved
+    pub fn save_state<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        persistence::save(writer, &self.state)
+    }
+
+    /// Restores the state estimate written by `save_state`
+    pub fn load_state<R: std::io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        self.state = persistence::load(reader)?;
+        Ok(())
+    }
 }
 
-impl<T: RealField, S: Dim, Z: Dim, U: Dim> BayesianFilter<T, S, Z, U>
-    for ExtendedKalmanFilter<T, S, Z, U>
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M> BayesianFilter<T, S, Z, U>
+    for ExtendedKalmanFilter<T, S, Z, U, H, M>
 where
+    H: MeasurementModel<T, S, Z>,
+    M: MotionModel<T, S, Z, U>,
     DefaultAllocator: Allocator<T, S>
         + Allocator<T, U>
         + Allocator<T, Z>
@@ -53,6 +176,7 @@ where
         + Allocator<T, S, Z>,
 {
     fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
+        let start = Instant::now();
         // predict
         let g = self
             .motion_model
@@ -66,10 +190,23 @@ where
 
         let s = &h * &self.state.cov * h.transpose() + &self.q;
         let kalman_gain = &self.state.cov * h.transpose() * s.try_inverse().unwrap();
-        self.state.x = &self.state.x + &kalman_gain * (z - z_pred);
-        let shape = self.state.cov.shape_generic();
-        self.state.cov =
-            (OMatrix::identity_generic(shape.0, shape.1) - kalman_gain * h) * &self.state.cov;
+        let innovation = z - z_pred;
+        self.state.x = &self.state.x + &kalman_gain * &innovation;
+        self.state.cov = corrected_covariance(
+            self.covariance_update,
+            &self.state.cov,
+            &kalman_gain,
+            &h,
+            &self.q,
+        );
+        diagnostics::notify(&mut self.observer, || FilterEvent::Innovation {
+            landmark: None,
+            innovation: innovation.iter().cloned().collect(),
+            gain_norm: Some(kalman_gain.norm()),
+        });
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::UpdateTime(start.elapsed())
+        });
     }
 
     fn gaussian_estimate(&self) -> GaussianState<T, S> {
@@ -77,16 +214,25 @@ where
     }
 }
 
-/// S : State Size, Z: Observation Size, U: Input Size
-pub struct ExtendedKalmanFilterKnownCorrespondences<T: RealField, S: Dim, Z: Dim, U: Dim>
-where
+/// S : State Size, Z: Observation Size, U: Input Size, H: Measurement Model, M: Motion Model
+pub struct ExtendedKalmanFilterKnownCorrespondences<
+    T: RealField,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    H = Box<dyn MeasurementModel<T, S, Z> + Send>,
+    M = Box<dyn MotionModel<T, S, Z, U> + Send>,
+> where
     DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
 {
     q: OMatrix<T, Z, Z>,
     landmarks: FxHashMap<u32, OVector<T, S>>,
-    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
-    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    measurement_model: H,
+    motion_model: M,
     state: GaussianState<T, S>,
+    covariance_update: CovarianceUpdate,
+    observer: Observer<T>,
+    _input: PhantomData<U>,
 }
 
 impl<T: RealField, S: Dim, Z: Dim, U: Dim> ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U>
@@ -100,19 +246,97 @@ where
         motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
         initial_state: GaussianState<T, S>,
     ) -> ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U> {
+        ExtendedKalmanFilterKnownCorrespondences::with_models(
+            q,
+            landmarks,
+            measurement_model,
+            motion_model,
+            initial_state,
+        )
+    }
+}
+
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M>
+    ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    pub fn with_models(
+        q: OMatrix<T, Z, Z>,
+        landmarks: FxHashMap<u32, OVector<T, S>>,
+        measurement_model: H,
+        motion_model: M,
+        initial_state: GaussianState<T, S>,
+    ) -> ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U, H, M> {
         ExtendedKalmanFilterKnownCorrespondences {
             q,
             landmarks,
             measurement_model,
             motion_model,
             state: initial_state,
+            covariance_update: CovarianceUpdate::default(),
+            observer: None,
+            _input: PhantomData,
         }
     }
+
+    pub fn set_covariance_update(&mut self, covariance_update: CovarianceUpdate) {
+        self.covariance_update = covariance_update;
+    }
+
+    /// Receives the innovations, the gain norms and the update times
+    pub fn set_observer(&mut self, observer: impl FilterObserver<T> + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Replaces the estimate, to recover from a tracking loss
+    pub fn reinitialize(&mut self, state: GaussianState<T, S>) {
+        self.state = state;
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M>
+    ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U, H, M>
+where
+    DefaultAllocator:
+        Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z> + Allocator<T, Const<1>, S>,
+{
+    /// Belief to save at shutdown
+    pub fn snapshot(&self) -> Belief<T, S> {
+        Belief::Gaussian(self.state.clone())
+    }
+
+    /// Restarts from the belief of a previous session with the inflated covariance
+    pub fn warm_start(&mut self, belief: &Belief<T, S>, config: &WarmStartConfig<T, S>) {
+        self.state = belief.inflated_gaussian(config);
+    }
+}
+
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M>
+    ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+    GaussianState<T, S>: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the state estimate as JSON, the models and noises are not saved
+    pub fn save_state<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        persistence::save(writer, &self.state)
+    }
+
+    /// Restores the state estimate written by `save_state`
+    pub fn load_state<R: std::io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        self.state = persistence::load(reader)?;
+        Ok(())
+    }
 }
 
-impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilterKnownCorrespondences<T, S, Z, U>
-    for ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U>
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M>
+    BayesianFilterKnownCorrespondences<T, S, Z, U>
+    for ExtendedKalmanFilterKnownCorrespondences<T, S, Z, U, H, M>
 where
+    H: MeasurementModel<T, S, Z>,
+    M: MotionModel<T, S, Z, U>,
     DefaultAllocator: Allocator<T, S>
         + Allocator<T, U>
         + Allocator<T, Z>
@@ -130,6 +354,7 @@ where
         measurements: Option<Vec<(u32, OVector<T, Z>)>>,
         dt: T,
     ) {
+        let start = Instant::now();
         // predict
         if let Some(u) = control {
             let g = self.motion_model.jacobian_wrt_state(&self.state.x, &u, dt);
@@ -142,21 +367,40 @@ where
 
         // update / correction step
         if let Some(measurements) = measurements {
-            let shape = self.state.cov.shape_generic();
-            for (id, z) in measurements
-                .iter()
-                .filter(|(id, _)| self.landmarks.contains_key(id))
-            {
-                let landmark = self.landmarks.get(id);
-                let z_pred = self.measurement_model.prediction(&self.state.x, landmark);
-                let h = self.measurement_model.jacobian(&self.state.x, landmark);
+            for (id, z) in measurements.iter() {
+                let Some(landmark) = self.landmarks.get(id) else {
+                    diagnostics::notify(&mut self.observer, || FilterEvent::Rejected {
+                        landmark: *id,
+                    });
+                    continue;
+                };
+                let z_pred = self
+                    .measurement_model
+                    .prediction(&self.state.x, Some(landmark));
+                let h = self
+                    .measurement_model
+                    .jacobian(&self.state.x, Some(landmark));
                 let s = &h * &self.state.cov * h.transpose() + &self.q;
                 let kalman_gain = &self.state.cov * h.transpose() * s.try_inverse().unwrap();
-                self.state.x += &kalman_gain * (z - z_pred);
-                self.state.cov = (OMatrix::identity_generic(shape.0, shape.1) - kalman_gain * h)
-                    * &self.state.cov;
+                let innovation = z - z_pred;
+                self.state.x += &kalman_gain * &innovation;
+                self.state.cov = corrected_covariance(
+                    self.covariance_update,
+                    &self.state.cov,
+                    &kalman_gain,
+                    &h,
+                    &self.q,
+                );
+                diagnostics::notify(&mut self.observer, || FilterEvent::Innovation {
+                    landmark: Some(*id),
+                    innovation: innovation.iter().cloned().collect(),
+                    gain_norm: Some(kalman_gain.norm()),
+                });
             }
         }
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::UpdateTime(start.elapsed())
+        });
     }
 
     fn gaussian_estimate(&self) -> GaussianState<T, S> {
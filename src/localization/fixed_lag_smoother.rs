@@ -0,0 +1,341 @@
+use std::collections::VecDeque;
+
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use rustc_hash::FxHashMap;
+
+use crate::localization::BayesianFilterKnownCorrespondences;
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::state::GaussianState;
+
+/// Step of the window, from `time - dt` to `time`
+struct Step<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, U>,
+{
+    time: T,
+    dt: T,
+    control: Option<OVector<T, U>>,
+    measurements: Vec<(u32, OVector<T, Z>)>,
+    predicted: GaussianState<T, S>,
+    jacobian: OMatrix<T, S, S>,
+    posterior: GaussianState<T, S>,
+}
+
+/// EKF with known correspondences keeping the controls and measurements of the last `lag`
+/// seconds. A measurement stamped in the window, e.g. a late GPS fix or visual detection, is
+/// inserted at its time and the window is filtered again up to the present. The window can
+/// also be smoothed (Rauch-Tung-Striebel)
+///
+/// The time is the sum of the `dt` given to `update_estimate`
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct FixedLagSmoother<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+    DefaultAllocator: Allocator<T, U>,
+{
+    pub lag: T,
+    q: OMatrix<T, Z, Z>,
+    landmarks: FxHashMap<u32, OVector<T, S>>,
+    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    /// Estimate at the start of the window
+    prior: GaussianState<T, S>,
+    prior_time: T,
+    steps: VecDeque<Step<T, S, Z, U>>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> FixedLagSmoother<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, S, Z>
+        + Allocator<T, U, S>,
+{
+    /// Same arguments as `ExtendedKalmanFilterKnownCorrespondences::new`, the time starts at 0
+    pub fn new(
+        lag: T,
+        q: OMatrix<T, Z, Z>,
+        landmarks: FxHashMap<u32, OVector<T, S>>,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+        motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+        initial_state: GaussianState<T, S>,
+    ) -> FixedLagSmoother<T, S, Z, U> {
+        FixedLagSmoother {
+            lag,
+            q,
+            landmarks,
+            measurement_model,
+            motion_model,
+            prior: initial_state,
+            prior_time: T::zero(),
+            steps: VecDeque::new(),
+        }
+    }
+
+    /// Time of the current estimate
+    pub fn time(&self) -> T {
+        self.steps.back().map_or(self.prior_time, |step| step.time)
+    }
+
+    /// Inserts measurements taken at `time` in the past and filters the window again, returns
+    /// false if `time` is before the window or after the current time
+    pub fn add_delayed_measurements(
+        &mut self,
+        time: T,
+        measurements: Vec<(u32, OVector<T, Z>)>,
+    ) -> bool {
+        if time < self.prior_time || time > self.time() {
+            return false;
+        }
+        // the time is a sum of `dt`, the rounding errors should not split a step
+        let tolerance = T::default_epsilon().sqrt();
+        let Some(index) = self
+            .steps
+            .iter()
+            .position(|step| step.time >= time - tolerance)
+        else {
+            // at the start of an empty window
+            let mut state = self.prior.clone();
+            self.correct(&mut state, &measurements);
+            self.prior = state;
+            return true;
+        };
+        if self.steps[index].time - time <= tolerance {
+            self.steps[index].measurements.extend(measurements);
+        } else {
+            // split the step at `time`, the control is the same on both sides
+            let step = &mut self.steps[index];
+            let before = step.dt - (step.time - time);
+            step.dt = step.time - time;
+            let inserted = Step {
+                time,
+                dt: before,
+                control: step.control.clone(),
+                measurements,
+                predicted: step.predicted.clone(),
+                jacobian: step.jacobian.clone(),
+                posterior: step.posterior.clone(),
+            };
+            self.steps.insert(index, inserted);
+        }
+        self.refilter(index);
+        true
+    }
+
+    /// Smoothed estimates of the window with their time, the last one is the current estimate
+    pub fn smoothed(&self) -> Vec<(T, GaussianState<T, S>)> {
+        let mut smoothed: Vec<(T, GaussianState<T, S>)> = Vec::with_capacity(self.steps.len());
+        let Some(last) = self.steps.back() else {
+            return vec![(self.prior_time, self.prior.clone())];
+        };
+        smoothed.push((last.time, last.posterior.clone()));
+        for k in (0..self.steps.len() - 1).rev() {
+            let (filtered, next) = (&self.steps[k].posterior, &self.steps[k + 1]);
+            let next_smoothed = smoothed.last().unwrap().1.clone();
+            let Some(predicted_inv) = next.predicted.cov.clone().try_inverse() else {
+                smoothed.push((self.steps[k].time, filtered.clone()));
+                continue;
+            };
+            let gain = &filtered.cov * next.jacobian.transpose() * predicted_inv;
+            let x = &filtered.x + &gain * (&next_smoothed.x - &next.predicted.x);
+            let cov = &filtered.cov
+                + &gain * (&next_smoothed.cov - &next.predicted.cov) * gain.transpose();
+            smoothed.push((self.steps[k].time, GaussianState { x, cov }));
+        }
+        smoothed.reverse();
+        smoothed
+    }
+
+    fn correct(&self, state: &mut GaussianState<T, S>, measurements: &[(u32, OVector<T, Z>)]) {
+        let shape = state.cov.shape_generic();
+        for (id, z) in measurements {
+            let Some(landmark) = self.landmarks.get(id) else {
+                continue;
+            };
+            let z_pred = self.measurement_model.prediction(&state.x, Some(landmark));
+            let h = self.measurement_model.jacobian(&state.x, Some(landmark));
+            let s = &h * &state.cov * h.transpose() + &self.q;
+            let Some(s_inv) = s.try_inverse() else {
+                continue;
+            };
+            let kalman_gain = &state.cov * h.transpose() * s_inv;
+            state.x += &kalman_gain * (z - z_pred);
+            state.cov =
+                (OMatrix::identity_generic(shape.0, shape.1) - kalman_gain * h) * &state.cov;
+        }
+    }
+
+    /// Filters the steps from `index` to the present again
+    fn refilter(&mut self, index: usize) {
+        for k in index..self.steps.len() {
+            let previous = if k == 0 {
+                self.prior.clone()
+            } else {
+                self.steps[k - 1].posterior.clone()
+            };
+            let step = &self.steps[k];
+            let (predicted, jacobian) = match &step.control {
+                Some(u) => {
+                    let g = self
+                        .motion_model
+                        .jacobian_wrt_state(&previous.x, u, step.dt);
+                    let v = self
+                        .motion_model
+                        .jacobian_wrt_input(&previous.x, u, step.dt);
+                    let m = self.motion_model.cov_noise_control_space(u);
+                    let x = self.motion_model.prediction(&previous.x, u, step.dt);
+                    let cov = &g * &previous.cov * g.transpose() + &v * m * v.transpose();
+                    (GaussianState { x, cov }, g)
+                }
+                None => {
+                    let shape = previous.cov.shape_generic();
+                    (previous, OMatrix::identity_generic(shape.0, shape.1))
+                }
+            };
+            let mut posterior = predicted.clone();
+            self.correct(&mut posterior, &step.measurements);
+            let step = &mut self.steps[k];
+            step.predicted = predicted;
+            step.jacobian = jacobian;
+            step.posterior = posterior;
+        }
+    }
+
+    /// Forgets the steps older than `lag`
+    fn slide(&mut self) {
+        let start = self.time() - self.lag;
+        while self.steps.len() > 1 && self.steps[0].time < start {
+            let step = self.steps.pop_front().unwrap();
+            self.prior = step.posterior;
+            self.prior_time = step.time;
+        }
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilterKnownCorrespondences<T, S, Z, U>
+    for FixedLagSmoother<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, S, Z>
+        + Allocator<T, U, S>,
+{
+    fn update_estimate(
+        &mut self,
+        control: Option<OVector<T, U>>,
+        measurements: Option<Vec<(u32, OVector<T, Z>)>>,
+        dt: T,
+    ) {
+        let current = self.gaussian_estimate();
+        let shape = current.cov.shape_generic();
+        self.steps.push_back(Step {
+            time: self.time() + dt,
+            dt,
+            control,
+            measurements: measurements.unwrap_or_default(),
+            predicted: current.clone(),
+            jacobian: OMatrix::identity_generic(shape.0, shape.1),
+            posterior: current,
+        });
+        self.refilter(self.steps.len() - 1);
+        self.slide();
+    }
+
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        self.steps
+            .back()
+            .map_or(self.prior.clone(), |step| step.posterior.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::ExtendedKalmanFilterKnownCorrespondences;
+    use crate::models::measurement::RangeBearingMeasurementModel;
+    use crate::models::motion::Velocity;
+    use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+
+    #[test]
+    fn delayed_measurements_match_the_on_time_filter() {
+        let landmarks: FxHashMap<u32, Vector3<f64>> = [
+            (1, Vector3::new(5.0, 0.0, 0.0)),
+            (2, Vector3::new(0.0, 5.0, 0.0)),
+        ]
+        .into_iter()
+        .collect();
+        let initial_state = GaussianState {
+            x: Vector3::zeros(),
+            cov: Matrix3::identity() * 0.1,
+        };
+        let mut ekf = ExtendedKalmanFilterKnownCorrespondences::new(
+            Matrix2::identity() * 0.01,
+            landmarks.clone(),
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.1; 6]),
+            initial_state.clone(),
+        );
+        let mut smoother = FixedLagSmoother::new(
+            0.5,
+            Matrix2::identity() * 0.01,
+            landmarks,
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.1; 6]),
+            initial_state,
+        );
+
+        let u = Vector2::new(1.0, 0.2);
+        let z = |t: f64, id: u32| Vector2::new(4.0 + t, 0.1 * id as f64 - t);
+        let dt = 0.125;
+        for i in 1..=10 {
+            let t = i as f64 * dt;
+            if i == 6 {
+                // a detection in the middle of the step received 0.3 s late
+                let t_late = t - dt / 2.0;
+                ekf.update_estimate(Some(u), Some(vec![(2, z(t_late, 2))]), dt / 2.0);
+                ekf.update_estimate(Some(u), Some(vec![(1, z(t, 1))]), dt / 2.0);
+            } else {
+                ekf.update_estimate(Some(u), Some(vec![(1, z(t, 1))]), dt);
+            }
+            if i == 3 {
+                ekf.update_estimate(None, Some(vec![(2, z(t, 2))]), 0.0);
+            }
+
+            smoother.update_estimate(Some(u), Some(vec![(1, z(t, 1))]), dt);
+            // a fix at a step time received 0.25 s late
+            if i == 5 {
+                assert!(smoother.add_delayed_measurements(0.375, vec![(2, z(0.375, 2))]));
+            }
+            if i == 8 {
+                assert!(smoother.add_delayed_measurements(0.6875, vec![(2, z(0.6875, 2))]));
+            }
+        }
+        assert_eq!(1.25, smoother.time());
+        let expected = ekf.gaussian_estimate();
+        let estimate = smoother.gaussian_estimate();
+        approx::assert_abs_diff_eq!(expected.x, estimate.x, epsilon = 1e-9);
+        approx::assert_abs_diff_eq!(expected.cov, estimate.cov, epsilon = 1e-9);
+
+        // before the window
+        assert!(!smoother.add_delayed_measurements(0.6, vec![(2, z(0.6, 2))]));
+        let smoothed = smoother.smoothed();
+        let (time, last) = smoothed.last().unwrap();
+        assert_eq!(1.25, *time);
+        approx::assert_abs_diff_eq!(estimate.x, last.x, epsilon = 1e-12);
+        assert!(smoothed[0].0 >= 0.75);
+    }
+}
@@ -0,0 +1,415 @@
+use std::ops::BitOr;
+
+use nalgebra::{
+    Const, DMatrix, DVector, Isometry3, Matrix3, Rotation3, SMatrix, SVector, Translation3,
+    UnitQuaternion, Vector3,
+};
+
+use crate::geo::{Geodetic, GnssFix, LocalFrame};
+use crate::localization::ImuSample;
+use crate::models::imu::{ImuNoise, NavState};
+use crate::utils::frames::STANDARD_GRAVITY;
+use crate::utils::state::GaussianState;
+
+/// Size of the error state
+const N: usize = 15;
+const POSITION: usize = 0;
+const VELOCITY: usize = 3;
+const ATTITUDE: usize = 6;
+const GYRO_BIAS: usize = 9;
+const ACCEL_BIAS: usize = 12;
+
+/// Dimensions of the state measured by a sensor which are fused, the others are ignored like the
+/// configuration of the sensors of robot_localization. The fused dimensions still correct the
+/// others through their correlations, e.g. the GNSS positions correct the heading and the biases
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct StateMask(u16);
+
+impl StateMask {
+    pub const NONE: StateMask = StateMask(0);
+    pub const ALL: StateMask = StateMask((1 << N) - 1);
+    pub const POSITION_X: StateMask = StateMask(1 << POSITION);
+    pub const POSITION_Y: StateMask = StateMask(1 << (POSITION + 1));
+    pub const POSITION_Z: StateMask = StateMask(1 << (POSITION + 2));
+    pub const POSITION: StateMask = StateMask(0b111 << POSITION);
+    /// The velocity measured by the odometry is in the body frame, the masks of its components
+    /// are the body axes
+    pub const VELOCITY_X: StateMask = StateMask(1 << VELOCITY);
+    pub const VELOCITY_Y: StateMask = StateMask(1 << (VELOCITY + 1));
+    pub const VELOCITY_Z: StateMask = StateMask(1 << (VELOCITY + 2));
+    pub const VELOCITY: StateMask = StateMask(0b111 << VELOCITY);
+    /// Rotations about the world axes, the roll, pitch and yaw when the robot is level
+    pub const ROLL: StateMask = StateMask(1 << ATTITUDE);
+    pub const PITCH: StateMask = StateMask(1 << (ATTITUDE + 1));
+    pub const YAW: StateMask = StateMask(1 << (ATTITUDE + 2));
+    pub const ATTITUDE: StateMask = StateMask(0b111 << ATTITUDE);
+
+    pub fn contains(self, dim: usize) -> bool {
+        self.0 & (1 << dim) != 0
+    }
+}
+
+impl BitOr for StateMask {
+    type Output = StateMask;
+
+    fn bitor(self, rhs: StateMask) -> StateMask {
+        StateMask(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FusedLocalizerConfig {
+    pub imu_noise: ImuNoise,
+    /// Random walks of the biases of the gyroscope [rad/s²/sqrt(Hz)] and of the accelerometer
+    /// [m/s³/sqrt(Hz)]
+    pub gyro_bias_walk: f64,
+    pub accel_bias_walk: f64,
+    /// In the world frame, ENU
+    pub gravity: Vector3<f64>,
+    /// Keeps the robot on the horizontal plane: the altitude, the vertical velocity, the roll and
+    /// the pitch stay at 0
+    pub planar: bool,
+    pub odometry_mask: StateMask,
+    pub gnss_mask: StateMask,
+    pub orientation_mask: StateMask,
+    /// Position of the GNSS antenna in the body frame [m]
+    pub gnss_lever_arm: Vector3<f64>,
+}
+
+impl Default for FusedLocalizerConfig {
+    fn default() -> Self {
+        FusedLocalizerConfig {
+            imu_noise: ImuNoise {
+                gyro: 1e-3,
+                accel: 1e-2,
+            },
+            gyro_bias_walk: 1e-5,
+            accel_bias_walk: 1e-4,
+            gravity: Vector3::new(0.0, 0.0, -STANDARD_GRAVITY),
+            planar: false,
+            odometry_mask: StateMask::VELOCITY,
+            gnss_mask: StateMask::POSITION,
+            orientation_mask: StateMask::ATTITUDE,
+            gnss_lever_arm: Vector3::zeros(),
+        }
+    }
+}
+
+/// Localizer fusing an IMU, the wheel odometry and a GNSS receiver at their own rates.
+///
+/// Error state EKF with 15 states: the position, velocity and orientation of the IMU in the
+/// world frame (local ENU) and the biases of the gyroscope and accelerometer. The IMU samples
+/// propagate the nominal state and the covariance, the other sensors correct them when they
+/// arrive, at the time of the last IMU sample. The orientation error is a rotation vector in the
+/// world frame, the error covariance is ordered (position, velocity, orientation, gyroscope
+/// bias, accelerometer bias)
+///
+/// Source : Quaternion kinematics for the error-state Kalman filter, Solà 2017
+pub struct FusedLocalizer {
+    pub config: FusedLocalizerConfig,
+    state: NavState,
+    gyro_bias: Vector3<f64>,
+    accel_bias: Vector3<f64>,
+    cov: SMatrix<f64, N, N>,
+    time: f64,
+    frame: Option<LocalFrame>,
+}
+
+impl FusedLocalizer {
+    pub fn new(
+        config: FusedLocalizerConfig,
+        initial_state: NavState,
+        initial_cov: SMatrix<f64, N, N>,
+    ) -> FusedLocalizer {
+        let mut localizer = FusedLocalizer {
+            config,
+            state: initial_state,
+            gyro_bias: Vector3::zeros(),
+            accel_bias: Vector3::zeros(),
+            cov: initial_cov,
+            time: 0.0,
+            frame: None,
+        };
+        localizer.constrain();
+        localizer
+    }
+
+    /// Origin of the local ENU frame of the GNSS fixes, by default the first fix
+    pub fn set_origin(&mut self, origin: Geodetic) {
+        self.frame = Some(LocalFrame::new(origin));
+    }
+
+    pub fn frame(&self) -> Option<&LocalFrame> {
+        self.frame.as_ref()
+    }
+
+    /// Time integrated from the IMU samples [s]
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    pub fn state(&self) -> &NavState {
+        &self.state
+    }
+
+    pub fn gyro_bias(&self) -> &Vector3<f64> {
+        &self.gyro_bias
+    }
+
+    pub fn accel_bias(&self) -> &Vector3<f64> {
+        &self.accel_bias
+    }
+
+    pub fn covariance(&self) -> &SMatrix<f64, N, N> {
+        &self.cov
+    }
+
+    pub fn pose(&self) -> Isometry3<f64> {
+        Isometry3::from_parts(
+            Translation3::from(self.state.position),
+            UnitQuaternion::from_rotation_matrix(&self.state.rotation),
+        )
+    }
+
+    /// (x, y, yaw) and its covariance, e.g. for the 2D planners or an `EstimateFuser`
+    pub fn pose_2d(&self) -> GaussianState<f64, Const<3>> {
+        let dims = [POSITION, POSITION + 1, ATTITUDE + 2];
+        let (_, _, yaw) = self.state.rotation.euler_angles();
+        GaussianState {
+            x: Vector3::new(self.state.position.x, self.state.position.y, yaw),
+            cov: Matrix3::from_fn(|i, j| self.cov[(dims[i], dims[j])]),
+        }
+    }
+
+    /// Propagates the state by `sample.dt`, the magnetometer is not used
+    pub fn predict(&mut self, sample: &ImuSample) {
+        let dt = sample.dt;
+        if dt <= 0.0 {
+            return;
+        }
+        let r = *self.state.rotation.matrix();
+        let w = sample.gyro - self.gyro_bias;
+        let a = sample.accel - self.accel_bias;
+        let acceleration = r * a + self.config.gravity;
+
+        let mut f = SMatrix::<f64, N, N>::identity();
+        f.fixed_view_mut::<3, 3>(POSITION, VELOCITY)
+            .fill_diagonal(dt);
+        f.fixed_view_mut::<3, 3>(VELOCITY, ATTITUDE)
+            .copy_from(&(-(r * a).cross_matrix() * dt));
+        f.fixed_view_mut::<3, 3>(VELOCITY, ACCEL_BIAS)
+            .copy_from(&(-r * dt));
+        f.fixed_view_mut::<3, 3>(ATTITUDE, GYRO_BIAS)
+            .copy_from(&(-r * dt));
+        let mut q = SVector::<f64, N>::zeros();
+        q.fixed_rows_mut::<3>(VELOCITY)
+            .fill(self.config.imu_noise.accel.powi(2) * dt);
+        q.fixed_rows_mut::<3>(ATTITUDE)
+            .fill(self.config.imu_noise.gyro.powi(2) * dt);
+        q.fixed_rows_mut::<3>(GYRO_BIAS)
+            .fill(self.config.gyro_bias_walk.powi(2) * dt);
+        q.fixed_rows_mut::<3>(ACCEL_BIAS)
+            .fill(self.config.accel_bias_walk.powi(2) * dt);
+        self.cov = f * self.cov * f.transpose() + SMatrix::from_diagonal(&q);
+
+        self.state.position += self.state.velocity * dt + acceleration * (0.5 * dt * dt);
+        self.state.velocity += acceleration * dt;
+        self.state.rotation *= Rotation3::new(w * dt);
+        self.time += dt;
+        self.constrain();
+    }
+
+    /// Velocity in the body frame measured by the wheel odometry [m/s], e.g. (v, 0, 0) for a
+    /// differential drive, and its covariance
+    pub fn update_odometry(&mut self, velocity: &Vector3<f64>, cov: &Matrix3<f64>) {
+        let world_to_body = self.state.rotation.inverse();
+        let mut h = SMatrix::<f64, 3, N>::zeros();
+        h.fixed_view_mut::<3, 3>(0, VELOCITY)
+            .copy_from(world_to_body.matrix());
+        h.fixed_view_mut::<3, 3>(0, ATTITUDE)
+            .copy_from(&(world_to_body.matrix() * self.state.velocity.cross_matrix()));
+        let innovation = velocity - world_to_body * self.state.velocity;
+        let dims = [VELOCITY, VELOCITY + 1, VELOCITY + 2];
+        self.update(&innovation, &h, cov, dims, self.config.odometry_mask);
+    }
+
+    /// Position of the GNSS antenna in the local ENU frame [m] and its covariance
+    pub fn update_position(&mut self, position: &Vector3<f64>, cov: &Matrix3<f64>) {
+        let lever_arm = self.state.rotation * self.config.gnss_lever_arm;
+        let mut h = SMatrix::<f64, 3, N>::zeros();
+        h.fixed_view_mut::<3, 3>(0, POSITION).fill_diagonal(1.0);
+        h.fixed_view_mut::<3, 3>(0, ATTITUDE)
+            .copy_from(&(-lever_arm.cross_matrix()));
+        let innovation = position - (self.state.position + lever_arm);
+        let dims = [POSITION, POSITION + 1, POSITION + 2];
+        self.update(&innovation, &h, cov, dims, self.config.gnss_mask);
+    }
+
+    /// GNSS fix valid at the time of the last IMU sample, the first fix sets the origin of the
+    /// local frame when there is none
+    pub fn update_gnss(&mut self, fix: &GnssFix) {
+        let frame = self
+            .frame
+            .get_or_insert_with(|| LocalFrame::new(fix.position));
+        let position = frame.to_enu(&fix.position);
+        self.update_position(&position, &fix.covariance);
+    }
+
+    /// Orientation (body to world) measured by an AHRS, e.g. an `AttitudeFilter`, and the
+    /// covariance of its rotation vector error in the world frame. Without magnetometer the
+    /// heading of an AHRS drifts and should not be fused
+    pub fn update_orientation(&mut self, orientation: &UnitQuaternion<f64>, cov: &Matrix3<f64>) {
+        let mut h = SMatrix::<f64, 3, N>::zeros();
+        h.fixed_view_mut::<3, 3>(0, ATTITUDE).fill_diagonal(1.0);
+        let innovation =
+            (orientation.to_rotation_matrix() * self.state.rotation.inverse()).scaled_axis();
+        let dims = [ATTITUDE, ATTITUDE + 1, ATTITUDE + 2];
+        self.update(&innovation, &h, cov, dims, self.config.orientation_mask);
+    }
+
+    /// Kalman update with the rows of the measurement whose dimension `dims` is in `mask`, with
+    /// the Joseph form to keep the covariance positive definite
+    fn update<const M: usize>(
+        &mut self,
+        innovation: &SVector<f64, M>,
+        h: &SMatrix<f64, M, N>,
+        r: &SMatrix<f64, M, M>,
+        dims: [usize; M],
+        mask: StateMask,
+    ) {
+        let rows: Vec<usize> = (0..M).filter(|&i| mask.contains(dims[i])).collect();
+        if rows.is_empty() {
+            return;
+        }
+        let m = rows.len();
+        let y = DVector::from_fn(m, |i, _| innovation[rows[i]]);
+        let h = DMatrix::from_fn(m, N, |i, j| h[(rows[i], j)]);
+        let r = DMatrix::from_fn(m, m, |i, j| r[(rows[i], rows[j])]);
+        let p = DMatrix::from_column_slice(N, N, self.cov.as_slice());
+
+        let s = &h * &p * h.transpose() + &r;
+        let Some(s_inv) = s.try_inverse() else {
+            return;
+        };
+        let k = &p * h.transpose() * s_inv;
+        let i_kh = DMatrix::identity(N, N) - &k * &h;
+        let p = &i_kh * p * i_kh.transpose() + &k * r * k.transpose();
+        let cov = SMatrix::<f64, N, N>::from_column_slice(p.as_slice());
+        self.cov = (cov + cov.transpose()) * 0.5;
+        let dx = k * y;
+        self.inject(&SVector::from_column_slice(dx.as_slice()));
+    }
+
+    /// Moves the nominal state by the estimated error, the error is then reset to 0 (the
+    /// jacobian of the reset is the identity to first order)
+    fn inject(&mut self, dx: &SVector<f64, N>) {
+        self.state.position += dx.fixed_rows::<3>(POSITION);
+        self.state.velocity += dx.fixed_rows::<3>(VELOCITY);
+        self.state.rotation =
+            Rotation3::new(dx.fixed_rows::<3>(ATTITUDE).into_owned()) * self.state.rotation;
+        self.gyro_bias += dx.fixed_rows::<3>(GYRO_BIAS);
+        self.accel_bias += dx.fixed_rows::<3>(ACCEL_BIAS);
+        self.constrain();
+    }
+
+    /// Projects the state on the horizontal plane in planar mode
+    fn constrain(&mut self) {
+        if !self.config.planar {
+            return;
+        }
+        self.state.position.z = 0.0;
+        self.state.velocity.z = 0.0;
+        let (_, _, yaw) = self.state.rotation.euler_angles();
+        self.state.rotation = Rotation3::from_euler_angles(0.0, 0.0, yaw);
+        for i in [POSITION + 2, VELOCITY + 2, ATTITUDE, ATTITUDE + 1] {
+            self.cov.row_mut(i).fill(0.0);
+            self.cov.column_mut(i).fill(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Vector2;
+
+    fn initial_cov() -> SMatrix<f64, N, N> {
+        let mut diagonal = SVector::<f64, N>::from_element(1e-2);
+        diagonal.fixed_rows_mut::<3>(GYRO_BIAS).fill(1e-4);
+        diagonal.fixed_rows_mut::<3>(ACCEL_BIAS).fill(1e-2);
+        SMatrix::from_diagonal(&diagonal)
+    }
+
+    /// Drives on a circle with the IMU at 100 Hz, the odometry at 20 Hz and the GNSS at 1 Hz,
+    /// the gyroscope has a bias
+    #[test]
+    fn multi_rate_circle() {
+        let (v, w) = (2.0, 0.2);
+        let gyro_bias = Vector3::new(0.0, 0.0, 0.01);
+        let config = FusedLocalizerConfig {
+            planar: true,
+            ..FusedLocalizerConfig::default()
+        };
+        let initial_state = NavState {
+            rotation: Rotation3::identity(),
+            velocity: Vector3::new(v, 0.0, 0.0),
+            position: Vector3::zeros(),
+        };
+        let mut localizer = FusedLocalizer::new(config, initial_state, initial_cov());
+        localizer.set_origin(Geodetic::new(45.0, 9.0, 0.0));
+        let frame = localizer.frame().unwrap().clone();
+
+        let dt = 0.01;
+        let sample = ImuSample {
+            gyro: Vector3::new(0.0, 0.0, w) + gyro_bias,
+            accel: Vector3::new(0.0, v * w, STANDARD_GRAVITY),
+            mag: None,
+            dt,
+        };
+        for k in 1..=6000 {
+            localizer.predict(&sample);
+            if k % 5 == 0 {
+                let cov = Matrix3::from_diagonal_element(1e-4);
+                localizer.update_odometry(&Vector3::new(v, 0.0, 0.0), &cov);
+            }
+            if k % 100 == 0 {
+                let t = k as f64 * dt;
+                let truth = Vector3::new((w * t).sin(), 1.0 - (w * t).cos(), 0.0) * (v / w);
+                let fix = GnssFix::with_accuracy(t, frame.from_enu(&truth), 0.5, 1.0);
+                localizer.update_gnss(&fix);
+            }
+        }
+        let t = 6000.0 * dt;
+        let truth = Vector3::new((w * t).sin(), 1.0 - (w * t).cos(), 0.0) * (v / w);
+        let pose = localizer.pose_2d();
+        assert!((pose.x.xy() - truth.xy()).norm() < 0.5);
+        let heading_error = pose.x.z - w * t;
+        assert!(heading_error.sin().atan2(heading_error.cos()).abs() < 0.05);
+        assert!((localizer.gyro_bias().z - gyro_bias.z).abs() < 0.003);
+        assert_eq!(localizer.state().position.z, 0.0);
+        assert!(pose.cov.symmetric_eigenvalues().min() > 0.0);
+    }
+
+    #[test]
+    fn masked_dimensions_are_not_fused() {
+        let config = FusedLocalizerConfig {
+            gnss_mask: StateMask::POSITION_X | StateMask::POSITION_Y,
+            ..FusedLocalizerConfig::default()
+        };
+        let initial_state = NavState {
+            rotation: Rotation3::identity(),
+            velocity: Vector3::zeros(),
+            position: Vector3::zeros(),
+        };
+        let mut localizer = FusedLocalizer::new(config, initial_state, initial_cov());
+        let cov = Matrix3::from_diagonal_element(1e-2);
+        localizer.update_position(&Vector3::new(1.0, 2.0, 100.0), &cov);
+        let position = localizer.state().position;
+        approx::assert_relative_eq!(position.xy(), Vector2::new(0.5, 1.0), epsilon = 1e-9);
+        assert_eq!(position.z, 0.0);
+
+        localizer.config.odometry_mask = StateMask::NONE;
+        localizer.update_odometry(&Vector3::new(5.0, 0.0, 0.0), &cov);
+        assert_eq!(localizer.state().velocity, Vector3::zeros());
+    }
+}
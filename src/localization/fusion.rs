@@ -0,0 +1,216 @@
+use nalgebra::{Const, Isometry2, Matrix3, Vector2, Vector3};
+
+use crate::utils::state::GaussianState;
+
+#[derive(Debug, Clone, Copy)]
+pub struct FusionConfig {
+    /// Time [s] without absolute fix after which the fuser is in outage
+    pub outage_timeout: f64,
+    /// Covariance added per second of odometry
+    pub odometry_noise: Matrix3<f64>,
+    /// Covariance added per second of odometry during an outage, on top of `odometry_noise`
+    pub outage_inflation: Matrix3<f64>,
+    /// Time constant [s] of the blending of a correction into the output pose
+    pub blend_time: f64,
+    /// Corrections moving the pose further [m] are applied at once, e.g. after a relocalization
+    pub max_blend_distance: f64,
+}
+
+impl Default for FusionConfig {
+    fn default() -> Self {
+        FusionConfig {
+            outage_timeout: 2.0,
+            odometry_noise: Matrix3::from_diagonal(&Vector3::new(1e-3, 1e-3, 1e-4)),
+            outage_inflation: Matrix3::from_diagonal(&Vector3::new(1e-2, 1e-2, 1e-3)),
+            blend_time: 1.0,
+            max_blend_distance: 2.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FusionStatus {
+    /// No absolute fix received yet
+    Uninitialized,
+    Tracking,
+    /// The last absolute fix is older than `outage_timeout`
+    Outage,
+}
+
+/// Supervisory fusion of a continuous relative source (wheel or visual odometry, in its own
+/// drifting frame) with intermittent absolute fixes (GPS, fiducials, MCL) of the pose (x, y, theta).
+///
+/// The odometry increments are composed on the fused estimate and its covariance grows with
+/// `odometry_noise`, and faster during an outage. A fix is fused with a Kalman update. The jump
+/// of the update is not seen by the controllers: `pose` keeps it as an offset which decays with
+/// the time constant `blend_time`. Fixes are assumed to be valid when they are added, late fixes
+/// should go through a `PoseExtrapolator` first
+pub struct EstimateFuser {
+    pub config: FusionConfig,
+    fused: Option<GaussianState<f64, Const<3>>>,
+    last_odometry: Option<(f64, Isometry2<f64>)>,
+    last_fix_time: Option<f64>,
+    offset: Vector3<f64>,
+}
+
+fn normalize_angle(a: f64) -> f64 {
+    f64::atan2(a.sin(), a.cos())
+}
+
+fn isometry(pose: &Vector3<f64>) -> Isometry2<f64> {
+    Isometry2::new(Vector2::new(pose.x, pose.y), pose.z)
+}
+
+impl EstimateFuser {
+    pub fn new(config: FusionConfig) -> EstimateFuser {
+        EstimateFuser {
+            config,
+            fused: None,
+            last_odometry: None,
+            last_fix_time: None,
+            offset: Vector3::zeros(),
+        }
+    }
+
+    pub fn status(&self, now: f64) -> FusionStatus {
+        match self.last_fix_time {
+            None => FusionStatus::Uninitialized,
+            Some(t) if now - t > self.config.outage_timeout => FusionStatus::Outage,
+            Some(_) => FusionStatus::Tracking,
+        }
+    }
+
+    /// Pose of the odometry at `time`, in the odometry frame
+    pub fn add_odometry(&mut self, time: f64, pose: &Vector3<f64>) {
+        let current = isometry(pose);
+        let Some((last_time, last)) = self.last_odometry.replace((time, current)) else {
+            return;
+        };
+        let dt = (time - last_time).max(0.0);
+        let outage = self.status(time) == FusionStatus::Outage;
+        let Some(fused) = self.fused.as_mut() else {
+            return;
+        };
+        let delta = last.inverse() * current;
+        let moved = isometry(&fused.x) * delta;
+        // jacobian of the composition wrt the fused pose
+        let t = moved.translation.vector - fused.x.xy();
+        #[rustfmt::skip]
+        let j = Matrix3::new(
+            1.0, 0.0, -t.y,
+            0.0, 1.0, t.x,
+            0.0, 0.0, 1.0,
+        );
+        let mut noise = self.config.odometry_noise;
+        if outage {
+            noise += self.config.outage_inflation;
+        }
+        fused.x = Vector3::new(
+            moved.translation.x,
+            moved.translation.y,
+            moved.rotation.angle(),
+        );
+        fused.cov = j * fused.cov * j.transpose() + noise * dt;
+        if self.config.blend_time > 0.0 {
+            self.offset *= (-dt / self.config.blend_time).exp();
+        } else {
+            self.offset = Vector3::zeros();
+        }
+    }
+
+    /// Absolute fix valid at `time`, the first fix initializes the estimate
+    pub fn add_fix(&mut self, time: f64, fix: &GaussianState<f64, Const<3>>) {
+        self.last_fix_time = Some(time);
+        let Some(fused) = self.fused.as_mut() else {
+            self.fused = Some(fix.clone());
+            self.offset = Vector3::zeros();
+            return;
+        };
+        let output = fused.x + self.offset;
+
+        let mut innovation = fix.x - fused.x;
+        innovation.z = normalize_angle(innovation.z);
+        let Some(s_inv) = (fused.cov + fix.cov).try_inverse() else {
+            return;
+        };
+        let k = fused.cov * s_inv;
+        fused.x += k * innovation;
+        fused.x.z = normalize_angle(fused.x.z);
+        fused.cov = (Matrix3::identity() - k) * fused.cov;
+
+        let mut jump = output - fused.x;
+        jump.z = normalize_angle(jump.z);
+        self.offset = if jump.xy().norm() > self.config.max_blend_distance {
+            Vector3::zeros()
+        } else {
+            jump
+        };
+    }
+
+    /// Fused estimate, it jumps at each fix
+    pub fn estimate(&self) -> Option<GaussianState<f64, Const<3>>> {
+        self.fused.clone()
+    }
+
+    /// Smooth pose for the controllers, the fused estimate with the correction not yet blended
+    pub fn pose(&self) -> Option<GaussianState<f64, Const<3>>> {
+        let fused = self.fused.as_ref()?;
+        let mut x = fused.x + self.offset;
+        x.z = normalize_angle(x.z);
+        Some(GaussianState { x, cov: fused.cov })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fix(x: f64, y: f64) -> GaussianState<f64, Const<3>> {
+        GaussianState {
+            x: Vector3::new(x, y, 0.0),
+            cov: Matrix3::identity() * 0.01,
+        }
+    }
+
+    #[test]
+    fn outage_and_smooth_correction() {
+        let mut fuser = EstimateFuser::new(FusionConfig::default());
+        assert_eq!(FusionStatus::Uninitialized, fuser.status(0.0));
+        fuser.add_odometry(0.0, &Vector3::zeros());
+        fuser.add_fix(0.0, &fix(0.0, 0.0));
+        assert!(fuser.pose().is_some());
+
+        // the odometry drifts: it reads 1.1 m/s while the robot moves at 1 m/s
+        let dt = 0.1;
+        let mut last_fix_cov = 0.0;
+        for i in 1..=100 {
+            let t = i as f64 * dt;
+            fuser.add_odometry(t, &Vector3::new(1.1 * t, 0.0, 0.0));
+            if i % 10 == 0 && t <= 3.0 {
+                fuser.add_fix(t, &fix(t, 0.0));
+                last_fix_cov = fuser.estimate().unwrap().cov[(0, 0)];
+            }
+            if t <= 5.0 {
+                continue;
+            }
+            assert_eq!(FusionStatus::Outage, fuser.status(t));
+        }
+        // 7 s of odometry since the last fix, 5 s of them in outage
+        let cov = fuser.estimate().unwrap().cov[(0, 0)];
+        approx::assert_abs_diff_eq!(last_fix_cov + 7e-3 + 5e-2, cov, epsilon = 1e-3);
+
+        // back from the outage, the output does not jump and converges to the estimate
+        let before = fuser.pose().unwrap().x;
+        fuser.add_fix(10.0, &fix(10.0, 0.0));
+        let after = fuser.pose().unwrap().x;
+        approx::assert_abs_diff_eq!(before, after, epsilon = 1e-12);
+        assert!((fuser.estimate().unwrap().x.x - 10.0).abs() < 0.15);
+        assert_eq!(FusionStatus::Tracking, fuser.status(10.0));
+        for i in 1..=50 {
+            let t = 10.0 + i as f64 * dt;
+            fuser.add_odometry(t, &Vector3::new(1.1 * 10.0, 0.0, 0.0));
+        }
+        let pose = fuser.pose().unwrap().x;
+        approx::assert_abs_diff_eq!(fuser.estimate().unwrap().x, pose, epsilon = 0.01);
+    }
+}
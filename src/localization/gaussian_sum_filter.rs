@@ -0,0 +1,338 @@
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use rustc_hash::FxHashMap;
+
+use crate::localization::BayesianFilterKnownCorrespondences;
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::state::GaussianState;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GaussianSumConfig<T> {
+    /// Components lighter than this are removed
+    pub prune_threshold: T,
+    /// Components closer than this squared Mahalanobis distance are merged
+    pub merge_threshold: T,
+    /// The lightest components are removed above this number
+    pub max_components: usize,
+}
+
+impl<T: RealField> Default for GaussianSumConfig<T> {
+    fn default() -> Self {
+        GaussianSumConfig {
+            prune_threshold: T::from_f64(1e-4).unwrap(),
+            merge_threshold: T::from_f64(1.0).unwrap(),
+            max_components: 32,
+        }
+    }
+}
+
+/// Mixture of weighted gaussians, one EKF per component and the weights are updated with the
+/// likelihood of each measurement. Represents the multimodal beliefs of the kidnapped robot or
+/// of a symmetric environment with a few components instead of many particules. The components
+/// are pruned and merged after each update
+///
+/// Source : Alspach & Sorenson, Nonlinear Bayesian estimation using Gaussian sum
+/// approximations, 1972
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct GaussianSumFilter<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    q: OMatrix<T, Z, Z>,
+    landmarks: FxHashMap<u32, OVector<T, S>>,
+    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    components: Vec<(T, GaussianState<T, S>)>,
+    pub config: GaussianSumConfig<T>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> GaussianSumFilter<T, S, Z, U>
+where
+    DefaultAllocator:
+        Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z> + Allocator<T, Const<1>, S>,
+{
+    /// `components` are the weighted hypotheses of the initial state, there should be at least
+    /// one. The weights are normalized
+    pub fn new(
+        q: OMatrix<T, Z, Z>,
+        landmarks: FxHashMap<u32, OVector<T, S>>,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+        motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+        components: Vec<(T, GaussianState<T, S>)>,
+        config: GaussianSumConfig<T>,
+    ) -> GaussianSumFilter<T, S, Z, U> {
+        assert!(
+            !components.is_empty(),
+            "the mixture should have at least one component"
+        );
+        let mut filter = GaussianSumFilter {
+            q,
+            landmarks,
+            measurement_model,
+            motion_model,
+            components,
+            config,
+        };
+        filter.normalize();
+        filter
+    }
+
+    /// Weighted components, the weights sum to 1
+    pub fn components(&self) -> &[(T, GaussianState<T, S>)] {
+        &self.components
+    }
+
+    /// Component with the largest weight
+    pub fn most_likely(&self) -> Option<&GaussianState<T, S>> {
+        self.components
+            .iter()
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(_, state)| state)
+    }
+
+    fn normalize(&mut self) {
+        let total = self
+            .components
+            .iter()
+            .fold(T::zero(), |acc, (w, _)| acc + *w);
+        if total > T::zero() {
+            for (w, _) in self.components.iter_mut() {
+                *w /= total;
+            }
+        }
+    }
+
+    /// Merges the close components into the heaviest one, prunes the light ones. The heaviest
+    /// component is always kept
+    fn reduce(&mut self) {
+        let mut remaining = std::mem::take(&mut self.components);
+        remaining.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+        let kept = 1 + remaining
+            .iter()
+            .skip(1)
+            .take_while(|(w, _)| *w >= self.config.prune_threshold)
+            .count();
+        remaining.truncate(kept);
+        while !remaining.is_empty() {
+            let heaviest = remaining.remove(0);
+            let Some(information) = heaviest.1.cov.clone().try_inverse() else {
+                self.components.push(heaviest);
+                continue;
+            };
+            let (mut close, far): (Vec<_>, Vec<_>) = remaining.into_iter().partition(|(_, s)| {
+                let d = &s.x - &heaviest.1.x;
+                d.dot(&(&information * &d)) < self.config.merge_threshold
+            });
+            remaining = far;
+            close.insert(0, heaviest);
+            self.components.push(merge(&close));
+        }
+        self.components.truncate(self.config.max_components);
+        self.normalize();
+    }
+}
+
+/// Log likelihood of the innovation, up to a constant, and the inverse of its covariance `s`.
+/// None if `s` is not positive definite
+pub(crate) fn log_likelihood<T: RealField + Copy, Z: Dim>(
+    innovation: &OVector<T, Z>,
+    s: OMatrix<T, Z, Z>,
+) -> Option<(T, OMatrix<T, Z, Z>)>
+where
+    DefaultAllocator: Allocator<T, Z> + Allocator<T, Z, Z>,
+{
+    let half = T::from_f64(0.5).unwrap();
+    let cholesky = s.cholesky()?;
+    let log_det = cholesky
+        .l_dirty()
+        .diagonal()
+        .iter()
+        .fold(T::zero(), |acc, l| acc + l.ln() * (half + half));
+    let s_inv = cholesky.inverse();
+    Some((
+        -(innovation.dot(&(&s_inv * innovation)) + log_det) * half,
+        s_inv,
+    ))
+}
+
+/// Moment matching of weighted components, there should be at least one
+pub(crate) fn merge<T: RealField + Copy, S: Dim>(
+    components: &[(T, GaussianState<T, S>)],
+) -> (T, GaussianState<T, S>)
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Const<1>, S>,
+{
+    let weight = components.iter().fold(T::zero(), |acc, (w, _)| acc + *w);
+    let first = &components[0].1;
+    if components.len() == 1 || weight <= T::zero() {
+        return (weight, first.clone());
+    }
+    let mut x = first.x.clone() * T::zero();
+    for (w, state) in components {
+        x += &state.x * *w;
+    }
+    x /= weight;
+    let mut cov = first.cov.clone() * T::zero();
+    for (w, state) in components {
+        let d = &state.x - &x;
+        cov += (&state.cov + &d * d.transpose()) * *w;
+    }
+    cov /= weight;
+    (weight, GaussianState { x, cov })
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilterKnownCorrespondences<T, S, Z, U>
+    for GaussianSumFilter<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, S, Z>
+        + Allocator<T, U, S>
+        + Allocator<T, Const<1>, S>,
+{
+    fn update_estimate(
+        &mut self,
+        control: Option<OVector<T, U>>,
+        measurements: Option<Vec<(u32, OVector<T, Z>)>>,
+        dt: T,
+    ) {
+        let mut log_weights: Vec<T> = self.components.iter().map(|(w, _)| w.ln()).collect();
+        for ((_, state), log_weight) in self.components.iter_mut().zip(log_weights.iter_mut()) {
+            if let Some(u) = &control {
+                let g = self.motion_model.jacobian_wrt_state(&state.x, u, dt);
+                let v = self.motion_model.jacobian_wrt_input(&state.x, u, dt);
+                let m = self.motion_model.cov_noise_control_space(u);
+                state.x = self.motion_model.prediction(&state.x, u, dt);
+                state.cov = &g * &state.cov * g.transpose() + &v * m * v.transpose();
+            }
+            let Some(measurements) = &measurements else {
+                continue;
+            };
+            let shape = state.cov.shape_generic();
+            for (id, z) in measurements {
+                let Some(landmark) = self.landmarks.get(id) else {
+                    continue;
+                };
+                let z_pred = self.measurement_model.prediction(&state.x, Some(landmark));
+                let h = self.measurement_model.jacobian(&state.x, Some(landmark));
+                let s = &h * &state.cov * h.transpose() + &self.q;
+                let innovation = z - z_pred;
+                let Some((log_likelihood, s_inv)) = log_likelihood(&innovation, s) else {
+                    continue;
+                };
+                *log_weight += log_likelihood;
+                let kalman_gain = &state.cov * h.transpose() * s_inv;
+                state.x += &kalman_gain * innovation;
+                state.cov =
+                    (OMatrix::identity_generic(shape.0, shape.1) - kalman_gain * h) * &state.cov;
+            }
+        }
+        if measurements.is_some() {
+            let max = log_weights
+                .iter()
+                .fold(T::min_value().unwrap(), |acc, w| acc.max(*w));
+            for ((w, _), log_weight) in self.components.iter_mut().zip(log_weights) {
+                *w = (log_weight - max).exp();
+            }
+            self.normalize();
+        }
+        self.reduce();
+    }
+
+    /// Moment matched gaussian of the mixture, see `components` for the modes
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        merge(&self.components).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::measurement::RangeBearingMeasurementModel;
+    use crate::models::motion::Velocity;
+    use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+
+    fn state(x: f64, y: f64) -> GaussianState<f64, Const<3>> {
+        GaussianState {
+            x: Vector3::new(x, y, 0.0),
+            cov: Matrix3::identity() * 0.05,
+        }
+    }
+
+    #[test]
+    fn resolve_two_hypotheses() {
+        let landmarks: FxHashMap<u32, Vector3<f64>> =
+            [(1, Vector3::new(5.0, 5.0, 0.0))].into_iter().collect();
+        let mut filter = GaussianSumFilter::new(
+            Matrix2::from_diagonal(&Vector2::new(0.01, 0.001)),
+            landmarks.clone(),
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.01; 6]),
+            // two symmetric hypotheses and a duplicate merged at the first update
+            vec![
+                (1.0, state(0.0, 0.0)),
+                (1.0, state(0.05, 0.0)),
+                (2.0, state(10.0, 0.0)),
+            ],
+            GaussianSumConfig::default(),
+        );
+        filter.update_estimate(None, None, 0.1);
+        assert_eq!(2, filter.components().len());
+        approx::assert_abs_diff_eq!(0.5, filter.components()[0].0, epsilon = 1e-12);
+        // the mixture mean is between the modes
+        approx::assert_abs_diff_eq!(5.0125, filter.gaussian_estimate().x.x, epsilon = 1e-9);
+
+        // the robot is at the origin
+        let model = RangeBearingMeasurementModel::new();
+        let truth = Vector3::zeros();
+        let z = model.prediction(&truth, landmarks.get(&1));
+        filter.update_estimate(None, Some(vec![(1, z)]), 0.1);
+        assert_eq!(1, filter.components().len());
+        let estimate = filter.most_likely().unwrap();
+        approx::assert_abs_diff_eq!(truth, estimate.x, epsilon = 0.05);
+    }
+
+    #[test]
+    fn keep_the_heaviest_component() {
+        let mut filter = GaussianSumFilter::new(
+            Matrix2::identity(),
+            FxHashMap::default(),
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.01; 6]),
+            vec![
+                (1.0, state(0.0, 0.0)),
+                (1.1, state(10.0, 0.0)),
+                (1.0, state(0.0, 10.0)),
+            ],
+            GaussianSumConfig {
+                prune_threshold: 0.5,
+                ..Default::default()
+            },
+        );
+        // all the components are lighter than the threshold
+        filter.update_estimate(None, None, 0.1);
+        assert_eq!(1, filter.components().len());
+        assert_eq!(1.0, filter.components()[0].0);
+        approx::assert_abs_diff_eq!(10.0, filter.gaussian_estimate().x.x);
+    }
+
+    #[test]
+    #[should_panic(expected = "the mixture should have at least one component")]
+    fn empty_mixture() {
+        GaussianSumFilter::<f64, Const<3>, Const<2>, Const<2>>::new(
+            Matrix2::identity(),
+            FxHashMap::default(),
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.01; 6]),
+            Vec::new(),
+            GaussianSumConfig::default(),
+        );
+    }
+}
@@ -0,0 +1,342 @@
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use rand::distributions::Distribution;
+use rand_distr::StandardNormal;
+
+use crate::localization::bayesian_filter::BayesianFilter;
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::mvn::MultiVariateNormal;
+#[cfg(feature = "serde-serialize")]
+use crate::utils::persistence;
+use crate::utils::state::GaussianState;
+#[cfg(feature = "serde-serialize")]
+use std::error::Error;
+
+/// Discrete Bayes filter over a regular grid of the state space
+///
+/// Probabilistic Robotics p. 86
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct HistogramFilter<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+{
+    r: OMatrix<T, S, S>,
+    measurement_noise: MultiVariateNormal<T, Z>,
+    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    lower: OVector<T, S>,
+    resolution: OVector<T, S>,
+    shape: Vec<usize>,
+    pub histogram: Vec<T>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> HistogramFilter<T, S, Z, U>
+where
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    /// The grid spans `[lower, upper]` with cells of size `resolution` in each dimension,
+    /// the initial belief is `initial_state` evaluated at the cell centers. The motion noise
+    /// `r` is applied one dimension at a time so it must be diagonal
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+        motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+        initial_state: GaussianState<T, S>,
+        lower: OVector<T, S>,
+        upper: OVector<T, S>,
+        resolution: OVector<T, S>,
+    ) -> HistogramFilter<T, S, Z, U> {
+        assert!(
+            r == OMatrix::from_diagonal(&r.diagonal()),
+            "r should be diagonal"
+        );
+        let shape = (&upper - &lower)
+            .component_div(&resolution)
+            .iter()
+            .map(|n| to_usize(n.ceil()).max(1))
+            .collect::<Vec<usize>>();
+        let len = shape.iter().product();
+
+        let mut filter = HistogramFilter {
+            r,
+            measurement_noise: MultiVariateNormal::zero_mean(&q).unwrap(),
+            measurement_model,
+            motion_model,
+            lower,
+            resolution,
+            shape,
+            histogram: vec![T::zero(); len],
+        };
+
+        let mvn = MultiVariateNormal::new(&initial_state.x, &initial_state.cov).unwrap();
+        for i in 0..len {
+            filter.histogram[i] = mvn.pdf(&filter.cell_center(i));
+        }
+        normalize(&mut filter.histogram);
+        filter
+    }
+
+    /// Center of the cell at the flat index `index`
+    pub fn cell_center(&self, index: usize) -> OVector<T, S> {
+        let mut center = self.lower.clone();
+        let mut rest = index;
+        for (d, n) in self.shape.iter().enumerate() {
+            let i = rest % n;
+            rest /= n;
+            center[d] +=
+                (T::from_usize(i).unwrap() + T::from_f64(0.5).unwrap()) * self.resolution[d];
+        }
+        center
+    }
+
+    /// Flat index of the cell containing `x`, None if `x` is outside of the grid
+    pub fn cell_index(&self, x: &OVector<T, S>) -> Option<usize> {
+        let mut index = 0;
+        let mut stride = 1;
+        for (d, n) in self.shape.iter().enumerate() {
+            let i = ((x[d] - self.lower[d]) / self.resolution[d]).floor();
+            if i < T::zero() || i >= T::from_usize(*n).unwrap() {
+                return None;
+            }
+            index += to_usize(i) * stride;
+            stride *= n;
+        }
+        Some(index)
+    }
+
+    /// Convolve the histogram with the (diagonal) motion noise, one dimension at a time
+    fn blur(&mut self) {
+        let mut stride = 1;
+        for (d, &n) in self.shape.iter().enumerate() {
+            let sigma = self.r[(d, d)].sqrt() / self.resolution[d];
+            if sigma > T::zero() {
+                let radius = to_usize((sigma * T::from_f64(3.0).unwrap()).ceil());
+                let kernel: Vec<T> = (0..=radius)
+                    .map(|k| {
+                        let k = T::from_usize(k).unwrap() / sigma;
+                        T::exp(T::from_f64(-0.5).unwrap() * k * k)
+                    })
+                    .collect();
+
+                let mut blurred = vec![T::zero(); self.histogram.len()];
+                for (i, &p) in self.histogram.iter().enumerate() {
+                    if p == T::zero() {
+                        continue;
+                    }
+                    let pos = (i / stride) % n;
+                    let lo = pos.saturating_sub(radius);
+                    let hi = (pos + radius).min(n - 1);
+                    let norm = (lo..=hi).fold(T::zero(), |a, j| a + kernel[pos.abs_diff(j)]);
+                    for j in lo..=hi {
+                        blurred[i + j * stride - pos * stride] +=
+                            p * kernel[pos.abs_diff(j)] / norm;
+                    }
+                }
+                self.histogram = blurred;
+            }
+            stride *= n;
+        }
+    }
+}
+
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, S: Dim, Z: Dim, U: Dim> HistogramFilter<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the histogram as JSON, the grid and the models are not saved
+    pub fn save_state<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        persistence::save(writer, &self.histogram)
+    }
+
+    /// Restores a histogram written by `save_state`, the grid must have the same shape
+    pub fn load_state<R: std::io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        let histogram: Vec<T> = persistence::load(reader)?;
+        if histogram.len() != self.histogram.len() {
+            return Err(format!(
+                "the saved histogram has {} cells but the grid has {}",
+                histogram.len(),
+                self.histogram.len()
+            )
+            .into());
+        }
+        self.histogram = histogram;
+        Ok(())
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilter<T, S, Z, U>
+    for HistogramFilter<T, S, Z, U>
+where
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
+        // predict
+        let mut predicted = vec![T::zero(); self.histogram.len()];
+        for (i, &p) in self.histogram.iter().enumerate() {
+            if p == T::zero() {
+                continue;
+            }
+            let x = self.motion_model.prediction(&self.cell_center(i), u, dt);
+            if let Some(j) = self.cell_index(&x) {
+                predicted[j] += p;
+            }
+        }
+        self.histogram = predicted;
+        self.blur();
+
+        // update
+        let prior = self.histogram.clone();
+        for i in 0..self.histogram.len() {
+            if self.histogram[i] == T::zero() {
+                continue;
+            }
+            let z_pred = self
+                .measurement_model
+                .prediction(&self.cell_center(i), None);
+            self.histogram[i] *= self.measurement_noise.pdf(&(z - z_pred));
+        }
+
+        // the measurement is inconsistent with the whole grid, keep the prediction
+        if !normalize(&mut self.histogram) {
+            self.histogram = prior;
+            normalize(&mut self.histogram);
+        }
+    }
+
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        let shape = self.lower.shape_generic();
+        let x = self
+            .histogram
+            .iter()
+            .enumerate()
+            .filter(|(_, &p)| p > T::zero())
+            .fold(OMatrix::zeros_generic(shape.0, shape.1), |a, (i, &p)| {
+                a + self.cell_center(i) * p
+            });
+        let cov = self
+            .histogram
+            .iter()
+            .enumerate()
+            .filter(|(_, &p)| p > T::zero())
+            .map(|(i, &p)| (self.cell_center(i) - &x, p))
+            .fold(OMatrix::zeros_generic(shape.0, shape.0), |a, (dx, p)| {
+                a + &dx * dx.transpose() * p
+            });
+        GaussianState { x, cov }
+    }
+}
+
+/// Returns false if the histogram has no mass left
+fn normalize<T: RealField + Copy>(histogram: &mut [T]) -> bool {
+    let total = histogram.iter().fold(T::zero(), |a, b| a + *b);
+    if total <= T::zero() || !total.is_finite() {
+        return false;
+    }
+    histogram.iter_mut().for_each(|p| *p /= total);
+    true
+}
+
+fn to_usize<T: RealField>(x: T) -> usize {
+    let x: f64 = x.to_subset().unwrap();
+    x as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix1, Vector1};
+
+    /// Position on a line driven by the velocity input
+    struct Line;
+
+    impl MotionModel<f64, Const<1>, Const<1>, Const<1>> for Line {
+        fn prediction(&self, x: &Vector1<f64>, u: &Vector1<f64>, dt: f64) -> Vector1<f64> {
+            x + u * dt
+        }
+        fn jacobian_wrt_state(
+            &self,
+            _x: &Vector1<f64>,
+            _u: &Vector1<f64>,
+            _dt: f64,
+        ) -> Matrix1<f64> {
+            Matrix1::identity()
+        }
+        fn jacobian_wrt_input(
+            &self,
+            _x: &Vector1<f64>,
+            _u: &Vector1<f64>,
+            dt: f64,
+        ) -> Matrix1<f64> {
+            Matrix1::new(dt)
+        }
+        fn cov_noise_control_space(&self, _u: &Vector1<f64>) -> Matrix1<f64> {
+            Matrix1::zeros()
+        }
+        fn sample(&self, x: &Vector1<f64>, u: &Vector1<f64>, dt: f64) -> Vector1<f64> {
+            self.prediction(x, u, dt)
+        }
+    }
+
+    /// Measurement = position
+    struct Position;
+
+    impl MeasurementModel<f64, Const<1>, Const<1>> for Position {
+        fn prediction(&self, x: &Vector1<f64>, _landmark: Option<&Vector1<f64>>) -> Vector1<f64> {
+            *x
+        }
+        fn jacobian(&self, _x: &Vector1<f64>, _landmark: Option<&Vector1<f64>>) -> Matrix1<f64> {
+            Matrix1::identity()
+        }
+    }
+
+    #[test]
+    fn belief_converges_on_a_line() {
+        let mut filter: HistogramFilter<f64, Const<1>, Const<1>, Const<1>> = HistogramFilter::new(
+            Matrix1::new(0.01),
+            Matrix1::new(0.25),
+            Box::new(Position),
+            Box::new(Line),
+            GaussianState {
+                x: Vector1::new(5.0),
+                cov: Matrix1::new(4.0),
+            },
+            Vector1::new(0.0),
+            Vector1::new(20.0),
+            Vector1::new(0.1),
+        );
+        let u = Vector1::new(1.0);
+        let mut position = 2.0;
+        let prior_variance = filter.gaussian_estimate().cov[0];
+        for i in 0..10 {
+            position += 1.0;
+            // measurements alternating around the true position
+            let z = Vector1::new(position + if i % 2 == 0 { 0.2 } else { -0.2 });
+            filter.update_estimate(&u, &z, 1.0);
+        }
+        let estimate = filter.gaussian_estimate();
+        approx::assert_abs_diff_eq!(estimate.x[0], position, epsilon = 0.2);
+        assert!(estimate.cov[0] < 0.1 && estimate.cov[0] < prior_variance);
+        approx::assert_relative_eq!(filter.histogram.iter().sum::<f64>(), 1.0, epsilon = 1e-9);
+    }
+}
@@ -0,0 +1,217 @@
+use nalgebra::{
+    allocator::Allocator, Const, DMatrix, DefaultAllocator, Dim, OMatrix, OVector, RealField,
+};
+
+use crate::localization::gaussian_sum_filter::{log_likelihood, merge};
+use crate::localization::BayesianFilter;
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::state::GaussianState;
+
+/// Interacting Multiple Model estimator, one EKF per motion model (mode) and the regime of the
+/// target switches between the modes as a Markov chain. At each step the estimates of the modes
+/// are mixed with the switching probabilities before the prediction, and the probabilities of
+/// the modes are updated with the likelihood of the measurement
+///
+/// Source : Bar-Shalom, Li & Kirubarajan, Estimation with Applications to Tracking and
+/// Navigation, 2001, ch. 11.6
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct InteractingMultipleModel<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    q: OMatrix<T, Z, Z>,
+    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+    motion_models: Vec<Box<dyn MotionModel<T, S, Z, U> + Send>>,
+    r: Vec<OMatrix<T, S, S>>,
+    /// (i, j) is the probability to switch from the mode i to the mode j
+    transition: DMatrix<T>,
+    probabilities: Vec<T>,
+    states: Vec<GaussianState<T, S>>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> InteractingMultipleModel<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    /// `modes` are the motion models and their process noise `r`, they start with the same
+    /// probability at `initial_state`. The rows of `transition` sum to 1
+    #[allow(clippy::type_complexity)]
+    pub fn new(
+        q: OMatrix<T, Z, Z>,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+        modes: Vec<(Box<dyn MotionModel<T, S, Z, U> + Send>, OMatrix<T, S, S>)>,
+        transition: DMatrix<T>,
+        initial_state: GaussianState<T, S>,
+    ) -> InteractingMultipleModel<T, S, Z, U> {
+        let n = modes.len();
+        assert!(n > 0, "at least one mode is needed");
+        assert_eq!(
+            (n, n),
+            transition.shape(),
+            "the transition matrix should be square with one row per mode"
+        );
+        let (motion_models, r) = modes.into_iter().unzip();
+        InteractingMultipleModel {
+            q,
+            measurement_model,
+            motion_models,
+            r,
+            transition,
+            probabilities: vec![T::one() / T::from_usize(n).unwrap(); n],
+            states: vec![initial_state; n],
+        }
+    }
+
+    /// Probability of each mode, in the order of `new`
+    pub fn mode_probabilities(&self) -> &[T] {
+        &self.probabilities
+    }
+
+    /// Estimate of each mode, in the order of `new`
+    pub fn mode_estimates(&self) -> &[GaussianState<T, S>] {
+        &self.states
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilter<T, S, Z, U>
+    for InteractingMultipleModel<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, S, Z>
+        + Allocator<T, Const<1>, S>,
+{
+    fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
+        let n = self.states.len();
+        // mixing
+        let predicted: Vec<T> = (0..n)
+            .map(|j| {
+                (0..n).fold(T::zero(), |acc, i| {
+                    acc + self.transition[(i, j)] * self.probabilities[i]
+                })
+            })
+            .collect();
+        let mixed: Vec<GaussianState<T, S>> = (0..n)
+            .map(|j| {
+                if predicted[j] <= T::zero() {
+                    return self.states[j].clone();
+                }
+                let weighted: Vec<_> = (0..n)
+                    .map(|i| {
+                        let w = self.transition[(i, j)] * self.probabilities[i] / predicted[j];
+                        (w, self.states[i].clone())
+                    })
+                    .collect();
+                merge(&weighted).1
+            })
+            .collect();
+
+        // EKF of each mode
+        let mut log_likelihoods = vec![T::zero(); n];
+        for (j, mut state) in mixed.into_iter().enumerate() {
+            let g = self.motion_models[j].jacobian_wrt_state(&state.x, u, dt);
+            state.x = self.motion_models[j].prediction(&state.x, u, dt);
+            state.cov = &g * &state.cov * g.transpose() + &self.r[j];
+
+            let h = self.measurement_model.jacobian(&state.x, None);
+            let z_pred = self.measurement_model.prediction(&state.x, None);
+            let s = &h * &state.cov * h.transpose() + &self.q;
+            let innovation = z - z_pred;
+            if let Some((log_likelihood, s_inv)) = log_likelihood(&innovation, s) {
+                log_likelihoods[j] = log_likelihood;
+                let kalman_gain = &state.cov * h.transpose() * s_inv;
+                state.x += &kalman_gain * innovation;
+                let shape = state.cov.shape_generic();
+                state.cov =
+                    (OMatrix::identity_generic(shape.0, shape.1) - kalman_gain * h) * &state.cov;
+            }
+            self.states[j] = state;
+        }
+
+        // mode probabilities
+        let max = log_likelihoods
+            .iter()
+            .fold(T::min_value().unwrap(), |acc, l| acc.max(*l));
+        for j in 0..n {
+            self.probabilities[j] = predicted[j] * (log_likelihoods[j] - max).exp();
+        }
+        let total = self.probabilities.iter().fold(T::zero(), |acc, p| acc + *p);
+        if total > T::zero() {
+            for p in self.probabilities.iter_mut() {
+                *p /= total;
+            }
+        }
+    }
+
+    /// Mixture of the estimates of the modes
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        let weighted: Vec<_> = self
+            .probabilities
+            .iter()
+            .copied()
+            .zip(self.states.iter().cloned())
+            .collect();
+        merge(&weighted).1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::measurement::PositionMeasurementModel;
+    use crate::models::motion::{ConstantVelocity, CoordinatedTurn};
+    use nalgebra::{Matrix2, Matrix5, Vector1, Vector2, Vector5};
+    use rand::SeedableRng;
+    use rand_distr::{Distribution, Normal};
+
+    #[test]
+    fn track_a_maneuvering_target() {
+        let dt = 0.1;
+        let r_cv = Matrix5::from_diagonal(&Vector5::new(1e-4, 1e-4, 1e-5, 1e-2, 1e-6));
+        let r_ct = Matrix5::from_diagonal(&Vector5::new(1e-4, 1e-4, 1e-4, 1e-2, 1e-2));
+        let mut imm = InteractingMultipleModel::<f64, _, _, _>::new(
+            Matrix2::identity() * 0.01,
+            PositionMeasurementModel::new(),
+            vec![
+                (ConstantVelocity::new() as Box<_>, r_cv),
+                (CoordinatedTurn::new() as Box<_>, r_ct),
+            ],
+            DMatrix::from_row_slice(2, 2, &[0.95, 0.05, 0.05, 0.95]),
+            GaussianState {
+                x: Vector5::new(0., 0., 0., 2., 0.),
+                cov: Matrix5::identity() * 0.1,
+            },
+        );
+
+        let mut rng = rand::rngs::StdRng::seed_from_u64(7);
+        let noise = Normal::new(0., 0.1).unwrap();
+        let mut truth = Vector5::new(0., 0., 0., 2., 0.);
+        let u = Vector1::zeros();
+        for step in 0..300 {
+            // straight, turn at 0.5 rad/s, straight
+            truth[4] = if (100..200).contains(&step) { 0.5 } else { 0. };
+            truth = CoordinatedTurn.prediction(&truth, &u, dt);
+            let z = Vector2::new(
+                truth[0] + noise.sample(&mut rng),
+                truth[1] + noise.sample(&mut rng),
+            );
+            imm.update_estimate(&u, &z, dt);
+            if step == 90 || step == 290 {
+                assert!(imm.mode_probabilities()[0] > 0.5);
+            }
+            if step == 190 {
+                assert!(imm.mode_probabilities()[1] > 0.5);
+            }
+            let estimate = imm.gaussian_estimate();
+            assert!((estimate.x.xy() - truth.xy()).norm() < 0.3);
+        }
+    }
+}
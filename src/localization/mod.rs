@@ -1,9 +1,44 @@
+mod attitude;
 mod bayesian_filter;
+mod builder;
+mod cubature_kalman_filter;
+mod diagnostics;
+mod ensemble_kalman_filter;
 mod extended_kalman_filter;
+mod fixed_lag_smoother;
+mod fused_localizer;
+mod fusion;
+mod gaussian_sum_filter;
+mod histogram_filter;
+mod imm;
 mod particle_filter;
+mod pose_extrapolator;
+mod relocalization;
+mod square_root_kalman_filter;
 mod unscented_kalman_filter;
+mod warm_start;
 
+pub use attitude::{AttitudeFilter, ComplementaryFilter, ImuSample, Madgwick, Mahony};
 pub use bayesian_filter::{BayesianFilter, BayesianFilterKnownCorrespondences};
-pub use extended_kalman_filter::{ExtendedKalmanFilter, ExtendedKalmanFilterKnownCorrespondences};
-pub use particle_filter::{ParticleFilter, ParticleFilterKnownCorrespondences, ResamplingScheme};
+pub use builder::{BuilderError, EkfBuilder, ParticleFilterBuilder};
+pub use cubature_kalman_filter::CubatureKalmanFilter;
+pub use diagnostics::{effective_sample_size, FilterEvent, FilterObserver};
+pub use ensemble_kalman_filter::EnsembleKalmanFilter;
+pub use extended_kalman_filter::{
+    CovarianceUpdate, ExtendedKalmanFilter, ExtendedKalmanFilterKnownCorrespondences,
+};
+pub use fixed_lag_smoother::FixedLagSmoother;
+pub use fused_localizer::{FusedLocalizer, FusedLocalizerConfig, StateMask};
+pub use fusion::{EstimateFuser, FusionConfig, FusionStatus};
+pub use gaussian_sum_filter::{GaussianSumConfig, GaussianSumFilter};
+pub use histogram_filter::HistogramFilter;
+pub use imm::InteractingMultipleModel;
+pub use particle_filter::{
+    LogLikelihoods, Parallelism, ParticleFilter, ParticleFilterKnownCorrespondences,
+    ParticleFilterVariant, ResamplingScheme,
+};
+pub use pose_extrapolator::PoseExtrapolator;
+pub use relocalization::{relocalize, PoseCandidate, RelocalizationConfig};
+pub use square_root_kalman_filter::SquareRootExtendedKalmanFilter;
 pub use unscented_kalman_filter::UnscentedKalmanFilter;
+pub use warm_start::{Belief, WarmStartConfig};
@@ -1,33 +1,126 @@
 #![allow(dead_code)] // TODO: remove this
 use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
 use rand::distributions::Distribution;
-use rand::Rng;
+use rand::{Rng, RngCore};
 use rand_distr::{Standard, StandardNormal};
+use rayon::prelude::*;
 use rustc_hash::FxHashMap;
 
 use crate::localization::bayesian_filter::{BayesianFilter, BayesianFilterKnownCorrespondences};
+use crate::localization::diagnostics::{
+    self, effective_sample_size, FilterEvent, FilterObserver, Observer,
+};
+use crate::localization::warm_start::{Belief, WarmStartConfig};
 use crate::models::measurement::MeasurementModel;
 use crate::models::motion::MotionModel;
-use crate::utils::mvn::MultiVariateNormal;
+use crate::utils::mvn::{self, MultiVariateNormal};
+#[cfg(feature = "serde-serialize")]
+use crate::utils::persistence;
+use crate::utils::rng::Philox;
 use crate::utils::state::GaussianState;
+#[cfg(feature = "serde-serialize")]
+use std::error::Error;
+use std::marker::PhantomData;
+use std::time::Instant;
 
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
 pub enum ResamplingScheme {
     IID,
     Stratified,
+    #[default]
     Systematic,
 }
 
-/// S : State Size, Z: Observation Size, U: Input Size
-pub struct ParticleFilter<T: RealField, S: Dim, Z: Dim, U: Dim>
+/// How the particules are processed in `update_estimate`, both give the same particules
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Parallelism {
+    #[default]
+    Sequential,
+    /// On the rayon thread pool in chunks of fixed size, the reductions stay sequential so the
+    /// results do not depend on the number of threads. The models are shared between the
+    /// threads so `set_parallelism` needs them to be `Sync`
+    Rayon,
+}
+
+/// How the particules are propagated and weighted in `update_estimate`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ParticleFilterVariant {
+    /// Propagated with the motion noise and weighted by the measurement likelihood
+    #[default]
+    Bootstrap,
+    /// The particules whose predicted mean matches the measurement are propagated more often,
+    /// the weights are then divided by the likelihood of the predicted mean
+    ///
+    /// Source : Filtering via Simulation: Auxiliary Particle Filters, Pitt & Shephard
+    Auxiliary,
+    /// The resampled particules are moved by a Gaussian kernel of the particules covariance
+    /// so the duplicates spread again
+    ///
+    /// Source : Improving Regularised Particle Filters, Musso, Oudjane & Le Gland
+    Regularized,
+}
+
+const CHUNK_SIZE: usize = 64;
+/// The kernel draws of the particule `i` come from the stream `KERNEL_STREAMS + i`, after all
+/// the particule and resampling streams
+const KERNEL_STREAMS: u64 = 1 << 32;
+
+/// Moves the particule `i` with the models, returns it with its innovation
+type ParticuleFn<'a, T, S, Z, H, M> =
+    dyn Fn(&H, &M, usize, &OVector<T, S>) -> (OVector<T, S>, OVector<T, Z>) + Sync + 'a;
+/// `map_sequential` or `map_rayon`, the latter is only chosen by `set_parallelism` where the
+/// models are known to be `Sync`
+type ModelMap<T, S, Z, H, M> = for<'a> fn(
+    &'a [OVector<T, S>],
+    &'a H,
+    &'a M,
+    &'a ParticuleFn<'a, T, S, Z, H, M>,
+) -> Vec<(OVector<T, S>, OVector<T, Z>)>;
+
+/// Each particule draws from its own Philox stream keyed by (seed, particule index, step), the
+/// resampling from the stream of index `num_particules` and the auxiliary resampling from the
+/// next one, so changing how one of them is drawn does not shift the draws of the others
+pub(crate) fn initial_particules<T: RealField + Copy, S: Dim>(
+    mvn: &MultiVariateNormal<T, S>,
+    num_particules: usize,
+    seed: u64,
+) -> Vec<OVector<T, S>>
 where
-    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Const<1>, S>,
 {
-    r: OMatrix<T, S, S>,
-    q: OMatrix<T, Z, Z>,
-    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
-    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    (0..num_particules)
+        .map(|i| mvn.sample_with_rng(&mut Philox::for_particule(seed, i as u64, 0)))
+        .collect()
+}
+
+/// S : State Size, Z: Observation Size, U: Input Size, H: Measurement Model, M: Motion Model
+///
+/// The models are boxed by default, `with_models` takes them by value so their calls are
+/// statically dispatched and can be inlined
+pub struct ParticleFilter<
+    T: RealField,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    H = Box<dyn MeasurementModel<T, S, Z> + Send>,
+    M = Box<dyn MotionModel<T, S, Z, U> + Send>,
+> where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+{
+    motion_noise: MultiVariateNormal<T, S>,
+    measurement_noise: MultiVariateNormal<T, Z>,
+    measurement_model: H,
+    motion_model: M,
     pub particules: Vec<OVector<T, S>>,
     resampling_scheme: ResamplingScheme,
+    variant: ParticleFilterVariant,
+    parallelism: Parallelism,
+    model_map: ModelMap<T, S, Z, H, M>,
+    seed: u64,
+    step: u64,
+    observer: Observer<T>,
+    _input: PhantomData<U>,
 }
 
 impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> ParticleFilter<T, S, Z, U>
@@ -36,6 +129,7 @@ where
     Standard: Distribution<T>,
     DefaultAllocator: Allocator<T, S>
         + Allocator<T, S, S>
+        + Allocator<T, Z>
         + Allocator<T, Z, Z>
         + Allocator<T, Const<1>, S>
         + Allocator<T, Const<1>, Z>,
@@ -49,26 +143,149 @@ where
         num_particules: usize,
         resampling_scheme: ResamplingScheme,
     ) -> ParticleFilter<T, S, Z, U> {
-        let mvn = MultiVariateNormal::new(&initial_state.x, &r).unwrap();
-        let mut particules = Vec::with_capacity(num_particules);
-        for _ in 0..num_particules {
-            particules.push(mvn.sample());
-        }
-
-        ParticleFilter {
+        ParticleFilter::with_models(
             r,
             q,
             measurement_model,
             motion_model,
+            initial_state,
+            num_particules,
+            resampling_scheme,
+        )
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> ParticleFilter<T, S, Z, U, H, M>
+where
+    StandardNormal: Distribution<T>,
+    Standard: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    pub fn with_models(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: H,
+        motion_model: M,
+        initial_state: GaussianState<T, S>,
+        num_particules: usize,
+        resampling_scheme: ResamplingScheme,
+    ) -> ParticleFilter<T, S, Z, U, H, M> {
+        let seed = rand::thread_rng().next_u64();
+        let motion_noise = MultiVariateNormal::zero_mean(&r).unwrap();
+        let measurement_noise = MultiVariateNormal::zero_mean(&q).unwrap();
+        let particules = initial_particules(
+            &motion_noise.with_mean(&initial_state.x),
+            num_particules,
+            seed,
+        );
+
+        ParticleFilter {
+            motion_noise,
+            measurement_noise,
+            measurement_model,
+            motion_model,
             particules,
             resampling_scheme,
+            variant: ParticleFilterVariant::default(),
+            parallelism: Parallelism::default(),
+            model_map: map_sequential,
+            seed,
+            step: 0,
+            observer: None,
+            _input: PhantomData,
         }
     }
+
+    pub fn set_variant(&mut self, variant: ParticleFilterVariant) {
+        self.variant = variant;
+    }
+
+    /// Noise of the next measurements, e.g. the covariance reported with each GNSS fix. The
+    /// previous noise is kept if `q` is not positive semi-definite
+    pub fn set_measurement_noise(&mut self, q: &OMatrix<T, Z, Z>) -> Result<(), mvn::Error> {
+        self.measurement_noise = MultiVariateNormal::zero_mean(q)?;
+        Ok(())
+    }
+
+    /// The seed is random by default, the particules are drawn again around `initial_state.x`
+    /// with the motion noise `r`, like in `new`, so the whole run can be replayed. The
+    /// covariance of `initial_state` is not used
+    pub fn set_seed(&mut self, seed: u64, initial_state: &GaussianState<T, S>) {
+        let mvn = self.motion_noise.with_mean(&initial_state.x);
+        self.particules = initial_particules(&mvn, self.particules.len(), seed);
+        self.seed = seed;
+        self.step = 0;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Receives the effective sample sizes, the resampling events and the update times
+    pub fn set_observer(&mut self, observer: impl FilterObserver<T> + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Belief to save at shutdown, at most `max_particules` particules
+    pub fn snapshot(&self, max_particules: usize) -> Belief<T, S> {
+        Belief::decimated(&self.particules, max_particules)
+    }
+
+    /// Restarts from the belief of a previous session instead of a global relocalization
+    pub fn warm_start(&mut self, belief: &Belief<T, S>, config: &WarmStartConfig<T, S>) {
+        self.step += 1;
+        self.particules = belief.sample(self.particules.len(), config, self.seed, self.step);
+    }
+}
+
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H: Sync, M: Sync> ParticleFilter<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+    OVector<T, S>: Send + Sync,
+    OVector<T, Z>: Send,
+{
+    pub fn set_parallelism(&mut self, parallelism: Parallelism) {
+        self.model_map = match parallelism {
+            Parallelism::Sequential => map_sequential,
+            Parallelism::Rayon => map_rayon,
+        };
+        self.parallelism = parallelism;
+    }
 }
 
-impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilter<T, S, Z, U>
-    for ParticleFilter<T, S, Z, U>
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M> ParticleFilter<T, S, Z, U, H, M>
 where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+    Vec<OVector<T, S>>: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the particules as JSON, the models and noises are not saved
+    pub fn save_state<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        persistence::save(writer, &self.particules)
+    }
+
+    /// Restores the particules written by `save_state`, the filter then has as many particules
+    /// as the saved set. An empty set is an error and the particules are kept
+    pub fn load_state<R: std::io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        let particules: Vec<OVector<T, S>> = persistence::load(reader)?;
+        if particules.is_empty() {
+            return Err("the saved particule set is empty".into());
+        }
+        self.particules = particules;
+        Ok(())
+    }
+}
+
+impl<T: LogLikelihoods, S: Dim, Z: Dim, U: Dim, H, M> BayesianFilter<T, S, Z, U>
+    for ParticleFilter<T, S, Z, U, H, M>
+where
+    H: MeasurementModel<T, S, Z>,
+    M: MotionModel<T, S, Z, U>,
     DefaultAllocator: Allocator<T, S>
         + Allocator<T, U>
         + Allocator<T, Z>
@@ -81,35 +298,94 @@ where
         + Allocator<T, Const<1>, Z>,
     Standard: Distribution<T>,
     StandardNormal: Distribution<T>,
+    OVector<T, S>: Send + Sync,
+    OVector<T, Z>: Send + Sync,
+    OVector<T, U>: Sync,
+    OMatrix<T, S, S>: Sync,
+    OMatrix<T, Z, Z>: Sync,
 {
     fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
-        let shape = self.particules[0].shape_generic();
-        let mvn =
-            MultiVariateNormal::new(&OMatrix::zeros_generic(shape.0, shape.1), &self.r).unwrap();
-
-        self.particules = self
-            .particules
-            .iter()
-            .map(|p| self.motion_model.prediction(p, u, dt) + mvn.sample())
-            .collect();
+        let start = Instant::now();
+        self.step += 1;
+        let (seed, step, parallelism) = (self.seed, self.step, self.parallelism);
+        let num_particules = self.particules.len();
+        let motion_noise = &self.motion_noise;
 
-        let mut weights = vec![T::one(); self.particules.len()];
-        let shape = z.shape_generic();
-        let mvn =
-            MultiVariateNormal::new(&OMatrix::zeros_generic(shape.0, shape.1), &self.q).unwrap();
+        // for the auxiliary filter, the predicted means to move and the log likelihoods the
+        // weights are divided by
+        let (means, look_ahead): (Option<Vec<_>>, Vec<_>) = match self.variant {
+            ParticleFilterVariant::Auxiliary => {
+                let (means, innovations): (Vec<_>, Vec<_>) = (self.model_map)(
+                    &self.particules,
+                    &self.measurement_model,
+                    &self.motion_model,
+                    &|measurement_model, motion_model, _, p| {
+                        let mean = motion_model.prediction(p, u, dt);
+                        let dz = z - measurement_model.prediction(&mean, None);
+                        (mean, dz)
+                    },
+                )
+                .into_iter()
+                .unzip();
+                let log_weights =
+                    T::log_likelihoods(&self.measurement_noise, &innovations, parallelism);
+                let indices: Vec<usize> = (0..num_particules).collect();
+                let mut rng = Philox::for_particule(seed, num_particules as u64 + 1, step);
+                let (means, look_ahead) = resampling_with_scheme(
+                    self.resampling_scheme,
+                    &indices,
+                    &normalized_weights(&log_weights),
+                    &mut rng,
+                )
+                .into_iter()
+                .map(|i| (means[i].clone(), log_weights[i]))
+                .unzip();
+                (Some(means), look_ahead)
+            }
+            _ => (None, vec![T::zero(); num_particules]),
+        };
 
-        for (i, particule) in self.particules.iter().enumerate() {
-            let z_pred = self.measurement_model.prediction(particule, None);
-            let error = z - z_pred;
-            let pdf = mvn.pdf(&error);
-            weights[i] *= pdf;
-        }
+        let predicted = means.is_some();
+        let (particules, innovations): (Vec<_>, Vec<_>) = (self.model_map)(
+            means.as_deref().unwrap_or(&self.particules),
+            &self.measurement_model,
+            &self.motion_model,
+            &|measurement_model, motion_model, i, p| {
+                let mut rng = Philox::for_particule(seed, i as u64, step);
+                let noise = motion_noise.sample_with_rng(&mut rng);
+                let p = if predicted {
+                    p + noise
+                } else {
+                    motion_model.prediction(p, u, dt) + noise
+                };
+                let dz = z - measurement_model.prediction(&p, None);
+                (p, dz)
+            },
+        )
+        .into_iter()
+        .unzip();
+        let log_weights: Vec<T> =
+            T::log_likelihoods(&self.measurement_noise, &innovations, parallelism)
+                .into_iter()
+                .zip(look_ahead)
+                .map(|(log_weight, log_look_ahead)| log_weight - log_look_ahead)
+                .collect();
+        let weights = normalized_weights(&log_weights);
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::EffectiveSampleSize(effective_sample_size(&weights))
+        });
 
-        self.particules = match self.resampling_scheme {
-            ResamplingScheme::IID => resampling_sort(&self.particules, &weights),
-            ResamplingScheme::Stratified => resampling_stratified(&self.particules, &weights),
-            ResamplingScheme::Systematic => resampling_systematic(&self.particules, &weights),
+        let mut rng = Philox::for_particule(seed, num_particules as u64, step);
+        let particules =
+            resampling_with_scheme(self.resampling_scheme, &particules, &weights, &mut rng);
+        self.particules = match self.variant {
+            ParticleFilterVariant::Regularized => regularized(particules, seed, step, parallelism),
+            _ => particules,
         };
+        diagnostics::notify(&mut self.observer, || FilterEvent::Resampled);
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::UpdateTime(start.elapsed())
+        });
     }
 
     fn gaussian_estimate(&self) -> GaussianState<T, S> {
@@ -117,24 +393,40 @@ where
     }
 }
 
-/// S : State Size, Z: Observation Size, U: Input Size
-pub struct ParticleFilterKnownCorrespondences<T: RealField, S: Dim, Z: Dim, U: Dim>
-where
-    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+/// S : State Size, Z: Observation Size, U: Input Size, H: Measurement Model, M: Motion Model
+pub struct ParticleFilterKnownCorrespondences<
+    T: RealField,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    H = Box<dyn MeasurementModel<T, S, Z> + Send>,
+    M = Box<dyn MotionModel<T, S, Z, U> + Send>,
+> where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
 {
-    q: OMatrix<T, Z, Z>,
+    initial_noise: MultiVariateNormal<T, S>,
+    measurement_noise: MultiVariateNormal<T, Z>,
     landmarks: FxHashMap<u32, OVector<T, S>>,
-    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
-    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    measurement_model: H,
+    motion_model: M,
     pub particules: Vec<OVector<T, S>>,
+    variant: ParticleFilterVariant,
+    seed: u64,
+    step: u64,
+    observer: Observer<T>,
+    _input: PhantomData<U>,
 }
 
 impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> ParticleFilterKnownCorrespondences<T, S, Z, U>
 where
     StandardNormal: Distribution<T>,
     Standard: Distribution<T>,
-    DefaultAllocator:
-        Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z> + Allocator<T, Const<1>, S>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
 {
     pub fn new(
         initial_noise: OMatrix<T, S, S>,
@@ -145,25 +437,153 @@ where
         initial_state: GaussianState<T, S>,
         num_particules: usize,
     ) -> ParticleFilterKnownCorrespondences<T, S, Z, U> {
-        let mvn = MultiVariateNormal::new(&initial_state.x, &initial_noise).unwrap();
-        let mut particules = Vec::with_capacity(num_particules);
-        for _ in 0..num_particules {
-            particules.push(mvn.sample());
-        }
+        ParticleFilterKnownCorrespondences::with_models(
+            initial_noise,
+            q,
+            landmarks,
+            measurement_model,
+            motion_model,
+            initial_state,
+            num_particules,
+        )
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M>
+    ParticleFilterKnownCorrespondences<T, S, Z, U, H, M>
+where
+    StandardNormal: Distribution<T>,
+    Standard: Distribution<T>,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, S, S>
+        + Allocator<T, Z>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Const<1>, S>
+        + Allocator<T, Const<1>, Z>,
+{
+    pub fn with_models(
+        initial_noise: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        landmarks: FxHashMap<u32, OVector<T, S>>,
+        measurement_model: H,
+        motion_model: M,
+        initial_state: GaussianState<T, S>,
+        num_particules: usize,
+    ) -> ParticleFilterKnownCorrespondences<T, S, Z, U, H, M> {
+        let seed = rand::thread_rng().next_u64();
+        let initial_noise = MultiVariateNormal::zero_mean(&initial_noise).unwrap();
+        let particules = initial_particules(
+            &initial_noise.with_mean(&initial_state.x),
+            num_particules,
+            seed,
+        );
 
         ParticleFilterKnownCorrespondences {
-            q,
+            initial_noise,
+            measurement_noise: MultiVariateNormal::zero_mean(&q).unwrap(),
             landmarks,
             measurement_model,
             motion_model,
             particules,
+            variant: ParticleFilterVariant::default(),
+            seed,
+            step: 0,
+            observer: None,
+            _input: PhantomData,
+        }
+    }
+
+    /// The auxiliary look-ahead is only done on the updates with both a control and
+    /// measurements
+    pub fn set_variant(&mut self, variant: ParticleFilterVariant) {
+        self.variant = variant;
+    }
+
+    /// The seed is random by default, the particules are drawn again around `initial_state.x`
+    /// with the `initial_noise` of `new` so the whole run can be replayed. The covariance of
+    /// `initial_state` is not used
+    pub fn set_seed(&mut self, seed: u64, initial_state: &GaussianState<T, S>) {
+        let mvn = self.initial_noise.with_mean(&initial_state.x);
+        self.particules = initial_particules(&mvn, self.particules.len(), seed);
+        self.seed = seed;
+        self.step = 0;
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Receives the effective sample sizes, the resampling events and the update times
+    pub fn set_observer(&mut self, observer: impl FilterObserver<T> + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
+    /// Belief to save at shutdown, at most `max_particules` particules
+    pub fn snapshot(&self, max_particules: usize) -> Belief<T, S> {
+        Belief::decimated(&self.particules, max_particules)
+    }
+
+    /// Restarts from the belief of a previous session instead of a global relocalization
+    pub fn warm_start(&mut self, belief: &Belief<T, S>, config: &WarmStartConfig<T, S>) {
+        self.step += 1;
+        self.particules = belief.sample(self.particules.len(), config, self.seed, self.step);
+    }
+
+    /// Draws the particules again around the candidate states, split evenly between them,
+    /// to recover from a tracking loss. Nothing is done without candidate
+    pub fn reinitialize(&mut self, candidates: &[GaussianState<T, S>]) {
+        if candidates.is_empty() {
+            return;
+        }
+        self.step += 1;
+        let (seed, step) = (self.seed, self.step);
+        let num_particules = self.particules.len();
+        let mut rngs = (0..num_particules).map(|i| Philox::for_particule(seed, i as u64, step));
+        self.particules = candidates
+            .iter()
+            .enumerate()
+            .flat_map(|(i, candidate)| {
+                let mvn = MultiVariateNormal::new(&candidate.x, &candidate.cov).unwrap();
+                let n = num_particules / candidates.len()
+                    + usize::from(i < num_particules % candidates.len());
+                (0..n)
+                    .map(|_| mvn.sample_with_rng(&mut rngs.next().unwrap()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+    }
+}
+
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M>
+    ParticleFilterKnownCorrespondences<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z> + Allocator<T, Z, Z>,
+    Vec<OVector<T, S>>: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the particules as JSON, the models and noises are not saved
+    pub fn save_state<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        persistence::save(writer, &self.particules)
+    }
+
+    /// Restores the particules written by `save_state`, the filter then has as many particules
+    /// as the saved set. An empty set is an error and the particules are kept
+    pub fn load_state<R: std::io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        let particules: Vec<OVector<T, S>> = persistence::load(reader)?;
+        if particules.is_empty() {
+            return Err("the saved particule set is empty".into());
         }
+        self.particules = particules;
+        Ok(())
     }
 }
 
-impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilterKnownCorrespondences<T, S, Z, U>
-    for ParticleFilterKnownCorrespondences<T, S, Z, U>
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M>
+    BayesianFilterKnownCorrespondences<T, S, Z, U>
+    for ParticleFilterKnownCorrespondences<T, S, Z, U, H, M>
 where
+    H: MeasurementModel<T, S, Z>,
+    M: MotionModel<T, S, Z, U>,
     StandardNormal: Distribution<T>,
     Standard: Distribution<T>,
     DefaultAllocator: Allocator<T, S>
@@ -176,6 +596,8 @@ where
         + Allocator<T, U, U>
         + Allocator<T, Const<1>, S>
         + Allocator<T, Const<1>, Z>,
+    OVector<T, S>: Send + Sync,
+    OMatrix<T, S, S>: Sync,
 {
     fn update_estimate(
         &mut self,
@@ -183,35 +605,96 @@ where
         measurements: Option<Vec<(u32, OVector<T, Z>)>>,
         dt: T,
     ) {
-        if let Some(u) = control {
-            self.particules = self
-                .particules
+        let start = Instant::now();
+        self.step += 1;
+        let (seed, step) = (self.seed, self.step);
+        let num_particules = self.particules.len();
+        let measurements: Option<Vec<(OVector<T, S>, OVector<T, Z>)>> =
+            measurements.map(|measurements| {
+                measurements
+                    .into_iter()
+                    .filter_map(|(id, z)| match self.landmarks.get(&id) {
+                        Some(landmark) => Some((landmark.clone(), z)),
+                        None => {
+                            diagnostics::notify(&mut self.observer, || FilterEvent::Rejected {
+                                landmark: id,
+                            });
+                            None
+                        }
+                    })
+                    .collect()
+            });
+        let measurement_model = &self.measurement_model;
+        let measurement_noise = &self.measurement_noise;
+        let log_likelihood =
+            |particule: &OVector<T, S>, measurements: &[(OVector<T, S>, OVector<T, Z>)]| {
+                measurements.iter().fold(T::zero(), |acc, (landmark, z)| {
+                    let error = z - measurement_model.prediction(particule, Some(landmark));
+                    acc + measurement_noise.log_pdf(&error)
+                })
+            };
+
+        // the particules to propagate and, for the auxiliary filter, the log likelihoods of
+        // their predicted means the weights are divided by
+        let (particules, look_ahead): (Vec<_>, Vec<_>) =
+            match (&control, &measurements, self.variant) {
+                (Some(u), Some(measurements), ParticleFilterVariant::Auxiliary) => {
+                    let log_weights: Vec<T> = self
+                        .particules
+                        .iter()
+                        .map(|p| {
+                            log_likelihood(&self.motion_model.prediction(p, u, dt), measurements)
+                        })
+                        .collect();
+                    let indices: Vec<usize> = (0..num_particules).collect();
+                    let mut rng = Philox::for_particule(seed, num_particules as u64 + 1, step);
+                    resampling(&indices, &normalized_weights(&log_weights), &mut rng)
+                        .into_iter()
+                        .map(|i| (self.particules[i].clone(), log_weights[i]))
+                        .unzip()
+                }
+                _ => (
+                    std::mem::take(&mut self.particules),
+                    vec![T::zero(); num_particules],
+                ),
+            };
+        self.particules = match control {
+            Some(u) => particules
                 .iter()
-                .map(|p| self.motion_model.sample(p, &u, dt))
-                .collect();
-        }
+                .enumerate()
+                .map(|(i, p)| {
+                    let mut rng = Philox::for_particule(seed, i as u64, step);
+                    self.motion_model.sample_with_rng(p, &u, dt, &mut rng)
+                })
+                .collect(),
+            None => particules,
+        };
 
         if let Some(measurements) = measurements {
-            let mut weights = vec![T::one(); self.particules.len()];
-            let shape = measurements[0].1.shape_generic();
-            let mvn = MultiVariateNormal::new(&OMatrix::zeros_generic(shape.0, shape.1), &self.q)
-                .unwrap();
-
-            for (id, z) in measurements
+            let log_weights: Vec<T> = self
+                .particules
                 .iter()
-                .filter(|(id, _)| self.landmarks.contains_key(id))
-            {
-                let landmark = self.landmarks.get(id);
-                for (i, particule) in self.particules.iter().enumerate() {
-                    let z_pred = self.measurement_model.prediction(particule, landmark);
-                    let error = z - z_pred;
-                    let pdf = mvn.pdf(&error);
-                    weights[i] *= pdf;
-                }
-            }
-            self.particules = resampling(&self.particules, &weights);
+                .zip(look_ahead)
+                .map(|(p, log_look_ahead)| log_likelihood(p, &measurements) - log_look_ahead)
+                .collect();
+            let weights = normalized_weights(&log_weights);
+            diagnostics::notify(&mut self.observer, || {
+                FilterEvent::EffectiveSampleSize(effective_sample_size(&weights))
+            });
+            let mut rng = Philox::for_particule(seed, num_particules as u64, step);
+            let particules = resampling(&self.particules, &weights, &mut rng);
             // self.particules = resampling_sort(&self.particules, weights);
+            self.particules = match self.variant {
+                ParticleFilterVariant::Regularized => {
+                    regularized(particules, seed, step, Parallelism::Sequential)
+                }
+                _ => particules,
+            };
+            diagnostics::notify(&mut self.observer, || FilterEvent::Resampled);
         }
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::UpdateTime(start.elapsed())
+        });
     }
 
     fn gaussian_estimate(&self) -> GaussianState<T, S> {
@@ -219,7 +702,180 @@ where
     }
 }
 
-fn gaussian_estimate<T: RealField + Copy, S: Dim>(
+/// Maps the particules with their index, on the rayon thread pool in chunks of fixed size with
+/// `Parallelism::Rayon`, in the order of the particules in both cases
+fn map_particules<T: RealField, S: Dim, R, F>(
+    particules: &[OVector<T, S>],
+    parallelism: Parallelism,
+    f: F,
+) -> Vec<R>
+where
+    DefaultAllocator: Allocator<T, S>,
+    OVector<T, S>: Sync,
+    R: Send,
+    F: Fn(usize, &OVector<T, S>) -> R + Sync,
+{
+    match parallelism {
+        Parallelism::Sequential => particules
+            .iter()
+            .enumerate()
+            .map(|(i, p)| f(i, p))
+            .collect(),
+        Parallelism::Rayon => particules
+            .par_chunks(CHUNK_SIZE)
+            .enumerate()
+            .flat_map_iter(|(c, chunk)| {
+                let f = &f;
+                chunk
+                    .iter()
+                    .enumerate()
+                    .map(move |(j, p)| f(c * CHUNK_SIZE + j, p))
+            })
+            .collect(),
+    }
+}
+
+fn map_sequential<'a, T: RealField, S: Dim, Z: Dim, H, M>(
+    particules: &'a [OVector<T, S>],
+    measurement_model: &'a H,
+    motion_model: &'a M,
+    f: &'a ParticuleFn<'a, T, S, Z, H, M>,
+) -> Vec<(OVector<T, S>, OVector<T, Z>)>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, Z>,
+{
+    particules
+        .iter()
+        .enumerate()
+        .map(|(i, p)| f(measurement_model, motion_model, i, p))
+        .collect()
+}
+
+fn map_rayon<'a, T: RealField, S: Dim, Z: Dim, H: Sync, M: Sync>(
+    particules: &'a [OVector<T, S>],
+    measurement_model: &'a H,
+    motion_model: &'a M,
+    f: &'a ParticuleFn<'a, T, S, Z, H, M>,
+) -> Vec<(OVector<T, S>, OVector<T, Z>)>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, Z>,
+    OVector<T, S>: Send + Sync,
+    OVector<T, Z>: Send,
+{
+    map_particules(particules, Parallelism::Rayon, |i, p| {
+        f(measurement_model, motion_model, i, p)
+    })
+}
+
+/// Moves each particule by the Gaussian kernel with the optimal bandwidth for the particules
+/// covariance. The particules are kept when they are all on a subspace
+fn regularized<T: RealField + Copy, S: Dim>(
+    particules: Vec<OVector<T, S>>,
+    seed: u64,
+    step: u64,
+    parallelism: Parallelism,
+) -> Vec<OVector<T, S>>
+where
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Const<1>, S>,
+    OVector<T, S>: Send + Sync,
+    OMatrix<T, S, S>: Sync,
+{
+    let Some(cholesky) = gaussian_estimate(&particules).cov.cholesky() else {
+        return particules;
+    };
+    let shape = particules[0].shape_generic();
+    let dim = T::from_usize(shape.0.value()).unwrap();
+    let n = T::from_usize(particules.len()).unwrap();
+    let four = T::from_usize(4).unwrap();
+    let bandwidth = (four / (n * (dim + T::from_usize(2).unwrap()))).powf(T::one() / (dim + four));
+    let kernel = cholesky.l() * bandwidth;
+    map_particules(&particules, parallelism, |i, p| {
+        let mut rng = Philox::for_particule(seed, KERNEL_STREAMS + i as u64, step);
+        let e =
+            OVector::<T, S>::from_fn_generic(shape.0, shape.1, |_, _| rng.sample(StandardNormal));
+        p + &kernel * e
+    })
+}
+
+/// Scalar of the particule weights, the log density of the measurement noise at each
+/// innovation is computed particule by particule by default. With the `simd` feature, the f32
+/// filters compute them in batches of 8 lanes, in chunks on the rayon thread pool with
+/// `Parallelism::Rayon`
+pub trait LogLikelihoods: RealField + Copy {
+    fn log_likelihoods<Z: Dim>(
+        noise: &MultiVariateNormal<Self, Z>,
+        innovations: &[OVector<Self, Z>],
+        parallelism: Parallelism,
+    ) -> Vec<Self>
+    where
+        DefaultAllocator: Allocator<Self, Z> + Allocator<Self, Z, Z> + Allocator<Self, Const<1>, Z>,
+        StandardNormal: Distribution<Self>,
+        OVector<Self, Z>: Sync,
+        OMatrix<Self, Z, Z>: Sync,
+    {
+        match parallelism {
+            Parallelism::Sequential => innovations.iter().map(|dz| noise.log_pdf(dz)).collect(),
+            Parallelism::Rayon => innovations.par_iter().map(|dz| noise.log_pdf(dz)).collect(),
+        }
+    }
+}
+
+impl LogLikelihoods for f64 {}
+
+#[cfg(not(feature = "simd"))]
+impl LogLikelihoods for f32 {}
+
+#[cfg(feature = "simd")]
+impl LogLikelihoods for f32 {
+    fn log_likelihoods<Z: Dim>(
+        noise: &MultiVariateNormal<f32, Z>,
+        innovations: &[OVector<f32, Z>],
+        parallelism: Parallelism,
+    ) -> Vec<f32>
+    where
+        DefaultAllocator: Allocator<f32, Z> + Allocator<f32, Z, Z> + Allocator<f32, Const<1>, Z>,
+        StandardNormal: Distribution<f32>,
+        OVector<f32, Z>: Sync,
+        OMatrix<f32, Z, Z>: Sync,
+    {
+        let precision = noise.precision().as_slice();
+        let log_factor = noise.log_factor();
+        let batch = |innovations: &[OVector<f32, Z>]| {
+            // the noise has a zero mean
+            let residuals: Vec<f32> = innovations.iter().flatten().map(|v| -v).collect();
+            crate::utils::simd::log_pdf_residuals(precision, log_factor, &residuals)
+        };
+        // the chunks are a multiple of the lanes, both give the same log densities
+        match parallelism {
+            Parallelism::Sequential => batch(innovations),
+            Parallelism::Rayon => innovations
+                .par_chunks(CHUNK_SIZE)
+                .flat_map_iter(batch)
+                .collect(),
+        }
+    }
+}
+
+/// Weights summing to 1 from the log weights, with the log-sum-exp trick so the products of
+/// many densities don't underflow. The weights are uniform when all the log weights are -inf
+pub(crate) fn normalized_weights<T: RealField + Copy>(log_weights: &[T]) -> Vec<T> {
+    let max = log_weights
+        .iter()
+        .fold(T::min_value().unwrap(), |acc, w| acc.max(*w));
+    let n = T::from_usize(log_weights.len()).unwrap();
+    if !max.is_finite() || max == T::min_value().unwrap() {
+        return vec![T::one() / n; log_weights.len()];
+    }
+    let log_total = max
+        + log_weights
+            .iter()
+            .fold(T::zero(), |acc, w| acc + (*w - max).exp())
+            .ln();
+    log_weights.iter().map(|w| (*w - log_total).exp()).collect()
+}
+
+pub(crate) fn gaussian_estimate<T: RealField + Copy, S: Dim>(
     particules: &[OVector<T, S>],
 ) -> GaussianState<T, S>
 where
@@ -239,12 +895,28 @@ where
     GaussianState { x, cov }
 }
 
-fn resampling<T: RealField + Copy, S: Dim>(
-    particules: &Vec<OVector<T, S>>,
+fn resampling_with_scheme<T: RealField + Copy, P: Clone, R: Rng + ?Sized>(
+    scheme: ResamplingScheme,
+    particules: &[P],
     weights: &[T],
-) -> Vec<OVector<T, S>>
+    rng: &mut R,
+) -> Vec<P>
+where
+    Standard: Distribution<T>,
+{
+    match scheme {
+        ResamplingScheme::IID => resampling_sort(particules, weights, rng),
+        ResamplingScheme::Stratified => resampling_stratified(particules, weights, rng),
+        ResamplingScheme::Systematic => resampling_systematic(particules, weights, rng),
+    }
+}
+
+fn resampling<T: RealField + Copy, P: Clone, R: Rng + ?Sized>(
+    particules: &[P],
+    weights: &[T],
+    rng: &mut R,
+) -> Vec<P>
 where
-    DefaultAllocator: Allocator<T, S>,
     Standard: Distribution<T>,
 {
     let cum_weight: Vec<T> = weights
@@ -257,7 +929,6 @@ where
     let weight_tot = *cum_weight.last().unwrap();
 
     // sampling
-    let mut rng = rand::thread_rng();
     (0..particules.len())
         .map(|_| {
             let rng_nb = rng.gen::<T>() * weight_tot;
@@ -271,32 +942,30 @@ where
         .collect()
 }
 
-fn resampling_sort<T: RealField + Copy, S: Dim>(
-    particules: &Vec<OVector<T, S>>,
+fn resampling_sort<T: RealField + Copy, P: Clone, R: Rng + ?Sized>(
+    particules: &[P],
     weights: &[T],
-) -> Vec<OVector<T, S>>
+    rng: &mut R,
+) -> Vec<P>
 where
-    DefaultAllocator: Allocator<T, S>,
     Standard: Distribution<T>,
 {
     let total_weight: T = weights.iter().fold(T::zero(), |a, b| a + *b);
-    let mut rng = rand::thread_rng();
     let mut draws: Vec<T> = (0..particules.len())
         .map(|_| rng.gen::<T>() * total_weight)
         .collect();
     resample(&mut draws, total_weight, particules, weights)
 }
 
-fn resampling_stratified<T: RealField + Copy, S: Dim>(
-    particules: &Vec<OVector<T, S>>,
+fn resampling_stratified<T: RealField + Copy, P: Clone, R: Rng + ?Sized>(
+    particules: &[P],
     weights: &[T],
-) -> Vec<OVector<T, S>>
+    rng: &mut R,
+) -> Vec<P>
 where
-    DefaultAllocator: Allocator<T, S>,
     Standard: Distribution<T>,
 {
     let total_weight: T = weights.iter().fold(T::zero(), |a, b| a + *b);
-    let mut rng = rand::thread_rng();
     let mut draws: Vec<T> = (0..particules.len())
         .map(|i| {
             (T::from_usize(i).unwrap() + rng.gen::<T>()) / T::from_usize(particules.len()).unwrap()
@@ -306,16 +975,15 @@ where
     resample(&mut draws, total_weight, particules, weights)
 }
 
-fn resampling_systematic<T: RealField + Copy, S: Dim>(
-    particules: &Vec<OVector<T, S>>,
+fn resampling_systematic<T: RealField + Copy, P: Clone, R: Rng + ?Sized>(
+    particules: &[P],
     weights: &[T],
-) -> Vec<OVector<T, S>>
+    rng: &mut R,
+) -> Vec<P>
 where
-    DefaultAllocator: Allocator<T, S>,
     Standard: Distribution<T>,
 {
     let total_weight: T = weights.iter().fold(T::zero(), |a, b| a + *b);
-    let mut rng = rand::thread_rng();
     let draw = rng.gen::<T>();
     let mut draws: Vec<T> = (0..particules.len())
         .map(|i| {
@@ -326,14 +994,13 @@ where
     resample(&mut draws, total_weight, particules, weights)
 }
 
-fn resample<T: RealField + Copy, S: Dim>(
+fn resample<T: RealField + Copy, P: Clone>(
     draws: &mut [T],
     total_weight: T,
-    particules: &Vec<OVector<T, S>>,
+    particules: &[P],
     weights: &[T],
-) -> Vec<OVector<T, S>>
+) -> Vec<P>
 where
-    DefaultAllocator: Allocator<T, S>,
     Standard: Distribution<T>,
 {
     draws.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap());
@@ -355,3 +1022,271 @@ where
         })
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn normalized_weights_without_underflow() {
+        // products of ~50 densities, the weights would all be 0 out of the log space
+        let log_weights = [-3000.0, -3001.0, -3000.0 - 2.0_f64.ln()];
+        let weights = normalized_weights(&log_weights);
+        assert_relative_eq!(weights.iter().sum::<f64>(), 1.0, epsilon = 1e-12);
+        assert_relative_eq!(weights[0] / weights[2], 2.0, epsilon = 1e-12);
+        assert_relative_eq!(weights[0] / weights[1], 1.0_f64.exp(), epsilon = 1e-12);
+
+        let weights = normalized_weights(&[f64::NEG_INFINITY; 4]);
+        assert_eq!(weights, vec![0.25; 4]);
+    }
+
+    #[test]
+    fn static_models_match_boxed_models() {
+        use crate::models::measurement::SimpleProblemMeasurementModel;
+        use crate::models::motion::SimpleProblemMotionModel;
+        use nalgebra::{Matrix2, Matrix4, Vector2, Vector4};
+
+        let r = Matrix4::from_diagonal(&Vector4::new(0.1, 0.1, 0.01, 1.0));
+        let q = Matrix2::identity();
+        let initial_state = GaussianState {
+            x: Vector4::zeros(),
+            cov: Matrix4::identity(),
+        };
+        let mut boxed = ParticleFilter::<f64, Const<4>, Const<2>, Const<2>>::new(
+            r,
+            q,
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state.clone(),
+            50,
+            ResamplingScheme::Systematic,
+        );
+        let mut inlined = ParticleFilter::<f64, Const<4>, Const<2>, Const<2>, _, _>::with_models(
+            r,
+            q,
+            SimpleProblemMeasurementModel {},
+            SimpleProblemMotionModel {},
+            initial_state.clone(),
+            50,
+            ResamplingScheme::Systematic,
+        );
+        boxed.set_seed(3, &initial_state);
+        inlined.set_seed(3, &initial_state);
+        for _ in 0..5 {
+            let (u, z) = (Vector2::new(1.0, 0.1), Vector2::new(0.1, 0.0));
+            boxed.update_estimate(&u, &z, 0.1);
+            inlined.update_estimate(&u, &z, 0.1);
+        }
+        assert_eq!(boxed.particules, inlined.particules);
+    }
+
+    #[cfg(feature = "simd")]
+    #[test]
+    fn simd_log_likelihoods() {
+        use nalgebra::{Matrix2, Vector2};
+
+        let noise = MultiVariateNormal::zero_mean(&Matrix2::new(0.5f32, 0.1, 0.1, 0.3)).unwrap();
+        // more than one chunk, not a multiple of the lanes
+        let innovations: Vec<Vector2<f32>> = (0..150)
+            .map(|i| Vector2::new(i as f32 * 0.01, 1.0 - i as f32 * 0.02))
+            .collect();
+        let sequential = f32::log_likelihoods(&noise, &innovations, Parallelism::Sequential);
+        assert_eq!(
+            sequential,
+            f32::log_likelihoods(&noise, &innovations, Parallelism::Rayon)
+        );
+        for (dz, log_likelihood) in innovations.iter().zip(sequential) {
+            assert_relative_eq!(noise.log_pdf(dz), log_likelihood, epsilon = 1e-4);
+        }
+    }
+
+    #[test]
+    fn models_without_sync() {
+        use crate::models::motion::SimpleProblemMotionModel;
+        use nalgebra::{Matrix2, Matrix2x4, Matrix4, Vector2, Vector4};
+        use std::cell::Cell;
+
+        // counts its calls, so it is Send but not Sync
+        struct CountingModel(Cell<usize>);
+        impl MeasurementModel<f64, Const<4>, Const<2>> for CountingModel {
+            fn prediction(&self, x: &Vector4<f64>, _: Option<&Vector4<f64>>) -> Vector2<f64> {
+                self.0.set(self.0.get() + 1);
+                x.xy()
+            }
+
+            fn jacobian(&self, _: &Vector4<f64>, _: Option<&Vector4<f64>>) -> Matrix2x4<f64> {
+                Matrix2x4::identity()
+            }
+        }
+
+        let initial_state = GaussianState {
+            x: Vector4::zeros(),
+            cov: Matrix4::identity(),
+        };
+        let mut pf = ParticleFilter::new(
+            Matrix4::identity() * 0.1,
+            Matrix2::identity(),
+            Box::new(CountingModel(Cell::new(0))),
+            SimpleProblemMotionModel::new(),
+            initial_state,
+            100,
+            ResamplingScheme::Systematic,
+        );
+        pf.update_estimate(&Vector2::zeros(), &Vector2::new(1.0, -0.5), 0.1);
+        assert_eq!(100, pf.particules.len());
+    }
+
+    fn simple_problem_filter(
+        variant: ParticleFilterVariant,
+    ) -> ParticleFilter<f64, Const<4>, Const<2>, Const<2>> {
+        use crate::models::measurement::SimpleProblemMeasurementModel;
+        use crate::models::motion::SimpleProblemMotionModel;
+        use nalgebra::{Matrix2, Matrix4, Vector4};
+
+        // the particules are drawn with the motion noise around the initial state
+        let r = Matrix4::from_diagonal(&Vector4::new(0.5, 0.5, 1e-4, 1e-4));
+        let initial_state = GaussianState {
+            x: Vector4::zeros(),
+            cov: r,
+        };
+        let mut pf = ParticleFilter::new(
+            r,
+            Matrix2::identity() * 0.2,
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state.clone(),
+            2000,
+            ResamplingScheme::Systematic,
+        );
+        pf.set_variant(variant);
+        pf.set_seed(11, &initial_state);
+        pf
+    }
+
+    #[test]
+    fn variants_match_the_kalman_posterior() {
+        use nalgebra::Vector2;
+
+        // prior N(0, 1) on the position after the prediction, measurement noise 0.2
+        let z = Vector2::new(1.0, -0.5);
+        let expected = z * (1.0 / 1.2);
+        for variant in [
+            ParticleFilterVariant::Bootstrap,
+            ParticleFilterVariant::Auxiliary,
+            ParticleFilterVariant::Regularized,
+        ] {
+            let mut pf = simple_problem_filter(variant);
+            pf.update_estimate(&Vector2::zeros(), &z, 0.1);
+            let estimate = pf.gaussian_estimate();
+            assert_relative_eq!(estimate.x.xy(), expected, epsilon = 0.1);
+        }
+    }
+
+    #[test]
+    fn regularized_particules_are_distinct() {
+        use nalgebra::Vector2;
+
+        let distinct = |particules: &[OVector<f64, Const<4>>]| {
+            (0..particules.len())
+                .filter(|&i| !particules[..i].contains(&particules[i]))
+                .count()
+        };
+        let z = Vector2::new(1.0, -0.5);
+        let mut bootstrap = simple_problem_filter(ParticleFilterVariant::Bootstrap);
+        bootstrap.update_estimate(&Vector2::zeros(), &z, 0.1);
+        assert!(distinct(&bootstrap.particules) < 2000);
+
+        let mut regularized = simple_problem_filter(ParticleFilterVariant::Regularized);
+        regularized.update_estimate(&Vector2::zeros(), &z, 0.1);
+        assert_eq!(distinct(&regularized.particules), 2000);
+    }
+
+    #[test]
+    fn known_correspondences_variants() {
+        use crate::models::measurement::RangeBearingMeasurementModel;
+        use crate::models::motion::Velocity;
+        use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+
+        let landmarks: FxHashMap<u32, Vector3<f64>> = [
+            (1, Vector3::new(5.0, 0.0, 0.0)),
+            (2, Vector3::new(0.0, 6.0, 0.0)),
+            (3, Vector3::new(-4.0, -3.0, 0.0)),
+        ]
+        .into_iter()
+        .collect();
+        let initial_state = GaussianState {
+            x: Vector3::new(1.0, 2.0, 0.5),
+            cov: Matrix3::identity() * 0.25,
+        };
+        let u = Vector2::new(1.0, 0.1);
+        let pose = Velocity::new([0.1; 6]).prediction(&initial_state.x, &u, 0.1);
+        let model = RangeBearingMeasurementModel::new();
+        let measurements: Vec<(u32, Vector2<f64>)> = landmarks
+            .iter()
+            .map(|(id, lm)| (*id, model.prediction(&pose, Some(lm))))
+            .collect();
+
+        for variant in [
+            ParticleFilterVariant::Bootstrap,
+            ParticleFilterVariant::Auxiliary,
+            ParticleFilterVariant::Regularized,
+        ] {
+            let mut pf = ParticleFilterKnownCorrespondences::new(
+                initial_state.cov,
+                Matrix2::identity() * 0.05,
+                landmarks.clone(),
+                RangeBearingMeasurementModel::new(),
+                Velocity::new([0.1; 6]),
+                initial_state.clone(),
+                1000,
+            );
+            pf.set_variant(variant);
+            pf.set_seed(5, &initial_state);
+            pf.update_estimate(Some(u), Some(measurements.clone()), 0.1);
+            approx::assert_abs_diff_eq!(pose, pf.gaussian_estimate().x, epsilon = 0.3);
+            if variant == ParticleFilterVariant::Regularized {
+                let distinct = (0..pf.particules.len())
+                    .filter(|&i| !pf.particules[..i].contains(&pf.particules[i]))
+                    .count();
+                assert_eq!(distinct, 1000);
+            }
+        }
+    }
+
+    #[test]
+    fn known_correspondences_replay() {
+        use crate::models::measurement::RangeBearingMeasurementModel;
+        use crate::models::motion::Velocity;
+        use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+
+        let landmarks: FxHashMap<u32, Vector3<f64>> = [
+            (1, Vector3::new(5.0, 0.0, 0.0)),
+            (2, Vector3::new(0.0, 6.0, 0.0)),
+        ]
+        .into_iter()
+        .collect();
+        let initial_state = GaussianState {
+            x: Vector3::new(1.0, 2.0, 0.5),
+            cov: Matrix3::identity(),
+        };
+        let run = || {
+            let mut pf = ParticleFilterKnownCorrespondences::new(
+                Matrix3::identity() * 0.1,
+                Matrix2::identity() * 0.05,
+                landmarks.clone(),
+                RangeBearingMeasurementModel::new(),
+                Velocity::new([0.1; 6]),
+                initial_state.clone(),
+                200,
+            );
+            pf.set_seed(9, &initial_state);
+            for i in 0..5 {
+                let measurements = vec![(1, Vector2::new(4.0 - i as f64 * 0.1, -0.5))];
+                pf.update_estimate(Some(Vector2::new(1.0, 0.1)), Some(measurements), 0.1);
+            }
+            pf.particules
+        };
+        assert_eq!(run(), run());
+    }
+}
@@ -0,0 +1,137 @@
+use std::collections::VecDeque;
+
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, RealField};
+
+use crate::models::motion::MotionModel;
+use crate::utils::interpolation::Stamped;
+use crate::utils::state::GaussianState;
+
+/// Low latency pose for the controllers: the latest filter estimate, which is late by the
+/// processing time of the sensors, is propagated up to "now" with the inputs (odometry, IMU)
+/// received since then.
+///
+/// An input is held until the next one, the time before the first input is not integrated.
+/// The covariance is propagated with the jacobians of the motion model, G P G^T + V M V^T
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct PoseExtrapolator<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, U> + Allocator<T, S, S>,
+{
+    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    estimate: Option<(T, GaussianState<T, S>)>,
+    inputs: VecDeque<Stamped<T, U>>,
+    max_horizon: T,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> PoseExtrapolator<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, S, S>
+        + Allocator<T, U, U>
+        + Allocator<T, S, U>
+        + Allocator<T, U, S>
+        + Allocator<T, Z, S>,
+{
+    /// `max_horizon` is the longest time [s] the estimate can be extrapolated
+    pub fn new(motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>, max_horizon: T) -> Self {
+        PoseExtrapolator {
+            motion_model,
+            estimate: None,
+            inputs: VecDeque::new(),
+            max_horizon,
+        }
+    }
+
+    /// Adds an input measured at `time`, late inputs are accepted
+    pub fn add_input(&mut self, input: Stamped<T, U>) {
+        let i = self.inputs.partition_point(|s| s.time <= input.time);
+        self.inputs.insert(i, input);
+    }
+
+    /// New filter estimate valid at `time`, the inputs that are no longer needed are dropped
+    pub fn set_estimate(&mut self, time: T, state: GaussianState<T, S>) {
+        if self.estimate.as_ref().is_some_and(|(t, _)| *t > time) {
+            return;
+        }
+        // keep the input active at `time`
+        while self.inputs.len() > 1 && self.inputs[1].time <= time {
+            self.inputs.pop_front();
+        }
+        self.estimate = Some((time, state));
+    }
+
+    /// Time of the latest estimate
+    pub fn estimate_time(&self) -> Option<T> {
+        self.estimate.as_ref().map(|(t, _)| *t)
+    }
+
+    /// Pose at `now`, None without estimate or if `now` is too far after the estimate
+    pub fn extrapolate(&self, now: T) -> Option<GaussianState<T, S>> {
+        let (time, state) = self.estimate.as_ref()?;
+        if now - *time > self.max_horizon {
+            return None;
+        }
+        let mut x = state.x.clone();
+        let mut cov = state.cov.clone();
+        let mut t = *time;
+
+        for (i, input) in self.inputs.iter().enumerate() {
+            let end = self
+                .inputs
+                .get(i + 1)
+                .map_or(now, |next| next.time.min(now));
+            let start = input.time.max(t);
+            if end <= start {
+                continue;
+            }
+            let dt = end - start;
+            let g = self.motion_model.jacobian_wrt_state(&x, &input.value, dt);
+            let v = self.motion_model.jacobian_wrt_input(&x, &input.value, dt);
+            let m = self.motion_model.cov_noise_control_space(&input.value);
+            x = self.motion_model.prediction(&x, &input.value, dt);
+            cov = &g * cov * g.transpose() + &v * m * v.transpose();
+            t = end;
+        }
+        Some(GaussianState { x, cov })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::motion::Velocity;
+    use nalgebra::{Matrix3, Vector2, Vector3};
+
+    fn stamped(time: f64, v: f64, w: f64) -> Stamped<f64, nalgebra::Const<2>> {
+        Stamped {
+            time,
+            value: Vector2::new(v, w),
+        }
+    }
+
+    #[test]
+    fn extrapolation_with_odometry() {
+        let mut extrapolator = PoseExtrapolator::new(Velocity::new([0.1; 6]), 1.0);
+        assert!(extrapolator.extrapolate(0.0).is_none());
+
+        extrapolator.add_input(stamped(-0.5, 5.0, 0.0));
+        extrapolator.add_input(stamped(-0.1, 1.0, 0.0));
+        extrapolator.add_input(stamped(0.2, 2.0, 0.0));
+        extrapolator.set_estimate(
+            0.0,
+            GaussianState {
+                x: Vector3::zeros(),
+                cov: Matrix3::identity() * 0.01,
+            },
+        );
+        assert_eq!(2, extrapolator.inputs.len());
+
+        // 0.2 s at 1 m/s then 0.3 s at 2 m/s
+        let pose = extrapolator.extrapolate(0.5).unwrap();
+        approx::assert_abs_diff_eq!(Vector3::new(0.8, 0.0, 0.0), pose.x, epsilon = 1e-12);
+        assert!(pose.cov[(0, 0)] > 0.01);
+        assert!(extrapolator.extrapolate(1.5).is_none());
+    }
+}
@@ -0,0 +1,233 @@
+use nalgebra::{Const, Isometry2, Matrix3, Point2, Vector2, Vector3};
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use rustc_hash::FxHashMap;
+
+use crate::utils::metrics::align_se2;
+use crate::utils::state::GaussianState;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RelocalizationConfig {
+    pub max_iterations: usize,
+    /// Maximum range error of an inlier [m]
+    pub range_tolerance: f64,
+    /// Maximum bearing error of an inlier [rad]
+    pub bearing_tolerance: f64,
+    /// Candidates explained by fewer observations are discarded
+    pub min_inliers: usize,
+    /// Covariance given to the candidate poses
+    pub covariance: Matrix3<f64>,
+    /// Seed of the RANSAC samples
+    pub seed: u64,
+}
+
+impl Default for RelocalizationConfig {
+    fn default() -> Self {
+        RelocalizationConfig {
+            max_iterations: 100,
+            range_tolerance: 0.3,
+            bearing_tolerance: 0.1,
+            min_inliers: 3,
+            covariance: Matrix3::from_diagonal(&Vector3::new(0.1, 0.1, 0.01)),
+            seed: 0,
+        }
+    }
+}
+
+/// Pose (x, y, theta) consistent with the observations `inliers`
+#[derive(Debug, Clone)]
+pub struct PoseCandidate {
+    pub state: GaussianState<f64, Const<3>>,
+    pub inliers: Vec<usize>,
+}
+
+/// Global relocalization from a single set of [range, bearing] observations with landmark
+/// signatures, some of them possibly wrong. Two observations are enough to solve for the pose,
+/// RANSAC keeps the poses explaining the most observations. The candidates are refined on their
+/// inliers and sorted from the best, poses closer than the tolerances to a better one are dropped.
+/// The same observations and seed give the same candidates
+pub fn relocalize(
+    observations: &[(u32, Vector2<f64>)],
+    landmarks: &FxHashMap<u32, Vector3<f64>>,
+    config: &RelocalizationConfig,
+) -> Vec<PoseCandidate> {
+    // observation in the robot frame and landmark in the map frame
+    let correspondences: Vec<(usize, Vector2<f64>, Vector2<f64>)> = observations
+        .iter()
+        .enumerate()
+        .filter_map(|(i, (id, z))| {
+            let lm = landmarks.get(id)?;
+            let (sin, cos) = z[1].sin_cos();
+            Some((i, Vector2::new(z[0] * cos, z[0] * sin), lm.xy()))
+        })
+        .collect();
+    if correspondences.len() < 2 {
+        return Vec::new();
+    }
+
+    let inliers_of = |pose: &Isometry2<f64>| -> Vec<usize> {
+        correspondences
+            .iter()
+            .filter(|(i, _, lm)| {
+                let lm = pose.inverse_transform_point(&Point2::from(*lm));
+                let z = observations[*i].1;
+                let range_error = (lm.coords.norm() - z[0]).abs();
+                let bearing_error = angle_difference(f64::atan2(lm.y, lm.x), z[1]).abs();
+                range_error <= config.range_tolerance && bearing_error <= config.bearing_tolerance
+            })
+            .map(|(i, _, _)| *i)
+            .collect()
+    };
+
+    let mut rng = StdRng::seed_from_u64(config.seed);
+    let mut candidates: Vec<(Isometry2<f64>, Vec<usize>)> = Vec::new();
+    for _ in 0..config.max_iterations {
+        let idx = sample(&mut rng, correspondences.len(), 2);
+        let (a, b) = (
+            &correspondences[idx.index(0)],
+            &correspondences[idx.index(1)],
+        );
+        if (a.2 - b.2).norm() < config.range_tolerance {
+            continue;
+        }
+        let pose = align_se2(&[a.1, b.1], &[a.2, b.2]);
+        let inliers = inliers_of(&pose);
+        if inliers.len() < config.min_inliers {
+            continue;
+        }
+        // refine on the inliers
+        let (robot, map): (Vec<Vector2<f64>>, Vec<Vector2<f64>>) = correspondences
+            .iter()
+            .filter(|(i, _, _)| inliers.contains(i))
+            .map(|(_, z, lm)| (*z, *lm))
+            .unzip();
+        let refined = align_se2(&robot, &map);
+        let refined_inliers = inliers_of(&refined);
+        if refined_inliers.len() >= inliers.len() {
+            candidates.push((refined, refined_inliers));
+        } else {
+            candidates.push((pose, inliers));
+        }
+    }
+
+    candidates.sort_by_key(|(_, inliers)| std::cmp::Reverse(inliers.len()));
+    let mut unique: Vec<(Isometry2<f64>, Vec<usize>)> = Vec::new();
+    for (pose, inliers) in candidates {
+        let duplicate = unique.iter().any(|(p, _)| {
+            (p.translation.vector - pose.translation.vector).norm() < config.range_tolerance
+                && angle_difference(p.rotation.angle(), pose.rotation.angle()).abs()
+                    < config.bearing_tolerance
+        });
+        if !duplicate {
+            unique.push((pose, inliers));
+        }
+    }
+
+    unique
+        .into_iter()
+        .map(|(pose, inliers)| PoseCandidate {
+            state: GaussianState {
+                x: Vector3::new(
+                    pose.translation.x,
+                    pose.translation.y,
+                    pose.rotation.angle(),
+                ),
+                cov: config.covariance,
+            },
+            inliers,
+        })
+        .collect()
+}
+
+fn angle_difference(a: f64, b: f64) -> f64 {
+    let d = a - b;
+    f64::atan2(d.sin(), d.cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::{
+        BayesianFilterKnownCorrespondences, ParticleFilterKnownCorrespondences,
+    };
+    use crate::models::measurement::{MeasurementModel, RangeBearingMeasurementModel};
+    use crate::models::motion::Velocity;
+    use nalgebra::Matrix2;
+
+    #[test]
+    fn relocalize_with_a_wrong_signature() {
+        let landmarks: FxHashMap<u32, Vector3<f64>> = [
+            (1, Vector3::new(5.0, 0.0, 0.0)),
+            (2, Vector3::new(0.0, 6.0, 0.0)),
+            (3, Vector3::new(-4.0, -3.0, 0.0)),
+            (4, Vector3::new(7.0, 7.0, 0.0)),
+            (5, Vector3::new(-6.0, 4.0, 0.0)),
+        ]
+        .into_iter()
+        .collect();
+        let pose = Vector3::new(1.0, 2.0, 0.5);
+        let model = RangeBearingMeasurementModel::new();
+        let mut observations: Vec<(u32, Vector2<f64>)> = landmarks
+            .iter()
+            .map(|(id, lm)| (*id, model.prediction(&pose, Some(lm))))
+            .collect();
+        observations.sort_by_key(|(id, _)| *id);
+        // the signature of the last observation is wrong
+        observations[4].0 = 1;
+
+        let candidates = relocalize(&observations, &landmarks, &RelocalizationConfig::default());
+        approx::assert_abs_diff_eq!(pose, candidates[0].state.x, epsilon = 1e-9);
+        assert_eq!(vec![0, 1, 2, 3], candidates[0].inliers);
+
+        let mut pf = ParticleFilterKnownCorrespondences::new(
+            Matrix3::identity(),
+            Matrix2::identity(),
+            landmarks,
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.1; 6]),
+            GaussianState {
+                x: Vector3::new(-10.0, -10.0, 0.0),
+                cov: Matrix3::identity(),
+            },
+            300,
+        );
+        let states: Vec<_> = candidates.into_iter().map(|c| c.state).collect();
+        pf.reinitialize(&states[..1]);
+        approx::assert_abs_diff_eq!(pose, pf.gaussian_estimate().x, epsilon = 0.1);
+    }
+
+    #[test]
+    fn relocalize_with_a_seed() {
+        let landmarks: FxHashMap<u32, Vector3<f64>> = [
+            (0, Vector3::new(5.0, 0.0, 0.0)),
+            (1, Vector3::new(0.0, 6.0, 0.0)),
+            (2, Vector3::new(-4.0, -3.0, 0.0)),
+            (3, Vector3::new(7.0, 7.0, 0.0)),
+            (4, Vector3::new(-6.0, 4.0, 0.0)),
+            (5, Vector3::new(2.0, -5.0, 0.0)),
+        ]
+        .into_iter()
+        .collect();
+        let model = RangeBearingMeasurementModel::new();
+        let observations: Vec<(u32, Vector2<f64>)> = (0..6)
+            .map(|i| {
+                let z = model.prediction(&Vector3::new(0.5, -1.0, 0.2), Some(&landmarks[&i]));
+                // ambiguous signatures, each seed may settle on different candidates
+                (i % 2, z)
+            })
+            .collect();
+        let config = RelocalizationConfig {
+            max_iterations: 20,
+            seed: 3,
+            ..Default::default()
+        };
+        let run = || {
+            relocalize(&observations, &landmarks, &config)
+                .into_iter()
+                .map(|c| (c.state.x, c.inliers))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(run(), run());
+    }
+}
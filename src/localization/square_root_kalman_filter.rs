@@ -0,0 +1,199 @@
+use nalgebra::{allocator::Allocator, DMatrix, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+
+use crate::localization::BayesianFilter;
+use crate::models::measurement::MeasurementModel;
+use crate::models::motion::MotionModel;
+use crate::utils::state::GaussianState;
+
+/// EKF propagating a Cholesky factor L of the covariance P = LL^T instead of P, so the
+/// covariance stays symmetric positive definite with rounding errors, e.g. in f32. Each step
+/// rebuilds the lower triangular factor from the QR decomposition of the stacked factors
+///
+/// Source : Grewal & Andrews, Kalman Filtering: Theory and Practice, 2015, ch. 6.5
+///
+/// S : State Size, Z: Observation Size, U: Input Size
+pub struct SquareRootExtendedKalmanFilter<T: RealField, S: Dim, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    r_sqrt: OMatrix<T, S, S>,
+    q_sqrt: OMatrix<T, Z, Z>,
+    measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    x: OVector<T, S>,
+    sqrt_cov: OMatrix<T, S, S>,
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> SquareRootExtendedKalmanFilter<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    /// Same arguments as `ExtendedKalmanFilter::new`, the covariances should be positive definite
+    pub fn new(
+        r: OMatrix<T, S, S>,
+        q: OMatrix<T, Z, Z>,
+        measurement_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
+        motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+        initial_state: GaussianState<T, S>,
+    ) -> SquareRootExtendedKalmanFilter<T, S, Z, U> {
+        SquareRootExtendedKalmanFilter {
+            r_sqrt: factor("r", r),
+            q_sqrt: factor("q", q),
+            measurement_model,
+            motion_model,
+            x: initial_state.x,
+            sqrt_cov: factor("initial_state.cov", initial_state.cov),
+        }
+    }
+
+    /// Lower triangular factor L of the covariance, P = LL^T
+    pub fn sqrt_covariance(&self) -> &OMatrix<T, S, S> {
+        &self.sqrt_cov
+    }
+}
+
+fn factor<T: RealField, D: Dim>(name: &str, cov: OMatrix<T, D, D>) -> OMatrix<T, D, D>
+where
+    DefaultAllocator: Allocator<T, D, D>,
+{
+    cov.cholesky()
+        .unwrap_or_else(|| panic!("{name} should be positive definite"))
+        .unpack()
+}
+
+/// Lower triangular L with LL^T = AA^T + BB^T, from the QR decomposition of [A B]^T. The
+/// diagonal of L is positive
+fn triangularize<T: RealField + Copy, S: Dim, C: Dim, D: Dim>(
+    a: &OMatrix<T, S, C>,
+    b: &OMatrix<T, S, D>,
+) -> OMatrix<T, S, S>
+where
+    DefaultAllocator: Allocator<T, S, C> + Allocator<T, S, D> + Allocator<T, S, S>,
+{
+    let (rows, _) = a.shape_generic();
+    let n = a.nrows();
+    let mut stacked = DMatrix::zeros(a.ncols() + b.ncols(), n);
+    for i in 0..n {
+        for j in 0..a.ncols() {
+            stacked[(j, i)] = a[(i, j)];
+        }
+        for j in 0..b.ncols() {
+            stacked[(a.ncols() + j, i)] = b[(i, j)];
+        }
+    }
+    let r = stacked.qr().r();
+    OMatrix::from_fn_generic(rows, rows, |i, j| {
+        if j >= r.nrows() {
+            T::zero()
+        } else if r[(j, j)] < T::zero() {
+            -r[(j, i)]
+        } else {
+            r[(j, i)]
+        }
+    })
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilter<T, S, Z, U>
+    for SquareRootExtendedKalmanFilter<T, S, Z, U>
+where
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, Z>
+        + Allocator<T, S, S>
+        + Allocator<T, Z, Z>
+        + Allocator<T, Z, S>
+        + Allocator<T, S, U>
+        + Allocator<T, U, U>
+        + Allocator<T, S, Z>,
+{
+    fn update_estimate(&mut self, u: &OVector<T, U>, z: &OVector<T, Z>, dt: T) {
+        // predict
+        let g = self.motion_model.jacobian_wrt_state(&self.x, u, dt);
+        self.x = self.motion_model.prediction(&self.x, u, dt);
+        self.sqrt_cov = triangularize(&(&g * &self.sqrt_cov), &self.r_sqrt);
+
+        // update
+        let h = self.measurement_model.jacobian(&self.x, None);
+        let z_pred = self.measurement_model.prediction(&self.x, None);
+        let hl = &h * &self.sqrt_cov;
+        let sqrt_s = triangularize(&hl, &self.q_sqrt);
+        // K = P H^T S^-1 with S = sqrt_s sqrt_s^T, solved with the triangular factors
+        let cross = &hl * self.sqrt_cov.transpose();
+        let Some(kalman_gain_t) = sqrt_s
+            .solve_lower_triangular(&cross)
+            .and_then(|y| sqrt_s.tr_solve_lower_triangular(&y))
+        else {
+            return;
+        };
+        let kalman_gain = kalman_gain_t.transpose();
+        self.x += &kalman_gain * (z - z_pred);
+        // Joseph form, (I - KH)P(I - KH)^T + KQK^T
+        let shape = self.sqrt_cov.shape_generic();
+        let i_kh = OMatrix::identity_generic(shape.0, shape.1) - &kalman_gain * h;
+        self.sqrt_cov = triangularize(&(i_kh * &self.sqrt_cov), &(kalman_gain * &self.q_sqrt));
+    }
+
+    fn gaussian_estimate(&self) -> GaussianState<T, S> {
+        GaussianState {
+            x: self.x.clone(),
+            cov: &self.sqrt_cov * self.sqrt_cov.transpose(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::{CovarianceUpdate, ExtendedKalmanFilter};
+    use crate::models::measurement::SimpleProblemMeasurementModel;
+    use crate::models::motion::SimpleProblemMotionModel;
+    use nalgebra::{Matrix2, Matrix4, Vector2, Vector4};
+
+    #[test]
+    fn same_estimate_as_the_ekf() {
+        let r = Matrix4::from_diagonal(&Vector4::new(0.1, 0.1, 0.01, 1.0));
+        let q = Matrix2::identity() * 0.5;
+        let initial_state = GaussianState {
+            x: Vector4::zeros(),
+            cov: Matrix4::identity(),
+        };
+        let mut ekf = ExtendedKalmanFilter::new(
+            r,
+            q,
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state.clone(),
+        );
+        let mut joseph = ExtendedKalmanFilter::new(
+            r,
+            q,
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state.clone(),
+        );
+        joseph.set_covariance_update(CovarianceUpdate::Joseph);
+        let mut sr_ekf = SquareRootExtendedKalmanFilter::new(
+            r,
+            q,
+            SimpleProblemMeasurementModel::new(),
+            SimpleProblemMotionModel::new(),
+            initial_state,
+        );
+
+        let u = Vector2::new(1.0, 0.1);
+        for i in 0..50 {
+            let t = i as f64 * 0.1;
+            let z = Vector2::new(t.cos() * 3.0, t.sin() * 3.0);
+            ekf.update_estimate(&u, &z, 0.1);
+            joseph.update_estimate(&u, &z, 0.1);
+            sr_ekf.update_estimate(&u, &z, 0.1);
+            let expected = ekf.gaussian_estimate();
+            for estimate in [joseph.gaussian_estimate(), sr_ekf.gaussian_estimate()] {
+                approx::assert_abs_diff_eq!(expected.x, estimate.x, epsilon = 1e-9);
+                approx::assert_abs_diff_eq!(expected.cov, estimate.cov, epsilon = 1e-9);
+            }
+        }
+        let l = sr_ekf.sqrt_covariance();
+        assert!(l.upper_triangle() == Matrix4::from_diagonal(&l.diagonal()));
+    }
+}
@@ -3,23 +3,41 @@ use nalgebra::{
 };
 
 use crate::localization::bayesian_filter::BayesianFilter;
+use crate::localization::diagnostics::{self, FilterEvent, FilterObserver, Observer};
 use crate::models::measurement::MeasurementModel;
 use crate::models::motion::MotionModel;
+#[cfg(feature = "serde-serialize")]
+use crate::utils::persistence;
 use crate::utils::state::GaussianState;
+#[cfg(feature = "serde-serialize")]
+use std::error::Error;
+use std::marker::PhantomData;
+use std::time::Instant;
 
-/// S : State Size, Z: Observation Size, U: Input Size
-pub struct UnscentedKalmanFilter<T: RealField, S: Dim, Z: Dim, U: Dim>
-where
+/// S : State Size, Z: Observation Size, U: Input Size, H: Measurement Model, M: Motion Model
+///
+/// The models are boxed by default, `with_models` takes them by value so their calls are
+/// statically dispatched and can be inlined
+pub struct UnscentedKalmanFilter<
+    T: RealField,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    H = Box<dyn MeasurementModel<T, S, Z> + Send>,
+    M = Box<dyn MotionModel<T, S, Z, U> + Send>,
+> where
     DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
 {
     q: OMatrix<T, S, S>,
     r: OMatrix<T, Z, Z>,
     gamma: T,
-    observation_model: Box<dyn MeasurementModel<T, S, Z> + Send>,
-    motion_model: Box<dyn MotionModel<T, S, Z, U> + Send>,
+    observation_model: H,
+    motion_model: M,
     mw: Vec<T>,
     cw: Vec<T>,
     state: GaussianState<T, S>,
+    observer: Observer<T>,
+    _input: PhantomData<U>,
 }
 
 impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> UnscentedKalmanFilter<T, S, Z, U>
@@ -37,9 +55,36 @@ where
         kappa: T,
         initial_state: GaussianState<T, S>,
     ) -> UnscentedKalmanFilter<T, S, Z, U> {
+        UnscentedKalmanFilter::with_models(
+            q,
+            r,
+            observation_model,
+            motion_model,
+            alpha,
+            beta,
+            kappa,
+            initial_state,
+        )
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> UnscentedKalmanFilter<T, S, Z, U, H, M>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+{
+    #[allow(clippy::too_many_arguments)]
+    pub fn with_models(
+        q: OMatrix<T, S, S>,
+        r: OMatrix<T, Z, Z>,
+        observation_model: H,
+        motion_model: M,
+        alpha: T,
+        beta: T,
+        kappa: T,
+        initial_state: GaussianState<T, S>,
+    ) -> UnscentedKalmanFilter<T, S, Z, U, H, M> {
         let dim = q.shape_generic().0.value();
-        let (mw, cw, gamma) =
-            UnscentedKalmanFilter::<T, S, Z, U>::sigma_weights(dim, alpha, beta, kappa);
+        let (mw, cw, gamma) = Self::sigma_weights(dim, alpha, beta, kappa);
         UnscentedKalmanFilter {
             q,
             r,
@@ -49,9 +94,16 @@ where
             mw,
             cw,
             state: initial_state,
+            observer: None,
+            _input: PhantomData,
         }
     }
 
+    /// Receives the innovations, the gain norms and the update times
+    pub fn set_observer(&mut self, observer: impl FilterObserver<T> + 'static) {
+        self.observer = Some(Box::new(observer));
+    }
+
     fn sigma_weights(dim: usize, alpha: T, beta: T, kappa: T) -> (Vec<T>, Vec<T>, T) {
         let n = T::from_usize(dim).unwrap();
         let lambda = alpha.powi(2) * (n + kappa) - n;
@@ -90,9 +142,29 @@ where
     }
 }
 
-impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim> BayesianFilter<T, S, Z, U>
-    for UnscentedKalmanFilter<T, S, Z, U>
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, H, M> UnscentedKalmanFilter<T, S, Z, U, H, M>
 where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S> + Allocator<T, Z, Z>,
+    GaussianState<T, S>: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the state estimate as JSON, the models and noises are not saved
+    pub fn save_state<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        persistence::save(writer, &self.state)
+    }
+
+    /// Restores the state estimate written by `save_state`
+    pub fn load_state<R: std::io::Read>(&mut self, reader: R) -> Result<(), Box<dyn Error>> {
+        self.state = persistence::load(reader)?;
+        Ok(())
+    }
+}
+
+impl<T: RealField + Copy, S: Dim, Z: Dim, U: Dim, H, M> BayesianFilter<T, S, Z, U>
+    for UnscentedKalmanFilter<T, S, Z, U, H, M>
+where
+    H: MeasurementModel<T, S, Z>,
+    M: MotionModel<T, S, Z, U>,
     DefaultAllocator: Allocator<T, S>
         + Allocator<T, U>
         + Allocator<T, Z>
@@ -112,6 +184,7 @@ where
         z: &OVector<T, Z>,
         dt: T,
     ) {
+        let start = Instant::now();
         let dim_s = self.q.shape_generic().0;
         let dim_z = self.r.shape_generic().0;
         // predict
@@ -172,12 +245,20 @@ where
         let y = z - mean_z;
         let kalman_gain = s * cov_z.clone().try_inverse().unwrap();
 
-        let x_est = mean_xpred + &kalman_gain * y;
+        let x_est = mean_xpred + &kalman_gain * &y;
         let cov_est = cov_xpred - &kalman_gain * cov_z * kalman_gain.transpose();
         self.state = GaussianState {
             x: x_est,
             cov: cov_est,
-        }
+        };
+        diagnostics::notify(&mut self.observer, || FilterEvent::Innovation {
+            landmark: None,
+            innovation: y.iter().copied().collect(),
+            gain_norm: Some(kalman_gain.norm()),
+        });
+        diagnostics::notify(&mut self.observer, || {
+            FilterEvent::UpdateTime(start.elapsed())
+        });
     }
 
     fn gaussian_estimate(&self) -> GaussianState<T, S> {
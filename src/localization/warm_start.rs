@@ -0,0 +1,168 @@
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+use rand::distributions::Distribution;
+use rand_distr::StandardNormal;
+
+use crate::utils::mvn::MultiVariateNormal;
+#[cfg(feature = "serde-serialize")]
+use crate::utils::persistence;
+use crate::utils::rng::Philox;
+use crate::utils::state::GaussianState;
+#[cfg(feature = "serde-serialize")]
+use std::error::Error;
+
+/// Converged belief saved at shutdown to warm start the filter at the next boot
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde-serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "OVector<T, D>: serde::Serialize, OMatrix<T, D, D>: serde::Serialize",
+        deserialize = "OVector<T, D>: serde::Deserialize<'de>, OMatrix<T, D, D>: serde::Deserialize<'de>"
+    ))
+)]
+pub enum Belief<T: RealField, D: Dim>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    Gaussian(GaussianState<T, D>),
+    Particules(Vec<OVector<T, D>>),
+}
+
+/// The belief is inflated because the robot may have been moved while it was off, the spread is
+/// scaled by `covariance_scale` (>= 1) and `additive_noise` is added to it. `additive_noise` must
+/// be positive definite
+pub struct WarmStartConfig<T: RealField, D: Dim>
+where
+    DefaultAllocator: Allocator<T, D, D>,
+{
+    pub covariance_scale: T,
+    pub additive_noise: OMatrix<T, D, D>,
+}
+
+impl<T: RealField + Copy, D: Dim> Belief<T, D>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D> + Allocator<T, Const<1>, D>,
+{
+    /// At most `max_particules` particules taken evenly from the set, they are equally weighted
+    /// after the resampling
+    pub fn decimated(particules: &[OVector<T, D>], max_particules: usize) -> Belief<T, D> {
+        let stride = particules.len().div_ceil(max_particules.max(1)).max(1);
+        Belief::Particules(particules.iter().step_by(stride).cloned().collect())
+    }
+
+    /// Mean and covariance of the belief
+    pub fn gaussian_estimate(&self) -> GaussianState<T, D> {
+        match self {
+            Belief::Gaussian(state) => state.clone(),
+            Belief::Particules(particules) => {
+                crate::localization::particle_filter::gaussian_estimate(particules)
+            }
+        }
+    }
+
+    /// Gaussian belief with the inflated covariance
+    pub fn inflated_gaussian(&self, config: &WarmStartConfig<T, D>) -> GaussianState<T, D> {
+        let state = self.gaussian_estimate();
+        GaussianState {
+            cov: state.cov * config.covariance_scale + &config.additive_noise,
+            x: state.x,
+        }
+    }
+}
+
+impl<T: RealField + Copy, D: Dim> Belief<T, D>
+where
+    StandardNormal: Distribution<T>,
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D> + Allocator<T, Const<1>, D>,
+{
+    /// Particules for the restart, the saved particules are spread around their mean by
+    /// sqrt(covariance_scale) and jittered with `additive_noise`. The particule i draws from
+    /// the stream (seed, i, step)
+    pub fn sample(
+        &self,
+        num_particules: usize,
+        config: &WarmStartConfig<T, D>,
+        seed: u64,
+        step: u64,
+    ) -> Vec<OVector<T, D>> {
+        let rng = |i: usize| Philox::for_particule(seed, i as u64, step);
+        match self {
+            Belief::Particules(particules) if !particules.is_empty() => {
+                let mean = self.gaussian_estimate().x;
+                let shape = mean.shape_generic();
+                let noise = MultiVariateNormal::new(
+                    &OMatrix::zeros_generic(shape.0, shape.1),
+                    &config.additive_noise,
+                )
+                .unwrap();
+                let spread = config.covariance_scale.sqrt();
+                (0..num_particules)
+                    .map(|i| {
+                        let p = &particules[i % particules.len()];
+                        &mean + (p - &mean) * spread + noise.sample_with_rng(&mut rng(i))
+                    })
+                    .collect()
+            }
+            _ => {
+                let state = self.inflated_gaussian(config);
+                let mvn = MultiVariateNormal::new(&state.x, &state.cov).unwrap();
+                (0..num_particules)
+                    .map(|i| mvn.sample_with_rng(&mut rng(i)))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, D: Dim> Belief<T, D>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+    OVector<T, D>: serde::Serialize + serde::de::DeserializeOwned,
+    OMatrix<T, D, D>: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the belief as JSON
+    pub fn save<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        persistence::save(writer, self)
+    }
+
+    /// Reads a belief written by `save`
+    pub fn load<R: std::io::Read>(reader: R) -> Result<Belief<T, D>, Box<dyn Error>> {
+        persistence::load(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix2, Vector2};
+
+    #[test]
+    fn decimate_and_inflate() {
+        let particules: Vec<Vector2<f64>> = (0..1000)
+            .map(|i| Vector2::new(i as f64 / 1000.0, 1.0))
+            .collect();
+        let belief = Belief::decimated(&particules, 100);
+        let Belief::Particules(decimated) = &belief else {
+            panic!("the belief should be particules");
+        };
+        assert_eq!(100, decimated.len());
+
+        let config = WarmStartConfig {
+            covariance_scale: 4.0,
+            additive_noise: Matrix2::identity() * 1e-6,
+        };
+        let restored = Belief::Particules(belief.sample(2000, &config, 1, 0));
+        let before = belief.gaussian_estimate();
+        let after = restored.gaussian_estimate();
+        approx::assert_abs_diff_eq!(before.x, after.x, epsilon = 1e-3);
+        approx::assert_abs_diff_eq!(4.0 * before.cov[(0, 0)], after.cov[(0, 0)], epsilon = 1e-3);
+
+        let gaussian = Belief::Gaussian(before.clone()).inflated_gaussian(&config);
+        approx::assert_abs_diff_eq!(
+            before.cov * 4.0 + config.additive_noise,
+            gaussian.cov,
+            epsilon = 1e-12
+        );
+    }
+}
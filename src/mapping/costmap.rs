@@ -0,0 +1,352 @@
+use nalgebra::{Isometry2, Point2, Vector2};
+
+use crate::mapping::occupancy_grid::bresenham;
+use crate::mapping::OccupancyGrid;
+
+#[derive(Debug, Clone)]
+pub struct CostmapConfig {
+    /// Cells of the static map more likely to be occupied than this are obstacles
+    pub occupied_threshold: f64,
+    /// The cells never observed in the static map are `Costmap::NO_INFORMATION` instead of free
+    pub track_unknown: bool,
+    /// The sensor obstacles not seen again for this long are cleared [s]
+    pub obstacle_decay: f64,
+    /// The scan points further than this from the sensor are ignored [m]
+    pub obstacle_max_range: f64,
+    /// The cost is `Costmap::FREE` further than this from the obstacles [m]
+    pub inflation_radius: f64,
+    /// Exponential decay rate of the cost between the inscribed and inflation radius [1/m]
+    pub cost_scaling: f64,
+    /// Polygon of the robot in its own frame
+    pub footprint: Vec<Vector2<f64>>,
+}
+
+impl Default for CostmapConfig {
+    fn default() -> Self {
+        CostmapConfig {
+            occupied_threshold: 0.65,
+            track_unknown: false,
+            obstacle_decay: 5.0,
+            obstacle_max_range: 10.0,
+            inflation_radius: 0.55,
+            cost_scaling: 10.0,
+            footprint: vec![
+                Vector2::new(0.2, 0.2),
+                Vector2::new(-0.2, 0.2),
+                Vector2::new(-0.2, -0.2),
+                Vector2::new(0.2, -0.2),
+            ],
+        }
+    }
+}
+
+/// Costs of the cells for the planners, the max of a static layer from an occupancy grid, a layer
+/// of the obstacles seen by the sensors which decay after a while, and of the costs of the
+/// inflation of both by the robot footprint. The costs are computed again by `update`
+///
+/// Source : Layered Costmaps for Context-Sensitive Navigation, Lu et al.
+#[derive(Debug, Clone)]
+pub struct Costmap {
+    /// World position of the corner of the cell (0, 0)
+    pub origin: Vector2<f64>,
+    /// Size of a cell [m]
+    pub resolution: f64,
+    pub width: usize,
+    pub height: usize,
+    config: CostmapConfig,
+    /// Radius of the largest circle inside the footprint [m]
+    inscribed_radius: f64,
+    static_costs: Vec<u8>,
+    /// Time of the last hit of each cell, -inf if none since the cell was last cleared
+    obstacle_stamps: Vec<f64>,
+    /// Row major, index = row * width + column
+    costs: Vec<u8>,
+}
+
+impl Costmap {
+    pub const FREE: u8 = 0;
+    /// The footprint hits an obstacle when its center is in the cell
+    pub const INSCRIBED: u8 = 253;
+    pub const LETHAL: u8 = 254;
+    pub const NO_INFORMATION: u8 = 255;
+
+    /// Costmap of `width` x `height` cells without static obstacles
+    pub fn new(
+        origin: Vector2<f64>,
+        resolution: f64,
+        width: usize,
+        height: usize,
+        config: CostmapConfig,
+    ) -> Costmap {
+        let inscribed_radius = inscribed_radius(&config.footprint);
+        Costmap {
+            origin,
+            resolution,
+            width,
+            height,
+            config,
+            inscribed_radius,
+            static_costs: vec![Costmap::FREE; width * height],
+            obstacle_stamps: vec![f64::NEG_INFINITY; width * height],
+            costs: vec![Costmap::FREE; width * height],
+        }
+    }
+
+    /// Costmap over the grid with its obstacles as static layer
+    pub fn from_grid(grid: &OccupancyGrid, config: CostmapConfig) -> Costmap {
+        let mut costmap = Costmap::new(
+            grid.origin,
+            grid.resolution,
+            grid.width,
+            grid.height,
+            config,
+        );
+        costmap.set_static_map(grid);
+        costmap.update(f64::NEG_INFINITY);
+        costmap
+    }
+
+    /// Replaces the static layer, the grid must have the size of the costmap
+    pub fn set_static_map(&mut self, grid: &OccupancyGrid) {
+        assert_eq!((grid.width, grid.height), (self.width, self.height));
+        for (i, log_odds) in grid.log_odds.iter().enumerate() {
+            let p = 1.0 - 1.0 / (1.0 + log_odds.exp());
+            self.static_costs[i] = if p > self.config.occupied_threshold {
+                Costmap::LETHAL
+            } else if self.config.track_unknown && *log_odds == 0.0 {
+                Costmap::NO_INFORMATION
+            } else {
+                Costmap::FREE
+            };
+        }
+    }
+
+    /// Cell (column, row) containing the world point
+    pub fn world_to_cell(&self, p: &Vector2<f64>) -> Option<(usize, usize)> {
+        let c = ((p - self.origin) / self.resolution).map(f64::floor);
+        if c.x < 0.0 || c.y < 0.0 || c.x >= self.width as f64 || c.y >= self.height as f64 {
+            return None;
+        }
+        Some((c.x as usize, c.y as usize))
+    }
+
+    /// World position of the center of the cell
+    pub fn cell_to_world(&self, (column, row): (usize, usize)) -> Vector2<f64> {
+        self.origin + Vector2::new(column as f64 + 0.5, row as f64 + 0.5) * self.resolution
+    }
+
+    pub fn index(&self, (column, row): (usize, usize)) -> usize {
+        row * self.width + column
+    }
+
+    pub fn cost(&self, cell: (usize, usize)) -> u8 {
+        self.costs[self.index(cell)]
+    }
+
+    /// Cost of the cell containing the world point, None out of the map
+    pub fn cost_at(&self, p: &Vector2<f64>) -> Option<u8> {
+        self.world_to_cell(p).map(|cell| self.cost(cell))
+    }
+
+    pub fn costs(&self) -> &[u8] {
+        &self.costs
+    }
+
+    pub fn inscribed_radius(&self) -> f64 {
+        self.inscribed_radius
+    }
+
+    /// Marks the cells of the scan points, given in the sensor frame, taken from `pose` at `time`
+    /// as obstacles and clears the cells crossed by the beams. The costs change at the next
+    /// `update`
+    pub fn insert_scan(&mut self, time: f64, pose: &Isometry2<f64>, points: &[Vector2<f64>]) {
+        let Some(start) = self.world_to_cell(&pose.translation.vector) else {
+            return;
+        };
+        for p in points {
+            if p.norm() > self.config.obstacle_max_range {
+                continue;
+            }
+            let Some(end) = self.world_to_cell(&(pose * Point2::from(*p)).coords) else {
+                continue;
+            };
+            let cells = bresenham(start, end);
+            let (last, free) = cells.split_last().unwrap();
+            for cell in free {
+                let i = self.index(*cell);
+                self.obstacle_stamps[i] = f64::NEG_INFINITY;
+            }
+            let i = self.index(*last);
+            self.obstacle_stamps[i] = time;
+        }
+    }
+
+    /// Forgets all the sensor obstacles, e.g. after a relocalization
+    pub fn clear_obstacles(&mut self) {
+        self.obstacle_stamps.fill(f64::NEG_INFINITY);
+    }
+
+    /// Computes the costs at `time`, the sensor obstacles older than the decay are cleared
+    pub fn update(&mut self, time: f64) {
+        for stamp in self.obstacle_stamps.iter_mut() {
+            if *stamp < time - self.config.obstacle_decay {
+                *stamp = f64::NEG_INFINITY;
+            }
+        }
+        self.costs.clone_from(&self.static_costs);
+        for (cost, stamp) in self.costs.iter_mut().zip(&self.obstacle_stamps) {
+            if stamp.is_finite() {
+                *cost = Costmap::LETHAL;
+            }
+        }
+        self.inflate();
+    }
+
+    /// Raises the cost of the cells around each lethal cell to `inflation_cost` of their distance
+    fn inflate(&mut self) {
+        let r = (self.config.inflation_radius / self.resolution).ceil() as i64;
+        // cost of each offset of the disk, the same around every obstacle
+        let mut kernel = Vec::new();
+        for dy in -r..=r {
+            for dx in -r..=r {
+                let distance = ((dx * dx + dy * dy) as f64).sqrt() * self.resolution;
+                let cost = self.inflation_cost(distance);
+                if cost > Costmap::FREE {
+                    kernel.push((dx, dy, cost));
+                }
+            }
+        }
+        let lethal: Vec<usize> = (0..self.costs.len())
+            .filter(|&i| self.costs[i] == Costmap::LETHAL)
+            .collect();
+        for i in lethal {
+            let (column, row) = ((i % self.width) as i64, (i / self.width) as i64);
+            for (dx, dy, cost) in &kernel {
+                let (x, y) = (column + dx, row + dy);
+                if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+                    continue;
+                }
+                let j = y as usize * self.width + x as usize;
+                if self.costs[j] < *cost {
+                    self.costs[j] = *cost;
+                }
+            }
+        }
+    }
+
+    /// Cost of a cell at `distance` of an obstacle
+    pub fn inflation_cost(&self, distance: f64) -> u8 {
+        if distance == 0.0 {
+            Costmap::LETHAL
+        } else if distance <= self.inscribed_radius {
+            Costmap::INSCRIBED
+        } else if distance > self.config.inflation_radius {
+            Costmap::FREE
+        } else {
+            let decay = (-self.config.cost_scaling * (distance - self.inscribed_radius)).exp();
+            ((Costmap::INSCRIBED - 1) as f64 * decay) as u8
+        }
+    }
+
+    /// Max cost of the cells under the outline of the footprint at `pose` and of the cell of its
+    /// center, None if a part of it is out of the map
+    pub fn footprint_cost(&self, pose: &Isometry2<f64>) -> Option<u8> {
+        let mut cost = self.cost_at(&pose.translation.vector)?;
+        let vertices = self
+            .config
+            .footprint
+            .iter()
+            .map(|v| self.world_to_cell(&(pose * Point2::from(*v)).coords))
+            .collect::<Option<Vec<_>>>()?;
+        for (i, start) in vertices.iter().enumerate() {
+            let end = vertices[(i + 1) % vertices.len()];
+            for cell in bresenham(*start, end) {
+                cost = cost.max(self.cost(cell));
+            }
+        }
+        Some(cost)
+    }
+
+    /// The footprint at `pose` is out of the map, on an obstacle or on an unknown cell
+    pub fn in_collision(&self, pose: &Isometry2<f64>) -> bool {
+        match self.footprint_cost(pose) {
+            None => true,
+            Some(cost) => cost >= Costmap::LETHAL || self.is_blocked(&pose.translation.vector),
+        }
+    }
+
+    /// The cell of the point is out of the map or closer to an obstacle than the inscribed
+    /// radius, what the grid planners avoid
+    pub fn is_blocked(&self, p: &Vector2<f64>) -> bool {
+        !matches!(self.cost_at(p), Some(cost) if cost < Costmap::INSCRIBED)
+    }
+}
+
+/// Distance from the origin to the closest edge of the polygon
+fn inscribed_radius(footprint: &[Vector2<f64>]) -> f64 {
+    (0..footprint.len())
+        .map(|i| {
+            let (a, b) = (footprint[i], footprint[(i + 1) % footprint.len()]);
+            let t = (-a.dot(&(b - a)) / (b - a).norm_squared()).clamp(0.0, 1.0);
+            (a + (b - a) * t).norm()
+        })
+        .fold(f64::INFINITY, f64::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 10 x 10 m of 0.1 m cells with a wall at x = 5
+    fn costmap() -> Costmap {
+        let mut grid = OccupancyGrid::new(Vector2::zeros(), 0.1, 100, 100);
+        for row in 0..100 {
+            grid.update_cell((50, row), 5.0);
+        }
+        Costmap::from_grid(&grid, CostmapConfig::default())
+    }
+
+    #[test]
+    fn inflation() {
+        let costmap = costmap();
+        approx::assert_relative_eq!(costmap.inscribed_radius(), 0.2);
+        let cost = |x: f64| costmap.cost_at(&Vector2::new(x, 2.05)).unwrap();
+        assert_eq!(cost(5.05), Costmap::LETHAL);
+        assert_eq!(cost(4.85), Costmap::INSCRIBED);
+        assert!(cost(4.65) < Costmap::INSCRIBED && cost(4.65) > cost(4.55));
+        assert!(cost(4.55) > Costmap::FREE);
+        assert_eq!(cost(4.35), Costmap::FREE);
+
+        assert!(costmap.in_collision(&Isometry2::new(Vector2::new(4.85, 2.0), 0.0)));
+        // the corner of the footprint is on the wall
+        assert!(costmap.in_collision(&Isometry2::new(Vector2::new(4.75, 2.0), 0.8)));
+        assert!(!costmap.in_collision(&Isometry2::new(Vector2::new(4.75, 2.0), 0.0)));
+        assert!(!costmap.in_collision(&Isometry2::new(Vector2::new(2.0, 2.0), 0.3)));
+    }
+
+    #[test]
+    fn sensor_obstacles_decay() {
+        let mut costmap = costmap();
+        let pose = Isometry2::new(Vector2::new(1.0, 5.0), 0.0);
+        let first = Vector2::new(3.05, 5.0);
+        let second = Vector2::new(4.05, 5.0);
+        costmap.insert_scan(0.0, &pose, &[Vector2::new(2.05, 0.0)]);
+        costmap.update(1.0);
+        assert_eq!(costmap.cost_at(&first), Some(Costmap::LETHAL));
+        assert!(costmap.is_blocked(&Vector2::new(2.85, 5.0)));
+
+        // the obstacle moved away, the longer beam clears its cell
+        costmap.insert_scan(2.0, &pose, &[Vector2::new(3.05, 0.0)]);
+        costmap.update(2.0);
+        assert_eq!(costmap.cost_at(&first), Some(Costmap::FREE));
+        assert_eq!(costmap.cost_at(&second), Some(Costmap::LETHAL));
+
+        costmap.update(2.0 + costmap.config.obstacle_decay + 0.1);
+        assert_eq!(costmap.cost_at(&second), Some(Costmap::FREE));
+        // the static wall stays
+        assert_eq!(
+            costmap.cost_at(&Vector2::new(5.05, 5.0)),
+            Some(Costmap::LETHAL)
+        );
+    }
+}
@@ -0,0 +1,253 @@
+use nalgebra::{Isometry2, Point2, Vector2};
+use rand::{Rng, SeedableRng};
+use rand_distr::{Distribution, Normal};
+
+use crate::mapping::OccupancyGrid;
+use crate::perception::scan_matching::{icp, IcpConfig};
+use crate::utils::kdtree::KdTree;
+
+#[derive(Debug, Clone, Copy)]
+pub struct GridSlamConfig {
+    pub num_particules: usize,
+    /// Standard deviation of the odometry error, like `Velocity`: translation per meter and per
+    /// radian, rotation per radian and per meter
+    pub odometry_noise: [f64; 4],
+    /// Scan matching of the proposal, against the map of the particule
+    pub icp: IcpConfig,
+    /// The matches below this fitness are ignored, the pose is sampled from the odometry
+    pub min_fitness: f64,
+    /// Standard deviation [m] of the distance of a scan point to the closest obstacle
+    pub scan_sigma: f64,
+    /// Resamples when the effective number of particules is below this fraction
+    pub resampling_threshold: f64,
+    pub seed: u64,
+}
+
+impl Default for GridSlamConfig {
+    fn default() -> Self {
+        GridSlamConfig {
+            num_particules: 30,
+            odometry_noise: [0.05, 0.01, 0.05, 0.02],
+            icp: IcpConfig::default(),
+            min_fitness: 0.7,
+            scan_sigma: 0.05,
+            resampling_threshold: 0.5,
+            seed: 0,
+        }
+    }
+}
+
+/// Trajectory hypothesis and the map built along it
+#[derive(Debug, Clone)]
+pub struct GridParticule {
+    pub pose: Isometry2<f64>,
+    pub log_weight: f64,
+    pub map: OccupancyGrid,
+}
+
+/// Rao-Blackwellized particule filter SLAM, each particule carries its own occupancy grid. The
+/// odometry proposal is improved by matching the scan against the map of the particule, and
+/// the particules are only resampled when their effective number drops
+///
+/// Source : Grisetti, Stachniss & Burgard, Improved Techniques for Grid Mapping With
+/// Rao-Blackwellized Particle Filters, 2007
+pub struct GridSlam {
+    pub config: GridSlamConfig,
+    particules: Vec<GridParticule>,
+    last_odometry: Option<Isometry2<f64>>,
+    rng: rand::rngs::StdRng,
+}
+
+impl GridSlam {
+    /// `grid` is the empty map copied in each particule, the robot starts at `initial_pose`
+    pub fn new(
+        grid: OccupancyGrid,
+        initial_pose: Isometry2<f64>,
+        config: GridSlamConfig,
+    ) -> GridSlam {
+        let particule = GridParticule {
+            pose: initial_pose,
+            log_weight: 0.0,
+            map: grid,
+        };
+        GridSlam {
+            particules: vec![particule; config.num_particules],
+            last_odometry: None,
+            rng: rand::rngs::StdRng::seed_from_u64(config.seed),
+            config,
+        }
+    }
+
+    pub fn particules(&self) -> &[GridParticule] {
+        &self.particules
+    }
+
+    /// Particule with the largest weight, its map is the most likely one
+    pub fn best(&self) -> &GridParticule {
+        self.particules
+            .iter()
+            .max_by(|a, b| a.log_weight.total_cmp(&b.log_weight))
+            .unwrap()
+    }
+
+    /// Effective number of particules, from 1 when a particule has all the weight to the number
+    /// of particules when they have the same weight
+    pub fn effective_particules(&self) -> f64 {
+        let weights = self.weights();
+        1.0 / weights.iter().map(|w| w * w).sum::<f64>()
+    }
+
+    fn weights(&self) -> Vec<f64> {
+        let max = self
+            .particules
+            .iter()
+            .fold(f64::NEG_INFINITY, |acc, p| acc.max(p.log_weight));
+        let weights: Vec<f64> = self
+            .particules
+            .iter()
+            .map(|p| (p.log_weight - max).exp())
+            .collect();
+        let total: f64 = weights.iter().sum();
+        weights.into_iter().map(|w| w / total).collect()
+    }
+
+    /// Odometry pose and scan in the robot frame
+    pub fn update(&mut self, odometry: &Isometry2<f64>, scan: &[Vector2<f64>]) {
+        let delta = self
+            .last_odometry
+            .map_or(Isometry2::identity(), |last| last.inverse() * odometry);
+        let moved = self.last_odometry.is_some();
+        self.last_odometry = Some(*odometry);
+
+        let a = self.config.odometry_noise;
+        let translation = delta.translation.vector.norm();
+        let rotation = delta.rotation.angle().abs();
+        let translation_noise = Normal::new(0.0, a[0] * translation + a[1] * rotation).unwrap();
+        let rotation_noise = Normal::new(0.0, a[2] * rotation + a[3] * translation).unwrap();
+        let inverse_variance = 1.0 / (2.0 * self.config.scan_sigma.powi(2));
+        let max_distance2 = self.config.icp.max_correspondence_distance.powi(2);
+
+        for particule in self.particules.iter_mut() {
+            if moved {
+                let noisy = Isometry2::new(
+                    delta.translation.vector
+                        + Vector2::new(
+                            translation_noise.sample(&mut self.rng),
+                            translation_noise.sample(&mut self.rng),
+                        ),
+                    delta.rotation.angle() + rotation_noise.sample(&mut self.rng),
+                );
+                particule.pose *= noisy;
+
+                let obstacles: Vec<Vector2<f64>> = (0..particule.map.height)
+                    .flat_map(|row| (0..particule.map.width).map(move |column| (column, row)))
+                    .filter(|cell| particule.map.log_odds[particule.map.index(*cell)] > 0.0)
+                    .map(|cell| particule.map.cell_to_world(cell))
+                    .collect();
+                if !obstacles.is_empty() {
+                    let tree = KdTree::new(&obstacles);
+                    if let Some(result) = icp(scan, &tree, &particule.pose, &self.config.icp)
+                        .filter(|result| result.fitness >= self.config.min_fitness)
+                    {
+                        particule.pose = result.transform;
+                    }
+                    // likelihood field of the scan at the proposed pose
+                    particule.log_weight -= scan
+                        .iter()
+                        .map(|p| {
+                            let p = (particule.pose * Point2::from(*p)).coords;
+                            let d2 = tree
+                                .nearest(&p)
+                                .map_or(max_distance2, |(_, d2)| d2.min(max_distance2));
+                            d2 * inverse_variance
+                        })
+                        .sum::<f64>();
+                }
+            }
+            particule.map.insert_scan(&particule.pose, scan);
+        }
+
+        let n = self.particules.len() as f64;
+        if self.effective_particules() < self.config.resampling_threshold * n {
+            self.resample();
+        }
+    }
+
+    /// Systematic resampling, the maps are copied with the particules
+    fn resample(&mut self) {
+        let weights = self.weights();
+        let n = self.particules.len();
+        let draw = self.rng.gen::<f64>();
+        let mut index = 0;
+        let mut cumulative = weights[0];
+        let particules = (0..n)
+            .map(|i| {
+                let target = (i as f64 + draw) / n as f64;
+                while cumulative < target && index < n - 1 {
+                    index += 1;
+                    cumulative += weights[index];
+                }
+                GridParticule {
+                    log_weight: 0.0,
+                    ..self.particules[index].clone()
+                }
+            })
+            .collect();
+        self.particules = particules;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Walls of a room, every 10 cm
+    fn world() -> Vec<Vector2<f64>> {
+        let mut points = Vec::new();
+        for i in 0..=100 {
+            let x = -2.0 + i as f64 * 0.1;
+            points.push(Vector2::new(x, -2.0));
+            points.push(Vector2::new(x, 4.0));
+        }
+        for i in 1..60 {
+            let y = -2.0 + i as f64 * 0.1;
+            points.push(Vector2::new(-2.0, y));
+            points.push(Vector2::new(8.0, y));
+        }
+        points
+    }
+
+    #[test]
+    fn map_a_room_with_drifting_odometry() {
+        let world = world();
+        let scan = |pose: &Isometry2<f64>| -> Vec<Vector2<f64>> {
+            world
+                .iter()
+                .map(|p| (pose.inverse() * Point2::from(*p)).coords)
+                .collect()
+        };
+        let grid = OccupancyGrid::new(Vector2::new(-3.0, -3.0), 0.05, 240, 160);
+        let mut slam = GridSlam::new(grid, Isometry2::identity(), GridSlamConfig::default());
+
+        let mut truth = Isometry2::identity();
+        let mut odometry = Isometry2::identity();
+        for _ in 0..40 {
+            slam.update(&odometry, &scan(&truth));
+            let step = Isometry2::new(Vector2::new(0.1, 0.0), 0.02);
+            truth *= step;
+            // the wheels slip and the gyro drifts
+            odometry *= Isometry2::new(Vector2::new(0.11, 0.0), 0.025);
+        }
+        slam.update(&odometry, &scan(&truth));
+
+        let best = slam.best();
+        assert!((best.pose.translation.vector - truth.translation.vector).norm() < 0.1);
+        assert!((best.pose.rotation.angle() - truth.rotation.angle()).abs() < 0.02);
+        let drift = odometry.translation.vector - truth.translation.vector;
+        assert!(drift.norm() > 0.3);
+        let wall = best.map.world_to_cell(&Vector2::new(3.0, 4.0)).unwrap();
+        let free = best.map.world_to_cell(&Vector2::new(3.0, 1.0)).unwrap();
+        assert!(best.map.probability(wall) > 0.5);
+        assert!(best.map.probability(free) < 0.5);
+    }
+}
@@ -0,0 +1,283 @@
+#[cfg(feature = "serde-serialize")]
+use nalgebra::OMatrix;
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OVector, RealField};
+use rustc_hash::FxHashMap;
+use std::sync::OnceLock;
+
+use crate::utils::kdtree::KdTree;
+use crate::utils::state::GaussianState;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LandmarkMapConfig<T> {
+    /// Log odds of existence added when a landmark is observed
+    pub l_observed: T,
+    /// Log odds of existence added when a landmark is expected in view but not observed
+    pub l_missed: T,
+    /// The landmarks below these log odds are deleted by `prune`
+    pub l_delete: T,
+    /// The log odds are clamped to [-l_max, l_max] so a landmark can still be deleted
+    pub l_max: T,
+}
+
+impl<T: RealField> Default for LandmarkMapConfig<T> {
+    fn default() -> Self {
+        LandmarkMapConfig {
+            l_observed: T::from_f64(0.85).unwrap(),
+            l_missed: T::from_f64(-0.4).unwrap(),
+            l_delete: T::from_f64(-1.0).unwrap(),
+            l_max: T::from_f64(5.0).unwrap(),
+        }
+    }
+}
+
+/// Estimate of a landmark and the log odds that it exists
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde-serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "T: serde::Serialize, OVector<T, D>: serde::Serialize, OMatrix<T, D, D>: serde::Serialize",
+        deserialize = "T: serde::Deserialize<'de>, OVector<T, D>: serde::Deserialize<'de>, OMatrix<T, D, D>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct Landmark<T: RealField, D: Dim>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    pub state: GaussianState<T, D>,
+    pub log_odds: T,
+}
+
+impl<T: RealField, D: Dim> Landmark<T, D>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    pub fn existence_probability(&self) -> T {
+        T::one() - T::one() / (T::one() + self.log_odds.clone().exp())
+    }
+}
+
+/// Landmarks learned by a SLAM, e.g. the map of a FastSLAM particule or of an EKF-SLAM, by id.
+/// The existence of each landmark is tracked like an occupancy grid cell so the spurious ones
+/// can be deleted
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde-serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "T: serde::Serialize, OVector<T, D>: serde::Serialize, OMatrix<T, D, D>: serde::Serialize",
+        deserialize = "T: serde::Deserialize<'de>, OVector<T, D>: serde::Deserialize<'de>, OMatrix<T, D, D>: serde::Deserialize<'de>"
+    ))
+)]
+pub struct LandmarkMap<T: RealField, D: Dim>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    #[cfg_attr(
+        feature = "serde-serialize",
+        serde(skip, default = "LandmarkMapConfig::default")
+    )]
+    pub config: LandmarkMapConfig<T>,
+    landmarks: FxHashMap<u32, Landmark<T, D>>,
+    /// Built by the first `spatial_index` after the landmarks changed
+    #[cfg_attr(feature = "serde-serialize", serde(skip))]
+    index: OnceLock<(KdTree<T, D>, Vec<u32>)>,
+}
+
+impl<T: RealField + Copy, D: Dim> LandmarkMap<T, D>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    pub fn new(config: LandmarkMapConfig<T>) -> LandmarkMap<T, D> {
+        LandmarkMap {
+            config,
+            landmarks: FxHashMap::default(),
+            index: OnceLock::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.landmarks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.landmarks.is_empty()
+    }
+
+    pub fn contains(&self, id: u32) -> bool {
+        self.landmarks.contains_key(&id)
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Landmark<T, D>> {
+        self.landmarks.get(&id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Landmark<T, D>)> {
+        self.landmarks.iter().map(|(id, landmark)| (*id, landmark))
+    }
+
+    /// Adds a landmark seen for the first time, replaces the landmark if the id exists
+    pub fn insert(&mut self, id: u32, state: GaussianState<T, D>) {
+        let landmark = Landmark {
+            state,
+            log_odds: self.config.l_observed,
+        };
+        self.landmarks.insert(id, landmark);
+        self.index.take();
+    }
+
+    /// Replaces the estimate of an observed landmark and increases its existence, returns false
+    /// if the id is unknown
+    pub fn observe(&mut self, id: u32, state: GaussianState<T, D>) -> bool {
+        let l_observed = self.config.l_observed;
+        let l_max = self.config.l_max;
+        let Some(landmark) = self.landmarks.get_mut(&id) else {
+            return false;
+        };
+        landmark.state = state;
+        landmark.log_odds = (landmark.log_odds + l_observed).clamp(-l_max, l_max);
+        self.index.take();
+        true
+    }
+
+    /// Decreases the existence of the landmarks in view, according to `in_view`, which are not
+    /// in `observed`
+    pub fn decay(&mut self, observed: &[u32], in_view: impl Fn(&GaussianState<T, D>) -> bool) {
+        let l_missed = self.config.l_missed;
+        let l_max = self.config.l_max;
+        for (id, landmark) in self.landmarks.iter_mut() {
+            if !observed.contains(id) && in_view(&landmark.state) {
+                landmark.log_odds = (landmark.log_odds + l_missed).clamp(-l_max, l_max);
+            }
+        }
+    }
+
+    /// Deletes the landmarks unsupported by the observations, returns their ids
+    pub fn prune(&mut self) -> Vec<u32> {
+        let mut deleted: Vec<u32> = self
+            .landmarks
+            .iter()
+            .filter(|(_, landmark)| landmark.log_odds < self.config.l_delete)
+            .map(|(id, _)| *id)
+            .collect();
+        deleted.sort_unstable();
+        for id in &deleted {
+            self.landmarks.remove(id);
+        }
+        if !deleted.is_empty() {
+            self.index.take();
+        }
+        deleted
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Landmark<T, D>> {
+        self.index.take();
+        self.landmarks.remove(&id)
+    }
+
+    /// Positions of the landmarks, as expected by `ExtendedKalmanFilterKnownCorrespondences`
+    pub fn positions(&self) -> FxHashMap<u32, OVector<T, D>> {
+        self.landmarks
+            .iter()
+            .map(|(id, landmark)| (*id, landmark.state.x.clone()))
+            .collect()
+    }
+
+    /// k-d tree of the positions of the landmarks and the id of each of its points, to find the
+    /// landmarks close to the observations without going through the whole map. The tree is
+    /// kept until the landmarks are inserted, observed or removed
+    pub fn spatial_index(&self) -> (&KdTree<T, D>, &[u32]) {
+        let (tree, ids) = self.index.get_or_init(|| {
+            let (ids, positions): (Vec<u32>, Vec<OVector<T, D>>) = self
+                .landmarks
+                .iter()
+                .map(|(id, landmark)| (*id, landmark.state.x.clone()))
+                .unzip();
+            (KdTree::new(&positions), ids)
+        });
+        (tree, ids)
+    }
+
+    /// Id of the landmark closest to `position` and their distance, None if the map is empty
+    pub fn nearest(&self, position: &OVector<T, D>) -> Option<(u32, T)> {
+        let (tree, ids) = self.spatial_index();
+        tree.nearest(position)
+            .map(|(index, d2)| (ids[index], d2.sqrt()))
+    }
+
+    /// Map of the particule with the largest weight, from (weight, map) pairs. This is the
+    /// maximum likelihood map of a FastSLAM
+    pub fn most_likely<'a>(
+        weighted: impl IntoIterator<Item = (T, &'a LandmarkMap<T, D>)>,
+    ) -> Option<&'a LandmarkMap<T, D>> {
+        weighted
+            .into_iter()
+            .max_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal))
+            .map(|(_, map)| map)
+    }
+}
+
+#[cfg(feature = "serde-serialize")]
+impl<T: RealField, D: Dim> LandmarkMap<T, D>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+    LandmarkMap<T, D>: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Writes the landmarks as JSON, the config is not saved
+    pub fn save<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn std::error::Error>> {
+        crate::utils::persistence::save(writer, self)
+    }
+
+    /// Reads a map written by `save`, with the default config
+    pub fn load<R: std::io::Read>(
+        reader: R,
+    ) -> Result<LandmarkMap<T, D>, Box<dyn std::error::Error>> {
+        crate::utils::persistence::load(reader)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Const, Matrix2, Vector2};
+
+    fn state(x: f64, y: f64) -> GaussianState<f64, Const<2>> {
+        GaussianState {
+            x: Vector2::new(x, y),
+            cov: Matrix2::identity() * 0.1,
+        }
+    }
+
+    #[test]
+    fn prune_unsupported_landmarks() {
+        let mut map = LandmarkMap::new(LandmarkMapConfig::default());
+        map.insert(1, state(1.0, 0.0));
+        map.insert(2, state(5.0, 0.0));
+        // a spurious landmark near the robot
+        map.insert(3, state(1.0, 1.0));
+        for _ in 0..5 {
+            assert!(map.observe(1, state(1.0, 0.0)));
+            map.decay(&[1], |s| s.x.norm() < 3.0);
+        }
+        assert!(!map.observe(4, state(0.0, 0.0)));
+        assert_eq!(vec![3], map.prune());
+        assert_eq!(2, map.len());
+        assert!(map.get(1).unwrap().existence_probability() > 0.95);
+        // out of view, unchanged
+        approx::assert_abs_diff_eq!(0.85, map.get(2).unwrap().log_odds);
+        assert_eq!(Some(&Vector2::new(5.0, 0.0)), map.positions().get(&2));
+        let (tree, ids) = map.spatial_index();
+        let (nearest, _) = tree.nearest(&Vector2::new(4.5, 0.2)).unwrap();
+        assert_eq!(2, ids[nearest]);
+        // the cached tree follows the landmarks
+        assert!(map.observe(2, state(-4.0, 0.0)));
+        assert_eq!(
+            Some(1),
+            map.nearest(&Vector2::new(4.5, 0.2)).map(|(id, _)| id)
+        );
+
+        let empty = LandmarkMap::new(LandmarkMapConfig::default());
+        let best = LandmarkMap::most_likely([(0.2, &empty), (0.8, &map)]).unwrap();
+        assert_eq!(2, best.len());
+    }
+}
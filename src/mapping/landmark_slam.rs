@@ -0,0 +1,472 @@
+use nalgebra::{Isometry2, Matrix2, Matrix3, Point2, Vector2, Vector3};
+use rand::rngs::StdRng;
+use rand::seq::index::sample;
+use rand::SeedableRng;
+use rustc_hash::FxHashMap;
+use std::error::Error;
+
+use crate::mapping::pose_graph_optimization::{
+    Edge, EdgeSE2, EdgeSE2_XY, Node, PoseGraph, PoseGraphSolver,
+};
+use crate::utils::metrics::align_se2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LandmarkSlamConfig {
+    /// A keyframe is added when the robot moved this distance [m] or angle [rad] since the last
+    pub keyframe_distance: f64,
+    pub keyframe_angle: f64,
+    /// Information of the odometry between two keyframes
+    pub odometry_information: Matrix3<f64>,
+    /// Covariance of the [range, bearing] observations
+    pub observation_noise: Matrix2<f64>,
+    /// The signatures last observed more than this number of keyframes ago are not trusted, they
+    /// go through the loop closure detection
+    pub loop_keyframe_gap: usize,
+    /// Minimum number of observations explained by a loop closure
+    pub loop_min_inliers: usize,
+    /// Maximum distance [m] between an observation moved by the relative pose of a loop closure
+    /// and the landmark it is matched with
+    pub loop_tolerance: f64,
+    pub ransac_iterations: usize,
+    /// Information of the relative pose of a verified loop closure
+    pub loop_information: Matrix3<f64>,
+    /// Seed of the RANSAC samples
+    pub seed: u64,
+}
+
+impl Default for LandmarkSlamConfig {
+    fn default() -> Self {
+        LandmarkSlamConfig {
+            keyframe_distance: 0.5,
+            keyframe_angle: 0.3,
+            odometry_information: Matrix3::from_diagonal(&Vector3::new(100.0, 100.0, 400.0)),
+            observation_noise: Matrix2::from_diagonal(&Vector2::new(0.01, 0.0025)),
+            loop_keyframe_gap: 10,
+            loop_min_inliers: 3,
+            loop_tolerance: 0.3,
+            ransac_iterations: 50,
+            loop_information: Matrix3::from_diagonal(&Vector3::new(100.0, 100.0, 400.0)),
+            seed: 0,
+        }
+    }
+}
+
+/// Keyframe node of the graph and its observations (signature, position in the robot frame)
+#[derive(Debug, Clone)]
+pub struct Keyframe {
+    pub id: u32,
+    pub observations: Vec<(u32, Vector2<f64>)>,
+}
+
+/// Frontend of a graph-based landmark SLAM: accumulates the odometry and the [range, bearing]
+/// observations of landmarks with signatures into the pose and landmark factors of a `PoseGraph`.
+///
+/// The signatures are trusted while a landmark is tracked. A signature observed again after
+/// `loop_keyframe_gap` keyframes is a loop closure candidate: the old keyframe sharing the most
+/// such signatures is matched with the current one, RANSAC on two correspondences finds the
+/// relative pose explaining the most observations. A verified loop closure adds a pose factor
+/// between both keyframes and its inliers are associated with the old landmarks, the other
+/// signatures start new landmarks, e.g. two similar looking places
+pub struct LandmarkSlamFrontend {
+    pub config: LandmarkSlamConfig,
+    nodes: FxHashMap<u32, Node>,
+    edges: Vec<Edge<f64>>,
+    keyframes: Vec<Keyframe>,
+    /// Landmark node of each signature and the index of the last keyframe observing it
+    landmarks: FxHashMap<u32, (u32, usize)>,
+    loop_closures: Vec<(u32, u32)>,
+    /// Odometry since the last keyframe
+    since_keyframe: Isometry2<f64>,
+    next_id: u32,
+    rng: StdRng,
+}
+
+/// Relative pose of a verified loop closure from the old keyframe `index`, and its inliers
+struct LoopClosure {
+    index: usize,
+    relative: Isometry2<f64>,
+    inliers: Vec<u32>,
+}
+
+impl LandmarkSlamFrontend {
+    pub fn new(config: LandmarkSlamConfig) -> LandmarkSlamFrontend {
+        LandmarkSlamFrontend {
+            config,
+            nodes: FxHashMap::default(),
+            edges: Vec::new(),
+            keyframes: Vec::new(),
+            landmarks: FxHashMap::default(),
+            loop_closures: Vec::new(),
+            since_keyframe: Isometry2::identity(),
+            next_id: 0,
+            rng: StdRng::seed_from_u64(config.seed),
+        }
+    }
+
+    pub fn nodes(&self) -> &FxHashMap<u32, Node> {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &[Edge<f64>] {
+        &self.edges
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// (old, new) keyframes of the verified loop closures
+    pub fn loop_closures(&self) -> &[(u32, u32)] {
+        &self.loop_closures
+    }
+
+    pub fn keyframe_pose(&self, id: u32) -> Option<Isometry2<f64>> {
+        match self.nodes.get(&id) {
+            Some(Node::SE2(pose)) => Some(*pose),
+            _ => None,
+        }
+    }
+
+    /// Position of the current landmark of each signature
+    pub fn landmarks(&self) -> FxHashMap<u32, Vector2<f64>> {
+        self.landmarks
+            .iter()
+            .filter_map(|(signature, (id, _))| match self.nodes.get(id) {
+                Some(Node::XY(position)) => Some((*signature, *position)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Pose of the last keyframe composed with the odometry since, the first keyframe is the
+    /// origin of the map
+    pub fn pose(&self) -> Isometry2<f64> {
+        let last = self
+            .keyframes
+            .last()
+            .and_then(|keyframe| self.keyframe_pose(keyframe.id))
+            .unwrap_or_else(Isometry2::identity);
+        last * self.since_keyframe
+    }
+
+    /// Motion since the previous call, in the frame of the robot at the previous call
+    pub fn add_odometry(&mut self, delta: &Isometry2<f64>) {
+        self.since_keyframe *= delta;
+    }
+
+    /// [range, bearing] observations with the signatures of the landmarks at the current pose.
+    /// They are added with a new keyframe when the robot moved enough since the last one, and
+    /// dropped otherwise. Returns the id of the keyframe node
+    pub fn add_observations(&mut self, observations: &[(u32, Vector2<f64>)]) -> Option<u32> {
+        if !self.keyframes.is_empty()
+            && self.since_keyframe.translation.vector.norm() < self.config.keyframe_distance
+            && self.since_keyframe.rotation.angle().abs() < self.config.keyframe_angle
+        {
+            return None;
+        }
+        let pose = self.pose();
+        let id = self.new_id();
+        self.nodes.insert(id, Node::SE2(pose));
+        if let Some(previous) = self.keyframes.last() {
+            let edge = EdgeSE2::new(
+                previous.id,
+                id,
+                self.since_keyframe,
+                self.config.odometry_information,
+            );
+            self.edges.push(Edge::SE2_SE2(edge));
+        }
+        self.since_keyframe = Isometry2::identity();
+
+        let index = self.keyframes.len();
+        let points: Vec<(u32, Vector2<f64>)> = observations
+            .iter()
+            .map(|(signature, z)| (*signature, to_point(z)))
+            .collect();
+        let revisited: Vec<u32> = points
+            .iter()
+            .filter(|(signature, _)| {
+                self.landmarks
+                    .get(signature)
+                    .is_some_and(|(_, last)| last + self.config.loop_keyframe_gap <= index)
+            })
+            .map(|(signature, _)| *signature)
+            .collect();
+        let mut accepted = Vec::new();
+        if !revisited.is_empty() {
+            if let Some(closure) = self.detect_loop(index, &points, &revisited) {
+                let old = self.keyframes[closure.index].id;
+                let corrected = self.keyframe_pose(old).unwrap() * closure.relative;
+                // revisited signatures unknown to the old keyframe are checked with the landmarks
+                accepted = closure.inliers;
+                for (signature, point) in &points {
+                    if revisited.contains(signature) && !accepted.contains(signature) {
+                        let landmark = self.landmarks()[signature];
+                        if (corrected * Point2::from(*point))
+                            .coords
+                            .metric_distance(&landmark)
+                            <= self.config.loop_tolerance
+                        {
+                            accepted.push(*signature);
+                        }
+                    }
+                }
+                let edge = EdgeSE2::new(old, id, closure.relative, self.config.loop_information);
+                self.edges.push(Edge::SE2_SE2(edge));
+                self.loop_closures.push((old, id));
+            }
+        }
+
+        for (z, (signature, point)) in observations.iter().zip(&points) {
+            let known = self
+                .landmarks
+                .get(signature)
+                .map(|(landmark, _)| *landmark)
+                .filter(|_| !revisited.contains(signature) || accepted.contains(signature));
+            let landmark = match known {
+                Some(landmark) => landmark,
+                None => {
+                    let landmark = self.new_id();
+                    let position = pose * Point2::from(*point);
+                    self.nodes.insert(landmark, Node::XY(position.coords));
+                    landmark
+                }
+            };
+            self.landmarks.insert(*signature, (landmark, index));
+            let information = self.information(&z.1);
+            let edge = EdgeSE2_XY::new(id, landmark, *point, information);
+            self.edges.push(Edge::SE2_XY(edge));
+        }
+        self.keyframes.push(Keyframe {
+            id,
+            observations: points,
+        });
+        Some(id)
+    }
+
+    /// Graph of the factors, for any backend
+    pub fn graph(&self, solver: PoseGraphSolver) -> PoseGraph {
+        PoseGraph::from_parts(
+            "landmark_slam",
+            self.nodes.clone(),
+            self.edges.clone(),
+            solver,
+        )
+    }
+
+    /// Optimizes the graph and moves the keyframes and the landmarks to their optimized
+    /// positions, returns the errors of the iterations
+    pub fn optimize(&mut self, num_iterations: usize) -> Result<Vec<f64>, Box<dyn Error>> {
+        if self.keyframes.len() < 2 {
+            return Ok(Vec::new());
+        }
+        let mut graph = self.graph(PoseGraphSolver::GaussNewton);
+        let errors = graph.optimize(num_iterations, false, false)?;
+        self.nodes = graph.nodes().clone();
+        Ok(errors)
+    }
+
+    fn new_id(&mut self) -> u32 {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+
+    /// Information of the position of an observation with the bearing `z[1]` and range `z[0]`
+    fn information(&self, z: &Vector2<f64>) -> Matrix2<f64> {
+        let (sin, cos) = z[1].sin_cos();
+        let jacobian = Matrix2::new(cos, -z[0] * sin, sin, z[0] * cos);
+        (jacobian * self.config.observation_noise * jacobian.transpose())
+            .try_inverse()
+            .unwrap_or_else(Matrix2::identity)
+    }
+
+    /// Matches the current observations `points` with the old keyframe sharing the most
+    /// `revisited` signatures
+    fn detect_loop(
+        &mut self,
+        index: usize,
+        points: &[(u32, Vector2<f64>)],
+        revisited: &[u32],
+    ) -> Option<LoopClosure> {
+        let old_keyframes = index.checked_sub(self.config.loop_keyframe_gap)? + 1;
+        let (candidate, shared) = self.keyframes[..old_keyframes]
+            .iter()
+            .enumerate()
+            .map(|(i, keyframe)| {
+                let shared = keyframe
+                    .observations
+                    .iter()
+                    .filter(|(signature, _)| revisited.contains(signature))
+                    .count();
+                (i, shared)
+            })
+            .max_by_key(|(i, shared)| (*shared, *i))?;
+        if shared < self.config.loop_min_inliers.max(2) {
+            return None;
+        }
+
+        // (signature, point in the current frame, point in the old frame)
+        let correspondences: Vec<(u32, Vector2<f64>, Vector2<f64>)> = points
+            .iter()
+            .filter(|(signature, _)| revisited.contains(signature))
+            .filter_map(|(signature, point)| {
+                let (_, old) = self.keyframes[candidate]
+                    .observations
+                    .iter()
+                    .find(|(s, _)| s == signature)?;
+                Some((*signature, *point, *old))
+            })
+            .collect();
+        let inliers_of = |relative: &Isometry2<f64>| -> Vec<u32> {
+            correspondences
+                .iter()
+                .filter(|(_, current, old)| {
+                    (relative * Point2::from(*current))
+                        .coords
+                        .metric_distance(old)
+                        <= self.config.loop_tolerance
+                })
+                .map(|(signature, _, _)| *signature)
+                .collect()
+        };
+
+        let mut best: Option<(Isometry2<f64>, Vec<u32>)> = None;
+        for _ in 0..self.config.ransac_iterations {
+            let idx = sample(&mut self.rng, correspondences.len(), 2);
+            let (a, b) = (
+                &correspondences[idx.index(0)],
+                &correspondences[idx.index(1)],
+            );
+            if (a.1 - b.1).norm() < self.config.loop_tolerance {
+                continue;
+            }
+            let relative = align_se2(&[a.1, b.1], &[a.2, b.2]);
+            let inliers = inliers_of(&relative);
+            if best.as_ref().map_or(true, |(_, b)| inliers.len() > b.len()) {
+                best = Some((relative, inliers));
+            }
+        }
+        let (relative, inliers) = best?;
+        // refine on the inliers
+        let (current, old): (Vec<Vector2<f64>>, Vec<Vector2<f64>>) = correspondences
+            .iter()
+            .filter(|(signature, _, _)| inliers.contains(signature))
+            .map(|(_, current, old)| (*current, *old))
+            .unzip();
+        let refined = align_se2(&current, &old);
+        let refined_inliers = inliers_of(&refined);
+        let (relative, inliers) = if refined_inliers.len() >= inliers.len() {
+            (refined, refined_inliers)
+        } else {
+            (relative, inliers)
+        };
+        if inliers.len() < self.config.loop_min_inliers {
+            return None;
+        }
+        Some(LoopClosure {
+            index: candidate,
+            relative,
+            inliers,
+        })
+    }
+}
+
+/// Position in the robot frame of a [range, bearing] observation
+fn to_point(z: &Vector2<f64>) -> Vector2<f64> {
+    let (sin, cos) = z[1].sin_cos();
+    Vector2::new(z[0] * cos, z[0] * sin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// [range, bearing] of the landmark at `position` seen from `pose`
+    fn observe(pose: &Isometry2<f64>, position: &Vector2<f64>) -> Vector2<f64> {
+        let p = pose.inverse_transform_point(&Point2::from(*position));
+        Vector2::new(p.coords.norm(), p.y.atan2(p.x))
+    }
+
+    #[test]
+    fn loop_closure_corrects_the_drift() {
+        let center = Vector2::new(0.0, 5.0);
+        let landmarks: Vec<Vector2<f64>> = (0..12)
+            .map(|i| {
+                let angle = i as f64 * std::f64::consts::PI / 6.0;
+                center + Vector2::new(angle.cos(), angle.sin()) * 7.0
+            })
+            .chain((0..8).map(|i| {
+                let angle = i as f64 * std::f64::consts::PI / 4.0 + 0.3;
+                center + Vector2::new(angle.cos(), angle.sin()) * 3.0
+            }))
+            .collect();
+
+        let mut frontend = LandmarkSlamFrontend::new(LandmarkSlamConfig::default());
+        // 1.5 lap on a circle of radius 5 with a biased odometry
+        let (v, w, dt) = (0.5, 0.1, 0.5);
+        let step = Isometry2::new(Vector2::new(v * dt, 0.0), w * dt);
+        let drifted = Isometry2::new(Vector2::new(v * dt * 1.02, 0.0), w * dt + 0.002);
+        let mut truth = Isometry2::identity();
+        for _ in 0..190 {
+            truth *= step;
+            frontend.add_odometry(&drifted);
+            let observations: Vec<(u32, Vector2<f64>)> = landmarks
+                .iter()
+                .enumerate()
+                .map(|(signature, position)| (signature as u32, observe(&truth, position)))
+                .filter(|(_, z)| z[0] < 4.0)
+                .collect();
+            frontend.add_observations(&observations);
+        }
+        assert!(!frontend.loop_closures().is_empty());
+        let drift = frontend.pose().translation.vector - truth.translation.vector;
+        assert!(drift.norm() > 1.0);
+
+        frontend.optimize(20).unwrap();
+        let error = frontend.pose().translation.vector - truth.translation.vector;
+        assert!(error.norm() < 0.2);
+        for (signature, position) in frontend.landmarks() {
+            assert!((position - landmarks[signature as usize]).norm() < 0.2);
+        }
+    }
+
+    #[test]
+    fn wrong_signature_is_rejected() {
+        let config = LandmarkSlamConfig {
+            loop_keyframe_gap: 1,
+            loop_min_inliers: 2,
+            ..LandmarkSlamConfig::default()
+        };
+        let mut frontend = LandmarkSlamFrontend::new(config);
+        let origin = Isometry2::identity();
+        let landmarks = [
+            Vector2::new(2.0, 0.0),
+            Vector2::new(0.0, 2.0),
+            Vector2::new(-2.0, 0.0),
+        ];
+        let observations: Vec<(u32, Vector2<f64>)> = landmarks
+            .iter()
+            .enumerate()
+            .map(|(signature, position)| (signature as u32, observe(&origin, position)))
+            .collect();
+        let first = frontend.add_observations(&observations).unwrap();
+
+        let moved = Isometry2::new(Vector2::new(1.0, 0.0), 0.0);
+        frontend.add_odometry(&moved);
+        let mut observations: Vec<(u32, Vector2<f64>)> = landmarks
+            .iter()
+            .enumerate()
+            .map(|(signature, position)| (signature as u32, observe(&moved, position)))
+            .collect();
+        // another landmark with the signature 2
+        observations[2].1 = observe(&moved, &Vector2::new(-2.0, -3.0));
+        let second = frontend.add_observations(&observations).unwrap();
+
+        assert_eq!(frontend.loop_closures(), &[(first, second)]);
+        let map = frontend.landmarks();
+        approx::assert_abs_diff_eq!(map[&0], landmarks[0], epsilon = 1e-9);
+        approx::assert_abs_diff_eq!(map[&2], Vector2::new(-2.0, -3.0), epsilon = 1e-9);
+        // the old landmark 2 is kept in the graph
+        assert_eq!(frontend.nodes().len(), 2 + 4);
+    }
+}
@@ -1,6 +1,17 @@
+mod costmap;
 mod ekf_slam_known;
 mod g2o;
+mod grid_slam;
+mod landmark_map;
+mod landmark_slam;
+mod occupancy_grid;
 mod pose_graph_optimization;
+pub mod pose_graph_tools;
 mod se2_se3;
 
-pub use pose_graph_optimization::{PoseGraph, PoseGraphSolver};
+pub use costmap::{Costmap, CostmapConfig};
+pub use grid_slam::{GridParticule, GridSlam, GridSlamConfig};
+pub use landmark_map::{Landmark, LandmarkMap, LandmarkMapConfig};
+pub use landmark_slam::{Keyframe, LandmarkSlamConfig, LandmarkSlamFrontend};
+pub use occupancy_grid::OccupancyGrid;
+pub use pose_graph_optimization::{Edge, EdgeSE2, EdgeSE3, Node, PoseGraph, PoseGraphSolver};
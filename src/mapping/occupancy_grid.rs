@@ -0,0 +1,148 @@
+use nalgebra::{Isometry2, Point2, Vector2};
+
+/// Log odds occupancy grid
+///
+/// Probabilistic Robotics p. 286
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde-serialize",
+    derive(serde::Serialize, serde::Deserialize)
+)]
+pub struct OccupancyGrid {
+    /// World position of the corner of the cell (0, 0)
+    pub origin: Vector2<f64>,
+    /// Size of a cell [m]
+    pub resolution: f64,
+    pub width: usize,
+    pub height: usize,
+    /// Row major, index = row * width + column
+    pub log_odds: Vec<f64>,
+    /// Log odds added to a cell hit by a beam
+    pub l_occupied: f64,
+    /// Log odds added to a cell crossed by a beam
+    pub l_free: f64,
+    /// The log odds are clamped to [-l_max, l_max] so the map stays responsive to changes
+    pub l_max: f64,
+}
+
+impl OccupancyGrid {
+    /// Unknown grid (p = 0.5) of `width` x `height` cells
+    pub fn new(
+        origin: Vector2<f64>,
+        resolution: f64,
+        width: usize,
+        height: usize,
+    ) -> OccupancyGrid {
+        OccupancyGrid {
+            origin,
+            resolution,
+            width,
+            height,
+            log_odds: vec![0.0; width * height],
+            l_occupied: 0.85,
+            l_free: -0.4,
+            l_max: 5.0,
+        }
+    }
+
+    /// Cell (column, row) containing the world point
+    pub fn world_to_cell(&self, p: &Vector2<f64>) -> Option<(usize, usize)> {
+        let c = ((p - self.origin) / self.resolution).map(f64::floor);
+        if c.x < 0.0 || c.y < 0.0 || c.x >= self.width as f64 || c.y >= self.height as f64 {
+            return None;
+        }
+        Some((c.x as usize, c.y as usize))
+    }
+
+    /// World position of the center of the cell
+    pub fn cell_to_world(&self, (column, row): (usize, usize)) -> Vector2<f64> {
+        self.origin + Vector2::new(column as f64 + 0.5, row as f64 + 0.5) * self.resolution
+    }
+
+    pub fn index(&self, (column, row): (usize, usize)) -> usize {
+        row * self.width + column
+    }
+
+    pub fn probability(&self, cell: (usize, usize)) -> f64 {
+        1.0 - 1.0 / (1.0 + self.log_odds[self.index(cell)].exp())
+    }
+
+    pub fn update_cell(&mut self, cell: (usize, usize), log_odds: f64) {
+        let i = self.index(cell);
+        self.log_odds[i] = (self.log_odds[i] + log_odds).clamp(-self.l_max, self.l_max);
+    }
+
+    /// Marks the cells between `from` and `to` as free, and the cell of `to` as occupied if `hit`
+    pub fn insert_ray(&mut self, from: &Vector2<f64>, to: &Vector2<f64>, hit: bool) {
+        let (Some(start), Some(end)) = (self.world_to_cell(from), self.world_to_cell(to)) else {
+            return;
+        };
+        let cells = bresenham(start, end);
+        let (last, free) = cells.split_last().unwrap();
+        for cell in free {
+            self.update_cell(*cell, self.l_free);
+        }
+        let l_last = if hit { self.l_occupied } else { self.l_free };
+        self.update_cell(*last, l_last);
+    }
+
+    /// Integrates the scan points, given in the sensor frame, taken from `pose`
+    pub fn insert_scan(&mut self, pose: &Isometry2<f64>, points: &[Vector2<f64>]) {
+        let origin = pose.translation.vector;
+        for p in points {
+            let p = (pose * Point2::from(*p)).coords;
+            self.insert_ray(&origin, &p, true);
+        }
+    }
+}
+
+/// Cells crossed by the segment, both ends included
+pub(crate) fn bresenham(start: (usize, usize), end: (usize, usize)) -> Vec<(usize, usize)> {
+    let (mut x, mut y) = (start.0 as i64, start.1 as i64);
+    let (x1, y1) = (end.0 as i64, end.1 as i64);
+    let dx = (x1 - x).abs();
+    let dy = -(y1 - y).abs();
+    let sx = if x < x1 { 1 } else { -1 };
+    let sy = if y < y1 { 1 } else { -1 };
+    let mut error = dx + dy;
+    let mut cells = Vec::with_capacity((dx - dy) as usize + 1);
+    loop {
+        cells.push((x as usize, y as usize));
+        if x == x1 && y == y1 {
+            break;
+        }
+        let e2 = 2 * error;
+        if e2 >= dy {
+            error += dy;
+            x += sx;
+        }
+        if e2 <= dx {
+            error += dx;
+            y += sy;
+        }
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scan_insertion() {
+        let mut grid = OccupancyGrid::new(Vector2::new(-5.0, -5.0), 0.1, 100, 100);
+        let pose = Isometry2::new(Vector2::new(0.0, 0.0), std::f64::consts::FRAC_PI_2);
+        // wall 2 m in front of the robot, which faces +y
+        grid.insert_scan(&pose, &[Vector2::new(2.0, 0.0)]);
+
+        let wall = grid.world_to_cell(&Vector2::new(0.0, 2.0)).unwrap();
+        let free = grid.world_to_cell(&Vector2::new(0.0, 1.0)).unwrap();
+        assert!(grid.probability(wall) > 0.5);
+        assert!(grid.probability(free) < 0.5);
+        approx::assert_abs_diff_eq!(
+            Vector2::new(0.05, 2.05),
+            grid.cell_to_world(wall),
+            epsilon = 1e-9
+        );
+    }
+}
@@ -15,7 +15,7 @@ use std::error::Error;
 use crate::mapping::g2o::parse_g2o;
 use crate::mapping::se2_se3::{jacobian_so3, skew, skew_m_and_mult_parts};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Edge<T> {
     SE2_SE2(EdgeSE2<T>),
     SE2_XY(EdgeSE2_XY<T>),
@@ -23,13 +23,25 @@ pub enum Edge<T> {
     SE3_XYZ,
 }
 
+impl<T> Edge<T> {
+    /// (from, to) ids of the nodes linked by the edge
+    pub fn nodes(&self) -> Option<(u32, u32)> {
+        match self {
+            Edge::SE2_SE2(e) => Some((e.from, e.to)),
+            Edge::SE2_XY(e) => Some((e.from, e.to)),
+            Edge::SE3_SE3(e) => Some((e.from, e.to)),
+            Edge::SE3_XYZ => None,
+        }
+    }
+}
+
 #[derive(PartialEq, Debug)]
 pub enum PoseGraphSolver {
     GaussNewton,
     LevenbergMarquardt,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EdgeSE2<T> {
     from: u32,
     to: u32,
@@ -53,7 +65,7 @@ impl<T> EdgeSE2<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EdgeSE2_XY<T> {
     from: u32,
     to: u32,
@@ -77,7 +89,7 @@ impl<T> EdgeSE2_XY<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct EdgeSE3<T> {
     from: u32,
     to: u32,
@@ -102,7 +114,7 @@ impl<T> EdgeSE3<T> {
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug, Clone)]
 pub enum Node {
     SE2(Isometry2<f64>),
     SE3(Isometry3<f64>),
@@ -240,6 +252,50 @@ impl PoseGraph {
         })
     }
 
+    /// Graph built in memory, e.g. by a SLAM frontend. Every node must be linked by an edge and
+    /// the first pose of the first `Edge::SE2_SE2` is fixed
+    pub fn from_parts(
+        name: &str,
+        nodes: FxHashMap<u32, Node>,
+        edges: Vec<Edge<f64>>,
+        solver: PoseGraphSolver,
+    ) -> PoseGraph {
+        let mut ids: Vec<u32> = nodes.keys().copied().collect();
+        ids.sort_unstable();
+        let mut lut = FxHashMap::default();
+        let mut len = 0;
+        for id in ids {
+            lut.insert(id, len);
+            len += match nodes[&id] {
+                Node::SE2(_) => 3,
+                Node::SE3(_) => 6,
+                Node::XY(_) => 2,
+                Node::XYZ(_) => 3,
+            };
+        }
+        PoseGraph {
+            len,
+            nodes,
+            edges,
+            lut,
+            iteration: 0,
+            name: name.to_string(),
+            solver,
+        }
+    }
+
+    pub fn nodes(&self) -> &FxHashMap<u32, Node> {
+        &self.nodes
+    }
+
+    pub fn edges(&self) -> &[Edge<f64>] {
+        &self.edges
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     fn update_nodes(&mut self, dx: &DVector<f64>) {
         self.nodes.par_iter_mut().for_each(|(id, node)| {
             let offset = *self.lut.get(id).unwrap();
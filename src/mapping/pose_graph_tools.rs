@@ -0,0 +1,214 @@
+use rustc_hash::FxHashMap;
+use std::fmt::Write;
+
+use crate::mapping::pose_graph_optimization::{Edge, Node, PoseGraph};
+
+/// Edges between poses whose ids are not consecutive, usually the loop closures
+fn is_loop_closure(edge: &Edge<f64>) -> bool {
+    match (edge, edge.nodes()) {
+        (Edge::SE2_SE2(_) | Edge::SE3_SE3(_), Some((from, to))) => from.abs_diff(to) > 1,
+        _ => false,
+    }
+}
+
+/// Position of a node in the plane (x, y)
+fn xy(node: &Node) -> (f64, f64) {
+    match node {
+        Node::SE2(n) => (n.translation.x, n.translation.y),
+        Node::SE3(n) => (n.translation.x, n.translation.y),
+        Node::XY(n) => (n.x, n.y),
+        Node::XYZ(n) => (n.x, n.y),
+    }
+}
+
+fn sorted_ids(nodes: &FxHashMap<u32, Node>) -> Vec<u32> {
+    let mut ids: Vec<u32> = nodes.keys().copied().collect();
+    ids.sort();
+    ids
+}
+
+/// Graphviz graph with the nodes pinned at their (x, y) position, render it with `neato -n`.
+/// The landmarks are boxes and the loop closures are red
+pub fn to_dot(graph: &PoseGraph) -> String {
+    let mut dot = String::new();
+    writeln!(dot, "graph \"{}\" {{", graph.name()).unwrap();
+    writeln!(dot, "  node [shape=point];").unwrap();
+    for id in sorted_ids(graph.nodes()) {
+        let node = &graph.nodes()[&id];
+        let (x, y) = xy(node);
+        let shape = match node {
+            Node::XY(_) | Node::XYZ(_) => ", shape=box, width=0.1, height=0.1",
+            _ => "",
+        };
+        writeln!(dot, "  {id} [pos=\"{x},{y}!\"{shape}];").unwrap();
+    }
+    for edge in graph.edges() {
+        let Some((from, to)) = edge.nodes() else {
+            continue;
+        };
+        let style = if is_loop_closure(edge) {
+            " [color=red]"
+        } else {
+            ""
+        };
+        writeln!(dot, "  {from} -- {to}{style};").unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// GeoJSON FeatureCollection with a Point per node and a LineString per edge,
+/// the coordinates are the (x, y) of the graph and not longitudes and latitudes
+pub fn to_geojson(graph: &PoseGraph) -> String {
+    let mut features = Vec::new();
+    for id in sorted_ids(graph.nodes()) {
+        let node = &graph.nodes()[&id];
+        let (x, y) = xy(node);
+        let (kind, extra) = match node {
+            Node::SE2(n) => ("SE2", format!(",\"theta\":{}", n.rotation.angle())),
+            Node::SE3(n) => ("SE3", format!(",\"z\":{}", n.translation.z)),
+            Node::XY(_) => ("XY", String::new()),
+            Node::XYZ(n) => ("XYZ", format!(",\"z\":{}", n.z)),
+        };
+        features.push(format!(
+            "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"Point\",\"coordinates\":[{x},{y}]}},\
+             \"properties\":{{\"id\":{id},\"kind\":\"{kind}\"{extra}}}}}"
+        ));
+    }
+    for edge in graph.edges() {
+        let Some((from, to)) = edge.nodes() else {
+            continue;
+        };
+        let (Some(a), Some(b)) = (graph.nodes().get(&from), graph.nodes().get(&to)) else {
+            continue;
+        };
+        let ((xa, ya), (xb, yb)) = (xy(a), xy(b));
+        features.push(format!(
+            "{{\"type\":\"Feature\",\"geometry\":{{\"type\":\"LineString\",\"coordinates\":[[{xa},{ya}],[{xb},{yb}]]}},\
+             \"properties\":{{\"from\":{from},\"to\":{to},\"loop_closure\":{}}}}}",
+            is_loop_closure(edge)
+        ));
+    }
+    format!(
+        "{{\"type\":\"FeatureCollection\",\"features\":[{}]}}",
+        features.join(",")
+    )
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NodeDisplacement {
+    pub id: u32,
+    pub translation: f64,
+    /// Rotation angle between the two orientations, 0 for the landmarks [rad]
+    pub rotation: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphDiff {
+    /// Sorted from the largest translation
+    pub displacements: Vec<NodeDisplacement>,
+    /// Nodes present in only one of the graphs
+    pub missing: Vec<u32>,
+    pub mean_translation: f64,
+    pub rmse_translation: f64,
+    pub max_translation: f64,
+    pub mean_rotation: f64,
+    pub max_rotation: f64,
+}
+
+/// Per node displacement between two graphs, typically the same graph before and after
+/// optimization or optimized by two backends. No alignment is done, the first pose is
+/// fixed by the optimizer
+pub fn diff(a: &PoseGraph, b: &PoseGraph) -> GraphDiff {
+    diff_nodes(a.nodes(), b.nodes())
+}
+
+pub fn diff_nodes(a: &FxHashMap<u32, Node>, b: &FxHashMap<u32, Node>) -> GraphDiff {
+    let mut displacements = Vec::new();
+    let mut missing: Vec<u32> = b.keys().filter(|id| !a.contains_key(id)).copied().collect();
+    for (id, node_a) in a {
+        let Some(node_b) = b.get(id) else {
+            missing.push(*id);
+            continue;
+        };
+        let (translation, rotation) = match (node_a, node_b) {
+            (Node::SE2(p), Node::SE2(q)) => (
+                (p.translation.vector - q.translation.vector).norm(),
+                p.rotation.angle_to(&q.rotation).abs(),
+            ),
+            (Node::SE3(p), Node::SE3(q)) => (
+                (p.translation.vector - q.translation.vector).norm(),
+                p.rotation.angle_to(&q.rotation),
+            ),
+            (Node::XY(p), Node::XY(q)) => ((p - q).norm(), 0.0),
+            (Node::XYZ(p), Node::XYZ(q)) => ((p - q).norm(), 0.0),
+            // the node changed type, it is not the same node
+            _ => {
+                missing.push(*id);
+                continue;
+            }
+        };
+        displacements.push(NodeDisplacement {
+            id: *id,
+            translation,
+            rotation,
+        });
+    }
+    displacements.sort_by(|x, y| {
+        y.translation
+            .partial_cmp(&x.translation)
+            .unwrap()
+            .then(x.id.cmp(&y.id))
+    });
+    missing.sort();
+
+    let n = displacements.len().max(1) as f64;
+    let translations = displacements.iter().map(|d| d.translation);
+    let rotations = displacements.iter().map(|d| d.rotation);
+    GraphDiff {
+        mean_translation: translations.clone().sum::<f64>() / n,
+        rmse_translation: (translations.clone().map(|t| t * t).sum::<f64>() / n).sqrt(),
+        max_translation: translations.fold(0.0, f64::max),
+        mean_rotation: rotations.clone().sum::<f64>() / n,
+        max_rotation: rotations.fold(0.0, f64::max),
+        displacements,
+        missing,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Isometry2, Vector2};
+
+    #[test]
+    fn export_and_diff() {
+        let graph = PoseGraph::from_g2o("dataset/g2o/simulation-pose-landmark.g2o").unwrap();
+        let dot = to_dot(&graph);
+        assert!(dot.starts_with("graph \"simulation-pose-landmark\" {"));
+        assert_eq!(graph.edges().len(), dot.matches(" -- ").count());
+        let geojson = to_geojson(&graph);
+        assert_eq!(
+            graph.nodes().len() + graph.edges().len(),
+            geojson.matches("\"type\":\"Feature\"").count()
+        );
+        assert_eq!(0.0, diff(&graph, &graph).max_translation);
+
+        let a: FxHashMap<u32, Node> = [
+            (0, Node::SE2(Isometry2::new(Vector2::new(0.0, 0.0), 0.0))),
+            (1, Node::SE2(Isometry2::new(Vector2::new(1.0, 0.0), 0.0))),
+            (2, Node::XY(Vector2::new(5.0, 5.0))),
+        ]
+        .into_iter()
+        .collect();
+        let mut b = a.clone();
+        b.insert(1, Node::SE2(Isometry2::new(Vector2::new(1.0, 2.0), 0.5)));
+        b.remove(&2);
+        let d = diff_nodes(&a, &b);
+        assert_eq!(vec![2], d.missing);
+        assert_eq!(1, d.displacements[0].id);
+        approx::assert_abs_diff_eq!(2.0, d.max_translation);
+        approx::assert_abs_diff_eq!(0.5, d.max_rotation, epsilon = 1e-12);
+        approx::assert_abs_diff_eq!(2.0_f64.sqrt(), d.rmse_translation);
+    }
+}
@@ -0,0 +1,322 @@
+use nalgebra::{
+    Isometry3, Matrix3, Matrix6, Rotation3, SMatrix, SVector, Translation3, UnitQuaternion, Vector3,
+};
+
+use crate::mapping::EdgeSE3;
+
+/// Orientation (body to world), velocity and position of the IMU in the world frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NavState {
+    pub rotation: Rotation3<f64>,
+    pub velocity: Vector3<f64>,
+    pub position: Vector3<f64>,
+}
+
+/// Continuous time white noise densities of the IMU, [rad/s/sqrt(Hz)] and [m/s²/sqrt(Hz)]
+#[derive(Debug, Clone, Copy)]
+pub struct ImuNoise {
+    pub gyro: f64,
+    pub accel: f64,
+}
+
+/// Right jacobian of SO(3)
+fn right_jacobian(phi: &Vector3<f64>) -> Matrix3<f64> {
+    let theta = phi.norm();
+    let k = phi.cross_matrix();
+    if theta < 1e-6 {
+        return Matrix3::identity() - k * 0.5;
+    }
+    Matrix3::identity() - k * ((1.0 - theta.cos()) / theta.powi(2))
+        + k * k * ((theta - theta.sin()) / theta.powi(3))
+}
+
+/// Rotation, velocity and position increments between two keyframes accumulated from the IMU
+/// samples in the frame of the first keyframe, independently of its state, so they are computed
+/// once and reused at each iteration of the estimator. A change of the bias is applied to first
+/// order with the jacobians instead of integrating again.
+///
+/// The covariance is ordered (rotation, velocity, position)
+///
+/// Source : Forster, Carlone, Dellaert & Scaramuzza, On-manifold preintegration for real-time
+/// visual-inertial odometry, 2017
+#[derive(Debug, Clone)]
+pub struct ImuPreintegration {
+    pub noise: ImuNoise,
+    /// Biases (gyroscope, accelerometer) of the integration
+    gyro_bias: Vector3<f64>,
+    accel_bias: Vector3<f64>,
+    delta_rotation: Rotation3<f64>,
+    delta_velocity: Vector3<f64>,
+    delta_position: Vector3<f64>,
+    delta_time: f64,
+    covariance: SMatrix<f64, 9, 9>,
+    d_rotation_d_gyro_bias: Matrix3<f64>,
+    d_velocity_d_gyro_bias: Matrix3<f64>,
+    d_velocity_d_accel_bias: Matrix3<f64>,
+    d_position_d_gyro_bias: Matrix3<f64>,
+    d_position_d_accel_bias: Matrix3<f64>,
+}
+
+impl ImuPreintegration {
+    pub fn new(
+        noise: ImuNoise,
+        gyro_bias: Vector3<f64>,
+        accel_bias: Vector3<f64>,
+    ) -> ImuPreintegration {
+        ImuPreintegration {
+            noise,
+            gyro_bias,
+            accel_bias,
+            delta_rotation: Rotation3::identity(),
+            delta_velocity: Vector3::zeros(),
+            delta_position: Vector3::zeros(),
+            delta_time: 0.0,
+            covariance: SMatrix::zeros(),
+            d_rotation_d_gyro_bias: Matrix3::zeros(),
+            d_velocity_d_gyro_bias: Matrix3::zeros(),
+            d_velocity_d_accel_bias: Matrix3::zeros(),
+            d_position_d_gyro_bias: Matrix3::zeros(),
+            d_position_d_accel_bias: Matrix3::zeros(),
+        }
+    }
+
+    /// Starts a new interval with the given biases, e.g. the latest estimate of the filter
+    pub fn reset(&mut self, gyro_bias: Vector3<f64>, accel_bias: Vector3<f64>) {
+        *self = ImuPreintegration::new(self.noise, gyro_bias, accel_bias);
+    }
+
+    /// Adds a sample of the gyroscope [rad/s] and accelerometer [m/s²] held for `dt` [s]
+    pub fn integrate(&mut self, gyro: &Vector3<f64>, accel: &Vector3<f64>, dt: f64) {
+        let a = accel - self.accel_bias;
+        let phi = (gyro - self.gyro_bias) * dt;
+        let increment = Rotation3::new(phi);
+        let jr = right_jacobian(&phi);
+        let r = *self.delta_rotation.matrix();
+        let a_skew = a.cross_matrix();
+        let dt2 = dt * dt;
+
+        // the jacobians use the increments before this sample
+        self.d_position_d_accel_bias += self.d_velocity_d_accel_bias * dt - r * (0.5 * dt2);
+        self.d_position_d_gyro_bias += self.d_velocity_d_gyro_bias * dt
+            - r * a_skew * self.d_rotation_d_gyro_bias * (0.5 * dt2);
+        self.d_velocity_d_accel_bias -= r * dt;
+        self.d_velocity_d_gyro_bias -= r * a_skew * self.d_rotation_d_gyro_bias * dt;
+        self.d_rotation_d_gyro_bias =
+            increment.matrix().transpose() * self.d_rotation_d_gyro_bias - jr * dt;
+
+        let mut transition = SMatrix::<f64, 9, 9>::identity();
+        transition
+            .fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&increment.matrix().transpose());
+        transition
+            .fixed_view_mut::<3, 3>(3, 0)
+            .copy_from(&(-r * a_skew * dt));
+        transition
+            .fixed_view_mut::<3, 3>(6, 0)
+            .copy_from(&(-r * a_skew * (0.5 * dt2)));
+        transition
+            .fixed_view_mut::<3, 3>(6, 3)
+            .copy_from(&(Matrix3::identity() * dt));
+        let mut input = SMatrix::<f64, 9, 6>::zeros();
+        input.fixed_view_mut::<3, 3>(0, 0).copy_from(&(jr * dt));
+        input.fixed_view_mut::<3, 3>(3, 3).copy_from(&(r * dt));
+        input
+            .fixed_view_mut::<3, 3>(6, 3)
+            .copy_from(&(r * (0.5 * dt2)));
+        let mut noise = SMatrix::<f64, 6, 6>::zeros();
+        noise
+            .fixed_view_mut::<3, 3>(0, 0)
+            .fill_diagonal(self.noise.gyro.powi(2) / dt);
+        noise
+            .fixed_view_mut::<3, 3>(3, 3)
+            .fill_diagonal(self.noise.accel.powi(2) / dt);
+        self.covariance = transition * self.covariance * transition.transpose()
+            + input * noise * input.transpose();
+
+        self.delta_position += self.delta_velocity * dt + r * a * (0.5 * dt2);
+        self.delta_velocity += r * a * dt;
+        self.delta_rotation *= increment;
+        self.delta_time += dt;
+    }
+
+    pub fn delta_time(&self) -> f64 {
+        self.delta_time
+    }
+
+    pub fn covariance(&self) -> &SMatrix<f64, 9, 9> {
+        &self.covariance
+    }
+
+    /// Increments (rotation, velocity, position) for other biases, to first order
+    pub fn corrected_deltas(
+        &self,
+        gyro_bias: &Vector3<f64>,
+        accel_bias: &Vector3<f64>,
+    ) -> (Rotation3<f64>, Vector3<f64>, Vector3<f64>) {
+        let dbg = gyro_bias - self.gyro_bias;
+        let dba = accel_bias - self.accel_bias;
+        (
+            self.delta_rotation * Rotation3::new(self.d_rotation_d_gyro_bias * dbg),
+            self.delta_velocity
+                + self.d_velocity_d_gyro_bias * dbg
+                + self.d_velocity_d_accel_bias * dba,
+            self.delta_position
+                + self.d_position_d_gyro_bias * dbg
+                + self.d_position_d_accel_bias * dba,
+        )
+    }
+
+    /// State at the end of the interval from the state at its start, `gravity` is in the world
+    /// frame, e.g. (0, 0, -9.81) in ENU
+    pub fn predict(
+        &self,
+        state: &NavState,
+        gravity: &Vector3<f64>,
+        gyro_bias: &Vector3<f64>,
+        accel_bias: &Vector3<f64>,
+    ) -> NavState {
+        let (dr, dv, dp) = self.corrected_deltas(gyro_bias, accel_bias);
+        let t = self.delta_time;
+        NavState {
+            rotation: state.rotation * dr,
+            velocity: state.velocity + gravity * t + state.rotation * dv,
+            position: state.position
+                + state.velocity * t
+                + gravity * (0.5 * t * t)
+                + state.rotation * dp,
+        }
+    }
+
+    /// Error (rotation, velocity, position) of the states `i` and `j` at the ends of the
+    /// interval, the residual of the IMU factor weighted by the inverse of the covariance
+    pub fn residual(
+        &self,
+        i: &NavState,
+        j: &NavState,
+        gravity: &Vector3<f64>,
+        gyro_bias: &Vector3<f64>,
+        accel_bias: &Vector3<f64>,
+    ) -> SVector<f64, 9> {
+        let (dr, dv, dp) = self.corrected_deltas(gyro_bias, accel_bias);
+        let t = self.delta_time;
+        let ri = i.rotation.inverse();
+        let er = (dr.inverse() * ri * j.rotation).scaled_axis();
+        let ev = ri * (j.velocity - i.velocity - gravity * t) - dv;
+        let ep = ri * (j.position - i.position - i.velocity * t - gravity * (0.5 * t * t)) - dp;
+        let mut e = SVector::<f64, 9>::zeros();
+        e.fixed_rows_mut::<3>(0).copy_from(&er);
+        e.fixed_rows_mut::<3>(3).copy_from(&ev);
+        e.fixed_rows_mut::<3>(6).copy_from(&ep);
+        e
+    }
+
+    /// Relative pose factor of the pose graph between the keyframes `from` and `to`, the
+    /// velocity of the state `i` at `from` is needed to remove its contribution. The information
+    /// is ordered (translation, rotation vector), None if the covariance is singular
+    pub fn to_edge(
+        &self,
+        from: u32,
+        to: u32,
+        i: &NavState,
+        gravity: &Vector3<f64>,
+    ) -> Option<EdgeSE3<f64>> {
+        let j = self.predict(i, gravity, &self.gyro_bias, &self.accel_bias);
+        let ri = i.rotation.inverse();
+        let translation = ri * (j.position - i.position);
+        let measurement = Isometry3::from_parts(
+            Translation3::from(translation),
+            UnitQuaternion::from_rotation_matrix(&self.delta_rotation),
+        );
+        let mut cov = Matrix6::zeros();
+        cov.fixed_view_mut::<3, 3>(0, 0)
+            .copy_from(&self.covariance.fixed_view::<3, 3>(6, 6));
+        cov.fixed_view_mut::<3, 3>(0, 3)
+            .copy_from(&self.covariance.fixed_view::<3, 3>(6, 0));
+        cov.fixed_view_mut::<3, 3>(3, 0)
+            .copy_from(&self.covariance.fixed_view::<3, 3>(0, 6));
+        cov.fixed_view_mut::<3, 3>(3, 3)
+            .copy_from(&self.covariance.fixed_view::<3, 3>(0, 0));
+        Some(EdgeSE3::new(from, to, measurement, cov.try_inverse()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOISE: ImuNoise = ImuNoise {
+        gyro: 1e-3,
+        accel: 1e-2,
+    };
+
+    /// Samples of a body turning and accelerating
+    fn samples() -> Vec<(Vector3<f64>, Vector3<f64>)> {
+        (0..200)
+            .map(|k| {
+                let t = k as f64 * 0.005;
+                (
+                    Vector3::new(0.3 * t.sin(), -0.2, 0.5 + 0.1 * t),
+                    Vector3::new(1.0 + t, 0.2 * t.cos(), 9.81 - 0.5 * t),
+                )
+            })
+            .collect()
+    }
+
+    fn start() -> NavState {
+        NavState {
+            rotation: Rotation3::from_euler_angles(0.1, -0.2, 0.7),
+            velocity: Vector3::new(1.0, 0.5, -0.1),
+            position: Vector3::new(3.0, -2.0, 1.0),
+        }
+    }
+
+    #[test]
+    fn preintegration_matches_the_direct_integration() {
+        let gravity = Vector3::new(0.0, 0.0, -9.81);
+        let dt = 0.005;
+        let bg = Vector3::new(0.01, -0.02, 0.005);
+        let ba = Vector3::new(0.05, 0.02, -0.03);
+        let mut preintegration = ImuPreintegration::new(NOISE, bg, ba);
+        let mut state = start();
+        for (w, a) in samples() {
+            preintegration.integrate(&w, &a, dt);
+            let acceleration = state.rotation * (a - ba) + gravity;
+            state.position += state.velocity * dt + acceleration * (0.5 * dt * dt);
+            state.velocity += acceleration * dt;
+            state.rotation *= Rotation3::new((w - bg) * dt);
+        }
+        approx::assert_abs_diff_eq!(1.0, preintegration.delta_time(), epsilon = 1e-12);
+
+        let predicted = preintegration.predict(&start(), &gravity, &bg, &ba);
+        approx::assert_abs_diff_eq!(state.position, predicted.position, epsilon = 1e-9);
+        approx::assert_abs_diff_eq!(state.velocity, predicted.velocity, epsilon = 1e-9);
+        assert!(state.rotation.angle_to(&predicted.rotation) < 1e-6);
+        let residual = preintegration.residual(&start(), &state, &gravity, &bg, &ba);
+        approx::assert_abs_diff_eq!(SVector::<f64, 9>::zeros(), residual, epsilon = 1e-6);
+
+        let cov = preintegration.covariance();
+        assert!(cov.symmetric_eigenvalues().min() >= 0.0);
+        assert!(cov[(6, 6)] > 0.0 && cov[(0, 0)] > 0.0);
+        assert!(preintegration.to_edge(0, 1, &start(), &gravity).is_some());
+    }
+
+    #[test]
+    fn first_order_bias_correction() {
+        let dt = 0.005;
+        let mut nominal = ImuPreintegration::new(NOISE, Vector3::zeros(), Vector3::zeros());
+        let bg = Vector3::new(0.002, -0.003, 0.001);
+        let ba = Vector3::new(0.02, -0.01, 0.03);
+        let mut exact = ImuPreintegration::new(NOISE, bg, ba);
+        for (w, a) in samples() {
+            nominal.integrate(&w, &a, dt);
+            exact.integrate(&w, &a, dt);
+        }
+        let (dr, dv, dp) = nominal.corrected_deltas(&bg, &ba);
+        let (er, ev, ep) = exact.corrected_deltas(&bg, &ba);
+        let (nr, nv, np) = nominal.corrected_deltas(&Vector3::zeros(), &Vector3::zeros());
+        // the correction removes most of the error of the stale biases
+        assert!(dr.angle_to(&er) < 0.05 * nr.angle_to(&er));
+        assert!((dv - ev).norm() < 0.05 * (nv - ev).norm());
+        assert!((dp - ep).norm() < 0.05 * (np - ep).norm());
+    }
+}
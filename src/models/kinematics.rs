@@ -0,0 +1,535 @@
+use nalgebra::{Const, Matrix2, Matrix3, Matrix3x2, Matrix3x4, Matrix4, Vector2, Vector3, Vector4};
+use rand::RngCore;
+use rand_distr::{Distribution, Normal};
+
+use crate::models::motion::MotionModel;
+
+/// Wheel angular velocities <-> (v, w) of a differential drive base
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DifferentialDrive {
+    pub wheel_radius: f64,
+    /// Distance between the two wheels
+    pub track_width: f64,
+}
+
+impl DifferentialDrive {
+    /// (v, w) from the angular velocities of the (left, right) wheels [rad/s]
+    pub fn forward(&self, wheels: &Vector2<f64>) -> Vector2<f64> {
+        self.twist_jacobian() * wheels
+    }
+
+    /// Angular velocities of the (left, right) wheels to move at (v, w)
+    pub fn inverse(&self, twist: &Vector2<f64>) -> Vector2<f64> {
+        let (v, w) = (twist.x, twist.y);
+        Vector2::new(
+            v - w * self.track_width / 2.0,
+            v + w * self.track_width / 2.0,
+        ) / self.wheel_radius
+    }
+
+    fn twist_jacobian(&self) -> Matrix2<f64> {
+        let r = self.wheel_radius;
+        #[rustfmt::skip]
+        let jac = Matrix2::new(
+            r / 2.0, r / 2.0,
+            -r / self.track_width, r / self.track_width,
+        );
+        jac
+    }
+}
+
+/// Car-like base, with the bicycle model: the front wheels are merged in a virtual wheel in the
+/// middle of the front axle and the reference point is the middle of the rear axle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ackermann {
+    /// Distance between the front and the rear axles
+    pub wheelbase: f64,
+    /// Distance between the two front wheels
+    pub track_width: f64,
+}
+
+impl Ackermann {
+    /// (v, w) of the rear axle from its speed and the steering angle of the virtual wheel
+    pub fn forward(&self, v: f64, steering: f64) -> Vector2<f64> {
+        Vector2::new(v, v * steering.tan() / self.wheelbase)
+    }
+
+    /// Steering angle of the virtual wheel to turn at w, None when the turn is not reachable
+    /// at the speed v
+    pub fn inverse(&self, twist: &Vector2<f64>) -> Option<f64> {
+        let (v, w) = (twist.x, twist.y);
+        if v == 0.0 {
+            return (w == 0.0).then_some(0.0);
+        }
+        Some((w * self.wheelbase / v).atan())
+    }
+
+    /// Steering angles of the (left, right) front wheels for the steering angle of the virtual
+    /// wheel, the inner wheel turns more so both turn around the same center
+    pub fn wheel_angles(&self, steering: f64) -> (f64, f64) {
+        if steering == 0.0 {
+            return (0.0, 0.0);
+        }
+        // signed radius of the turn of the rear axle, > 0 to the left
+        let radius = self.wheelbase / steering.tan();
+        (
+            (self.wheelbase / (radius - self.track_width / 2.0)).atan(),
+            (self.wheelbase / (radius + self.track_width / 2.0)).atan(),
+        )
+    }
+
+    fn twist_jacobian(&self, v: f64, steering: f64) -> Matrix2<f64> {
+        #[rustfmt::skip]
+        let jac = Matrix2::new(
+            1., 0.,
+            steering.tan() / self.wheelbase, v / (self.wheelbase * steering.cos().powi(2)),
+        );
+        jac
+    }
+}
+
+/// Wheel angular velocities <-> (vx, vy, w) of a holonomic base with four mecanum wheels, the
+/// rollers in X seen from above. Same kinematics for four omni wheels at 45 degrees
+///
+/// The wheels are ordered front left, front right, rear left, rear right
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mecanum {
+    pub wheel_radius: f64,
+    /// Half the distance between the front and the rear axles
+    pub half_length: f64,
+    /// Half the distance between the left and the right wheels
+    pub half_width: f64,
+}
+
+impl Mecanum {
+    /// (vx, vy, w) from the angular velocities of the wheels [rad/s]
+    pub fn forward(&self, wheels: &Vector4<f64>) -> Vector3<f64> {
+        self.twist_jacobian() * wheels
+    }
+
+    /// Angular velocities of the wheels to move at (vx, vy, w)
+    pub fn inverse(&self, twist: &Vector3<f64>) -> Vector4<f64> {
+        let (vx, vy, w) = (twist.x, twist.y, twist.z);
+        let k = (self.half_length + self.half_width) * w;
+        Vector4::new(vx - vy - k, vx + vy + k, vx + vy - k, vx - vy + k) / self.wheel_radius
+    }
+
+    fn twist_jacobian(&self) -> Matrix3x4<f64> {
+        let r = self.wheel_radius / 4.0;
+        let l = self.half_length + self.half_width;
+        #[rustfmt::skip]
+        let jac = Matrix3x4::new(
+            r, r, r, r,
+            -r, r, r, -r,
+            -r / l, r / l, -r / l, r / l,
+        );
+        jac
+    }
+}
+
+/// Pose [x, y, theta] after moving at the body twist (vx, vy, w) during dt, with the heading
+/// in the middle of the step
+fn integrate(x: &Vector3<f64>, twist: &Vector3<f64>, dt: f64) -> Vector3<f64> {
+    let heading = x.z + twist.z * dt / 2.0;
+    let (sin, cos) = heading.sin_cos();
+    let theta = x.z + twist.z * dt;
+    Vector3::new(
+        x.x + (cos * twist.x - sin * twist.y) * dt,
+        x.y + (sin * twist.x + cos * twist.y) * dt,
+        f64::atan2(theta.sin(), theta.cos()),
+    )
+}
+
+fn integrate_jacobian_wrt_state(x: &Vector3<f64>, twist: &Vector3<f64>, dt: f64) -> Matrix3<f64> {
+    let (sin, cos) = (x.z + twist.z * dt / 2.0).sin_cos();
+    #[rustfmt::skip]
+    let jac = Matrix3::new(
+        1., 0., -(sin * twist.x + cos * twist.y) * dt,
+        0., 1., (cos * twist.x - sin * twist.y) * dt,
+        0., 0., 1.,
+    );
+    jac
+}
+
+fn integrate_jacobian_wrt_twist(x: &Vector3<f64>, twist: &Vector3<f64>, dt: f64) -> Matrix3<f64> {
+    let (sin, cos) = (x.z + twist.z * dt / 2.0).sin_cos();
+    let half_dt2 = dt * dt / 2.0;
+    #[rustfmt::skip]
+    let jac = Matrix3::new(
+        cos * dt, -sin * dt, -(sin * twist.x + cos * twist.y) * half_dt2,
+        sin * dt, cos * dt, (cos * twist.x - sin * twist.y) * half_dt2,
+        0., 0., dt,
+    );
+    jac
+}
+
+/// Standard deviation of an encoder reading, `noise[0] * |value| + noise[1]`
+fn encoder_std(noise: &[f64; 2], value: f64) -> f64 {
+    noise[0] * value.abs() + noise[1]
+}
+
+fn sample_normal(mean: f64, std: f64, rng: &mut dyn RngCore) -> f64 {
+    if std <= 0.0 {
+        return mean;
+    }
+    Normal::new(mean, std).unwrap().sample(rng)
+}
+
+/// Differential drive moved by the angular velocities of the (left, right) wheels measured by
+/// the encoders, the state is [x, y, theta]
+pub struct DifferentialDriveOdometry {
+    pub kinematics: DifferentialDrive,
+    /// Noise of each wheel, see `encoder_std`
+    encoder_noise: [f64; 2],
+}
+
+impl DifferentialDriveOdometry {
+    pub fn new(kinematics: DifferentialDrive, encoder_noise: [f64; 2]) -> Box<Self> {
+        Box::new(DifferentialDriveOdometry {
+            kinematics,
+            encoder_noise,
+        })
+    }
+}
+
+impl MotionModel<f64, Const<3>, Const<2>, Const<2>> for DifferentialDriveOdometry {
+    fn prediction(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Vector3<f64> {
+        let twist = self.kinematics.forward(u);
+        integrate(x, &Vector3::new(twist.x, 0.0, twist.y), dt)
+    }
+
+    fn jacobian_wrt_state(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Matrix3<f64> {
+        let twist = self.kinematics.forward(u);
+        integrate_jacobian_wrt_state(x, &Vector3::new(twist.x, 0.0, twist.y), dt)
+    }
+
+    fn jacobian_wrt_input(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Matrix3x2<f64> {
+        let twist = self.kinematics.forward(u);
+        let jac = integrate_jacobian_wrt_twist(x, &Vector3::new(twist.x, 0.0, twist.y), dt);
+        // vy is always 0
+        let jac = Matrix3x2::from_columns(&[jac.column(0), jac.column(2)]);
+        jac * self.kinematics.twist_jacobian()
+    }
+
+    fn cov_noise_control_space(&self, u: &Vector2<f64>) -> Matrix2<f64> {
+        Matrix2::from_diagonal(&u.map(|w| encoder_std(&self.encoder_noise, w).powi(2)))
+    }
+
+    fn sample(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Vector3<f64> {
+        MotionModel::<f64, Const<3>, Const<2>, Const<2>>::sample_with_rng(
+            self,
+            x,
+            u,
+            dt,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    fn sample_with_rng(
+        &self,
+        x: &Vector3<f64>,
+        u: &Vector2<f64>,
+        dt: f64,
+        rng: &mut dyn RngCore,
+    ) -> Vector3<f64> {
+        let left = sample_normal(u.x, encoder_std(&self.encoder_noise, u.x), rng);
+        let right = sample_normal(u.y, encoder_std(&self.encoder_noise, u.y), rng);
+        MotionModel::<f64, Const<3>, Const<2>, Const<2>>::prediction(
+            self,
+            x,
+            &Vector2::new(left, right),
+            dt,
+        )
+    }
+}
+
+/// Car-like base moved by the speed of the rear axle measured by the encoders and the measured
+/// steering angle of the virtual wheel, the input is [v, steering] and the state [x, y, theta]
+pub struct AckermannOdometry {
+    pub kinematics: Ackermann,
+    /// Noise of the speed, see `encoder_std`
+    speed_noise: [f64; 2],
+    /// Standard deviation of the steering angle
+    steering_noise: f64,
+}
+
+impl AckermannOdometry {
+    pub fn new(kinematics: Ackermann, speed_noise: [f64; 2], steering_noise: f64) -> Box<Self> {
+        Box::new(AckermannOdometry {
+            kinematics,
+            speed_noise,
+            steering_noise,
+        })
+    }
+}
+
+impl MotionModel<f64, Const<3>, Const<2>, Const<2>> for AckermannOdometry {
+    fn prediction(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Vector3<f64> {
+        let twist = self.kinematics.forward(u.x, u.y);
+        integrate(x, &Vector3::new(twist.x, 0.0, twist.y), dt)
+    }
+
+    fn jacobian_wrt_state(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Matrix3<f64> {
+        let twist = self.kinematics.forward(u.x, u.y);
+        integrate_jacobian_wrt_state(x, &Vector3::new(twist.x, 0.0, twist.y), dt)
+    }
+
+    fn jacobian_wrt_input(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Matrix3x2<f64> {
+        let twist = self.kinematics.forward(u.x, u.y);
+        let jac = integrate_jacobian_wrt_twist(x, &Vector3::new(twist.x, 0.0, twist.y), dt);
+        let jac = Matrix3x2::from_columns(&[jac.column(0), jac.column(2)]);
+        jac * self.kinematics.twist_jacobian(u.x, u.y)
+    }
+
+    fn cov_noise_control_space(&self, u: &Vector2<f64>) -> Matrix2<f64> {
+        Matrix2::from_diagonal(&Vector2::new(
+            encoder_std(&self.speed_noise, u.x).powi(2),
+            self.steering_noise.powi(2),
+        ))
+    }
+
+    fn sample(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Vector3<f64> {
+        MotionModel::<f64, Const<3>, Const<2>, Const<2>>::sample_with_rng(
+            self,
+            x,
+            u,
+            dt,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    fn sample_with_rng(
+        &self,
+        x: &Vector3<f64>,
+        u: &Vector2<f64>,
+        dt: f64,
+        rng: &mut dyn RngCore,
+    ) -> Vector3<f64> {
+        let v = sample_normal(u.x, encoder_std(&self.speed_noise, u.x), rng);
+        let steering = sample_normal(u.y, self.steering_noise, rng);
+        MotionModel::<f64, Const<3>, Const<2>, Const<2>>::prediction(
+            self,
+            x,
+            &Vector2::new(v, steering),
+            dt,
+        )
+    }
+}
+
+/// Mecanum base moved by the angular velocities of the four wheels measured by the encoders,
+/// in the order of `Mecanum`, the state is [x, y, theta]
+pub struct MecanumOdometry {
+    pub kinematics: Mecanum,
+    /// Noise of each wheel, see `encoder_std`
+    encoder_noise: [f64; 2],
+}
+
+impl MecanumOdometry {
+    pub fn new(kinematics: Mecanum, encoder_noise: [f64; 2]) -> Box<Self> {
+        Box::new(MecanumOdometry {
+            kinematics,
+            encoder_noise,
+        })
+    }
+}
+
+impl MotionModel<f64, Const<3>, Const<2>, Const<4>> for MecanumOdometry {
+    fn prediction(&self, x: &Vector3<f64>, u: &Vector4<f64>, dt: f64) -> Vector3<f64> {
+        integrate(x, &self.kinematics.forward(u), dt)
+    }
+
+    fn jacobian_wrt_state(&self, x: &Vector3<f64>, u: &Vector4<f64>, dt: f64) -> Matrix3<f64> {
+        integrate_jacobian_wrt_state(x, &self.kinematics.forward(u), dt)
+    }
+
+    fn jacobian_wrt_input(&self, x: &Vector3<f64>, u: &Vector4<f64>, dt: f64) -> Matrix3x4<f64> {
+        integrate_jacobian_wrt_twist(x, &self.kinematics.forward(u), dt)
+            * self.kinematics.twist_jacobian()
+    }
+
+    fn cov_noise_control_space(&self, u: &Vector4<f64>) -> Matrix4<f64> {
+        Matrix4::from_diagonal(&u.map(|w| encoder_std(&self.encoder_noise, w).powi(2)))
+    }
+
+    fn sample(&self, x: &Vector3<f64>, u: &Vector4<f64>, dt: f64) -> Vector3<f64> {
+        MotionModel::<f64, Const<3>, Const<2>, Const<4>>::sample_with_rng(
+            self,
+            x,
+            u,
+            dt,
+            &mut rand::thread_rng(),
+        )
+    }
+
+    fn sample_with_rng(
+        &self,
+        x: &Vector3<f64>,
+        u: &Vector4<f64>,
+        dt: f64,
+        rng: &mut dyn RngCore,
+    ) -> Vector3<f64> {
+        let wheels = u.map(|w| sample_normal(w, encoder_std(&self.encoder_noise, w), rng));
+        MotionModel::<f64, Const<3>, Const<2>, Const<4>>::prediction(self, x, &wheels, dt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::{OMatrix, OVector};
+
+    /// Central differences of `f` around `x`
+    fn numerical_jacobian<const N: usize>(
+        f: impl Fn(&OVector<f64, Const<N>>) -> Vector3<f64>,
+        x: &OVector<f64, Const<N>>,
+    ) -> OMatrix<f64, Const<3>, Const<N>> {
+        let h = 1e-6;
+        let mut jac = OMatrix::<f64, Const<3>, Const<N>>::zeros();
+        for i in 0..N {
+            let mut dx = OVector::<f64, Const<N>>::zeros();
+            dx[i] = h;
+            jac.set_column(i, &((f(&(x + dx)) - f(&(x - dx))) / (2.0 * h)));
+        }
+        jac
+    }
+
+    #[test]
+    fn forward_inverse_round_trips() {
+        let diff = DifferentialDrive {
+            wheel_radius: 0.1,
+            track_width: 0.5,
+        };
+        let twist = Vector2::new(0.8, -0.3);
+        assert_relative_eq!(diff.forward(&diff.inverse(&twist)), twist, epsilon = 1e-12);
+        // spinning in place
+        let wheels = diff.inverse(&Vector2::new(0.0, 1.0));
+        assert_relative_eq!(wheels.x, -wheels.y);
+
+        let mecanum = Mecanum {
+            wheel_radius: 0.05,
+            half_length: 0.2,
+            half_width: 0.15,
+        };
+        let twist = Vector3::new(0.3, -0.4, 0.7);
+        assert_relative_eq!(
+            mecanum.forward(&mecanum.inverse(&twist)),
+            twist,
+            epsilon = 1e-12
+        );
+        // pure sideways motion, the diagonal wheels spin together
+        let wheels = mecanum.inverse(&Vector3::new(0.0, 1.0, 0.0));
+        assert_relative_eq!(
+            wheels,
+            Vector4::new(-20.0, 20.0, 20.0, -20.0),
+            epsilon = 1e-12
+        );
+
+        let car = Ackermann {
+            wheelbase: 2.5,
+            track_width: 1.5,
+        };
+        let steering = car.inverse(&car.forward(2.0, 0.3)).unwrap();
+        assert_relative_eq!(steering, 0.3, epsilon = 1e-12);
+        assert_eq!(car.inverse(&Vector2::new(0.0, 0.5)), None);
+        let (left, right) = car.wheel_angles(0.3);
+        assert!(left > 0.3 && 0.3 > right && right > 0.0);
+        // both wheels turn around the center of the turn of the rear axle
+        let radius = car.wheelbase / 0.3_f64.tan();
+        assert_relative_eq!(
+            left.tan() * (radius - car.track_width / 2.0),
+            right.tan() * (radius + car.track_width / 2.0),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn jacobians_match_the_finite_differences() {
+        let x = Vector3::new(1.0, -2.0, 0.4);
+        let dt = 0.1;
+
+        let diff = DifferentialDriveOdometry::new(
+            DifferentialDrive {
+                wheel_radius: 0.1,
+                track_width: 0.5,
+            },
+            [0.01, 0.001],
+        );
+        let u = Vector2::new(8.0, 10.0);
+        let numerical = numerical_jacobian(|x| diff.prediction(x, &u, dt), &x);
+        assert_relative_eq!(
+            diff.jacobian_wrt_state(&x, &u, dt),
+            numerical,
+            epsilon = 1e-6
+        );
+        let numerical = numerical_jacobian(|u| diff.prediction(&x, u, dt), &u);
+        assert_relative_eq!(
+            diff.jacobian_wrt_input(&x, &u, dt),
+            numerical,
+            epsilon = 1e-6
+        );
+
+        let car = AckermannOdometry::new(
+            Ackermann {
+                wheelbase: 2.5,
+                track_width: 1.5,
+            },
+            [0.01, 0.01],
+            0.01,
+        );
+        let u = Vector2::new(3.0, -0.2);
+        let numerical = numerical_jacobian(|x| car.prediction(x, &u, dt), &x);
+        assert_relative_eq!(
+            car.jacobian_wrt_state(&x, &u, dt),
+            numerical,
+            epsilon = 1e-6
+        );
+        let numerical = numerical_jacobian(|u| car.prediction(&x, u, dt), &u);
+        assert_relative_eq!(
+            car.jacobian_wrt_input(&x, &u, dt),
+            numerical,
+            epsilon = 1e-6
+        );
+
+        let mecanum = MecanumOdometry::new(
+            Mecanum {
+                wheel_radius: 0.05,
+                half_length: 0.2,
+                half_width: 0.15,
+            },
+            [0.01, 0.01],
+        );
+        let u = Vector4::new(5.0, 7.0, 6.0, 4.0);
+        let numerical = numerical_jacobian(|x| mecanum.prediction(x, &u, dt), &x);
+        assert_relative_eq!(
+            mecanum.jacobian_wrt_state(&x, &u, dt),
+            numerical,
+            epsilon = 1e-6
+        );
+        let numerical = numerical_jacobian(|u| mecanum.prediction(&x, u, dt), &u);
+        assert_relative_eq!(
+            mecanum.jacobian_wrt_input(&x, &u, dt),
+            numerical,
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn straight_line_and_noise() {
+        let diff = DifferentialDriveOdometry::new(
+            DifferentialDrive {
+                wheel_radius: 0.1,
+                track_width: 0.5,
+            },
+            [0.0, 0.0],
+        );
+        let x = Vector3::new(0.0, 0.0, std::f64::consts::FRAC_PI_2);
+        let u = Vector2::new(10.0, 10.0);
+        let next = diff.prediction(&x, &u, 1.0);
+        assert_relative_eq!(next, Vector3::new(0.0, 1.0, x.z), epsilon = 1e-12);
+        // without noise the sample is the prediction
+        let mut rng = rand::thread_rng();
+        assert_relative_eq!(diff.sample_with_rng(&x, &u, 1.0, &mut rng), next);
+        assert_eq!(diff.cov_noise_control_space(&u), Matrix2::zeros());
+    }
+}
@@ -11,6 +11,21 @@ where
     fn jacobian(&self, x: &OVector<T, S>, landmark: Option<&OVector<T, S>>) -> OMatrix<T, Z, S>;
 }
 
+// Boxed models can be stored by the filters generic over their models
+impl<T: RealField, S: Dim, Z: Dim, H> MeasurementModel<T, S, Z> for Box<H>
+where
+    H: MeasurementModel<T, S, Z> + ?Sized,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, Z> + Allocator<T, S, S> + Allocator<T, Z, S>,
+{
+    fn prediction(&self, x: &OVector<T, S>, landmark: Option<&OVector<T, S>>) -> OVector<T, Z> {
+        (**self).prediction(x, landmark)
+    }
+
+    fn jacobian(&self, x: &OVector<T, S>, landmark: Option<&OVector<T, S>>) -> OMatrix<T, Z, S> {
+        (**self).jacobian(x, landmark)
+    }
+}
+
 /// Measurement = [range, bearing, signature]
 /// Probabilistic Robotics p. 177
 pub struct RangeBearingMeasurementModel;
@@ -39,7 +54,8 @@ impl MeasurementModel<f64, Const<3>, Const<2>> for RangeBearingMeasurementModel
 
         let range = q_sqrt;
         let bearing = f64::atan2(l_y - x_y, l_x - x_x) - x_theta;
-        Vector2::new(range, bearing)
+        // within [-pi, pi] like the measured bearings
+        Vector2::new(range, f64::atan2(bearing.sin(), bearing.cos()))
     }
 
     fn jacobian(&self, x: &Vector3<f64>, landmark: Option<&Vector3<f64>>) -> Matrix2x3<f64> {
@@ -87,3 +103,59 @@ impl MeasurementModel<f64, Const<4>, Const<2>> for SimpleProblemMeasurementModel
         jac
     }
 }
+
+/// Measurement = [x, y], the first two components of the state, e.g. a tracked position
+pub struct PositionMeasurementModel;
+
+impl PositionMeasurementModel {
+    pub fn new() -> Box<PositionMeasurementModel> {
+        Box::new(PositionMeasurementModel {})
+    }
+}
+
+impl<S: Dim> MeasurementModel<f64, S, Const<2>> for PositionMeasurementModel
+where
+    DefaultAllocator: Allocator<f64, S> + Allocator<f64, S, S> + Allocator<f64, Const<2>, S>,
+{
+    fn prediction(&self, x: &OVector<f64, S>, _landmark: Option<&OVector<f64, S>>) -> Vector2<f64> {
+        Vector2::new(x[0], x[1])
+    }
+
+    fn jacobian(
+        &self,
+        x: &OVector<f64, S>,
+        _landmark: Option<&OVector<f64, S>>,
+    ) -> OMatrix<f64, Const<2>, S> {
+        let mut jac = OMatrix::zeros_generic(Const::<2>, x.shape_generic().0);
+        jac[(0, 0)] = 1.;
+        jac[(1, 1)] = 1.;
+        jac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn bearing_is_wrapped() {
+        let model = RangeBearingMeasurementModel::new();
+        // landmark behind on the right, the difference of the angles is below -pi
+        let x = Vector3::new(0.0, 0.0, 3.0);
+        let landmark = Vector3::new(-1.0, -0.1, 0.0);
+        let z = model.prediction(&x, Some(&landmark));
+        approx::assert_abs_diff_eq!(
+            z[1],
+            f64::atan2(-0.1, -1.0) - 3.0 + 2.0 * PI,
+            epsilon = 1e-12
+        );
+
+        // right behind, on both sides of pi
+        let x = Vector3::zeros();
+        for (y, bearing) in [(1e-9, PI), (-1e-9, -PI)] {
+            let z = model.prediction(&x, Some(&Vector3::new(-1.0, y, 0.0)));
+            approx::assert_abs_diff_eq!(z[1], bearing, epsilon = 1e-8);
+        }
+    }
+}
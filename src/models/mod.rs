@@ -1,2 +1,4 @@
+pub mod imu;
+pub mod kinematics;
 pub mod measurement;
 pub mod motion;
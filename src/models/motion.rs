@@ -1,9 +1,10 @@
 // use enum_dispatch::enum_dispatch;
 use nalgebra::{
     allocator::Allocator, Const, DefaultAllocator, Dim, Matrix2, Matrix3, Matrix3x2, Matrix4,
-    Matrix4x2, OMatrix, OVector, RealField, Vector2, Vector3, Vector4,
+    Matrix4x2, Matrix5, OMatrix, OVector, RealField, Vector2, Vector3, Vector4, Vector5,
 };
 
+use rand::RngCore;
 use rand_distr::{Distribution, Normal};
 
 // #[enum_dispatch(MM<T, S, Z, U>)]
@@ -21,6 +22,60 @@ where
     fn jacobian_wrt_input(&self, x: &OVector<T, S>, u: &OVector<T, U>, dt: T) -> OMatrix<T, S, U>;
     fn cov_noise_control_space(&self, u: &OVector<T, U>) -> OMatrix<T, U, U>;
     fn sample(&self, x: &OVector<T, S>, u: &OVector<T, U>, dt: T) -> OVector<T, S>;
+    /// Same as `sample` with the given generator, the particle filters give each particule its
+    /// own stream so the runs can be replayed. The default ignores `rng` and calls `sample`, it
+    /// is only reproducible for the models without noise, the noisy models should override it
+    fn sample_with_rng(
+        &self,
+        x: &OVector<T, S>,
+        u: &OVector<T, U>,
+        dt: T,
+        _rng: &mut dyn RngCore,
+    ) -> OVector<T, S> {
+        self.sample(x, u, dt)
+    }
+}
+
+// Boxed models can be stored by the filters generic over their models
+impl<T: RealField, S: Dim, Z: Dim, U: Dim, M> MotionModel<T, S, Z, U> for Box<M>
+where
+    M: MotionModel<T, S, Z, U> + ?Sized,
+    DefaultAllocator: Allocator<T, S>
+        + Allocator<T, U>
+        + Allocator<T, S, S>
+        + Allocator<T, U, U>
+        + Allocator<T, S, U>
+        + Allocator<T, Z, S>,
+{
+    fn prediction(&self, x: &OVector<T, S>, u: &OVector<T, U>, dt: T) -> OVector<T, S> {
+        (**self).prediction(x, u, dt)
+    }
+
+    fn jacobian_wrt_state(&self, x: &OVector<T, S>, u: &OVector<T, U>, dt: T) -> OMatrix<T, S, S> {
+        (**self).jacobian_wrt_state(x, u, dt)
+    }
+
+    fn jacobian_wrt_input(&self, x: &OVector<T, S>, u: &OVector<T, U>, dt: T) -> OMatrix<T, S, U> {
+        (**self).jacobian_wrt_input(x, u, dt)
+    }
+
+    fn cov_noise_control_space(&self, u: &OVector<T, U>) -> OMatrix<T, U, U> {
+        (**self).cov_noise_control_space(u)
+    }
+
+    fn sample_with_rng(
+        &self,
+        x: &OVector<T, S>,
+        u: &OVector<T, U>,
+        dt: T,
+        rng: &mut dyn RngCore,
+    ) -> OVector<T, S> {
+        (**self).sample_with_rng(x, u, dt, rng)
+    }
+
+    fn sample(&self, x: &OVector<T, S>, u: &OVector<T, U>, dt: T) -> OVector<T, S> {
+        (**self).sample(x, u, dt)
+    }
 }
 
 pub struct Velocity {
@@ -95,7 +150,7 @@ impl MotionModel<f64, Const<3>, Const<2>, Const<2>> for Velocity {
         let theta = x[2];
         //control
         let v = u[0];
-        let w = x[1];
+        let w = u[1];
 
         if w != 0.0 {
             let sint = theta.sin();
@@ -134,6 +189,16 @@ impl MotionModel<f64, Const<3>, Const<2>, Const<2>> for Velocity {
     }
 
     fn sample(&self, x: &Vector3<f64>, u: &Vector2<f64>, dt: f64) -> Vector3<f64> {
+        self.sample_with_rng(x, u, dt, &mut rand::thread_rng())
+    }
+
+    fn sample_with_rng(
+        &self,
+        x: &Vector3<f64>,
+        u: &Vector2<f64>,
+        dt: f64,
+        rng: &mut dyn RngCore,
+    ) -> Vector3<f64> {
         //state
         let theta = x[2];
         //control
@@ -143,16 +208,15 @@ impl MotionModel<f64, Const<3>, Const<2>, Const<2>> for Velocity {
         let v2 = v.powi(2);
         let w2 = w.powi(2);
         let eps = 0.00001;
-        let mut rng = rand::thread_rng();
         let v_noisy = Normal::new(v, (self.a[0] * v2 + self.a[1] * w2 + eps).sqrt())
             .unwrap()
-            .sample(&mut rng);
+            .sample(rng);
         let w_noisy = Normal::new(w, (self.a[2] * v2 + self.a[3] * w2 + eps).sqrt())
             .unwrap()
-            .sample(&mut rng);
+            .sample(rng);
         let gamma_noisy = Normal::new(0.0, (self.a[4] * v2 + self.a[5] * w2).sqrt())
             .unwrap()
-            .sample(&mut rng);
+            .sample(rng);
 
         let delta = Vector3::new(
             v_noisy / w_noisy * (-theta.sin() + (theta + w_noisy * dt).sin()),
@@ -235,4 +299,192 @@ impl MotionModel<f64, Const<4>, Const<2>, Const<2>> for SimpleProblemMotionModel
     fn sample(&self, _x: &Vector4<f64>, _u: &Vector2<f64>, _dt: f64) -> Vector4<f64> {
         unimplemented!()
     }
+    fn sample_with_rng(
+        &self,
+        _x: &Vector4<f64>,
+        _u: &Vector2<f64>,
+        _dt: f64,
+        _rng: &mut dyn RngCore,
+    ) -> Vector4<f64> {
+        unimplemented!()
+    }
+}
+
+/// Target moving straight at constant speed, the state is [x, y, yaw, v, omega] and the turn
+/// rate is reset to 0. There is no input, e.g. for a tracked target, and the process noise is
+/// given to the filter so `sample` is the prediction
+pub struct ConstantVelocity;
+
+impl ConstantVelocity {
+    pub fn new() -> Box<ConstantVelocity> {
+        Box::new(ConstantVelocity)
+    }
+}
+
+impl<U: Dim> MotionModel<f64, Const<5>, Const<2>, U> for ConstantVelocity
+where
+    DefaultAllocator: Allocator<f64, U> + Allocator<f64, U, U> + Allocator<f64, Const<5>, U>,
+{
+    fn prediction(&self, x: &Vector5<f64>, _u: &OVector<f64, U>, dt: f64) -> Vector5<f64> {
+        let (yaw, v) = (x[2], x[3]);
+        Vector5::new(
+            x[0] + v * yaw.cos() * dt,
+            x[1] + v * yaw.sin() * dt,
+            yaw,
+            v,
+            0.,
+        )
+    }
+
+    fn jacobian_wrt_state(&self, x: &Vector5<f64>, _u: &OVector<f64, U>, dt: f64) -> Matrix5<f64> {
+        let (yaw, v) = (x[2], x[3]);
+        #[rustfmt::skip]
+        let jac = Matrix5::<f64>::new(
+            1., 0., -v * yaw.sin() * dt, yaw.cos() * dt, 0.,
+            0., 1., v * yaw.cos() * dt, yaw.sin() * dt, 0.,
+            0., 0., 1., 0., 0.,
+            0., 0., 0., 1., 0.,
+            0., 0., 0., 0., 0.,
+        );
+        jac
+    }
+
+    fn jacobian_wrt_input(
+        &self,
+        _x: &Vector5<f64>,
+        u: &OVector<f64, U>,
+        _dt: f64,
+    ) -> OMatrix<f64, Const<5>, U> {
+        OMatrix::zeros_generic(Const::<5>, u.shape_generic().0)
+    }
+
+    fn cov_noise_control_space(&self, u: &OVector<f64, U>) -> OMatrix<f64, U, U> {
+        let (rows, _) = u.shape_generic();
+        OMatrix::zeros_generic(rows, rows)
+    }
+
+    fn sample(&self, x: &Vector5<f64>, u: &OVector<f64, U>, dt: f64) -> Vector5<f64> {
+        MotionModel::<f64, Const<5>, Const<2>, U>::prediction(self, x, u, dt)
+    }
+
+    fn sample_with_rng(
+        &self,
+        x: &Vector5<f64>,
+        u: &OVector<f64, U>,
+        dt: f64,
+        _rng: &mut dyn RngCore,
+    ) -> Vector5<f64> {
+        MotionModel::<f64, Const<5>, Const<2>, U>::prediction(self, x, u, dt)
+    }
+}
+
+/// Target turning at constant speed and turn rate, the state is [x, y, yaw, v, omega]. There is
+/// no input and the process noise is given to the filter so `sample` is the prediction
+pub struct CoordinatedTurn;
+
+impl CoordinatedTurn {
+    pub fn new() -> Box<CoordinatedTurn> {
+        Box::new(CoordinatedTurn)
+    }
+}
+
+impl<U: Dim> MotionModel<f64, Const<5>, Const<2>, U> for CoordinatedTurn
+where
+    DefaultAllocator: Allocator<f64, U> + Allocator<f64, U, U> + Allocator<f64, Const<5>, U>,
+{
+    fn prediction(&self, x: &Vector5<f64>, _u: &OVector<f64, U>, dt: f64) -> Vector5<f64> {
+        let (yaw, v, w) = (x[2], x[3], x[4]);
+        let delta = if w.abs() > 1e-6 {
+            Vector2::new(
+                v / w * ((yaw + w * dt).sin() - yaw.sin()),
+                v / w * (yaw.cos() - (yaw + w * dt).cos()),
+            )
+        } else {
+            // no rotation
+            Vector2::new(v * yaw.cos() * dt, v * yaw.sin() * dt)
+        };
+        Vector5::new(x[0] + delta.x, x[1] + delta.y, yaw + w * dt, v, w)
+    }
+
+    fn jacobian_wrt_state(&self, x: &Vector5<f64>, _u: &OVector<f64, U>, dt: f64) -> Matrix5<f64> {
+        let (yaw, v, w) = (x[2], x[3], x[4]);
+        if w.abs() > 1e-6 {
+            let sint = yaw.sin();
+            let cost = yaw.cos();
+            let sintdt = (yaw + w * dt).sin();
+            let costdt = (yaw + w * dt).cos();
+            let w2 = w * w;
+            #[rustfmt::skip]
+            let jac = Matrix5::<f64>::new(
+                1., 0., v / w * (costdt - cost), (sintdt - sint) / w, v * (costdt * dt / w - (sintdt - sint) / w2),
+                0., 1., v / w * (sintdt - sint), (cost - costdt) / w, v * (sintdt * dt / w - (cost - costdt) / w2),
+                0., 0., 1., 0., dt,
+                0., 0., 0., 1., 0.,
+                0., 0., 0., 0., 1.,
+            );
+            jac
+        } else {
+            #[rustfmt::skip]
+            let jac = Matrix5::<f64>::new(
+                1., 0., -v * yaw.sin() * dt, yaw.cos() * dt, -v * yaw.sin() * dt * dt / 2.,
+                0., 1., v * yaw.cos() * dt, yaw.sin() * dt, v * yaw.cos() * dt * dt / 2.,
+                0., 0., 1., 0., dt,
+                0., 0., 0., 1., 0.,
+                0., 0., 0., 0., 1.,
+            );
+            jac
+        }
+    }
+
+    fn jacobian_wrt_input(
+        &self,
+        _x: &Vector5<f64>,
+        u: &OVector<f64, U>,
+        _dt: f64,
+    ) -> OMatrix<f64, Const<5>, U> {
+        OMatrix::zeros_generic(Const::<5>, u.shape_generic().0)
+    }
+
+    fn cov_noise_control_space(&self, u: &OVector<f64, U>) -> OMatrix<f64, U, U> {
+        let (rows, _) = u.shape_generic();
+        OMatrix::zeros_generic(rows, rows)
+    }
+
+    fn sample(&self, x: &Vector5<f64>, u: &OVector<f64, U>, dt: f64) -> Vector5<f64> {
+        MotionModel::<f64, Const<5>, Const<2>, U>::prediction(self, x, u, dt)
+    }
+
+    fn sample_with_rng(
+        &self,
+        x: &Vector5<f64>,
+        u: &OVector<f64, U>,
+        dt: f64,
+        _rng: &mut dyn RngCore,
+    ) -> Vector5<f64> {
+        MotionModel::<f64, Const<5>, Const<2>, U>::prediction(self, x, u, dt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn velocity_jacobian_wrt_input() {
+        // the turn rate is the second input, not the y of the state
+        let model = Velocity::new([0.0; 6]);
+        let x = Vector3::new(1.0, -2.0, 0.3);
+        let u = Vector2::new(1.5, 0.4);
+        let dt = 0.1;
+        let jac = model.jacobian_wrt_input(&x, &u, dt);
+        let h = 1e-6;
+        for i in 0..2 {
+            let mut du = Vector2::zeros();
+            du[i] = h;
+            let expected = (model.prediction(&x, &(u + du), dt)
+                - model.prediction(&x, &(u - du), dt))
+                / (2.0 * h);
+            approx::assert_abs_diff_eq!(jac.column(i).into_owned(), expected, epsilon = 1e-6);
+        }
+    }
 }
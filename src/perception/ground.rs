@@ -0,0 +1,212 @@
+use nalgebra::{Matrix3, Vector2, Vector3};
+use rand::seq::index::sample;
+
+/// Plane n.p + d = 0 with |n| = 1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    pub normal: Vector3<f64>,
+    pub d: f64,
+}
+
+impl Plane {
+    pub fn from_points(a: &Vector3<f64>, b: &Vector3<f64>, c: &Vector3<f64>) -> Option<Plane> {
+        let normal = (b - a).cross(&(c - a)).try_normalize(1e-12)?;
+        // keep the normal pointing up so the tilt is well defined
+        let normal = if normal.z < 0.0 { -normal } else { normal };
+        Some(Plane {
+            normal,
+            d: -normal.dot(a),
+        })
+    }
+
+    /// Least squares plane through the points
+    pub fn fit(points: &[Vector3<f64>]) -> Option<Plane> {
+        if points.len() < 3 {
+            return None;
+        }
+        let centroid = points.iter().fold(Vector3::zeros(), |a, p| a + p) / points.len() as f64;
+        let cov = points
+            .iter()
+            .map(|p| p - centroid)
+            .fold(Matrix3::zeros(), |a, dp| a + dp * dp.transpose());
+        let eigen = cov.symmetric_eigen();
+        let normal = eigen
+            .eigenvectors
+            .column(eigen.eigenvalues.imin())
+            .into_owned();
+        let normal = if normal.z < 0.0 { -normal } else { normal };
+        Some(Plane {
+            normal,
+            d: -normal.dot(&centroid),
+        })
+    }
+
+    pub fn signed_distance(&self, p: &Vector3<f64>) -> f64 {
+        self.normal.dot(p) + self.d
+    }
+
+    /// Angle between the plane normal and the z axis
+    pub fn tilt(&self) -> f64 {
+        self.normal.z.clamp(-1.0, 1.0).acos()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GroundRemovalConfig {
+    /// Maximum distance of a ground point to the plane [m]
+    pub distance_threshold: f64,
+    pub max_iterations: usize,
+    /// Candidate planes tilted more than this are rejected [rad]
+    pub max_tilt: f64,
+}
+
+impl Default for GroundRemovalConfig {
+    fn default() -> Self {
+        GroundRemovalConfig {
+            distance_threshold: 0.1,
+            max_iterations: 100,
+            max_tilt: 0.2,
+        }
+    }
+}
+
+/// RANSAC plane fit restricted to near horizontal planes, the best plane is refined on its inliers.
+/// Returns the plane and the indices of its inliers
+pub fn fit_ground_plane(
+    points: &[Vector3<f64>],
+    config: &GroundRemovalConfig,
+) -> Option<(Plane, Vec<usize>)> {
+    if points.len() < 3 {
+        return None;
+    }
+    let mut rng = rand::thread_rng();
+    let inliers_of = |plane: &Plane| -> Vec<usize> {
+        points
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| plane.signed_distance(p).abs() <= config.distance_threshold)
+            .map(|(i, _)| i)
+            .collect()
+    };
+
+    let mut best: Option<(Plane, Vec<usize>)> = None;
+    for _ in 0..config.max_iterations {
+        let idx = sample(&mut rng, points.len(), 3);
+        let Some(plane) = Plane::from_points(
+            &points[idx.index(0)],
+            &points[idx.index(1)],
+            &points[idx.index(2)],
+        ) else {
+            continue;
+        };
+        if plane.tilt() > config.max_tilt {
+            continue;
+        }
+        let inliers = inliers_of(&plane);
+        if best.as_ref().is_none_or(|(_, b)| inliers.len() > b.len()) {
+            best = Some((plane, inliers));
+        }
+    }
+
+    let (plane, inliers) = best?;
+    let inlier_points: Vec<Vector3<f64>> = inliers.iter().map(|&i| points[i]).collect();
+    match Plane::fit(&inlier_points) {
+        Some(refined) if refined.tilt() <= config.max_tilt => {
+            let refined_inliers = inliers_of(&refined);
+            Some((refined, refined_inliers))
+        }
+        _ => Some((plane, inliers)),
+    }
+}
+
+/// Splits the cloud into (ground, non ground) points
+pub fn remove_ground(
+    points: &[Vector3<f64>],
+    config: &GroundRemovalConfig,
+) -> (Vec<Vector3<f64>>, Vec<Vector3<f64>>) {
+    let Some((_, inliers)) = fit_ground_plane(points, config) else {
+        return (Vec::new(), points.to_vec());
+    };
+    let mut is_ground = vec![false; points.len()];
+    inliers.iter().for_each(|&i| is_ground[i] = true);
+    points
+        .iter()
+        .zip(is_ground)
+        .fold((Vec::new(), Vec::new()), |(mut g, mut o), (p, ground)| {
+            if ground {
+                g.push(*p);
+            } else {
+                o.push(*p);
+            }
+            (g, o)
+        })
+}
+
+/// Keeps the points whose height above the plane is in [min_height, max_height]
+pub fn height_band_filter(
+    points: &[Vector3<f64>],
+    plane: &Plane,
+    min_height: f64,
+    max_height: f64,
+) -> Vec<Vector3<f64>> {
+    points
+        .iter()
+        .filter(|p| {
+            let h = plane.signed_distance(p);
+            min_height <= h && h <= max_height
+        })
+        .copied()
+        .collect()
+}
+
+/// Drops the z coordinate so the cloud can be used by the 2D algorithms
+pub fn flatten(points: &[Vector3<f64>]) -> Vec<Vector2<f64>> {
+    points.iter().map(|p| p.xy()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cloud() -> Vec<Vector3<f64>> {
+        let mut points = Vec::new();
+        // slightly elevated ground
+        for i in 0..20 {
+            for j in 0..20 {
+                points.push(Vector3::new(i as f64 * 0.5, j as f64 * 0.5, 0.3));
+            }
+        }
+        // box obstacle
+        for i in 0..5 {
+            for k in 1..10 {
+                points.push(Vector3::new(
+                    2.0 + i as f64 * 0.1,
+                    2.0,
+                    0.3 + k as f64 * 0.2,
+                ));
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn ground_plane_found() {
+        let points = cloud();
+        let (plane, inliers) = fit_ground_plane(&points, &GroundRemovalConfig::default()).unwrap();
+        assert_eq!(400, inliers.len());
+        approx::assert_abs_diff_eq!(Vector3::z(), plane.normal, epsilon = 1e-6);
+        approx::assert_abs_diff_eq!(-0.3, plane.d, epsilon = 1e-6);
+    }
+
+    #[test]
+    fn ground_removed_and_band_filtered() {
+        let points = cloud();
+        let (ground, obstacles) = remove_ground(&points, &GroundRemovalConfig::default());
+        assert_eq!(400, ground.len());
+        assert_eq!(45, obstacles.len());
+
+        let plane = Plane::fit(&ground).unwrap();
+        let band = height_band_filter(&obstacles, &plane, 0.0, 1.1);
+        assert_eq!(25, band.len());
+    }
+}
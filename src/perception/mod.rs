@@ -0,0 +1,4 @@
+pub mod ground;
+pub mod obstacles;
+pub mod scan;
+pub mod scan_matching;
@@ -0,0 +1,268 @@
+use nalgebra::Vector2;
+
+use crate::utils::kdtree::KdTree;
+
+/// Polygonal obstacle primitive extracted from a cluster of scan points
+#[derive(Debug, Clone)]
+pub struct Obstacle {
+    /// Points of the cluster
+    pub points: Vec<Vector2<f64>>,
+    /// Polygon enclosing the points, counter-clockwise
+    pub polygon: Vec<Vector2<f64>>,
+    pub centroid: Vector2<f64>,
+    /// Radius of the smallest circle centered on the centroid containing all the points
+    pub radius: f64,
+}
+
+impl Obstacle {
+    pub fn new(points: Vec<Vector2<f64>>, polygon: Vec<Vector2<f64>>) -> Obstacle {
+        let centroid = points.iter().fold(Vector2::zeros(), |a, p| a + p) / points.len() as f64;
+        let radius = points
+            .iter()
+            .map(|p| (p - centroid).norm())
+            .fold(0.0, f64::max);
+        Obstacle {
+            points,
+            polygon,
+            centroid,
+            radius,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HullType {
+    Convex,
+    /// Concave hull obtained by digging into the convex hull edges, an edge is dug
+    /// if `edge length / distance to the closest inner point` is larger than the threshold
+    Concave(f64),
+}
+
+/// Groups points closer than `tolerance` to each other, clusters smaller than `min_size`
+/// are discarded. Returns the indices of the points of each cluster
+pub fn euclidean_clustering(
+    points: &[Vector2<f64>],
+    tolerance: f64,
+    min_size: usize,
+) -> Vec<Vec<usize>> {
+    let tree = KdTree::new(points);
+    let mut visited = vec![false; points.len()];
+    let mut clusters = Vec::new();
+    for seed in 0..points.len() {
+        if visited[seed] {
+            continue;
+        }
+        visited[seed] = true;
+        let mut cluster = vec![seed];
+        let mut i = 0;
+        while i < cluster.len() {
+            for j in tree.within(&points[cluster[i]], tolerance) {
+                if !visited[j] {
+                    visited[j] = true;
+                    cluster.push(j);
+                }
+            }
+            i += 1;
+        }
+        if cluster.len() >= min_size {
+            clusters.push(cluster);
+        }
+    }
+    clusters
+}
+
+/// Andrew's monotone chain, returns the hull counter-clockwise without repeating the first point
+pub fn convex_hull(points: &[Vector2<f64>]) -> Vec<Vector2<f64>> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| {
+        a.x.partial_cmp(&b.x)
+            .unwrap()
+            .then(a.y.partial_cmp(&b.y).unwrap())
+    });
+    sorted.dedup();
+    if sorted.len() < 3 {
+        return sorted;
+    }
+
+    let mut hull: Vec<Vector2<f64>> = Vec::with_capacity(2 * sorted.len());
+    // lower hull
+    for p in sorted.iter() {
+        while hull.len() >= 2 && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0.0 {
+            hull.pop();
+        }
+        hull.push(*p);
+    }
+    // upper hull
+    let lower_len = hull.len() + 1;
+    for p in sorted.iter().rev().skip(1) {
+        while hull.len() >= lower_len
+            && cross(&hull[hull.len() - 2], &hull[hull.len() - 1], p) <= 0.0
+        {
+            hull.pop();
+        }
+        hull.push(*p);
+    }
+    hull.pop();
+    hull
+}
+
+/// Source : A New Concave Hull Algorithm and Concaveness Measure for n-dimensional Datasets, Park & Oh
+pub fn concave_hull(points: &[Vector2<f64>], threshold: f64) -> Vec<Vector2<f64>> {
+    let mut hull = convex_hull(points);
+    if hull.len() < 3 {
+        return hull;
+    }
+    let mut inner: Vec<Vector2<f64>> = points
+        .iter()
+        .filter(|p| !hull.contains(p))
+        .copied()
+        .collect();
+
+    let mut i = 0;
+    while i < hull.len() {
+        let a = hull[i];
+        let b = hull[(i + 1) % hull.len()];
+        let edge_length = (b - a).norm();
+
+        // closest inner point to the edge, it must be closer to this edge than to its neighbours
+        let candidate = inner
+            .iter()
+            .enumerate()
+            .map(|(k, p)| (k, distance_to_segment(p, &a, &b)))
+            .filter(|(k, d)| {
+                let prev = hull[(i + hull.len() - 1) % hull.len()];
+                let next = hull[(i + 2) % hull.len()];
+                *d <= distance_to_segment(&inner[*k], &prev, &a)
+                    && *d <= distance_to_segment(&inner[*k], &b, &next)
+            })
+            .min_by(|x, y| x.1.partial_cmp(&y.1).unwrap());
+
+        if let Some((k, _)) = candidate {
+            let p = inner[k];
+            let decision = edge_length / (p - a).norm().min((p - b).norm());
+            if decision > threshold && !intersects_polygon(&hull, i, &p) {
+                hull.insert(i + 1, p);
+                inner.swap_remove(k);
+                continue;
+            }
+        }
+        i += 1;
+    }
+    hull
+}
+
+/// Clusters the points and wraps each cluster into an obstacle polygon
+pub fn extract_obstacles(
+    points: &[Vector2<f64>],
+    tolerance: f64,
+    min_size: usize,
+    hull_type: HullType,
+) -> Vec<Obstacle> {
+    euclidean_clustering(points, tolerance, min_size)
+        .into_iter()
+        .map(|cluster| {
+            let cluster: Vec<Vector2<f64>> = cluster.into_iter().map(|i| points[i]).collect();
+            let polygon = match hull_type {
+                HullType::Convex => convex_hull(&cluster),
+                HullType::Concave(threshold) => concave_hull(&cluster, threshold),
+            };
+            Obstacle::new(cluster, polygon)
+        })
+        .collect()
+}
+
+fn cross(o: &Vector2<f64>, a: &Vector2<f64>, b: &Vector2<f64>) -> f64 {
+    (a.x - o.x) * (b.y - o.y) - (a.y - o.y) * (b.x - o.x)
+}
+
+fn distance_to_segment(p: &Vector2<f64>, a: &Vector2<f64>, b: &Vector2<f64>) -> f64 {
+    let ab = b - a;
+    let len2 = ab.norm_squared();
+    if len2 == 0.0 {
+        return (p - a).norm();
+    }
+    let t = ((p - a).dot(&ab) / len2).clamp(0.0, 1.0);
+    (p - (a + ab * t)).norm()
+}
+
+fn segments_intersect(
+    p1: &Vector2<f64>,
+    p2: &Vector2<f64>,
+    q1: &Vector2<f64>,
+    q2: &Vector2<f64>,
+) -> bool {
+    let d1 = cross(q1, q2, p1);
+    let d2 = cross(q1, q2, p2);
+    let d3 = cross(p1, p2, q1);
+    let d4 = cross(p1, p2, q2);
+    d1 * d2 < 0.0 && d3 * d4 < 0.0
+}
+
+/// Would replacing the edge `edge` of the polygon by two edges going through `p` create an intersection
+fn intersects_polygon(polygon: &[Vector2<f64>], edge: usize, p: &Vector2<f64>) -> bool {
+    let n = polygon.len();
+    let a = polygon[edge];
+    let b = polygon[(edge + 1) % n];
+    (0..n).filter(|&j| j != edge).any(|j| {
+        let c = polygon[j];
+        let d = polygon[(j + 1) % n];
+        segments_intersect(&a, p, &c, &d) || segments_intersect(p, &b, &c, &d)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clustering_two_blobs() {
+        let mut points = Vec::new();
+        for i in 0..10 {
+            points.push(Vector2::new(i as f64 * 0.1, 0.0));
+            points.push(Vector2::new(5.0 + i as f64 * 0.1, 5.0));
+        }
+        points.push(Vector2::new(-10.0, -10.0)); // outlier
+
+        let clusters = euclidean_clustering(&points, 0.15, 3);
+        assert_eq!(2, clusters.len());
+        assert!(clusters.iter().all(|c| c.len() == 10));
+    }
+
+    #[test]
+    fn convex_hull_square() {
+        let mut points = vec![
+            Vector2::new(0.0, 0.0),
+            Vector2::new(1.0, 0.0),
+            Vector2::new(1.0, 1.0),
+            Vector2::new(0.0, 1.0),
+        ];
+        points.push(Vector2::new(0.5, 0.5));
+        points.push(Vector2::new(0.2, 0.7));
+
+        let hull = convex_hull(&points);
+        assert_eq!(4, hull.len());
+        assert!(!hull.contains(&Vector2::new(0.5, 0.5)));
+    }
+
+    #[test]
+    fn concave_hull_u_shape() {
+        let mut points = Vec::new();
+        for i in 0..=10 {
+            let t = i as f64 * 0.1;
+            points.push(Vector2::new(t, 0.0)); // bottom
+            points.push(Vector2::new(0.0, t)); // left
+            points.push(Vector2::new(1.0, t)); // right
+        }
+        for i in 0..=4 {
+            let t = i as f64 * 0.1;
+            points.push(Vector2::new(0.3 + t, 0.5)); // bottom of the notch
+            points.push(Vector2::new(0.3, 0.6 + t)); // left of the notch
+            points.push(Vector2::new(0.7, 0.6 + t)); // right of the notch
+        }
+
+        let convex = convex_hull(&points);
+        let concave = concave_hull(&points, 2.0);
+        assert_eq!(4, convex.len());
+        assert!(concave.contains(&Vector2::new(0.5, 0.5)));
+    }
+}
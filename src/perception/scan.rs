@@ -0,0 +1,166 @@
+use nalgebra::{Isometry2, Point2, Vector2};
+use rustc_hash::FxHashMap;
+
+/// Planar scan of a rotating lidar, the beams are evenly spaced in angle and time
+#[derive(Debug, Clone)]
+pub struct LaserScan {
+    /// Angle of the first beam [rad]
+    pub angle_min: f64,
+    /// Angle between two consecutive beams [rad]
+    pub angle_increment: f64,
+    /// Time between two consecutive beams [s]
+    pub time_increment: f64,
+    pub range_min: f64,
+    pub range_max: f64,
+    pub ranges: Vec<f64>,
+}
+
+impl LaserScan {
+    pub fn angle(&self, i: usize) -> f64 {
+        self.angle_min + i as f64 * self.angle_increment
+    }
+
+    pub fn is_valid(&self, i: usize) -> bool {
+        let r = self.ranges[i];
+        r.is_finite() && self.range_min <= r && r <= self.range_max
+    }
+
+    /// Duration of the sweep [s]
+    pub fn duration(&self) -> f64 {
+        self.ranges.len().saturating_sub(1) as f64 * self.time_increment
+    }
+
+    /// Cartesian points of the valid beams, in the sensor frame
+    pub fn to_points(&self) -> Vec<Vector2<f64>> {
+        (0..self.ranges.len())
+            .filter(|&i| self.is_valid(i))
+            .map(|i| {
+                let (sin, cos) = self.angle(i).sin_cos();
+                Vector2::new(self.ranges[i] * cos, self.ranges[i] * sin)
+            })
+            .collect()
+    }
+
+    /// Cartesian points of the valid beams expressed in the sensor frame at the time of the last beam.
+    ///
+    /// The sensor is assumed to move with the constant forward velocity `v` and angular velocity `w`
+    /// during the sweep, each beam is moved by the motion between its timestamp and the end of the scan
+    pub fn deskew(&self, v: f64, w: f64) -> Vec<Vector2<f64>> {
+        let end_inverse = unicycle_displacement(v, w, self.duration()).inverse();
+        (0..self.ranges.len())
+            .filter(|&i| self.is_valid(i))
+            .map(|i| {
+                let (sin, cos) = self.angle(i).sin_cos();
+                let p = Point2::new(self.ranges[i] * cos, self.ranges[i] * sin);
+                let beam_pose = unicycle_displacement(v, w, i as f64 * self.time_increment);
+                (end_inverse * beam_pose * p).coords
+            })
+            .collect()
+    }
+
+    /// Keeps one beam every `step` beams
+    pub fn angular_downsample(&self, step: usize) -> LaserScan {
+        let step = step.max(1);
+        LaserScan {
+            angle_min: self.angle_min,
+            angle_increment: self.angle_increment * step as f64,
+            time_increment: self.time_increment * step as f64,
+            range_min: self.range_min,
+            range_max: self.range_max,
+            ranges: self.ranges.iter().step_by(step).copied().collect(),
+        }
+    }
+}
+
+/// Pose reached after driving `dt` seconds at constant (v, w) from the origin
+pub fn unicycle_displacement(v: f64, w: f64, dt: f64) -> Isometry2<f64> {
+    let theta = w * dt;
+    let translation = if w.abs() < 1e-9 {
+        Vector2::new(v * dt, 0.0)
+    } else {
+        Vector2::new(v / w * theta.sin(), v / w * (1.0 - theta.cos()))
+    };
+    Isometry2::new(translation, theta)
+}
+
+/// Drops the points closer than `min_distance` to the previously kept point,
+/// the scan order is preserved so dense returns close to the sensor are thinned out
+pub fn radial_downsample(points: &[Vector2<f64>], min_distance: f64) -> Vec<Vector2<f64>> {
+    let mut kept: Vec<Vector2<f64>> = Vec::with_capacity(points.len());
+    for p in points {
+        if kept
+            .last()
+            .is_none_or(|last| (p - last).norm() >= min_distance)
+        {
+            kept.push(*p);
+        }
+    }
+    kept
+}
+
+/// Replaces the points falling in the same square cell by their centroid
+pub fn voxel_downsample(points: &[Vector2<f64>], voxel_size: f64) -> Vec<Vector2<f64>> {
+    let mut cells: FxHashMap<(i64, i64), (Vector2<f64>, usize)> = FxHashMap::default();
+    let mut order = Vec::new();
+    for p in points {
+        let key = (
+            (p.x / voxel_size).floor() as i64,
+            (p.y / voxel_size).floor() as i64,
+        );
+        let cell = cells.entry(key).or_insert_with(|| {
+            order.push(key);
+            (Vector2::zeros(), 0)
+        });
+        cell.0 += p;
+        cell.1 += 1;
+    }
+    order
+        .iter()
+        .map(|key| {
+            let (sum, n) = cells[key];
+            sum / n as f64
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deskew_moving_towards_wall() {
+        // wall at x = 5, the robot drives towards it during the sweep
+        let v = 2.0;
+        let time_increment = 0.01;
+        let angles = [-0.2, -0.1, 0.0, 0.1, 0.2];
+        let ranges = angles
+            .iter()
+            .enumerate()
+            .map(|(i, a)| (5.0 - v * i as f64 * time_increment) / f64::cos(*a))
+            .collect();
+        let scan = LaserScan {
+            angle_min: -0.2,
+            angle_increment: 0.1,
+            time_increment,
+            range_min: 0.1,
+            range_max: 30.0,
+            ranges,
+        };
+
+        let wall_x = 5.0 - v * scan.duration();
+        let raw = scan.to_points();
+        assert!(raw.iter().any(|p| (p.x - wall_x).abs() > 0.05));
+        for p in scan.deskew(v, 0.0) {
+            approx::assert_abs_diff_eq!(wall_x, p.x, epsilon = 1e-9);
+        }
+    }
+
+    #[test]
+    fn downsampling() {
+        let points: Vec<Vector2<f64>> = (0..100)
+            .map(|i| Vector2::new(i as f64 * 0.01, 0.0))
+            .collect();
+        assert_eq!(10, radial_downsample(&points, 0.1 - 1e-9).len());
+        assert_eq!(10, voxel_downsample(&points, 0.1).len());
+    }
+}
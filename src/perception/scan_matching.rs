@@ -0,0 +1,539 @@
+use nalgebra::{Const, Isometry2, Matrix2, Matrix3, Point2, Vector2, Vector3};
+use rustc_hash::FxHashMap;
+
+use crate::mapping::EdgeSE2;
+use crate::utils::kdtree::KdTree;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IcpMethod {
+    #[default]
+    PointToPoint,
+    /// Minimizes the distance along the normals of the target, converges faster on structured
+    /// environments (walls)
+    PointToPlane,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct IcpConfig {
+    pub method: IcpMethod,
+    pub max_iterations: usize,
+    /// Pairs further apart [m] are outliers
+    pub max_correspondence_distance: f64,
+    /// Stops when the update of the transform is below, in translation [m] and rotation [rad]
+    pub tolerance: f64,
+    /// Radius [m] of the neighbourhood of the normals of the target
+    pub normal_radius: f64,
+}
+
+impl Default for IcpConfig {
+    fn default() -> Self {
+        IcpConfig {
+            method: IcpMethod::default(),
+            max_iterations: 50,
+            max_correspondence_distance: 1.0,
+            tolerance: 1e-6,
+            normal_radius: 0.3,
+        }
+    }
+}
+
+/// Result of a scan matcher, the fitness and rmse come from the nearest target point of each
+/// source point so they are comparable between the matchers
+#[derive(Debug, Clone, Copy)]
+pub struct MatchResult {
+    /// Pose of the source in the frame of the target
+    pub transform: Isometry2<f64>,
+    /// Fraction of the source points with a correspondence
+    pub fitness: f64,
+    /// Root mean square distance of the correspondences
+    pub rmse: f64,
+    pub iterations: usize,
+    pub converged: bool,
+}
+
+impl MatchResult {
+    /// Edge of the pose graph between the pose `from` of the target scan and the pose `to` of
+    /// the source scan
+    pub fn to_edge(&self, from: u32, to: u32, information: Matrix3<f64>) -> EdgeSE2<f64> {
+        EdgeSE2::new(from, to, self.transform, information)
+    }
+}
+
+/// Unit normal of the points around `p`, None if they are not spread along a line
+fn normal(tree: &KdTree<f64, Const<2>>, p: &Vector2<f64>, radius: f64) -> Option<Vector2<f64>> {
+    let neighbours = tree.within(p, radius);
+    if neighbours.len() < 3 {
+        return None;
+    }
+    let n = neighbours.len() as f64;
+    let mean = neighbours
+        .iter()
+        .fold(Vector2::zeros(), |acc, i| acc + tree.points()[*i])
+        / n;
+    let cov = neighbours.iter().fold(Matrix2::zeros(), |acc, i| {
+        let d = tree.points()[*i] - mean;
+        acc + d * d.transpose()
+    }) / n;
+    let eigen = cov.symmetric_eigen();
+    let (small, large) = if eigen.eigenvalues[0] < eigen.eigenvalues[1] {
+        (0, 1)
+    } else {
+        (1, 0)
+    };
+    (eigen.eigenvalues[large] > 0.0).then(|| eigen.eigenvectors.column(small).into_owned())
+}
+
+/// Closed form rigid transform aligning the pairs (source, target)
+fn align(pairs: &[(Vector2<f64>, Vector2<f64>)]) -> Isometry2<f64> {
+    let n = pairs.len() as f64;
+    let (ms, mt) = pairs
+        .iter()
+        .fold((Vector2::zeros(), Vector2::zeros()), |acc, (s, t)| {
+            (acc.0 + s, acc.1 + t)
+        });
+    let (ms, mt) = (ms / n, mt / n);
+    let (mut sxx, mut sxy) = (0.0, 0.0);
+    for (s, t) in pairs {
+        let (s, t) = (s - ms, t - mt);
+        sxx += s.dot(&t);
+        sxy += s.x * t.y - s.y * t.x;
+    }
+    let rotation = nalgebra::UnitComplex::new(sxy.atan2(sxx));
+    Isometry2::from_parts((mt - rotation * ms).into(), rotation)
+}
+
+/// Gauss-Newton step of the point to plane error, pairs (source, target, target normal)
+fn align_point_to_plane(
+    pairs: &[(Vector2<f64>, Vector2<f64>, Vector2<f64>)],
+) -> Option<Isometry2<f64>> {
+    let mut h = Matrix3::zeros();
+    let mut g = Vector3::zeros();
+    for (s, t, n) in pairs {
+        let j = Vector3::new(n.x, n.y, n.y * s.x - n.x * s.y);
+        let r = n.dot(&(s - t));
+        h += j * j.transpose();
+        g += j * r;
+    }
+    let delta = h.cholesky()?.solve(&(-g));
+    Some(Isometry2::new(Vector2::new(delta.x, delta.y), delta.z))
+}
+
+/// Iterative closest point of `source` on `target` from the guess `initial`, None if there are
+/// not enough correspondences
+///
+/// Source : Besl & McKay, A method for registration of 3-D shapes, 1992 (point to point)
+/// and Chen & Medioni, Object modelling by registration of multiple range images, 1992
+/// (point to plane)
+pub fn icp(
+    source: &[Vector2<f64>],
+    target: &KdTree<f64, Const<2>>,
+    initial: &Isometry2<f64>,
+    config: &IcpConfig,
+) -> Option<MatchResult> {
+    let normals: Vec<Option<Vector2<f64>>> = match config.method {
+        IcpMethod::PointToPoint => Vec::new(),
+        IcpMethod::PointToPlane => target
+            .points
+            .iter()
+            .map(|p| normal(target, p, config.normal_radius))
+            .collect(),
+    };
+    let max_d2 = config.max_correspondence_distance.powi(2);
+    let mut transform = *initial;
+    let mut iterations = 0;
+    let mut converged = false;
+
+    while iterations < config.max_iterations {
+        iterations += 1;
+        let moved: Vec<Vector2<f64>> = source
+            .iter()
+            .map(|p| (transform * Point2::from(*p)).coords)
+            .collect();
+        let matches = moved
+            .iter()
+            .filter_map(|p| target.nearest(p).map(|(i, d2)| (p, i, d2)))
+            .filter(|(_, _, d2)| *d2 <= max_d2);
+        let delta = match config.method {
+            IcpMethod::PointToPoint => {
+                let pairs: Vec<_> = matches.map(|(p, i, _)| (*p, target.points[i])).collect();
+                if pairs.len() < 3 {
+                    return None;
+                }
+                align(&pairs)
+            }
+            IcpMethod::PointToPlane => {
+                let pairs: Vec<_> = matches
+                    .filter_map(|(p, i, _)| Some((*p, target.points[i], normals[i]?)))
+                    .collect();
+                if pairs.len() < 3 {
+                    return None;
+                }
+                align_point_to_plane(&pairs)?
+            }
+        };
+        transform = delta * transform;
+        if delta.translation.vector.norm() < config.tolerance
+            && delta.rotation.angle().abs() < config.tolerance
+        {
+            converged = true;
+            break;
+        }
+    }
+
+    evaluate(
+        source,
+        target,
+        transform,
+        config.max_correspondence_distance,
+        iterations,
+        converged,
+    )
+}
+
+/// Fitness and rmse of the source points closer than `max_distance` to the target
+fn evaluate(
+    source: &[Vector2<f64>],
+    target: &KdTree<f64, Const<2>>,
+    transform: Isometry2<f64>,
+    max_distance: f64,
+    iterations: usize,
+    converged: bool,
+) -> Option<MatchResult> {
+    let distances: Vec<f64> = source
+        .iter()
+        .filter_map(|p| target.nearest(&(transform * Point2::from(*p)).coords))
+        .map(|(_, d2)| d2)
+        .filter(|d2| *d2 <= max_distance * max_distance)
+        .collect();
+    if distances.is_empty() {
+        return None;
+    }
+    Some(MatchResult {
+        transform,
+        fitness: distances.len() as f64 / source.len() as f64,
+        rmse: (distances.iter().sum::<f64>() / distances.len() as f64).sqrt(),
+        iterations,
+        converged,
+    })
+}
+
+/// Common interface of the scan matchers, the target (reference scan or map) is preprocessed
+/// once and many sources are matched against it
+pub trait ScanMatcher {
+    fn set_target(&mut self, target: &[Vector2<f64>]);
+
+    /// Pose of the source in the frame of the target from the guess `initial`, None if the
+    /// scans do not overlap enough
+    fn match_scan(&self, source: &[Vector2<f64>], initial: &Isometry2<f64>) -> Option<MatchResult>;
+}
+
+/// ICP as a `ScanMatcher`
+#[derive(Debug, Clone)]
+pub struct Icp {
+    pub config: IcpConfig,
+    target: KdTree<f64, Const<2>>,
+}
+
+impl Icp {
+    pub fn new(config: IcpConfig) -> Icp {
+        Icp {
+            config,
+            target: KdTree::new(&[]),
+        }
+    }
+}
+
+impl ScanMatcher for Icp {
+    fn set_target(&mut self, target: &[Vector2<f64>]) {
+        self.target = KdTree::new(target);
+    }
+
+    fn match_scan(&self, source: &[Vector2<f64>], initial: &Isometry2<f64>) -> Option<MatchResult> {
+        icp(source, &self.target, initial, &self.config)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NdtConfig {
+    /// Size [m] of the cells
+    pub resolution: f64,
+    /// Cells with fewer points are ignored
+    pub min_points: usize,
+    pub max_iterations: usize,
+    /// Stops when the update of the transform is below, in translation [m] and rotation [rad]
+    pub tolerance: f64,
+    /// Distance [m] used for the fitness of the result
+    pub max_correspondence_distance: f64,
+}
+
+impl Default for NdtConfig {
+    fn default() -> Self {
+        NdtConfig {
+            resolution: 1.0,
+            min_points: 3,
+            max_iterations: 50,
+            tolerance: 1e-6,
+            max_correspondence_distance: 1.0,
+        }
+    }
+}
+
+/// Gaussian of the points of a cell
+#[derive(Debug, Clone, Copy)]
+struct NdtCell {
+    mean: Vector2<f64>,
+    information: Matrix2<f64>,
+}
+
+/// Normal Distributions Transform, the target is voxelized into cells holding the mean and
+/// covariance of their points and the pose maximizes the sum of the gaussians at the source
+/// points with Newton steps. Smooth and robust to sparse or noisy scans, each point is scored
+/// against its cell and its 8 neighbours
+///
+/// Source : Biber & Straßer, The normal distributions transform: a new approach to laser scan
+/// matching, 2003
+#[derive(Debug, Clone)]
+pub struct Ndt {
+    pub config: NdtConfig,
+    cells: FxHashMap<(i64, i64), NdtCell>,
+    target: KdTree<f64, Const<2>>,
+}
+
+impl Ndt {
+    pub fn new(config: NdtConfig) -> Ndt {
+        Ndt {
+            config,
+            cells: FxHashMap::default(),
+            target: KdTree::new(&[]),
+        }
+    }
+
+    fn cell_of(&self, p: &Vector2<f64>) -> (i64, i64) {
+        (
+            (p.x / self.config.resolution).floor() as i64,
+            (p.y / self.config.resolution).floor() as i64,
+        )
+    }
+
+    /// Score (negative likelihood), gradient and hessian wrt (x, y, theta) at the transform
+    fn derivatives(
+        &self,
+        source: &[Vector2<f64>],
+        transform: &Isometry2<f64>,
+    ) -> (f64, Vector3<f64>, Matrix3<f64>) {
+        let (s, c) = transform.rotation.angle().sin_cos();
+        let mut score = 0.0;
+        let mut g = Vector3::zeros();
+        let mut h = Matrix3::zeros();
+        for p in source {
+            let moved = (transform * Point2::from(*p)).coords;
+            #[rustfmt::skip]
+            let j = nalgebra::Matrix2x3::new(
+                1.0, 0.0, -s * p.x - c * p.y,
+                0.0, 1.0, c * p.x - s * p.y,
+            );
+            let second = Vector2::new(-c * p.x + s * p.y, -s * p.x - c * p.y);
+            let (cx, cy) = self.cell_of(&moved);
+            for (dx, dy) in (-1..=1).flat_map(|dx| (-1..=1).map(move |dy| (dx, dy))) {
+                let Some(cell) = self.cells.get(&(cx + dx, cy + dy)) else {
+                    continue;
+                };
+                let d = moved - cell.mean;
+                let cd = cell.information * d;
+                let e = (-0.5 * d.dot(&cd)).exp();
+                if e < 1e-12 {
+                    continue;
+                }
+                score -= e;
+                let dj = j.transpose() * cd;
+                g += dj * e;
+                h += (j.transpose() * cell.information * j - dj * dj.transpose()) * e;
+                h[(2, 2)] += cd.dot(&second) * e;
+            }
+        }
+        (score, g, h)
+    }
+}
+
+impl ScanMatcher for Ndt {
+    fn set_target(&mut self, target: &[Vector2<f64>]) {
+        let mut groups: FxHashMap<(i64, i64), Vec<Vector2<f64>>> = FxHashMap::default();
+        for p in target {
+            groups.entry(self.cell_of(p)).or_default().push(*p);
+        }
+        self.cells = groups
+            .into_iter()
+            .filter(|(_, points)| points.len() >= self.config.min_points.max(2))
+            .filter_map(|(key, points)| {
+                let n = points.len() as f64;
+                let mean = points.iter().sum::<Vector2<f64>>() / n;
+                let cov = points.iter().fold(Matrix2::zeros(), |acc, p| {
+                    let d = p - mean;
+                    acc + d * d.transpose()
+                }) / (n - 1.0);
+                // keeps the cells of points on a line invertible
+                let mut eigen = cov.symmetric_eigen();
+                let max = eigen.eigenvalues.max();
+                if max <= 0.0 {
+                    return None;
+                }
+                eigen.eigenvalues = eigen.eigenvalues.map(|l| l.max(max * 1e-2));
+                let information = eigen.recompose().try_inverse()?;
+                Some((key, NdtCell { mean, information }))
+            })
+            .collect();
+        self.target = KdTree::new(target);
+    }
+
+    fn match_scan(&self, source: &[Vector2<f64>], initial: &Isometry2<f64>) -> Option<MatchResult> {
+        let mut transform = *initial;
+        let (mut score, mut g, mut h) = self.derivatives(source, &transform);
+        let mut iterations = 0;
+        let mut converged = false;
+        while iterations < self.config.max_iterations {
+            iterations += 1;
+            // Levenberg damping while the hessian is not positive definite
+            let mut lambda = 0.0;
+            let step = loop {
+                if let Some(cholesky) = (h + Matrix3::identity() * lambda).cholesky() {
+                    break cholesky.solve(&(-g));
+                }
+                lambda = (lambda * 10.0).max(1e-3 * h.diagonal().abs().max().max(1e-9));
+            };
+            // backtracking on the score
+            let mut alpha = 1.0;
+            let mut accepted = None;
+            for _ in 0..10 {
+                let delta = step * alpha;
+                let candidate = Isometry2::new(
+                    transform.translation.vector + delta.xy(),
+                    transform.rotation.angle() + delta.z,
+                );
+                let (candidate_score, candidate_g, candidate_h) =
+                    self.derivatives(source, &candidate);
+                if candidate_score <= score {
+                    accepted = Some((delta, candidate, candidate_score, candidate_g, candidate_h));
+                    break;
+                }
+                alpha /= 2.0;
+            }
+            let Some((delta, candidate, new_score, new_g, new_h)) = accepted else {
+                converged = true;
+                break;
+            };
+            transform = candidate;
+            (score, g, h) = (new_score, new_g, new_h);
+            if delta.xy().norm() < self.config.tolerance && delta.z.abs() < self.config.tolerance {
+                converged = true;
+                break;
+            }
+        }
+        evaluate(
+            source,
+            &self.target,
+            transform,
+            self.config.max_correspondence_distance,
+            iterations,
+            converged,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng};
+
+    /// Walls of a 10 x 6 room with a pillar
+    fn room() -> Vec<Vector2<f64>> {
+        let mut points = Vec::new();
+        for i in 0..=500 {
+            let x = i as f64 * 0.02;
+            points.push(Vector2::new(x, 0.0));
+            points.push(Vector2::new(x, 6.0));
+        }
+        for i in 1..300 {
+            let y = i as f64 * 0.02;
+            points.push(Vector2::new(0.0, y));
+            points.push(Vector2::new(10.0, y));
+        }
+        for i in 0..50 {
+            points.push(Vector2::new(6.0, 2.0 + i as f64 * 0.02));
+            points.push(Vector2::new(6.0 + i as f64 * 0.02, 2.0));
+        }
+        points
+    }
+
+    #[test]
+    fn matchers_recover_the_motion() {
+        let target = room();
+        let motion = Isometry2::new(Vector2::new(0.3, -0.2), 0.08);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        // sparse and noisy source
+        let source: Vec<Vector2<f64>> = target
+            .iter()
+            .step_by(7)
+            .map(|p| {
+                let noise = Vector2::new(rng.gen_range(-0.02..0.02), rng.gen_range(-0.02..0.02));
+                (motion.inverse() * Point2::from(*p)).coords + noise
+            })
+            .collect();
+        let mut matchers: Vec<Box<dyn ScanMatcher>> = vec![
+            Box::new(Icp::new(IcpConfig {
+                method: IcpMethod::PointToPlane,
+                ..Default::default()
+            })),
+            Box::new(Ndt::new(NdtConfig::default())),
+        ];
+        for matcher in matchers.iter_mut() {
+            matcher.set_target(&target);
+            let result = matcher.match_scan(&source, &Isometry2::identity()).unwrap();
+            approx::assert_abs_diff_eq!(
+                motion.translation.vector,
+                result.transform.translation.vector,
+                epsilon = 0.02
+            );
+            approx::assert_abs_diff_eq!(
+                motion.rotation.angle(),
+                result.transform.rotation.angle(),
+                epsilon = 0.01
+            );
+            assert!(result.fitness > 0.99);
+        }
+    }
+
+    #[test]
+    fn icp_recovers_the_motion() {
+        let target = room();
+        let motion = Isometry2::new(Vector2::new(0.2, -0.1), 0.05);
+        let source: Vec<Vector2<f64>> = target
+            .iter()
+            .map(|p| (motion.inverse() * Point2::from(*p)).coords)
+            .collect();
+        let tree = KdTree::new(&target);
+        // point to point is only accurate to the spacing of the points, 2 cm
+        for (method, epsilon) in [
+            (IcpMethod::PointToPoint, 0.02),
+            (IcpMethod::PointToPlane, 1e-3),
+        ] {
+            let config = IcpConfig {
+                method,
+                ..Default::default()
+            };
+            let result = icp(&source, &tree, &Isometry2::identity(), &config).unwrap();
+            assert!(result.converged, "{method:?}");
+            approx::assert_abs_diff_eq!(
+                motion.translation.vector,
+                result.transform.translation.vector,
+                epsilon = epsilon
+            );
+            approx::assert_abs_diff_eq!(
+                motion.rotation.angle(),
+                result.transform.rotation.angle(),
+                epsilon = epsilon
+            );
+            assert!(result.fitness > 0.99);
+            assert!(result.rmse < 0.02);
+        }
+    }
+}
@@ -0,0 +1,638 @@
+use nalgebra::{allocator::Allocator, Const, DefaultAllocator, Dim, OVector, RealField};
+use std::sync::mpsc::{Receiver, Sender};
+
+use crate::datasets::event::{Event, TimedEvent};
+use crate::localization::BayesianFilterKnownCorrespondences;
+use crate::utils::state::GaussianState;
+
+/// Produces the messages entering a pipeline, None when nothing is available for now
+pub trait Source<M> {
+    fn poll(&mut self) -> Option<M>;
+}
+
+/// Transforms a message, None drops it
+pub trait Stage<I, O> {
+    fn process(&mut self, input: I) -> Option<O>;
+}
+
+/// Lets a message through or drops it
+pub trait Gate<M> {
+    fn accept(&mut self, message: &M) -> bool;
+}
+
+/// Consumes the messages, a controller, a logger, a publisher, ...
+pub trait Sink<M> {
+    fn consume(&mut self, message: &M);
+}
+
+/// Typed channel from another thread, e.g. a sensor driver or another pipeline
+impl<M> Source<M> for Receiver<M> {
+    fn poll(&mut self) -> Option<M> {
+        self.try_recv().ok()
+    }
+}
+
+/// Typed channel to another thread, e.g. a controller or another pipeline. The messages are
+/// dropped once the receiver is gone
+impl<M: Clone> Sink<M> for Sender<M> {
+    fn consume(&mut self, message: &M) {
+        let _ = self.send(message.clone());
+    }
+}
+
+/// Source replaying the messages of an iterator, e.g. the events of a dataset
+pub struct IterSource<I: Iterator>(pub I);
+
+impl<I: Iterator> Source<I::Item> for IterSource<I> {
+    fn poll(&mut self) -> Option<I::Item> {
+        self.0.next()
+    }
+}
+
+impl<I, O, F: FnMut(I) -> Option<O>> Stage<I, O> for F {
+    fn process(&mut self, input: I) -> Option<O> {
+        self(input)
+    }
+}
+
+impl<M, F: FnMut(&M) -> bool> Gate<M> for F {
+    fn accept(&mut self, message: &M) -> bool {
+        self(message)
+    }
+}
+
+impl<M, F: FnMut(&M)> Sink<M> for F {
+    fn consume(&mut self, message: &M) {
+        self(message)
+    }
+}
+
+/// Drops the messages whose squared Mahalanobis distance, computed by `distance`, is above
+/// `threshold`. The threshold is the chi-square quantile for the degrees of freedom of the
+/// distance, at 95 % : 3.84 (1), 5.99 (2), 7.81 (3), 9.49 (4), see
+/// `utils::metrics::chi_square_quantile` for the others
+pub struct ChiSquareGate<M, F: FnMut(&M) -> f64> {
+    pub threshold: f64,
+    distance: F,
+    pub rejected: usize,
+    _message: std::marker::PhantomData<fn(&M)>,
+}
+
+impl<M, F: FnMut(&M) -> f64> ChiSquareGate<M, F> {
+    pub fn new(threshold: f64, distance: F) -> ChiSquareGate<M, F> {
+        ChiSquareGate {
+            threshold,
+            distance,
+            rejected: 0,
+            _message: std::marker::PhantomData,
+        }
+    }
+}
+
+impl<M, F: FnMut(&M) -> f64> Gate<M> for ChiSquareGate<M, F> {
+    fn accept(&mut self, message: &M) -> bool {
+        let accepted = (self.distance)(message) <= self.threshold;
+        self.rejected += usize::from(!accepted);
+        accepted
+    }
+}
+
+/// Estimate of a filter at a given time
+#[derive(Debug, Clone)]
+pub struct TimedEstimate<T: RealField, S: Dim>
+where
+    DefaultAllocator: Allocator<T, S> + Allocator<T, S, S>,
+{
+    pub time: T,
+    pub state: GaussianState<T, S>,
+}
+
+/// Control applied from `time` until the next one
+#[derive(Debug, Clone)]
+pub struct ControlInput<T: RealField, U: Dim>
+where
+    DefaultAllocator: Allocator<T, U>,
+{
+    pub time: T,
+    pub u: OVector<T, U>,
+}
+
+/// Measurements of known landmarks taken at `time`, e.g. by one of the sensors
+#[derive(Debug, Clone)]
+pub struct Measurement<T: RealField, Z: Dim>
+where
+    DefaultAllocator: Allocator<T, Z>,
+{
+    pub time: T,
+    pub measurements: Vec<(u32, OVector<T, Z>)>,
+}
+
+/// Input of a `FilterPipeline` or of a `FilterStage`
+#[derive(Debug, Clone)]
+pub enum FilterInput<T: RealField, Z: Dim, U: Dim>
+where
+    DefaultAllocator: Allocator<T, Z> + Allocator<T, U>,
+{
+    Control(ControlInput<T, U>),
+    Measurement(Measurement<T, Z>),
+}
+
+impl<T: RealField + Copy, Z: Dim, U: Dim> FilterInput<T, Z, U>
+where
+    DefaultAllocator: Allocator<T, Z> + Allocator<T, U>,
+{
+    pub fn time(&self) -> T {
+        match self {
+            FilterInput::Control(c) => c.time,
+            FilterInput::Measurement(m) => m.time,
+        }
+    }
+
+    /// At the same time the controls come first, like `sort_events`
+    fn rank(&self) -> u8 {
+        match self {
+            FilterInput::Control(_) => 0,
+            FilterInput::Measurement(_) => 1,
+        }
+    }
+}
+
+impl FilterInput<f64, Const<2>, Const<2>> {
+    /// Input of a dataset event, None for the ground truth. The measurements without landmark
+    /// id are dropped
+    pub fn from_event(event: TimedEvent) -> Option<FilterInput<f64, Const<2>, Const<2>>> {
+        let time = event.time;
+        match event.event {
+            Event::GroundTruth { .. } => None,
+            Event::Control(u) => Some(FilterInput::Control(ControlInput { time, u })),
+            Event::Measurements(measurements) => Some(FilterInput::Measurement(Measurement {
+                time,
+                measurements: measurements
+                    .into_iter()
+                    .filter_map(|(id, z)| Some((id?, z)))
+                    .collect(),
+            })),
+        }
+    }
+}
+
+/// Feeds timestamped controls and measurements from asynchronous sensors to a filter with known
+/// correspondences in time order, `dt` is the time since the previous event and the last control
+/// is applied until the next one. The events are buffered for
+/// `reorder_window` after the newest one so the late ones are sorted in, the events older than
+/// the last applied one are dropped
+pub struct FilterPipeline<T: RealField, S: Dim, Z: Dim, U: Dim, F>
+where
+    DefaultAllocator: Allocator<T, Z> + Allocator<T, U>,
+{
+    pub filter: F,
+    pub reorder_window: T,
+    buffer: Vec<FilterInput<T, Z, U>>,
+    control: Option<OVector<T, U>>,
+    last_time: Option<T>,
+    newest: Option<T>,
+    dropped: usize,
+    _state: std::marker::PhantomData<S>,
+}
+
+impl<T, S, Z, U, F> FilterPipeline<T, S, Z, U, F>
+where
+    T: RealField + Copy,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    F: BayesianFilterKnownCorrespondences<T, S, Z, U>,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, Z> + Allocator<T, U> + Allocator<T, S, S>,
+{
+    pub fn new(filter: F, reorder_window: T) -> FilterPipeline<T, S, Z, U, F> {
+        FilterPipeline {
+            filter,
+            reorder_window,
+            buffer: Vec::new(),
+            control: None,
+            last_time: None,
+            newest: None,
+            dropped: 0,
+            _state: std::marker::PhantomData,
+        }
+    }
+
+    /// Returns false if the control is older than the last applied event and was dropped
+    pub fn push_control(&mut self, control: ControlInput<T, U>) -> bool {
+        self.push(FilterInput::Control(control))
+    }
+
+    /// Returns false if the measurements are older than the last applied event and were dropped
+    pub fn push_measurement(&mut self, measurement: Measurement<T, Z>) -> bool {
+        self.push(FilterInput::Measurement(measurement))
+    }
+
+    /// Returns false if the input is older than the last applied event and was dropped
+    pub fn push(&mut self, event: FilterInput<T, Z, U>) -> bool {
+        let time = event.time();
+        if self.last_time.is_some_and(|last| time < last) {
+            self.dropped += 1;
+            return false;
+        }
+        let rank = event.rank();
+        let position = self
+            .buffer
+            .partition_point(|e| e.time() < time || (e.time() == time && e.rank() <= rank));
+        self.buffer.insert(position, event);
+        self.newest = Some(self.newest.map_or(time, |newest| newest.max(time)));
+        self.apply_until(self.newest.unwrap() - self.reorder_window);
+        true
+    }
+
+    /// Applies all the buffered events, e.g. at the end of a log
+    pub fn flush(&mut self) {
+        if let Some(newest) = self.newest {
+            self.apply_until(newest);
+        }
+    }
+
+    fn apply_until(&mut self, time: T) {
+        let count = self.buffer.partition_point(|e| e.time() <= time);
+        for event in self.buffer.drain(..count).collect::<Vec<_>>() {
+            let time = event.time();
+            let dt = self.last_time.map_or(T::zero(), |last| time - last);
+            let (control, measurements) = match event {
+                FilterInput::Control(c) => (Some(c.u), None),
+                FilterInput::Measurement(m) => (None, Some(m.measurements)),
+            };
+            self.filter
+                .update_estimate(self.control.clone(), measurements, dt);
+            self.last_time = Some(time);
+            if control.is_some() {
+                self.control = control;
+            }
+        }
+    }
+
+    /// Time of the last applied event
+    pub fn time(&self) -> Option<T> {
+        self.last_time
+    }
+
+    /// Estimate at `time`
+    pub fn estimate(&self) -> GaussianState<T, S> {
+        self.filter.gaussian_estimate()
+    }
+
+    /// Number of events waiting in the reordering window
+    pub fn pending(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Number of events dropped because they arrived too late
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+}
+
+/// `FilterPipeline` as a stage, outputs the estimate each time inputs are applied. The inputs
+/// dropped by the pipeline, the ground truth of the datasets and the inputs still waiting in the
+/// reordering window give no output
+pub struct FilterStage<T: RealField, S: Dim, Z: Dim, U: Dim, F>
+where
+    DefaultAllocator: Allocator<T, Z> + Allocator<T, U>,
+{
+    pub pipeline: FilterPipeline<T, S, Z, U, F>,
+}
+
+impl<T, S, Z, U, F> FilterStage<T, S, Z, U, F>
+where
+    T: RealField + Copy,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    F: BayesianFilterKnownCorrespondences<T, S, Z, U>,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, Z> + Allocator<T, U> + Allocator<T, S, S>,
+{
+    /// The inputs are applied as they come, the late ones are dropped
+    pub fn new(filter: F) -> FilterStage<T, S, Z, U, F> {
+        FilterStage::with_reorder_window(filter, T::zero())
+    }
+
+    pub fn with_reorder_window(filter: F, reorder_window: T) -> FilterStage<T, S, Z, U, F> {
+        FilterStage {
+            pipeline: FilterPipeline::new(filter, reorder_window),
+        }
+    }
+}
+
+impl<T, S, Z, U, F> Stage<FilterInput<T, Z, U>, TimedEstimate<T, S>> for FilterStage<T, S, Z, U, F>
+where
+    T: RealField + Copy,
+    S: Dim,
+    Z: Dim,
+    U: Dim,
+    F: BayesianFilterKnownCorrespondences<T, S, Z, U>,
+    DefaultAllocator: Allocator<T, S> + Allocator<T, Z> + Allocator<T, U> + Allocator<T, S, S>,
+{
+    fn process(&mut self, input: FilterInput<T, Z, U>) -> Option<TimedEstimate<T, S>> {
+        let pending = self.pipeline.pending();
+        if !self.pipeline.push(input) || self.pipeline.pending() > pending {
+            return None;
+        }
+        Some(TimedEstimate {
+            time: self.pipeline.time()?,
+            state: self.pipeline.estimate(),
+        })
+    }
+}
+
+/// Dataset events, see `FilterInput::from_event`
+impl<S: Dim, F> Stage<TimedEvent, TimedEstimate<f64, S>>
+    for FilterStage<f64, S, Const<2>, Const<2>, F>
+where
+    F: BayesianFilterKnownCorrespondences<f64, S, Const<2>, Const<2>>,
+    DefaultAllocator: Allocator<f64, S> + Allocator<f64, S, S>,
+{
+    fn process(&mut self, input: TimedEvent) -> Option<TimedEstimate<f64, S>> {
+        let input = FilterInput::from_event(input)?;
+        Stage::<FilterInput<f64, Const<2>, Const<2>>, _>::process(self, input)
+    }
+}
+
+/// Declarative wiring of sources, stages (filters), gates and sinks. The type of the messages
+/// changes along the chain, `I` enters and `O` leaves the last stage. A sink sees the messages
+/// of the stage it was added after. Channels connect pipelines running in different threads, a
+/// `Receiver` is a source and a `Sender` a sink
+///
+/// ```ignore
+/// let mut pipeline = Pipeline::new()
+///     .add_source(IterSource(dataset.events()))
+///     .add_source(sensor_receiver)
+///     .add_filter(FilterInput::from_event)
+///     .add_gate(ChiSquareGate::new(chi_square_quantile(2, 0.99), |input: &FilterInput<..>| ...))
+///     .add_filter(FilterStage::new(ekf))
+///     .add_sink(controller_sender);
+/// pipeline.run();
+/// ```
+pub struct Pipeline<'a, I, O> {
+    sources: Vec<Box<dyn Source<I> + 'a>>,
+    chain: Box<dyn FnMut(I) -> Option<O> + 'a>,
+    sinks: Vec<Box<dyn Sink<O> + 'a>>,
+}
+
+impl<'a, I: 'a> Pipeline<'a, I, I> {
+    pub fn new() -> Pipeline<'a, I, I> {
+        Pipeline {
+            sources: Vec::new(),
+            chain: Box::new(Some),
+            sinks: Vec::new(),
+        }
+    }
+}
+
+impl<'a, I: 'a> Default for Pipeline<'a, I, I> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, I: 'a, O: 'a> Pipeline<'a, I, O> {
+    /// The sources are polled in the order they were added
+    pub fn add_source(mut self, source: impl Source<I> + 'a) -> Self {
+        self.sources.push(Box::new(source));
+        self
+    }
+
+    pub fn add_filter<O2: 'a>(self, mut stage: impl Stage<O, O2> + 'a) -> Pipeline<'a, I, O2> {
+        let mut chain = self.chain;
+        let mut sinks = self.sinks;
+        Pipeline {
+            sources: self.sources,
+            chain: Box::new(move |input| {
+                let message = chain(input)?;
+                for sink in sinks.iter_mut() {
+                    sink.consume(&message);
+                }
+                stage.process(message)
+            }),
+            sinks: Vec::new(),
+        }
+    }
+
+    pub fn add_gate(self, mut gate: impl Gate<O> + 'a) -> Self {
+        self.add_filter(move |message: O| gate.accept(&message).then_some(message))
+    }
+
+    pub fn add_sink(mut self, sink: impl Sink<O> + 'a) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Polls each source once, returns the number of messages read
+    pub fn step(&mut self) -> usize {
+        let mut count = 0;
+        for i in 0..self.sources.len() {
+            let Some(input) = self.sources[i].poll() else {
+                continue;
+            };
+            count += 1;
+            if let Some(output) = (self.chain)(input) {
+                for sink in self.sinks.iter_mut() {
+                    sink.consume(&output);
+                }
+            }
+        }
+        count
+    }
+
+    /// Steps until no source has a message, returns the number of messages read
+    pub fn run(&mut self) -> usize {
+        let mut total = 0;
+        loop {
+            let count = self.step();
+            if count == 0 {
+                return total;
+            }
+            total += count;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::localization::ExtendedKalmanFilterKnownCorrespondences;
+    use crate::models::measurement::{MeasurementModel, RangeBearingMeasurementModel};
+    use crate::models::motion::{MotionModel, Velocity};
+    use crate::utils::metrics::{chi_square_quantile, nis};
+    use nalgebra::{Matrix2, Matrix3, Vector2, Vector3};
+    use rustc_hash::FxHashMap;
+    use std::cell::RefCell;
+    use std::sync::mpsc;
+
+    #[test]
+    fn pipeline_with_a_filter_a_gate_and_sinks() {
+        let landmarks: FxHashMap<u32, Vector3<f64>> = [
+            (1, Vector3::new(5.0, 0.0, 0.0)),
+            (2, Vector3::new(0.0, 5.0, 0.0)),
+        ]
+        .into_iter()
+        .collect();
+        let motion_model = Velocity::new([0.1; 6]);
+        let measurement_model = RangeBearingMeasurementModel::new();
+        let q = Matrix2::identity() * 0.01;
+        let u = Vector2::new(1.0, 0.1);
+        let mut pose = Vector3::zeros();
+        let mut events = Vec::new();
+        for i in 0..20 {
+            let time = i as f64 * 0.1;
+            if i > 0 {
+                pose = motion_model.prediction(&pose, &u, 0.1);
+            }
+            events.push(TimedEvent {
+                time,
+                event: Event::Control(u),
+            });
+            let measurements = landmarks
+                .iter()
+                .map(|(id, lm)| {
+                    let mut z = measurement_model.prediction(&pose, Some(lm));
+                    // outlier, 3 m too far
+                    if i == 10 && *id == 1 {
+                        z.x += 3.0;
+                    }
+                    (Some(*id), z)
+                })
+                .collect();
+            events.push(TimedEvent {
+                time,
+                event: Event::Measurements(measurements),
+            });
+        }
+        let (sender, receiver) = mpsc::channel();
+        sender
+            .send(TimedEvent {
+                time: 2.0,
+                event: Event::GroundTruth {
+                    xy: pose.xy(),
+                    heading: None,
+                },
+            })
+            .unwrap();
+
+        let ekf = ExtendedKalmanFilterKnownCorrespondences::new(
+            q,
+            landmarks.clone(),
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([0.1; 6]),
+            GaussianState {
+                x: Vector3::zeros(),
+                cov: Matrix3::identity() * 0.01,
+            },
+        );
+        // NIS of the measurements against the last estimate
+        let latest: RefCell<Option<TimedEstimate<f64, Const<3>>>> = RefCell::new(None);
+        let nis_max = |input: &FilterInput<f64, Const<2>, Const<2>>| {
+            let (FilterInput::Measurement(m), Some(estimate)) = (input, latest.borrow().clone())
+            else {
+                return 0.0;
+            };
+            let state = estimate.state;
+            m.measurements
+                .iter()
+                .map(|(id, z)| {
+                    let landmark = landmarks.get(id);
+                    let h = measurement_model.jacobian(&state.x, landmark);
+                    let s = h * state.cov * h.transpose() + q;
+                    let innovation = z - measurement_model.prediction(&state.x, landmark);
+                    nis(&innovation, &s).unwrap()
+                })
+                .fold(0.0, f64::max)
+        };
+        let (estimate_sender, estimate_receiver) = mpsc::channel();
+        let mut inputs = 0;
+        let mut pipeline = Pipeline::new()
+            .add_source(IterSource(events.into_iter()))
+            .add_source(receiver)
+            .add_sink(|_: &TimedEvent| inputs += 1)
+            .add_filter(FilterInput::from_event)
+            .add_gate(ChiSquareGate::new(chi_square_quantile(2, 0.99), nis_max))
+            .add_filter(FilterStage::new(ekf))
+            .add_sink(|e: &TimedEstimate<f64, Const<3>>| *latest.borrow_mut() = Some(e.clone()))
+            .add_sink(estimate_sender);
+        assert_eq!(41, pipeline.run());
+        drop(pipeline);
+
+        assert_eq!(41, inputs);
+        // the ground truth and the measurements with the outlier are dropped
+        let estimates: Vec<TimedEstimate<f64, Const<3>>> = estimate_receiver.try_iter().collect();
+        assert_eq!(39, estimates.len());
+        approx::assert_abs_diff_eq!(pose, estimates[38].state.x, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn filter_pipeline_reorders_late_events() {
+        let landmarks: FxHashMap<u32, Vector3<f64>> = [
+            (1, Vector3::new(5.0, 0.0, 0.0)),
+            (2, Vector3::new(0.0, 5.0, 0.0)),
+        ]
+        .into_iter()
+        .collect();
+        let ekf = || {
+            ExtendedKalmanFilterKnownCorrespondences::new(
+                Matrix2::identity() * 0.01,
+                landmarks.clone(),
+                RangeBearingMeasurementModel::new(),
+                Velocity::new([0.1; 6]),
+                GaussianState {
+                    x: Vector3::zeros(),
+                    cov: Matrix3::identity() * 0.01,
+                },
+            )
+        };
+        let motion_model = Velocity::new([0.1; 6]);
+        let measurement_model = RangeBearingMeasurementModel::new();
+        let mut pose = Vector3::zeros();
+        let mut controls = Vec::new();
+        let mut measurements = Vec::new();
+        for i in 0..20 {
+            let u = Vector2::new(1.0, 0.1 * (i % 3) as f64);
+            controls.push(ControlInput {
+                time: i as f64 * 0.1,
+                u,
+            });
+            pose = motion_model.prediction(&pose, &u, 0.1);
+            // two sensors sampled between the controls
+            for (id, offset) in [(1, 0.03), (2, 0.07)] {
+                let z = measurement_model.prediction(&pose, landmarks.get(&id));
+                measurements.push(Measurement {
+                    time: i as f64 * 0.1 + offset,
+                    measurements: vec![(id, z)],
+                });
+            }
+        }
+
+        let mut in_order = FilterPipeline::new(ekf(), 0.0);
+        let mut reordered = FilterPipeline::new(ekf(), 0.25);
+        for (i, control) in controls.iter().enumerate() {
+            in_order.push_control(control.clone());
+            for m in &measurements[2 * i..2 * i + 2] {
+                in_order.push_measurement(m.clone());
+            }
+            // the second sensor lags by 0.2 s
+            reordered.push_control(control.clone());
+            reordered.push_measurement(measurements[2 * i].clone());
+            if i >= 2 {
+                assert!(reordered.push_measurement(measurements[2 * (i - 2) + 1].clone()));
+            }
+        }
+        for i in 18..20 {
+            reordered.push_measurement(measurements[2 * i + 1].clone());
+        }
+        assert!(reordered.pending() > 0);
+        reordered.flush();
+        assert_eq!(0, reordered.pending());
+        assert_eq!(in_order.time(), reordered.time());
+        approx::assert_abs_diff_eq!(in_order.estimate().x, reordered.estimate().x);
+
+        // older than the last applied event
+        assert!(!reordered.push_measurement(measurements[0].clone()));
+        assert_eq!(1, reordered.dropped());
+    }
+}
@@ -0,0 +1,434 @@
+use nalgebra::Vector2;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+use crate::mapping::{Costmap, OccupancyGrid};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Heuristic {
+    /// Dijkstra
+    Zero,
+    Manhattan,
+    Euclidean,
+    /// Exact distance with 8-connectivity
+    #[default]
+    Octile,
+}
+
+impl Heuristic {
+    fn cost(&self, a: (usize, usize), b: (usize, usize)) -> f64 {
+        let dx = a.0.abs_diff(b.0) as f64;
+        let dy = a.1.abs_diff(b.1) as f64;
+        match self {
+            Heuristic::Zero => 0.0,
+            Heuristic::Manhattan => dx + dy,
+            Heuristic::Euclidean => dx.hypot(dy),
+            Heuristic::Octile => dx.max(dy) + (std::f64::consts::SQRT_2 - 1.0) * dx.min(dy),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Connectivity {
+    Four,
+    #[default]
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GridPlannerConfig {
+    pub heuristic: Heuristic,
+    pub connectivity: Connectivity,
+    /// Cells more likely to be occupied than this are obstacles
+    pub occupied_threshold: f64,
+    /// The obstacles are inflated by this radius [m]
+    pub robot_radius: f64,
+    /// The steps through a cell of the costmap are longer by this times its cost over
+    /// `Costmap::INSCRIBED`, so the paths of `plan_costmap` keep away from the obstacles
+    pub cost_weight: f64,
+}
+
+impl Default for GridPlannerConfig {
+    fn default() -> Self {
+        GridPlannerConfig {
+            heuristic: Heuristic::default(),
+            connectivity: Connectivity::default(),
+            occupied_threshold: 0.65,
+            robot_radius: 0.0,
+            cost_weight: 1.0,
+        }
+    }
+}
+
+/// Obstacle mask of the grid with the obstacles grown by `radius`, row major like the grid
+pub fn inflate(grid: &OccupancyGrid, occupied_threshold: f64, radius: f64) -> Vec<bool> {
+    let r = (radius / grid.resolution).ceil() as i64;
+    let r2 = (radius / grid.resolution).powi(2);
+    let mut blocked = vec![false; grid.width * grid.height];
+    for row in 0..grid.height {
+        for column in 0..grid.width {
+            if grid.probability((column, row)) <= occupied_threshold {
+                continue;
+            }
+            for dy in -r..=r {
+                for dx in -r..=r {
+                    let (x, y) = (column as i64 + dx, row as i64 + dy);
+                    if x < 0 || y < 0 || x >= grid.width as i64 || y >= grid.height as i64 {
+                        continue;
+                    }
+                    if (dx * dx + dy * dy) as f64 <= r2 {
+                        blocked[y as usize * grid.width + x as usize] = true;
+                    }
+                }
+            }
+        }
+    }
+    blocked
+}
+
+/// Open list entry, ordered by increasing f = g + h
+#[derive(PartialEq)]
+struct Open {
+    f: f64,
+    index: usize,
+}
+
+impl Eq for Open {}
+
+impl Ord for Open {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.f.partial_cmp(&self.f).unwrap_or(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Open {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Occupancy grid with its obstacles inflated once, for several plans on the same map. The
+/// grid is borrowed so the mask can't get out of date
+pub struct InflatedGrid<'a> {
+    pub grid: &'a OccupancyGrid,
+    blocked: Vec<bool>,
+}
+
+impl<'a> InflatedGrid<'a> {
+    /// Inflates the obstacles by the robot radius of the config
+    pub fn new(grid: &'a OccupancyGrid, config: &GridPlannerConfig) -> InflatedGrid<'a> {
+        InflatedGrid {
+            grid,
+            blocked: inflate(grid, config.occupied_threshold, config.robot_radius),
+        }
+    }
+
+    pub fn is_blocked(&self, (column, row): (usize, usize)) -> bool {
+        self.blocked[row * self.grid.width + column]
+    }
+
+    /// Same as `plan`, the occupancy threshold and the robot radius of the config are not used
+    pub fn plan(
+        &self,
+        start: &Vector2<f64>,
+        goal: &Vector2<f64>,
+        config: &GridPlannerConfig,
+    ) -> Option<Vec<Vector2<f64>>> {
+        let grid = self.grid;
+        let start_cell = grid.world_to_cell(start)?;
+        let goal_cell = grid.world_to_cell(goal)?;
+        let path = search(
+            (grid.width, grid.height),
+            &self.blocked,
+            |_| 1.0,
+            start_cell,
+            goal_cell,
+            config,
+        )?;
+        Some(
+            path.iter()
+                .map(|i| grid.cell_to_world((i % grid.width, i / grid.width)))
+                .collect(),
+        )
+    }
+}
+
+/// A* from `start` to `goal` in world coordinates, returns the centers of the cells of the path,
+/// `start` and `goal` included. None if one of them is blocked or out of the grid, or if there is
+/// no path. `Heuristic::Zero` gives Dijkstra. The grid is inflated at each call, use
+/// `InflatedGrid` to plan several times on the same map
+pub fn plan(
+    grid: &OccupancyGrid,
+    start: &Vector2<f64>,
+    goal: &Vector2<f64>,
+    config: &GridPlannerConfig,
+) -> Option<Vec<Vector2<f64>>> {
+    InflatedGrid::new(grid, config).plan(start, goal, config)
+}
+
+/// A* on the costmap, the cells closer to an obstacle than the inscribed radius or unknown are
+/// blocked and the other ones are more expensive near the obstacles. The occupancy threshold
+/// and the robot radius of the config are not used, the costmap already includes them
+pub fn plan_costmap(
+    costmap: &Costmap,
+    start: &Vector2<f64>,
+    goal: &Vector2<f64>,
+    config: &GridPlannerConfig,
+) -> Option<Vec<Vector2<f64>>> {
+    let costs = costmap.costs();
+    let blocked: Vec<bool> = costs.iter().map(|c| *c >= Costmap::INSCRIBED).collect();
+    let start_cell = costmap.world_to_cell(start)?;
+    let goal_cell = costmap.world_to_cell(goal)?;
+    let path = search(
+        (costmap.width, costmap.height),
+        &blocked,
+        |i| 1.0 + config.cost_weight * costs[i] as f64 / Costmap::INSCRIBED as f64,
+        start_cell,
+        goal_cell,
+        config,
+    )?;
+    Some(
+        path.iter()
+            .map(|i| costmap.cell_to_world((i % costmap.width, i / costmap.width)))
+            .collect(),
+    )
+}
+
+/// A* over the cells of a row major grid, `weight` scales the length of the steps into a cell.
+/// The diagonal steps are only allowed when both cells they cut through are free, so the paths
+/// don't clip the corners of the obstacles. Returns the indices of the cells of the path from
+/// `start` to `goal`
+fn search(
+    (width, height): (usize, usize),
+    blocked: &[bool],
+    weight: impl Fn(usize) -> f64,
+    start_cell: (usize, usize),
+    goal_cell: (usize, usize),
+    config: &GridPlannerConfig,
+) -> Option<Vec<usize>> {
+    let index = |(column, row): (usize, usize)| row * width + column;
+    if blocked[index(start_cell)] || blocked[index(goal_cell)] {
+        return None;
+    }
+
+    let neighbours: &[(i64, i64)] = match config.connectivity {
+        Connectivity::Four => &[(1, 0), (-1, 0), (0, 1), (0, -1)],
+        Connectivity::Eight => &[
+            (1, 0),
+            (-1, 0),
+            (0, 1),
+            (0, -1),
+            (1, 1),
+            (1, -1),
+            (-1, 1),
+            (-1, -1),
+        ],
+    };
+
+    let n = width * height;
+    let mut g = vec![f64::INFINITY; n];
+    let mut parent = vec![usize::MAX; n];
+    let mut closed = vec![false; n];
+    let mut open = BinaryHeap::new();
+    let start_index = index(start_cell);
+    let goal_index = index(goal_cell);
+    g[start_index] = 0.0;
+    open.push(Open {
+        f: config.heuristic.cost(start_cell, goal_cell),
+        index: start_index,
+    });
+
+    while let Some(Open { index: current, .. }) = open.pop() {
+        if current == goal_index {
+            break;
+        }
+        if closed[current] {
+            continue;
+        }
+        closed[current] = true;
+        let cell = (current % width, current / width);
+        for (dx, dy) in neighbours {
+            let (x, y) = (cell.0 as i64 + dx, cell.1 as i64 + dy);
+            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                continue;
+            }
+            let next = (x as usize, y as usize);
+            let next_index = index(next);
+            if blocked[next_index] || closed[next_index] {
+                continue;
+            }
+            if dx * dy != 0
+                && (blocked[index((next.0, cell.1))] || blocked[index((cell.0, next.1))])
+            {
+                continue;
+            }
+            let step = if dx * dy == 0 {
+                1.0
+            } else {
+                std::f64::consts::SQRT_2
+            };
+            let cost = g[current] + step * weight(next_index);
+            if cost < g[next_index] {
+                g[next_index] = cost;
+                parent[next_index] = current;
+                open.push(Open {
+                    f: cost + config.heuristic.cost(next, goal_cell),
+                    index: next_index,
+                });
+            }
+        }
+    }
+
+    if !g[goal_index].is_finite() {
+        return None;
+    }
+    let mut path = vec![goal_index];
+    while *path.last().unwrap() != start_index {
+        path.push(parent[*path.last().unwrap()]);
+    }
+    path.reverse();
+    Some(path)
+}
+
+/// Length of a path in world units
+pub fn path_length(path: &[Vector2<f64>]) -> f64 {
+    path.windows(2).map(|w| (w[1] - w[0]).norm()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 20 x 20 cells of 1 m with a wall at x = 10 and a 3 cells gap at the top
+    fn grid() -> OccupancyGrid {
+        let mut grid = OccupancyGrid::new(Vector2::zeros(), 1.0, 20, 20);
+        for row in 0..17 {
+            grid.update_cell((10, row), 5.0);
+        }
+        grid
+    }
+
+    #[test]
+    fn astar_and_dijkstra() {
+        let grid = grid();
+        let start = Vector2::new(2.5, 2.5);
+        let goal = Vector2::new(17.5, 2.5);
+
+        let astar = plan(&grid, &start, &goal, &GridPlannerConfig::default()).unwrap();
+        let dijkstra = plan(
+            &grid,
+            &start,
+            &goal,
+            &GridPlannerConfig {
+                heuristic: Heuristic::Zero,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(start, astar[0]);
+        assert_eq!(goal, *astar.last().unwrap());
+        approx::assert_abs_diff_eq!(path_length(&astar), path_length(&dijkstra), epsilon = 1e-9);
+        assert!(astar.iter().any(|p| p.y > 17.0));
+
+        let four = plan(
+            &grid,
+            &start,
+            &goal,
+            &GridPlannerConfig {
+                connectivity: Connectivity::Four,
+                heuristic: Heuristic::Manhattan,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(four.windows(2).all(|w| (w[1] - w[0]).norm() == 1.0));
+    }
+
+    #[test]
+    fn no_corner_cutting() {
+        // diagonal wall, the cells on each side only touch by their corners
+        let mut grid = OccupancyGrid::new(Vector2::zeros(), 1.0, 10, 10);
+        for i in 0..10 {
+            grid.update_cell((i, i), 5.0);
+        }
+        let (start, goal) = (Vector2::new(3.5, 1.5), Vector2::new(1.5, 3.5));
+        assert!(plan(&grid, &start, &goal, &GridPlannerConfig::default()).is_none());
+
+        // around a single obstacle, the diagonal steps next to it are forbidden
+        let mut grid = OccupancyGrid::new(Vector2::zeros(), 1.0, 5, 5);
+        grid.update_cell((2, 2), 5.0);
+        let inflated = InflatedGrid::new(&grid, &GridPlannerConfig::default());
+        let path = inflated
+            .plan(
+                &Vector2::new(1.5, 2.5),
+                &Vector2::new(2.5, 3.5),
+                &GridPlannerConfig::default(),
+            )
+            .unwrap();
+        assert_eq!(3, path.len());
+        assert_eq!(Vector2::new(1.5, 3.5), path[1]);
+    }
+
+    #[test]
+    fn inflation_closes_the_gap() {
+        let grid = grid();
+        let config = GridPlannerConfig {
+            robot_radius: 3.0,
+            ..Default::default()
+        };
+        let inflated = InflatedGrid::new(&grid, &config);
+        assert!(inflated.is_blocked((10, 18)));
+        assert!(!inflated.is_blocked((5, 18)));
+        assert!(plan(
+            &grid,
+            &Vector2::new(2.5, 2.5),
+            &Vector2::new(17.5, 2.5),
+            &config
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn costmap_paths_keep_away_from_the_wall() {
+        use crate::mapping::CostmapConfig;
+
+        let costmap = Costmap::from_grid(
+            &grid(),
+            CostmapConfig {
+                inflation_radius: 4.0,
+                cost_scaling: 0.5,
+                footprint: vec![
+                    Vector2::new(0.4, 0.4),
+                    Vector2::new(-0.4, 0.4),
+                    Vector2::new(-0.4, -0.4),
+                    Vector2::new(0.4, -0.4),
+                ],
+                ..Default::default()
+            },
+        );
+        let start = Vector2::new(2.5, 2.5);
+        let goal = Vector2::new(17.5, 2.5);
+        let config = GridPlannerConfig {
+            cost_weight: 5.0,
+            ..Default::default()
+        };
+        let path = plan_costmap(&costmap, &start, &goal, &config).unwrap();
+        assert_eq!(start, path[0]);
+        assert_eq!(goal, *path.last().unwrap());
+        assert!(path.iter().all(|p| !costmap.is_blocked(p)));
+        // through the top of the gap instead of along the end of the wall
+        assert!(path.iter().filter(|p| p.x == 10.5).all(|p| p.y == 19.5));
+
+        let shortest = plan_costmap(
+            &costmap,
+            &start,
+            &goal,
+            &GridPlannerConfig {
+                cost_weight: 0.0,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(path_length(&path) > path_length(&shortest));
+    }
+}
@@ -0,0 +1,342 @@
+use nalgebra::SVector;
+use rand::Rng;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RrtConfig {
+    pub max_iterations: usize,
+    /// Maximum length of a new edge
+    pub step_size: f64,
+    /// Probability of sampling the goal instead of a random configuration
+    pub goal_bias: f64,
+    /// A node closer than this to the goal can be connected to it
+    pub goal_tolerance: f64,
+    /// Distance between the collision checks along an edge
+    pub collision_resolution: f64,
+    /// RRT* only, the new nodes are connected and rewired to the nodes within this radius
+    pub rewire_radius: f64,
+    /// Random shortcuts tried on the final path, 0 to keep the path of the tree
+    pub shortcut_iterations: usize,
+}
+
+impl Default for RrtConfig {
+    fn default() -> Self {
+        RrtConfig {
+            max_iterations: 5000,
+            step_size: 0.5,
+            goal_bias: 0.05,
+            goal_tolerance: 0.5,
+            collision_resolution: 0.05,
+            rewire_radius: 1.5,
+            shortcut_iterations: 100,
+        }
+    }
+}
+
+struct Tree<const D: usize> {
+    nodes: Vec<SVector<f64, D>>,
+    parents: Vec<usize>,
+    costs: Vec<f64>,
+}
+
+impl<const D: usize> Tree<D> {
+    fn new(root: SVector<f64, D>) -> Tree<D> {
+        Tree {
+            nodes: vec![root],
+            parents: vec![0],
+            costs: vec![0.0],
+        }
+    }
+
+    fn nearest(&self, q: &SVector<f64, D>) -> usize {
+        self.nodes
+            .iter()
+            .map(|n| (n - q).norm_squared())
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .unwrap()
+            .0
+    }
+
+    fn near(&self, q: &SVector<f64, D>, radius: f64) -> Vec<usize> {
+        (0..self.nodes.len())
+            .filter(|i| (self.nodes[*i] - q).norm() <= radius)
+            .collect()
+    }
+
+    fn push(&mut self, q: SVector<f64, D>, parent: usize) -> usize {
+        let cost = self.costs[parent] + (q - self.nodes[parent]).norm();
+        self.nodes.push(q);
+        self.parents.push(parent);
+        self.costs.push(cost);
+        self.nodes.len() - 1
+    }
+
+    /// From the root to `leaf`
+    fn path(&self, mut leaf: usize) -> Vec<SVector<f64, D>> {
+        let mut path = vec![self.nodes[leaf]];
+        while leaf != 0 {
+            leaf = self.parents[leaf];
+            path.push(self.nodes[leaf]);
+        }
+        path.reverse();
+        path
+    }
+
+    /// Changes the parent of `node` and updates the costs of its subtree
+    fn rewire(&mut self, node: usize, parent: usize) {
+        self.parents[node] = parent;
+        let mut stack = vec![node];
+        while let Some(i) = stack.pop() {
+            let p = self.parents[i];
+            self.costs[i] = self.costs[p] + (self.nodes[i] - self.nodes[p]).norm();
+            stack.extend((1..self.nodes.len()).filter(|j| self.parents[*j] == i));
+        }
+    }
+}
+
+fn sample<const D: usize, R: Rng + ?Sized>(
+    rng: &mut R,
+    (lower, upper): &(SVector<f64, D>, SVector<f64, D>),
+    goal: &SVector<f64, D>,
+    goal_bias: f64,
+) -> SVector<f64, D> {
+    if rng.gen::<f64>() < goal_bias {
+        return *goal;
+    }
+    SVector::from_fn(|i, _| rng.gen_range(lower[i]..=upper[i]))
+}
+
+fn steer<const D: usize>(
+    from: &SVector<f64, D>,
+    to: &SVector<f64, D>,
+    step: f64,
+) -> SVector<f64, D> {
+    let d = to - from;
+    let norm = d.norm();
+    if norm <= step {
+        *to
+    } else {
+        from + d * (step / norm)
+    }
+}
+
+/// The configurations along the segment are checked every `resolution`, both ends included
+pub fn edge_is_free<const D: usize, F: Fn(&SVector<f64, D>) -> bool>(
+    a: &SVector<f64, D>,
+    b: &SVector<f64, D>,
+    is_free: &F,
+    resolution: f64,
+) -> bool {
+    let n = ((b - a).norm() / resolution).ceil().max(1.0) as usize;
+    (0..=n).all(|i| is_free(&a.lerp(b, i as f64 / n as f64)))
+}
+
+/// RRT from `start` to `goal`, the configurations are sampled uniformly in the box `bounds`
+/// (lower, upper) and `is_free` tells if a configuration is collision free. Returns the first path
+/// found, `start` and `goal` included, after shortcutting
+///
+/// Source : Rapidly-Exploring Random Trees: A New Tool for Path Planning, LaValle 1998
+pub fn rrt<const D: usize, F: Fn(&SVector<f64, D>) -> bool, R: Rng + ?Sized>(
+    start: &SVector<f64, D>,
+    goal: &SVector<f64, D>,
+    bounds: &(SVector<f64, D>, SVector<f64, D>),
+    is_free: F,
+    config: &RrtConfig,
+    rng: &mut R,
+) -> Option<Vec<SVector<f64, D>>> {
+    if !is_free(start) || !is_free(goal) {
+        return None;
+    }
+    let mut tree = Tree::new(*start);
+    for _ in 0..config.max_iterations {
+        let q = sample(rng, bounds, goal, config.goal_bias);
+        let nearest = tree.nearest(&q);
+        let new = steer(&tree.nodes[nearest], &q, config.step_size);
+        if !edge_is_free(
+            &tree.nodes[nearest],
+            &new,
+            &is_free,
+            config.collision_resolution,
+        ) {
+            continue;
+        }
+        let i = tree.push(new, nearest);
+        if (new - goal).norm() <= config.goal_tolerance
+            && edge_is_free(&new, goal, &is_free, config.collision_resolution)
+        {
+            let leaf = tree.push(*goal, i);
+            let path = tree.path(leaf);
+            return Some(shortcut(&path, &is_free, config, rng));
+        }
+    }
+    None
+}
+
+/// RRT*, the new nodes are connected to the cheapest parent within `rewire_radius` and the
+/// neighbours are rewired through them. It runs for all the iterations and returns the cheapest
+/// path to the goal, after shortcutting
+///
+/// Source : Sampling-based Algorithms for Optimal Motion Planning, Karaman and Frazzoli 2011
+pub fn rrt_star<const D: usize, F: Fn(&SVector<f64, D>) -> bool, R: Rng + ?Sized>(
+    start: &SVector<f64, D>,
+    goal: &SVector<f64, D>,
+    bounds: &(SVector<f64, D>, SVector<f64, D>),
+    is_free: F,
+    config: &RrtConfig,
+    rng: &mut R,
+) -> Option<Vec<SVector<f64, D>>> {
+    if !is_free(start) || !is_free(goal) {
+        return None;
+    }
+    let resolution = config.collision_resolution;
+    let mut tree = Tree::new(*start);
+    for _ in 0..config.max_iterations {
+        let q = sample(rng, bounds, goal, config.goal_bias);
+        let nearest = tree.nearest(&q);
+        let new = steer(&tree.nodes[nearest], &q, config.step_size);
+        if !edge_is_free(&tree.nodes[nearest], &new, &is_free, resolution) {
+            continue;
+        }
+        let near = tree.near(&new, config.rewire_radius);
+        let parent = near
+            .iter()
+            .map(|j| (*j, tree.costs[*j] + (new - tree.nodes[*j]).norm()))
+            .filter(|(j, _)| {
+                *j == nearest || edge_is_free(&tree.nodes[*j], &new, &is_free, resolution)
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map_or(nearest, |(j, _)| j);
+        let i = tree.push(new, parent);
+        for j in near {
+            let cost = tree.costs[i] + (tree.nodes[j] - new).norm();
+            if cost < tree.costs[j] && edge_is_free(&new, &tree.nodes[j], &is_free, resolution) {
+                tree.rewire(j, i);
+            }
+        }
+    }
+
+    let best = (0..tree.nodes.len())
+        .filter(|i| (tree.nodes[*i] - goal).norm() <= config.goal_tolerance)
+        .filter(|i| edge_is_free(&tree.nodes[*i], goal, &is_free, resolution))
+        .map(|i| (i, tree.costs[i] + (tree.nodes[i] - goal).norm()))
+        .min_by(|a, b| a.1.total_cmp(&b.1))?
+        .0;
+    let mut path = tree.path(best);
+    if path.last() != Some(goal) {
+        path.push(*goal);
+    }
+    Some(shortcut(&path, &is_free, config, rng))
+}
+
+/// Tries `config.shortcut_iterations` times to replace the section between two random
+/// waypoints by a straight collision free segment
+pub fn shortcut<const D: usize, F: Fn(&SVector<f64, D>) -> bool, R: Rng + ?Sized>(
+    path: &[SVector<f64, D>],
+    is_free: &F,
+    config: &RrtConfig,
+    rng: &mut R,
+) -> Vec<SVector<f64, D>> {
+    let mut path = path.to_vec();
+    for _ in 0..config.shortcut_iterations {
+        if path.len() < 3 {
+            break;
+        }
+        let a = rng.gen_range(0..path.len() - 2);
+        let b = rng.gen_range(a + 2..path.len());
+        if edge_is_free(&path[a], &path[b], is_free, config.collision_resolution) {
+            path.drain(a + 1..b);
+        }
+    }
+    path
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::planning::grid::path_length;
+    use crate::utils::rng::Philox;
+    use nalgebra::{Vector2, Vector3};
+
+    /// 10 x 10 square with a wall at x = 5 leaving a gap for y > 8
+    fn is_free(q: &Vector2<f64>) -> bool {
+        !((4.5..=5.5).contains(&q.x) && q.y < 8.0)
+    }
+
+    #[test]
+    fn rrt_and_rrt_star_around_a_wall() {
+        let start = Vector2::new(1.0, 1.0);
+        let goal = Vector2::new(9.0, 1.0);
+        let bounds = (Vector2::zeros(), Vector2::new(10.0, 10.0));
+        let config = RrtConfig {
+            shortcut_iterations: 0,
+            ..Default::default()
+        };
+
+        let path = rrt(
+            &start,
+            &goal,
+            &bounds,
+            is_free,
+            &config,
+            &mut Philox::new(1, 0),
+        )
+        .unwrap();
+        assert_eq!(start, path[0]);
+        assert_eq!(goal, *path.last().unwrap());
+        assert!(path.windows(2).all(|w| edge_is_free(
+            &w[0],
+            &w[1],
+            &is_free,
+            config.collision_resolution
+        )));
+
+        let shortened = shortcut(
+            &path,
+            &is_free,
+            &RrtConfig::default(),
+            &mut Philox::new(1, 1),
+        );
+        assert!(path_length(&shortened) <= path_length(&path));
+
+        let star = rrt_star(
+            &start,
+            &goal,
+            &bounds,
+            is_free,
+            &config,
+            &mut Philox::new(1, 2),
+        )
+        .unwrap();
+        assert!(star.windows(2).all(|w| edge_is_free(
+            &w[0],
+            &w[1],
+            &is_free,
+            config.collision_resolution
+        )));
+        // the shortest path goes through the corners (4.5, 8) and (5.5, 8) of the wall
+        let optimal = 2.0 * (3.5_f64.powi(2) + 7.0_f64.powi(2)).sqrt() + 1.0;
+        assert!(path_length(&star) < 1.2 * optimal);
+    }
+
+    #[test]
+    fn rrt_in_3d() {
+        let is_free = |q: &Vector3<f64>| (q - Vector3::new(0.5, 0.5, 0.5)).norm() > 0.3;
+        let bounds = (Vector3::zeros(), Vector3::new(1.0, 1.0, 1.0));
+        let config = RrtConfig {
+            step_size: 0.1,
+            goal_tolerance: 0.1,
+            collision_resolution: 0.01,
+            ..Default::default()
+        };
+        let path = rrt(
+            &Vector3::new(0.1, 0.1, 0.1),
+            &Vector3::new(0.9, 0.9, 0.9),
+            &bounds,
+            is_free,
+            &config,
+            &mut Philox::new(3, 0),
+        )
+        .unwrap();
+        assert!(path.iter().all(is_free));
+    }
+}
@@ -0,0 +1,325 @@
+use nalgebra::{Vector2, Vector3};
+
+/// Reference of the tracking controllers at `time`, the pose (x, y, theta), the speed [m/s] and
+/// the yaw rate [rad/s], i.e. the (v, w) input of the unicycle models
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryPoint {
+    pub time: f64,
+    pub pose: Vector3<f64>,
+    pub velocity: f64,
+    pub yaw_rate: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrajectoryLimits {
+    pub max_velocity: f64,
+    pub max_acceleration: f64,
+    /// Bounds the speed in the turns, v^2 |curvature| <= max_lateral_acceleration
+    pub max_lateral_acceleration: f64,
+}
+
+/// Natural cubic spline y(t), C² with a zero second derivative at the ends
+#[derive(Debug, Clone)]
+pub struct CubicSpline {
+    t: Vec<f64>,
+    y: Vec<f64>,
+    /// Second derivatives at the knots
+    m: Vec<f64>,
+}
+
+impl CubicSpline {
+    /// None with less than 2 knots, or if `t` is not strictly increasing
+    pub fn new(t: &[f64], y: &[f64]) -> Option<CubicSpline> {
+        let n = t.len();
+        if n < 2 || y.len() != n || t.windows(2).any(|w| w[1] <= w[0]) {
+            return None;
+        }
+        // tridiagonal system of the second derivatives, Thomas algorithm
+        let mut m = vec![0.0; n];
+        let mut c = vec![0.0; n];
+        let mut d = vec![0.0; n];
+        for i in 1..n - 1 {
+            let h0 = t[i] - t[i - 1];
+            let h1 = t[i + 1] - t[i];
+            let rhs = 6.0 * ((y[i + 1] - y[i]) / h1 - (y[i] - y[i - 1]) / h0);
+            let diag = 2.0 * (h0 + h1) - h0 * c[i - 1];
+            c[i] = h1 / diag;
+            d[i] = (rhs - h0 * d[i - 1]) / diag;
+        }
+        for i in (1..n - 1).rev() {
+            m[i] = d[i] - c[i] * m[i + 1];
+        }
+        Some(CubicSpline {
+            t: t.to_vec(),
+            y: y.to_vec(),
+            m,
+        })
+    }
+
+    fn segment(&self, t: f64) -> usize {
+        self.t
+            .partition_point(|ti| *ti <= t)
+            .clamp(1, self.t.len() - 1)
+            - 1
+    }
+
+    /// Value, first and second derivatives at `t`, extrapolated outside of the knots
+    pub fn evaluate(&self, t: f64) -> (f64, f64, f64) {
+        let i = self.segment(t);
+        let h = self.t[i + 1] - self.t[i];
+        let (m0, m1) = (self.m[i], self.m[i + 1]);
+        let a = self.t[i + 1] - t;
+        let b = t - self.t[i];
+        let slope = (self.y[i + 1] - self.y[i]) / h;
+        let y = (m0 * a.powi(3) + m1 * b.powi(3)) / (6.0 * h)
+            + (self.y[i] / h - m0 * h / 6.0) * a
+            + (self.y[i + 1] / h - m1 * h / 6.0) * b;
+        let dy = (-m0 * a * a + m1 * b * b) / (2.0 * h) + slope - (m1 - m0) * h / 6.0;
+        let ddy = (m0 * a + m1 * b) / h;
+        (y, dy, ddy)
+    }
+}
+
+/// Quintic polynomial between two states (position, velocity, acceleration) in `duration`, the
+/// minimum jerk motion between them
+///
+/// Source : Flash & Hogan, The coordination of arm movements, 1985
+#[derive(Debug, Clone, Copy)]
+pub struct QuinticPolynomial {
+    coefficients: [f64; 6],
+    pub duration: f64,
+}
+
+impl QuinticPolynomial {
+    pub fn new(start: [f64; 3], end: [f64; 3], duration: f64) -> QuinticPolynomial {
+        let [p0, v0, a0] = start;
+        let [p1, v1, a1] = end;
+        let t = duration;
+        let d = p1 - p0 - v0 * t - a0 * t * t / 2.0;
+        let dv = v1 - v0 - a0 * t;
+        let da = a1 - a0;
+        QuinticPolynomial {
+            coefficients: [
+                p0,
+                v0,
+                a0 / 2.0,
+                10.0 * d / t.powi(3) - 4.0 * dv / t.powi(2) + da / (2.0 * t),
+                -15.0 * d / t.powi(4) + 7.0 * dv / t.powi(3) - da / t.powi(2),
+                6.0 * d / t.powi(5) - 3.0 * dv / t.powi(4) + da / (2.0 * t.powi(3)),
+            ],
+            duration,
+        }
+    }
+
+    /// Position, velocity and acceleration at `t`
+    pub fn evaluate(&self, t: f64) -> (f64, f64, f64) {
+        let c = &self.coefficients;
+        let p = c.iter().rev().fold(0.0, |acc, ci| acc * t + ci);
+        let v = (1..6).rev().fold(0.0, |acc, i| acc * t + i as f64 * c[i]);
+        let a = (2..6)
+            .rev()
+            .fold(0.0, |acc, i| acc * t + (i * (i - 1)) as f64 * c[i]);
+        (p, v, a)
+    }
+}
+
+/// C² path through the waypoints, the splines are parameterized by the chord length, followed
+/// from rest to rest at the highest speed within the limits. The path is sampled every `ds` [m].
+/// None with less than 2 distinct waypoints
+pub fn cubic_spline_trajectory(
+    waypoints: &[Vector2<f64>],
+    limits: &TrajectoryLimits,
+    ds: f64,
+) -> Option<Vec<TrajectoryPoint>> {
+    let mut knots = vec![0.0];
+    let mut points = vec![*waypoints.first()?];
+    for p in &waypoints[1..] {
+        let d = (p - points.last().unwrap()).norm();
+        if d > f64::EPSILON {
+            knots.push(knots.last().unwrap() + d);
+            points.push(*p);
+        }
+    }
+    let x_spline = CubicSpline::new(&knots, &points.iter().map(|p| p.x).collect::<Vec<_>>())?;
+    let y_spline = CubicSpline::new(&knots, &points.iter().map(|p| p.y).collect::<Vec<_>>())?;
+    let length = *knots.last().unwrap();
+    let n = (length / ds).ceil().max(1.0) as usize;
+
+    // arc length, pose and curvature of the samples
+    let mut samples = Vec::with_capacity(n + 1);
+    let mut previous: Option<Vector2<f64>> = None;
+    let mut s = 0.0;
+    for i in 0..=n {
+        let u = length * i as f64 / n as f64;
+        let (x, dx, ddx) = x_spline.evaluate(u);
+        let (y, dy, ddy) = y_spline.evaluate(u);
+        let position = Vector2::new(x, y);
+        s += previous.map_or(0.0, |p| (position - p).norm());
+        previous = Some(position);
+        let speed = dx.hypot(dy).max(f64::EPSILON);
+        let curvature = (dx * ddy - dy * ddx) / speed.powi(3);
+        samples.push((s, Vector3::new(x, y, dy.atan2(dx)), curvature));
+    }
+
+    let mut velocities: Vec<f64> = samples
+        .iter()
+        .map(|(_, _, k)| {
+            limits
+                .max_velocity
+                .min((limits.max_lateral_acceleration / k.abs().max(f64::EPSILON)).sqrt())
+        })
+        .collect();
+    velocities[0] = 0.0;
+    velocities[n] = 0.0;
+    for i in 1..=n {
+        let ds = samples[i].0 - samples[i - 1].0;
+        let reachable = (velocities[i - 1].powi(2) + 2.0 * limits.max_acceleration * ds).sqrt();
+        velocities[i] = velocities[i].min(reachable);
+    }
+    for i in (0..n).rev() {
+        let ds = samples[i + 1].0 - samples[i].0;
+        let reachable = (velocities[i + 1].powi(2) + 2.0 * limits.max_acceleration * ds).sqrt();
+        velocities[i] = velocities[i].min(reachable);
+    }
+
+    let mut time = 0.0;
+    let mut trajectory = Vec::with_capacity(n + 1);
+    for i in 0..=n {
+        if i > 0 {
+            let ds = samples[i].0 - samples[i - 1].0;
+            time += 2.0 * ds / (velocities[i] + velocities[i - 1]).max(f64::EPSILON);
+        }
+        trajectory.push(TrajectoryPoint {
+            time,
+            pose: samples[i].1,
+            velocity: velocities[i],
+            yaw_rate: velocities[i] * samples[i].2,
+        });
+    }
+    Some(trajectory)
+}
+
+/// Straight segments between the waypoints, each one a minimum jerk motion from rest to rest
+/// whose duration is the shortest within the limits, sampled every `dt` [s]. The robot turns
+/// on the spot at the waypoints. None without waypoint
+pub fn minimum_jerk_trajectory(
+    waypoints: &[Vector2<f64>],
+    limits: &TrajectoryLimits,
+    dt: f64,
+) -> Option<Vec<TrajectoryPoint>> {
+    let first = waypoints.first()?;
+    let mut trajectory = vec![TrajectoryPoint {
+        time: 0.0,
+        pose: Vector3::new(first.x, first.y, 0.0),
+        velocity: 0.0,
+        yaw_rate: 0.0,
+    }];
+    let mut start_time = 0.0;
+    for w in waypoints.windows(2) {
+        let direction = w[1] - w[0];
+        let distance = direction.norm();
+        if distance <= f64::EPSILON {
+            continue;
+        }
+        let heading = direction.y.atan2(direction.x);
+        // peak velocity 15/8 d/T and acceleration 10/sqrt(3) d/T² from rest to rest
+        let duration = (1.875 * distance / limits.max_velocity)
+            .max((10.0 / 3f64.sqrt() * distance / limits.max_acceleration).sqrt());
+        let polynomial = QuinticPolynomial::new([0.0; 3], [distance, 0.0, 0.0], duration);
+        let steps = (duration / dt).ceil().max(1.0) as usize;
+        for i in 1..=steps {
+            let t = duration * i as f64 / steps as f64;
+            let (s, v, _) = polynomial.evaluate(t);
+            let p = w[0] + direction / distance * s;
+            trajectory.push(TrajectoryPoint {
+                time: start_time + t,
+                pose: Vector3::new(p.x, p.y, heading),
+                velocity: v,
+                yaw_rate: 0.0,
+            });
+        }
+        start_time += duration;
+    }
+    if let Some(second) = trajectory.get(1).map(|p| p.pose.z) {
+        trajectory[0].pose.z = second;
+    }
+    Some(trajectory)
+}
+
+/// Positions of the trajectory, the path of `PurePursuit` and `Stanley`
+pub fn as_path(trajectory: &[TrajectoryPoint]) -> Vec<Vector2<f64>> {
+    trajectory.iter().map(|p| p.pose.xy()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMITS: TrajectoryLimits = TrajectoryLimits {
+        max_velocity: 2.0,
+        max_acceleration: 1.0,
+        max_lateral_acceleration: 0.5,
+    };
+
+    #[test]
+    fn spline_through_the_waypoints() {
+        let t = [0.0, 1.0, 2.5, 4.0];
+        let y = [0.0, 2.0, -1.0, 0.5];
+        let spline = CubicSpline::new(&t, &y).unwrap();
+        for (ti, yi) in t.iter().zip(y) {
+            approx::assert_abs_diff_eq!(yi, spline.evaluate(*ti).0, epsilon = 1e-12);
+        }
+        // C² at the inner knots, natural at the ends
+        for ti in &t[1..3] {
+            let (_, d0, dd0) = spline.evaluate(ti - 1e-9);
+            let (_, d1, dd1) = spline.evaluate(ti + 1e-9);
+            approx::assert_abs_diff_eq!(d0, d1, epsilon = 1e-6);
+            approx::assert_abs_diff_eq!(dd0, dd1, epsilon = 1e-6);
+        }
+        approx::assert_abs_diff_eq!(0.0, spline.evaluate(0.0).2, epsilon = 1e-12);
+
+        let waypoints = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(5.0, 2.0),
+            Vector2::new(10.0, 0.0),
+            Vector2::new(15.0, 3.0),
+        ];
+        let trajectory = cubic_spline_trajectory(&waypoints, &LIMITS, 0.05).unwrap();
+        let last = trajectory.last().unwrap();
+        approx::assert_abs_diff_eq!(waypoints[3], last.pose.xy(), epsilon = 1e-9);
+        assert_eq!(0.0, last.velocity);
+        for w in trajectory.windows(2) {
+            assert!(w[1].time > w[0].time);
+            assert!(w[1].velocity <= LIMITS.max_velocity + 1e-9);
+            assert!(w[1].velocity * w[1].yaw_rate.abs() <= LIMITS.max_lateral_acceleration + 1e-6);
+            let acceleration = (w[1].velocity - w[0].velocity) / (w[1].time - w[0].time);
+            assert!(acceleration.abs() <= LIMITS.max_acceleration + 1e-6);
+        }
+    }
+
+    #[test]
+    fn minimum_jerk_within_the_limits() {
+        let polynomial = QuinticPolynomial::new([1.0, 0.5, 0.0], [3.0, -0.2, 0.1], 2.0);
+        let (p, v, a) = polynomial.evaluate(2.0);
+        approx::assert_abs_diff_eq!(3.0, p, epsilon = 1e-12);
+        approx::assert_abs_diff_eq!(-0.2, v, epsilon = 1e-12);
+        approx::assert_abs_diff_eq!(0.1, a, epsilon = 1e-12);
+
+        let waypoints = [
+            Vector2::new(0.0, 0.0),
+            Vector2::new(10.0, 0.0),
+            Vector2::new(10.0, 1.0),
+        ];
+        let trajectory = minimum_jerk_trajectory(&waypoints, &LIMITS, 0.01).unwrap();
+        let peak = trajectory.iter().map(|p| p.velocity).fold(0.0, f64::max);
+        // the long segment is velocity limited
+        approx::assert_abs_diff_eq!(LIMITS.max_velocity, peak, epsilon = 1e-3);
+        for w in trajectory.windows(2) {
+            let acceleration = (w[1].velocity - w[0].velocity) / (w[1].time - w[0].time);
+            assert!(acceleration.abs() <= LIMITS.max_acceleration + 1e-3);
+        }
+        let last = trajectory.last().unwrap();
+        approx::assert_abs_diff_eq!(waypoints[2], last.pose.xy(), epsilon = 1e-9);
+        assert_eq!(as_path(&trajectory).len(), trajectory.len());
+    }
+}
@@ -0,0 +1,220 @@
+//! Conversions between the ROS2 messages and the crate types for planar robots,
+//! the state is (x, y, yaw) and the control input is (v, w). The messages are plain mirrors to
+//! be copied from/into the rclrs generated types by the application.
+//!
+//! Publishing is deliberately out of scope, the crate does not depend on rclrs and has no node.
+//! `estimate_to_tf` and `estimate_to_pose` only build the messages the application publishes
+//! on /tf and on its pose topic
+
+pub mod msg;
+
+use nalgebra::{Const, Matrix3, Quaternion, UnitQuaternion, Vector2, Vector3};
+
+use crate::perception::scan;
+use crate::utils::state::GaussianState;
+
+/// Indices of x, y and yaw in the 6x6 ROS covariances
+const POSE_INDICES: [usize; 3] = [0, 1, 5];
+
+impl msg::Time {
+    pub fn from_secs(t: f64) -> msg::Time {
+        let sec = t.floor();
+        msg::Time {
+            sec: sec as i32,
+            nanosec: ((t - sec) * 1e9).round().min(999_999_999.0) as u32,
+        }
+    }
+
+    pub fn as_secs(&self) -> f64 {
+        self.sec as f64 + self.nanosec as f64 * 1e-9
+    }
+}
+
+fn yaw(q: &msg::Quaternion) -> f64 {
+    UnitQuaternion::from_quaternion(Quaternion::new(q.w, q.x, q.y, q.z))
+        .euler_angles()
+        .2
+}
+
+fn quaternion(yaw: f64) -> msg::Quaternion {
+    let q = UnitQuaternion::from_euler_angles(0.0, 0.0, yaw);
+    msg::Quaternion {
+        x: q.i,
+        y: q.j,
+        z: q.k,
+        w: q.w,
+    }
+}
+
+impl From<&msg::PoseWithCovariance> for GaussianState<f64, Const<3>> {
+    fn from(pose: &msg::PoseWithCovariance) -> Self {
+        let p = &pose.pose;
+        let cov = Matrix3::from_fn(|i, j| pose.covariance[POSE_INDICES[i] * 6 + POSE_INDICES[j]]);
+        GaussianState {
+            x: Vector3::new(p.position.x, p.position.y, yaw(&p.orientation)),
+            cov,
+        }
+    }
+}
+
+impl From<&GaussianState<f64, Const<3>>> for msg::PoseWithCovariance {
+    fn from(state: &GaussianState<f64, Const<3>>) -> Self {
+        let mut covariance = [0.0; 36];
+        for i in 0..3 {
+            for j in 0..3 {
+                covariance[POSE_INDICES[i] * 6 + POSE_INDICES[j]] = state.cov[(i, j)];
+            }
+        }
+        msg::PoseWithCovariance {
+            pose: msg::Pose {
+                position: msg::Point {
+                    x: state.x[0],
+                    y: state.x[1],
+                    z: 0.0,
+                },
+                orientation: quaternion(state.x[2]),
+            },
+            covariance,
+        }
+    }
+}
+
+impl msg::Odometry {
+    /// Estimate and control input (v, w) as the odometry of `child_frame_id` in `frame_id`
+    pub fn from_estimate(
+        state: &GaussianState<f64, Const<3>>,
+        u: &Vector2<f64>,
+        time: f64,
+        frame_id: &str,
+        child_frame_id: &str,
+    ) -> msg::Odometry {
+        msg::Odometry {
+            header: msg::Header {
+                stamp: msg::Time::from_secs(time),
+                frame_id: frame_id.to_owned(),
+            },
+            child_frame_id: child_frame_id.to_owned(),
+            pose: state.into(),
+            twist: msg::TwistWithCovariance {
+                twist: msg::Twist {
+                    linear: msg::Vector3 {
+                        x: u.x,
+                        ..Default::default()
+                    },
+                    angular: msg::Vector3 {
+                        z: u.y,
+                        ..Default::default()
+                    },
+                },
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Control input (v, w) for the velocity motion model
+    pub fn control_input(&self) -> Vector2<f64> {
+        Vector2::new(self.twist.twist.linear.x, self.twist.twist.angular.z)
+    }
+
+    /// Pose of `child_frame_id` in `header.frame_id`
+    pub fn gaussian_state(&self) -> GaussianState<f64, Const<3>> {
+        (&self.pose).into()
+    }
+}
+
+impl From<&msg::LaserScan> for scan::LaserScan {
+    fn from(msg: &msg::LaserScan) -> Self {
+        scan::LaserScan {
+            angle_min: msg.angle_min as f64,
+            angle_increment: msg.angle_increment as f64,
+            time_increment: msg.time_increment as f64,
+            range_min: msg.range_min as f64,
+            range_max: msg.range_max as f64,
+            ranges: msg.ranges.iter().map(|r| *r as f64).collect(),
+        }
+    }
+}
+
+impl From<&scan::LaserScan> for msg::LaserScan {
+    fn from(scan: &scan::LaserScan) -> Self {
+        msg::LaserScan {
+            angle_min: scan.angle_min as f32,
+            angle_max: scan.angle(scan.ranges.len().saturating_sub(1)) as f32,
+            angle_increment: scan.angle_increment as f32,
+            time_increment: scan.time_increment as f32,
+            range_min: scan.range_min as f32,
+            range_max: scan.range_max as f32,
+            ranges: scan.ranges.iter().map(|r| *r as f32).collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl msg::LaserScan {
+    /// (range, bearing) measurements of the valid beams
+    pub fn range_bearing(&self) -> Vec<Vector2<f64>> {
+        let scan = scan::LaserScan::from(self);
+        (0..scan.ranges.len())
+            .filter(|&i| scan.is_valid(i))
+            .map(|i| Vector2::new(scan.ranges[i], scan.angle(i)))
+            .collect()
+    }
+}
+
+/// Estimate as the transform `frame_id` -> `child_frame_id` for /tf
+pub fn estimate_to_tf(
+    state: &GaussianState<f64, Const<3>>,
+    time: f64,
+    frame_id: &str,
+    child_frame_id: &str,
+) -> msg::TransformStamped {
+    msg::TransformStamped {
+        header: msg::Header {
+            stamp: msg::Time::from_secs(time),
+            frame_id: frame_id.to_owned(),
+        },
+        child_frame_id: child_frame_id.to_owned(),
+        transform: msg::Transform {
+            translation: msg::Vector3 {
+                x: state.x[0],
+                y: state.x[1],
+                z: 0.0,
+            },
+            rotation: quaternion(state.x[2]),
+        },
+    }
+}
+
+pub fn estimate_to_pose(
+    state: &GaussianState<f64, Const<3>>,
+    time: f64,
+    frame_id: &str,
+) -> msg::PoseWithCovarianceStamped {
+    msg::PoseWithCovarianceStamped {
+        header: msg::Header {
+            stamp: msg::Time::from_secs(time),
+            frame_id: frame_id.to_owned(),
+        },
+        pose: state.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn laser_scan_measurements() {
+        let msg = msg::LaserScan {
+            angle_min: -0.5,
+            angle_increment: 0.5,
+            range_min: 0.1,
+            range_max: 10.0,
+            ranges: vec![1.0, f32::INFINITY, 2.0],
+            ..Default::default()
+        };
+        let z = msg.range_bearing();
+        assert_eq!(2, z.len());
+        approx::assert_abs_diff_eq!(Vector2::new(2.0, 0.5), z[1]);
+    }
+}
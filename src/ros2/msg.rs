@@ -0,0 +1,202 @@
+//! Plain mirrors of the ROS2 messages used by the crate, the field names and layouts follow
+//! the .msg definitions so they map one to one to the rclrs generated types
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Time {
+    pub sec: i32,
+    pub nanosec: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Header {
+    pub stamp: Time,
+    pub frame_id: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Vector3 {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub w: f64,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Quaternion {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+            w: 1.0,
+        }
+    }
+}
+
+/// geometry_msgs/Pose
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pose {
+    pub position: Point,
+    pub orientation: Quaternion,
+}
+
+/// geometry_msgs/PoseWithCovariance, the covariance is row major over (x, y, z, roll, pitch, yaw)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoseWithCovariance {
+    pub pose: Pose,
+    pub covariance: [f64; 36],
+}
+
+impl Default for PoseWithCovariance {
+    fn default() -> Self {
+        PoseWithCovariance {
+            pose: Pose::default(),
+            covariance: [0.0; 36],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct PoseWithCovarianceStamped {
+    pub header: Header,
+    pub pose: PoseWithCovariance,
+}
+
+/// geometry_msgs/Twist
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Twist {
+    pub linear: Vector3,
+    pub angular: Vector3,
+}
+
+/// geometry_msgs/TwistWithCovariance, the covariance is row major over (vx, vy, vz, wx, wy, wz)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TwistWithCovariance {
+    pub twist: Twist,
+    pub covariance: [f64; 36],
+}
+
+impl Default for TwistWithCovariance {
+    fn default() -> Self {
+        TwistWithCovariance {
+            twist: Twist::default(),
+            covariance: [0.0; 36],
+        }
+    }
+}
+
+/// nav_msgs/Odometry
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Odometry {
+    pub header: Header,
+    pub child_frame_id: String,
+    pub pose: PoseWithCovariance,
+    pub twist: TwistWithCovariance,
+}
+
+/// sensor_msgs/LaserScan
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LaserScan {
+    pub header: Header,
+    pub angle_min: f32,
+    pub angle_max: f32,
+    pub angle_increment: f32,
+    pub time_increment: f32,
+    pub scan_time: f32,
+    pub range_min: f32,
+    pub range_max: f32,
+    pub ranges: Vec<f32>,
+    pub intensities: Vec<f32>,
+}
+
+/// geometry_msgs/Transform
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Transform {
+    pub translation: Vector3,
+    pub rotation: Quaternion,
+}
+
+/// geometry_msgs/TransformStamped, as published on /tf
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TransformStamped {
+    pub header: Header,
+    pub child_frame_id: String,
+    pub transform: Transform,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::perception::scan;
+    use crate::utils::state::GaussianState;
+    use nalgebra::{Const, Matrix3, Vector2};
+
+    fn state() -> GaussianState<f64, Const<3>> {
+        GaussianState {
+            x: nalgebra::Vector3::new(1.0, -2.0, 2.5),
+            cov: Matrix3::new(0.1, 0.01, 0.02, 0.01, 0.2, 0.03, 0.02, 0.03, 0.3),
+        }
+    }
+
+    #[test]
+    fn pose_with_covariance_round_trip() {
+        let state = state();
+        let msg = PoseWithCovariance::from(&state);
+        assert_eq!(0.3, msg.covariance[35]);
+        let back = GaussianState::from(&msg);
+        approx::assert_abs_diff_eq!(state.x, back.x, epsilon = 1e-12);
+        approx::assert_abs_diff_eq!(state.cov, back.cov);
+
+        let time = Time::from_secs(12.25);
+        assert_eq!((12, 250_000_000), (time.sec, time.nanosec));
+        approx::assert_abs_diff_eq!(12.25, time.as_secs());
+    }
+
+    #[test]
+    fn odometry_round_trip() {
+        let state = state();
+        let u = Vector2::new(0.8, -0.2);
+        let msg = Odometry::from_estimate(&state, &u, 3.5, "odom", "base_link");
+        assert_eq!("odom", msg.header.frame_id);
+        assert_eq!("base_link", msg.child_frame_id);
+        assert_eq!(3.5, msg.header.stamp.as_secs());
+        assert_eq!(u, msg.control_input());
+        let back = msg.gaussian_state();
+        approx::assert_abs_diff_eq!(state.x, back.x, epsilon = 1e-12);
+        approx::assert_abs_diff_eq!(state.cov, back.cov);
+    }
+
+    #[test]
+    fn laser_scan_round_trip() {
+        let scan = scan::LaserScan {
+            angle_min: -0.5,
+            angle_increment: 0.25,
+            time_increment: 0.001,
+            range_min: 0.1,
+            range_max: 10.0,
+            ranges: vec![1.0, 2.5, 0.5, 8.0, 3.25],
+        };
+        let msg = LaserScan::from(&scan);
+        assert_eq!(0.5, msg.angle_max);
+        let back = scan::LaserScan::from(&msg);
+        assert_eq!(scan.ranges, back.ranges);
+        approx::assert_abs_diff_eq!(scan.angle_min, back.angle_min);
+        approx::assert_abs_diff_eq!(scan.angle_increment, back.angle_increment);
+        approx::assert_abs_diff_eq!(scan.time_increment, back.time_increment, epsilon = 1e-9);
+        approx::assert_abs_diff_eq!(scan.range_min, back.range_min, epsilon = 1e-7);
+        approx::assert_abs_diff_eq!(scan.range_max, back.range_max);
+    }
+}
@@ -0,0 +1,382 @@
+use nalgebra::{Isometry2, Vector2, Vector3};
+use rand_distr::{Distribution, Normal};
+use rustc_hash::FxHashMap;
+
+use crate::datasets::event::{Event, TimedEvent};
+use crate::perception::scan::{unicycle_displacement, LaserScan};
+use crate::utils::rng::Philox;
+
+/// Circular obstacle, seen by the lidar
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Obstacle {
+    pub center: Vector2<f64>,
+    pub radius: f64,
+}
+
+/// Landmarks seen by the range-bearing sensor and obstacles seen by the lidar
+#[derive(Debug, Clone, Default)]
+pub struct World {
+    pub landmarks: Vec<(u32, Vector2<f64>)>,
+    pub obstacles: Vec<Obstacle>,
+}
+
+impl World {
+    /// `n` landmarks evenly spaced on the circle of radius `radius` around `center`
+    pub fn landmark_ring(n: usize, center: Vector2<f64>, radius: f64) -> World {
+        let landmarks = (0..n)
+            .map(|i| {
+                let angle = 2.0 * std::f64::consts::PI * i as f64 / n as f64;
+                (
+                    i as u32,
+                    center + radius * Vector2::new(angle.cos(), angle.sin()),
+                )
+            })
+            .collect();
+        World {
+            landmarks,
+            obstacles: Vec::new(),
+        }
+    }
+
+    /// Landmarks as expected by the filters with known correspondences, [x, y, 0]
+    pub fn landmark_map(&self) -> FxHashMap<u32, Vector3<f64>> {
+        self.landmarks
+            .iter()
+            .map(|(id, lm)| (*id, Vector3::new(lm.x, lm.y, 0.0)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// Duration of a step [s]
+    pub dt: f64,
+    /// Standard deviations of the (v, w) measured by the odometry
+    pub odometry_noise: Vector2<f64>,
+    /// Standard deviations of the [range, bearing] measurements
+    pub measurement_noise: Vector2<f64>,
+    /// Range of the range-bearing sensor and of the lidar [m]
+    pub max_range: f64,
+    /// Half angle of the field of view of the range-bearing sensor, pi to see all around
+    pub half_fov: f64,
+    /// The landmarks are measured every `measurement_period` steps
+    pub measurement_period: usize,
+    pub lidar_beams: usize,
+    /// Standard deviation of the lidar ranges
+    pub lidar_noise: f64,
+    pub seed: u64,
+}
+
+impl Default for SimConfig {
+    fn default() -> Self {
+        SimConfig {
+            dt: 0.1,
+            odometry_noise: Vector2::new(0.05, 0.02),
+            measurement_noise: Vector2::new(0.1, 0.02),
+            max_range: 10.0,
+            half_fov: std::f64::consts::PI,
+            measurement_period: 1,
+            lidar_beams: 360,
+            lidar_noise: 0.01,
+            seed: 0,
+        }
+    }
+}
+
+/// What the sensors saw during a step
+#[derive(Debug, Clone)]
+pub struct SimStep {
+    /// Time at the end of the step
+    pub time: f64,
+    /// True pose [x, y, theta] at `time`
+    pub truth: Vector3<f64>,
+    /// Noisy (v, w) measured by the odometry during the step
+    pub odometry: Vector2<f64>,
+    /// Noisy [range, bearing] of the landmarks in sight, empty between the measurement periods
+    pub measurements: Vec<(u32, Vector2<f64>)>,
+}
+
+/// Differential drive robot moving in a `World` with the exact (v, w) commands, the sensors add
+/// gaussian noise drawn from a stream seeded with `SimConfig::seed` so the runs are reproducible
+pub struct Simulator {
+    pub world: World,
+    pub config: SimConfig,
+    pose: Vector3<f64>,
+    time: f64,
+    step: usize,
+    rng: Philox,
+    /// Ground truth (time, pose), starting with the initial pose
+    pub truth: Vec<(f64, Vector3<f64>)>,
+}
+
+impl Simulator {
+    pub fn new(world: World, config: SimConfig, initial_pose: Vector3<f64>) -> Simulator {
+        let rng = Philox::new(config.seed, 0);
+        Simulator {
+            world,
+            config,
+            pose: initial_pose,
+            time: 0.0,
+            step: 0,
+            rng,
+            truth: vec![(0.0, initial_pose)],
+        }
+    }
+
+    pub fn pose(&self) -> Vector3<f64> {
+        self.pose
+    }
+
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Moves the robot with the command `u` = (v, w) during `dt`
+    pub fn step(&mut self, u: &Vector2<f64>) -> SimStep {
+        let pose = Isometry2::new(self.pose.xy(), self.pose.z)
+            * unicycle_displacement(u.x, u.y, self.config.dt);
+        self.pose = Vector3::new(
+            pose.translation.x,
+            pose.translation.y,
+            pose.rotation.angle(),
+        );
+        self.time += self.config.dt;
+        self.step += 1;
+        self.truth.push((self.time, self.pose));
+
+        let odometry = Vector2::new(
+            u.x + self.noise(self.config.odometry_noise.x),
+            u.y + self.noise(self.config.odometry_noise.y),
+        );
+        let measurements = if self.step % self.config.measurement_period.max(1) == 0 {
+            self.observe_landmarks()
+        } else {
+            Vec::new()
+        };
+        SimStep {
+            time: self.time,
+            truth: self.pose,
+            odometry,
+            measurements,
+        }
+    }
+
+    /// Noisy [range, bearing] of the landmarks within the range and the field of view
+    pub fn observe_landmarks(&mut self) -> Vec<(u32, Vector2<f64>)> {
+        let pose = self.pose;
+        let visible: Vec<(u32, f64, f64)> = self
+            .world
+            .landmarks
+            .iter()
+            .map(|(id, lm)| {
+                let delta = lm - pose.xy();
+                let bearing = normalize_angle(delta.y.atan2(delta.x) - pose.z);
+                (*id, delta.norm(), bearing)
+            })
+            .filter(|(_, range, bearing)| {
+                *range <= self.config.max_range && bearing.abs() <= self.config.half_fov
+            })
+            .collect();
+        visible
+            .into_iter()
+            .map(|(id, range, bearing)| {
+                let range = range + self.noise(self.config.measurement_noise.x);
+                let bearing =
+                    normalize_angle(bearing + self.noise(self.config.measurement_noise.y));
+                (id, Vector2::new(range, bearing))
+            })
+            .collect()
+    }
+
+    /// Noisy lidar scan of the obstacles all around the robot, the beams hitting nothing within
+    /// `max_range` are infinite
+    pub fn scan(&mut self) -> LaserScan {
+        let n = self.config.lidar_beams.max(1);
+        let angle_increment = 2.0 * std::f64::consts::PI / n as f64;
+        let angle_min = -std::f64::consts::PI;
+        let origin = self.pose.xy();
+        let ranges: Vec<f64> = (0..n)
+            .map(|i| {
+                let angle = self.pose.z + angle_min + i as f64 * angle_increment;
+                let direction = Vector2::new(angle.cos(), angle.sin());
+                self.world
+                    .obstacles
+                    .iter()
+                    .filter_map(|obstacle| ray_circle(&origin, &direction, obstacle))
+                    .fold(f64::INFINITY, f64::min)
+            })
+            .collect();
+        let ranges = ranges
+            .into_iter()
+            .map(|range| {
+                if range <= self.config.max_range {
+                    range + self.noise(self.config.lidar_noise)
+                } else {
+                    f64::INFINITY
+                }
+            })
+            .collect();
+        LaserScan {
+            angle_min,
+            angle_increment,
+            time_increment: 0.0,
+            range_min: 0.0,
+            range_max: self.config.max_range,
+            ranges,
+        }
+    }
+
+    /// Applies the commands one step each and returns the events like the dataset loaders: the
+    /// odometry of a step is a control at its start, the ground truth and the measurements are
+    /// at its end
+    pub fn run(&mut self, commands: &[Vector2<f64>]) -> Vec<TimedEvent> {
+        let mut events = Vec::new();
+        for u in commands {
+            let start = self.time;
+            let step = self.step(u);
+            events.push(TimedEvent {
+                time: start,
+                event: Event::Control(step.odometry),
+            });
+            events.push(TimedEvent {
+                time: step.time,
+                event: Event::GroundTruth {
+                    xy: step.truth.xy(),
+                    heading: Some(step.truth.z),
+                },
+            });
+            if !step.measurements.is_empty() {
+                events.push(TimedEvent {
+                    time: step.time,
+                    event: Event::Measurements(
+                        step.measurements
+                            .into_iter()
+                            .map(|(id, z)| (Some(id), z))
+                            .collect(),
+                    ),
+                });
+            }
+        }
+        events
+    }
+
+    fn noise(&mut self, std: f64) -> f64 {
+        if std <= 0.0 {
+            return 0.0;
+        }
+        Normal::new(0.0, std).unwrap().sample(&mut self.rng)
+    }
+}
+
+/// Distance along the ray to the first intersection with the circle, None if it is missed
+fn ray_circle(origin: &Vector2<f64>, direction: &Vector2<f64>, obstacle: &Obstacle) -> Option<f64> {
+    let to_center = obstacle.center - origin;
+    let b = direction.dot(&to_center);
+    let discriminant = b * b - to_center.norm_squared() + obstacle.radius.powi(2);
+    if discriminant < 0.0 {
+        return None;
+    }
+    let sqrt = discriminant.sqrt();
+    [b - sqrt, b + sqrt].into_iter().find(|t| *t >= 0.0)
+}
+
+fn normalize_angle(a: f64) -> f64 {
+    f64::atan2(a.sin(), a.cos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datasets::event::sort_events;
+    use crate::localization::ExtendedKalmanFilterKnownCorrespondences;
+    use crate::models::measurement::{MeasurementModel, RangeBearingMeasurementModel};
+    use crate::models::motion::Velocity;
+    use crate::pipeline::{FilterStage, Stage};
+    use crate::utils::state::GaussianState;
+    use approx::assert_relative_eq;
+    use nalgebra::{Matrix2, Matrix3};
+
+    fn noiseless() -> SimConfig {
+        SimConfig {
+            odometry_noise: Vector2::zeros(),
+            measurement_noise: Vector2::zeros(),
+            lidar_noise: 0.0,
+            ..SimConfig::default()
+        }
+    }
+
+    #[test]
+    fn noiseless_measurements_match_the_model() {
+        let world = World::landmark_ring(8, Vector2::new(0.0, 5.0), 8.0);
+        let landmarks = world.landmark_map();
+        let mut sim = Simulator::new(world, noiseless(), Vector3::zeros());
+        let model = RangeBearingMeasurementModel::new();
+        for _ in 0..50 {
+            let step = sim.step(&Vector2::new(1.0, 0.2));
+            assert_eq!(step.odometry, Vector2::new(1.0, 0.2));
+            assert!(!step.measurements.is_empty());
+            for (id, z) in &step.measurements {
+                let z_pred = model.prediction(&step.truth, landmarks.get(id));
+                assert_relative_eq!(z.x, z_pred.x, epsilon = 1e-9);
+                assert_relative_eq!(normalize_angle(z.y - z_pred.y), 0.0, epsilon = 1e-9);
+                assert!(z.x <= sim.config.max_range);
+            }
+        }
+        assert_relative_eq!(sim.time(), 5.0, epsilon = 1e-9);
+        assert_eq!(sim.truth.len(), 51);
+    }
+
+    #[test]
+    fn scan_hits_the_obstacle() {
+        let world = World {
+            landmarks: Vec::new(),
+            obstacles: vec![Obstacle {
+                center: Vector2::new(3.0, 0.0),
+                radius: 1.0,
+            }],
+        };
+        let config = SimConfig {
+            lidar_beams: 4,
+            ..noiseless()
+        };
+        let mut sim = Simulator::new(world, config, Vector3::zeros());
+        let scan = sim.scan();
+        // beams at -pi, -pi / 2, 0 and pi / 2
+        assert_relative_eq!(scan.ranges[2], 2.0, epsilon = 1e-9);
+        assert!(!scan.is_valid(0) && !scan.is_valid(1) && !scan.is_valid(3));
+    }
+
+    #[test]
+    fn ekf_converges_in_simulation() {
+        let world = World::landmark_ring(8, Vector2::new(0.0, 5.0), 8.0);
+        let landmarks = world.landmark_map();
+        let config = SimConfig {
+            half_fov: std::f64::consts::FRAC_PI_2,
+            ..SimConfig::default()
+        };
+        let (v_std, w_std) = (config.odometry_noise.x, config.odometry_noise.y);
+        let q = Matrix2::from_diagonal(&config.measurement_noise.map(|s| s * s));
+        let mut sim = Simulator::new(world, config, Vector3::zeros());
+        let mut events = sim.run(&vec![Vector2::new(1.0, 0.2); 400]);
+        sort_events(&mut events);
+
+        let initial_state = GaussianState {
+            x: Vector3::new(0.5, -0.5, 0.1),
+            cov: Matrix3::from_diagonal(&Vector3::new(1.0, 1.0, 0.1)),
+        };
+        let ekf = ExtendedKalmanFilterKnownCorrespondences::new(
+            q,
+            landmarks,
+            RangeBearingMeasurementModel::new(),
+            Velocity::new([v_std * v_std, 0.0, w_std * w_std, 0.0, 0.0, 0.0]),
+            initial_state,
+        );
+        let mut stage = FilterStage::new(ekf);
+        for event in events {
+            stage.process(event);
+        }
+        let estimate = stage.pipeline.estimate();
+        assert!((estimate.x.xy() - sim.pose().xy()).norm() < 0.3);
+        assert!(normalize_angle(estimate.x.z - sim.pose().z).abs() < 0.1);
+    }
+}
@@ -0,0 +1,442 @@
+use nalgebra::{Isometry3, Matrix3, Rotation3, UnitQuaternion, Vector3};
+use rustc_hash::FxHashMap;
+use std::marker::PhantomData;
+use std::ops::Mul;
+
+pub const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// Axes conventions, ENU/NED for the world frames and FLU/FRD for the body frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Convention {
+    /// East North Up, z up
+    Enu,
+    /// North East Down, z down
+    Ned,
+    /// Forward Left Up, z up (ROS)
+    Flu,
+    /// Forward Right Down, z down (aerospace)
+    Frd,
+}
+
+impl Convention {
+    pub fn is_world(&self) -> bool {
+        matches!(self, Convention::Enu | Convention::Ned)
+    }
+
+    pub fn z_up(&self) -> bool {
+        matches!(self, Convention::Enu | Convention::Flu)
+    }
+
+    /// Gravity in a frame of this convention whose z axis is vertical [m/s^2]
+    pub fn gravity(&self, g: f64) -> Vector3<f64> {
+        if self.z_up() {
+            Vector3::new(0.0, 0.0, -g)
+        } else {
+            Vector3::new(0.0, 0.0, g)
+        }
+    }
+
+    /// Rotation mapping the coordinates in `self` to the coordinates in `other`,
+    /// None between a world and a body convention
+    pub fn rotation_to(&self, other: Convention) -> Option<Rotation3<f64>> {
+        if self.is_world() != other.is_world() {
+            return None;
+        }
+        if self == &other {
+            return Some(Rotation3::identity());
+        }
+        let m = if self.is_world() {
+            Matrix3::new(0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, -1.0)
+        } else {
+            Matrix3::from_diagonal(&Vector3::new(1.0, -1.0, -1.0))
+        };
+        Some(Rotation3::from_matrix_unchecked(m))
+    }
+}
+
+/// Swaps x and y and negates z, it is its own inverse
+pub fn enu_to_ned(v: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(v.y, v.x, -v.z)
+}
+
+pub fn ned_to_enu(v: &Vector3<f64>) -> Vector3<f64> {
+    enu_to_ned(v)
+}
+
+/// Negates y and z, it is its own inverse
+pub fn flu_to_frd(v: &Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(v.x, -v.y, -v.z)
+}
+
+pub fn frd_to_flu(v: &Vector3<f64>) -> Vector3<f64> {
+    flu_to_frd(v)
+}
+
+/// Attitude (body to world rotation) given in the conventions `from` (world, body) expressed in
+/// the conventions `to`, None if a world convention is given for a body or the opposite
+pub fn convert_attitude(
+    q: &UnitQuaternion<f64>,
+    from: (Convention, Convention),
+    to: (Convention, Convention),
+) -> Option<UnitQuaternion<f64>> {
+    if !from.0.is_world() || !to.0.is_world() || from.1.is_world() || to.1.is_world() {
+        return None;
+    }
+    let world = UnitQuaternion::from_rotation_matrix(&from.0.rotation_to(to.0)?);
+    let body = UnitQuaternion::from_rotation_matrix(&to.1.rotation_to(from.1)?);
+    Some(world * q * body)
+}
+
+/// Frame known at compile time, `Transform` can only be composed between matching frames
+pub trait Frame {
+    const CONVENTION: Convention;
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Enu;
+#[derive(Debug, Clone, Copy)]
+pub struct Ned;
+#[derive(Debug, Clone, Copy)]
+pub struct Flu;
+#[derive(Debug, Clone, Copy)]
+pub struct Frd;
+
+impl Frame for Enu {
+    const CONVENTION: Convention = Convention::Enu;
+}
+impl Frame for Ned {
+    const CONVENTION: Convention = Convention::Ned;
+}
+impl Frame for Flu {
+    const CONVENTION: Convention = Convention::Flu;
+}
+impl Frame for Frd {
+    const CONVENTION: Convention = Convention::Frd;
+}
+
+/// Vector expressed in the frame `F`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FramedVector<F: Frame> {
+    pub v: Vector3<f64>,
+    frame: PhantomData<F>,
+}
+
+impl<F: Frame> FramedVector<F> {
+    pub fn new(v: Vector3<f64>) -> FramedVector<F> {
+        FramedVector {
+            v,
+            frame: PhantomData,
+        }
+    }
+
+    /// Change of convention, None between a world and a body frame
+    pub fn to<G: Frame>(&self) -> Option<FramedVector<G>> {
+        let r = F::CONVENTION.rotation_to(G::CONVENTION)?;
+        Some(FramedVector::new(r * self.v))
+    }
+}
+
+/// Maps the coordinates in `From` to the coordinates in `To`, i.e. the pose of `From` in `To`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform<From: Frame, To: Frame> {
+    pub isometry: Isometry3<f64>,
+    frames: PhantomData<(From, To)>,
+}
+
+impl<From: Frame, To: Frame> Transform<From, To> {
+    pub fn new(isometry: Isometry3<f64>) -> Transform<From, To> {
+        Transform {
+            isometry,
+            frames: PhantomData,
+        }
+    }
+
+    pub fn inverse(&self) -> Transform<To, From> {
+        Transform::new(self.isometry.inverse())
+    }
+
+    /// Rotates a vector, use `transform_point` for positions
+    pub fn transform_vector(&self, v: &FramedVector<From>) -> FramedVector<To> {
+        FramedVector::new(self.isometry.transform_vector(&v.v))
+    }
+
+    pub fn transform_point(&self, p: &FramedVector<From>) -> FramedVector<To> {
+        FramedVector::new(self.isometry.transform_point(&p.v.into()).coords)
+    }
+}
+
+impl<A: Frame, B: Frame, C: Frame> Mul<Transform<A, B>> for Transform<B, C> {
+    type Output = Transform<A, C>;
+
+    fn mul(self, rhs: Transform<A, B>) -> Transform<A, C> {
+        Transform::new(self.isometry * rhs.isometry)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameError {
+    UnknownFrame(String),
+    /// The frame already has a parent
+    AlreadyParented(String),
+    Cycle(String),
+    /// No common root between the two frames
+    Disconnected(String, String),
+    /// The frame does not have the expected convention
+    ConventionMismatch {
+        frame: String,
+        expected: Convention,
+        found: Convention,
+    },
+    /// The rotation between two world frames does not match their conventions
+    InconsistentWorldFrames(String, String),
+    /// The root of the frame is not a world frame
+    NoWorldFrame(String),
+}
+
+impl std::error::Error for FrameError {}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FrameError::UnknownFrame(frame) => write!(f, "unknown frame `{frame}`"),
+            FrameError::AlreadyParented(frame) => write!(f, "`{frame}` already has a parent"),
+            FrameError::Cycle(frame) => write!(f, "`{frame}` would be its own ancestor"),
+            FrameError::Disconnected(a, b) => write!(f, "`{a}` and `{b}` are not connected"),
+            FrameError::ConventionMismatch {
+                frame,
+                expected,
+                found,
+            } => write!(f, "`{frame}` should be {expected:?} but is {found:?}"),
+            FrameError::InconsistentWorldFrames(a, b) => {
+                write!(
+                    f,
+                    "the rotation between `{a}` and `{b}` contradicts their conventions"
+                )
+            }
+            FrameError::NoWorldFrame(frame) => {
+                write!(f, "the root of `{frame}` is not a world frame")
+            }
+        }
+    }
+}
+
+/// Frames known at runtime, each frame has a convention and at most one parent
+#[derive(Debug, Clone, Default)]
+pub struct TransformTree {
+    conventions: FxHashMap<String, Convention>,
+    /// child -> (parent, pose of the child in the parent)
+    parents: FxHashMap<String, (String, Isometry3<f64>)>,
+}
+
+impl TransformTree {
+    pub fn new() -> TransformTree {
+        TransformTree::default()
+    }
+
+    pub fn add_frame(&mut self, frame: &str, convention: Convention) {
+        self.conventions.insert(frame.to_owned(), convention);
+    }
+
+    pub fn convention(&self, frame: &str) -> Result<Convention, FrameError> {
+        self.conventions
+            .get(frame)
+            .copied()
+            .ok_or_else(|| FrameError::UnknownFrame(frame.to_owned()))
+    }
+
+    pub fn expect_convention(&self, frame: &str, expected: Convention) -> Result<(), FrameError> {
+        let found = self.convention(frame)?;
+        if found != expected {
+            return Err(FrameError::ConventionMismatch {
+                frame: frame.to_owned(),
+                expected,
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// `pose` is the pose of `child` in `parent`. Two world frames must be related by the
+    /// rotation of their conventions
+    pub fn add_transform(
+        &mut self,
+        parent: &str,
+        child: &str,
+        pose: Isometry3<f64>,
+    ) -> Result<(), FrameError> {
+        let parent_convention = self.convention(parent)?;
+        let child_convention = self.convention(child)?;
+        if self.parents.contains_key(child) {
+            return Err(FrameError::AlreadyParented(child.to_owned()));
+        }
+        if self.ancestors(parent).0.contains(&child) {
+            return Err(FrameError::Cycle(child.to_owned()));
+        }
+        if parent_convention.is_world() && child_convention.is_world() {
+            let expected = child_convention.rotation_to(parent_convention).unwrap();
+            let rotation = pose.rotation.to_rotation_matrix();
+            if (rotation.matrix() - expected.matrix()).abs().max() > 1e-6 {
+                return Err(FrameError::InconsistentWorldFrames(
+                    parent.to_owned(),
+                    child.to_owned(),
+                ));
+            }
+        }
+        self.parents
+            .insert(child.to_owned(), (parent.to_owned(), pose));
+        Ok(())
+    }
+
+    /// The frame and its ancestors up to the root, with the pose of the frame in the root
+    fn ancestors<'a>(&'a self, frame: &'a str) -> (Vec<&'a str>, Isometry3<f64>) {
+        let mut chain = vec![frame];
+        let mut pose = Isometry3::identity();
+        while let Some((parent, parent_from_child)) = self.parents.get(*chain.last().unwrap()) {
+            pose = parent_from_child * pose;
+            chain.push(parent);
+        }
+        (chain, pose)
+    }
+
+    fn pose_in_root(&self, frame: &str) -> Result<(String, Isometry3<f64>), FrameError> {
+        self.convention(frame)?;
+        let (chain, pose) = self.ancestors(frame);
+        Ok((chain.last().unwrap().to_string(), pose))
+    }
+
+    /// Maps the coordinates in `source` to the coordinates in `target`
+    pub fn lookup(&self, target: &str, source: &str) -> Result<Isometry3<f64>, FrameError> {
+        let (root_source, source_in_root) = self.pose_in_root(source)?;
+        let (root_target, target_in_root) = self.pose_in_root(target)?;
+        if root_source != root_target {
+            return Err(FrameError::Disconnected(
+                source.to_owned(),
+                target.to_owned(),
+            ));
+        }
+        Ok(target_in_root.inverse() * source_in_root)
+    }
+
+    /// Gravity expressed in `frame`, the root of the frame must be a world frame
+    pub fn gravity_in(&self, frame: &str, g: f64) -> Result<Vector3<f64>, FrameError> {
+        let (root, frame_in_root) = self.pose_in_root(frame)?;
+        let root_convention = self.convention(&root)?;
+        if !root_convention.is_world() {
+            return Err(FrameError::NoWorldFrame(frame.to_owned()));
+        }
+        Ok(frame_in_root.inverse_transform_vector(&root_convention.gravity(g)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::Translation3;
+
+    #[test]
+    fn conventions_and_typed_transforms() {
+        let v = Vector3::new(1.0, 2.0, 3.0);
+        assert_eq!(Vector3::new(2.0, 1.0, -3.0), enu_to_ned(&v));
+        assert_eq!(v, ned_to_enu(&enu_to_ned(&v)));
+        assert_eq!(v, frd_to_flu(&flu_to_frd(&v)));
+        assert_eq!(
+            enu_to_ned(&v),
+            FramedVector::<Enu>::new(v).to::<Ned>().unwrap().v
+        );
+        assert!(FramedVector::<Enu>::new(v).to::<Flu>().is_none());
+
+        // robot heading north (yaw 90 deg in ENU), i.e. yaw 0 in NED
+        let q_enu_flu = UnitQuaternion::from_euler_angles(0.0, 0.0, std::f64::consts::FRAC_PI_2);
+        let q_ned_frd = convert_attitude(
+            &q_enu_flu,
+            (Convention::Enu, Convention::Flu),
+            (Convention::Ned, Convention::Frd),
+        )
+        .unwrap();
+        approx::assert_abs_diff_eq!(0.0, q_ned_frd.angle(), epsilon = 1e-12);
+
+        let body_in_world = Transform::<Flu, Enu>::new(Isometry3::from_parts(
+            Translation3::new(1.0, 0.0, 0.0),
+            q_enu_flu,
+        ));
+        let sensor_in_body = Transform::<Frd, Flu>::new(Isometry3::from_parts(
+            Translation3::identity(),
+            UnitQuaternion::from_rotation_matrix(
+                &Convention::Frd.rotation_to(Convention::Flu).unwrap(),
+            ),
+        ));
+        let sensor_in_world: Transform<Frd, Enu> = body_in_world * sensor_in_body;
+        let forward = FramedVector::<Frd>::new(Vector3::x());
+        approx::assert_abs_diff_eq!(
+            Vector3::y(),
+            sensor_in_world.transform_vector(&forward).v,
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn transform_tree() {
+        let mut tree = TransformTree::new();
+        tree.add_frame("map", Convention::Enu);
+        tree.add_frame("map_ned", Convention::Ned);
+        tree.add_frame("base_link", Convention::Flu);
+        tree.add_frame("imu", Convention::Frd);
+
+        let enu_in_ned = Isometry3::from_parts(
+            Translation3::identity(),
+            UnitQuaternion::from_rotation_matrix(
+                &Convention::Enu.rotation_to(Convention::Ned).unwrap(),
+            ),
+        );
+        assert_eq!(
+            Err(FrameError::InconsistentWorldFrames(
+                "map_ned".to_owned(),
+                "map".to_owned()
+            )),
+            tree.add_transform("map_ned", "map", Isometry3::identity())
+        );
+        tree.add_transform("map_ned", "map", enu_in_ned).unwrap();
+        let pitch = UnitQuaternion::from_euler_angles(0.0, 0.3, 0.0);
+        tree.add_transform(
+            "map",
+            "base_link",
+            Isometry3::from_parts(Translation3::new(1.0, 2.0, 0.0), pitch),
+        )
+        .unwrap();
+        let imu_in_base = Isometry3::from_parts(
+            Translation3::identity(),
+            UnitQuaternion::from_rotation_matrix(
+                &Convention::Frd.rotation_to(Convention::Flu).unwrap(),
+            ),
+        );
+        tree.add_transform("base_link", "imu", imu_in_base).unwrap();
+        assert_eq!(
+            Err(FrameError::Cycle("map_ned".to_owned())),
+            tree.add_transform("imu", "map_ned", Isometry3::identity())
+        );
+
+        // at rest, the imu measures the opposite of the gravity
+        let g = tree.gravity_in("imu", STANDARD_GRAVITY).unwrap();
+        let expected =
+            flu_to_frd(&pitch.inverse_transform_vector(&Vector3::new(0.0, 0.0, -STANDARD_GRAVITY)));
+        approx::assert_abs_diff_eq!(expected, g, epsilon = 1e-9);
+        approx::assert_abs_diff_eq!(
+            Vector3::new(0.0, 0.0, STANDARD_GRAVITY),
+            tree.gravity_in("map_ned", STANDARD_GRAVITY).unwrap(),
+            epsilon = 1e-12
+        );
+
+        let imu_in_ned = tree.lookup("map_ned", "imu").unwrap();
+        approx::assert_abs_diff_eq!(
+            Vector3::new(2.0, 1.0, 0.0),
+            imu_in_ned.translation.vector,
+            epsilon = 1e-12
+        );
+        assert!(tree.expect_convention("imu", Convention::Flu).is_err());
+        tree.add_frame("odom", Convention::Enu);
+        assert!(matches!(
+            tree.lookup("odom", "imu"),
+            Err(FrameError::Disconnected(_, _))
+        ));
+    }
+}
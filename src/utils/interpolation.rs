@@ -0,0 +1,166 @@
+use std::collections::VecDeque;
+
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OMatrix, OVector, RealField};
+
+use crate::utils::state::GaussianState;
+
+/// Measurement or control with its acquisition time
+#[derive(Debug, Clone)]
+pub struct Stamped<T: RealField, D: Dim>
+where
+    DefaultAllocator: Allocator<T, D>,
+{
+    pub time: T,
+    pub value: OVector<T, D>,
+}
+
+/// Time ordered buffer of asynchronous samples, resampled at the filter update time.
+///
+/// The value at time t is linearly interpolated between the two samples surrounding t,
+/// or extrapolated from the two closest samples if t is outside of the buffer.
+/// If the second derivative of the signal is bounded by a, the error of the linear estimate
+/// is at most a (t - t0)(t - t1) / 2. With `acceleration_noise` the covariance Q_acc of this
+/// second derivative, the returned covariance is
+///
+/// Q_acc ((t - t0)(t - t1) / 2)^2
+///
+/// which is zero on the samples and must be added to the measurement (or control) noise.
+/// Angles are interpolated like any other component, unwrap them before pushing.
+pub struct InterpolationBuffer<T: RealField, D: Dim>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    samples: VecDeque<Stamped<T, D>>,
+    capacity: usize,
+    acceleration_noise: OMatrix<T, D, D>,
+    max_extrapolation: T,
+}
+
+impl<T: RealField + Copy, D: Dim> InterpolationBuffer<T, D>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    /// Keeps at most `capacity` samples and refuses to extrapolate further than
+    /// `max_extrapolation` seconds from the closest sample
+    pub fn new(
+        capacity: usize,
+        acceleration_noise: OMatrix<T, D, D>,
+        max_extrapolation: T,
+    ) -> Self {
+        InterpolationBuffer {
+            samples: VecDeque::with_capacity(capacity),
+            capacity: capacity.max(2),
+            acceleration_noise,
+            max_extrapolation,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Time span covered by the buffer
+    pub fn span(&self) -> Option<(T, T)> {
+        Some((self.samples.front()?.time, self.samples.back()?.time))
+    }
+
+    /// Inserts the sample in time order, late samples are accepted.
+    /// A sample with the same time as a buffered one replaces it
+    pub fn push(&mut self, sample: Stamped<T, D>) {
+        let i = self.samples.partition_point(|s| s.time < sample.time);
+        if self.samples.get(i).is_some_and(|s| s.time == sample.time) {
+            self.samples[i] = sample;
+        } else {
+            self.samples.insert(i, sample);
+        }
+        while self.samples.len() > self.capacity {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Drops the samples older than `time`, keeping the last one before it to interpolate
+    pub fn drop_before(&mut self, time: T) {
+        while self.samples.len() > 1 && self.samples[1].time <= time {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Estimate at `time` with the covariance of the interpolation error,
+    /// None if the buffer has less than two samples or `time` is too far from them
+    pub fn sample_at(&self, time: T) -> Option<GaussianState<T, D>> {
+        if let Some(s) = self.samples.iter().find(|s| s.time == time) {
+            let shape = s.value.shape_generic();
+            return Some(GaussianState {
+                x: s.value.clone(),
+                cov: OMatrix::zeros_generic(shape.0, shape.0),
+            });
+        }
+        let n = self.samples.len();
+        if n < 2 {
+            return None;
+        }
+
+        // pair of samples used for the linear estimate
+        let i = self
+            .samples
+            .partition_point(|s| s.time < time)
+            .clamp(1, n - 1);
+        let (s0, s1) = (&self.samples[i - 1], &self.samples[i]);
+        let (first, last) = self.span()?;
+        if time < first - self.max_extrapolation || time > last + self.max_extrapolation {
+            return None;
+        }
+
+        let alpha = (time - s0.time) / (s1.time - s0.time);
+        let x = &s0.value + (&s1.value - &s0.value) * alpha;
+        let error = (time - s0.time) * (time - s1.time) / T::from_f64(2.0).unwrap();
+        let cov = &self.acceleration_noise * (error * error);
+        Some(GaussianState { x, cov })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Const, Matrix2, Vector2};
+
+    fn buffer() -> InterpolationBuffer<f64, Const<2>> {
+        let mut buffer = InterpolationBuffer::new(10, Matrix2::identity(), 0.5);
+        // out of order on purpose
+        for t in [0.0, 2.0, 1.0] {
+            buffer.push(Stamped {
+                time: t,
+                value: Vector2::new(t, -2.0 * t),
+            });
+        }
+        buffer
+    }
+
+    #[test]
+    fn interpolation() {
+        let buffer = buffer();
+        let s = buffer.sample_at(1.5).unwrap();
+        approx::assert_abs_diff_eq!(Vector2::new(1.5, -3.0), s.x, epsilon = 1e-12);
+        // (0.5 * -0.5 / 2)^2
+        approx::assert_abs_diff_eq!(Matrix2::identity() * 0.015625, s.cov, epsilon = 1e-12);
+
+        let s = buffer.sample_at(1.0).unwrap();
+        approx::assert_abs_diff_eq!(Matrix2::zeros(), s.cov);
+    }
+
+    #[test]
+    fn extrapolation() {
+        let mut buffer = buffer();
+        let s = buffer.sample_at(2.5).unwrap();
+        approx::assert_abs_diff_eq!(Vector2::new(2.5, -5.0), s.x, epsilon = 1e-12);
+        assert!(s.cov[(0, 0)] > buffer.sample_at(1.5).unwrap().cov[(0, 0)]);
+        assert!(buffer.sample_at(2.6).is_none());
+
+        buffer.drop_before(1.5);
+        assert_eq!(Some((1.0, 2.0)), buffer.span());
+    }
+}
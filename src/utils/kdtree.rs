@@ -0,0 +1,237 @@
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OVector, RealField};
+use std::cmp::Ordering;
+
+/// k-d tree over points of any dimension, balanced by median splits. The queries return the
+/// indices of the points in the slice given to `new`
+#[derive(Debug, Clone)]
+pub struct KdTree<T: RealField, D: Dim>
+where
+    DefaultAllocator: Allocator<T, D>,
+{
+    points: Vec<OVector<T, D>>,
+    /// Indices of the points, the node of a range is its middle, split on the axis depth % dim
+    nodes: Vec<usize>,
+    dim: usize,
+}
+
+impl<T: RealField + Copy, D: Dim> KdTree<T, D>
+where
+    DefaultAllocator: Allocator<T, D>,
+{
+    pub fn new(points: &[OVector<T, D>]) -> KdTree<T, D> {
+        let dim = points.first().map_or(0, |p| p.nrows());
+        let mut nodes: Vec<usize> = (0..points.len()).collect();
+        build(points, &mut nodes, 0, dim);
+        KdTree {
+            points: points.to_vec(),
+            nodes,
+            dim,
+        }
+    }
+
+    pub fn points(&self) -> &[OVector<T, D>] {
+        &self.points
+    }
+
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Index of the closest point and its squared distance, None if the tree is empty
+    pub fn nearest(&self, p: &OVector<T, D>) -> Option<(usize, T)> {
+        let mut best = None;
+        self.nearest_in(0, self.nodes.len(), 0, p, &mut best);
+        best
+    }
+
+    fn nearest_in(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        p: &OVector<T, D>,
+        best: &mut Option<(usize, T)>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let index = self.nodes[mid];
+        let d2 = (&self.points[index] - p).norm_squared();
+        if best.is_none_or(|(_, b)| d2 < b) {
+            *best = Some((index, d2));
+        }
+        let axis = depth % self.dim;
+        let diff = p[axis] - self.points[index][axis];
+        let (near, far) = if diff < T::zero() {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.nearest_in(near.0, near.1, depth + 1, p, best);
+        if best.is_none_or(|(_, b)| diff * diff < b) {
+            self.nearest_in(far.0, far.1, depth + 1, p, best);
+        }
+    }
+
+    /// Indices of the `k` closest points and their squared distances, by increasing distance
+    pub fn nearest_k(&self, p: &OVector<T, D>, k: usize) -> Vec<(usize, T)> {
+        let mut found = Vec::with_capacity(k + 1);
+        if k > 0 {
+            self.nearest_k_in(0, self.nodes.len(), 0, p, k, &mut found);
+        }
+        found
+    }
+
+    fn nearest_k_in(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        p: &OVector<T, D>,
+        k: usize,
+        found: &mut Vec<(usize, T)>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let index = self.nodes[mid];
+        let d2 = (&self.points[index] - p).norm_squared();
+        if found.len() < k || d2 < found[k - 1].1 {
+            let position = found.partition_point(|(_, d)| *d <= d2);
+            found.insert(position, (index, d2));
+            found.truncate(k);
+        }
+        let axis = depth % self.dim;
+        let diff = p[axis] - self.points[index][axis];
+        let (near, far) = if diff < T::zero() {
+            ((start, mid), (mid + 1, end))
+        } else {
+            ((mid + 1, end), (start, mid))
+        };
+        self.nearest_k_in(near.0, near.1, depth + 1, p, k, found);
+        if found.len() < k || diff * diff < found[k - 1].1 {
+            self.nearest_k_in(far.0, far.1, depth + 1, p, k, found);
+        }
+    }
+
+    /// Indices of the points closer than `radius`
+    pub fn within(&self, p: &OVector<T, D>, radius: T) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.within_in(0, self.nodes.len(), 0, p, radius, &mut found);
+        found
+    }
+
+    fn within_in(
+        &self,
+        start: usize,
+        end: usize,
+        depth: usize,
+        p: &OVector<T, D>,
+        radius: T,
+        found: &mut Vec<usize>,
+    ) {
+        if start >= end {
+            return;
+        }
+        let mid = (start + end) / 2;
+        let index = self.nodes[mid];
+        if (&self.points[index] - p).norm_squared() <= radius * radius {
+            found.push(index);
+        }
+        let axis = depth % self.dim;
+        let diff = p[axis] - self.points[index][axis];
+        if diff <= radius {
+            self.within_in(start, mid, depth + 1, p, radius, found);
+        }
+        if diff >= -radius {
+            self.within_in(mid + 1, end, depth + 1, p, radius, found);
+        }
+    }
+}
+
+fn build<T: RealField, D: Dim>(
+    points: &[OVector<T, D>],
+    nodes: &mut [usize],
+    depth: usize,
+    dim: usize,
+) where
+    DefaultAllocator: Allocator<T, D>,
+{
+    if nodes.len() <= 1 {
+        return;
+    }
+    let axis = depth % dim;
+    let mid = nodes.len() / 2;
+    nodes.select_nth_unstable_by(mid, |a, b| {
+        points[*a][axis]
+            .partial_cmp(&points[*b][axis])
+            .unwrap_or(Ordering::Equal)
+    });
+    let (left, right) = nodes.split_at_mut(mid);
+    build(points, left, depth + 1, dim);
+    build(points, &mut right[1..], depth + 1, dim);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Vector2, Vector3};
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn kd_tree_matches_brute_force() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(3);
+        let points: Vec<Vector2<f64>> = (0..500)
+            .map(|_| Vector2::new(rng.gen_range(-5.0..5.0), rng.gen_range(-5.0..5.0)))
+            .collect();
+        let tree = KdTree::new(&points);
+        for _ in 0..100 {
+            let p = Vector2::new(rng.gen_range(-6.0..6.0), rng.gen_range(-6.0..6.0));
+            let (i, d2) = tree.nearest(&p).unwrap();
+            let best = points
+                .iter()
+                .map(|q| (q - p).norm_squared())
+                .fold(f64::INFINITY, f64::min);
+            approx::assert_abs_diff_eq!(best, d2);
+            approx::assert_abs_diff_eq!(best, (points[i] - p).norm_squared());
+
+            let mut within = tree.within(&p, 1.0);
+            within.sort();
+            let expected: Vec<usize> = (0..points.len())
+                .filter(|j| (points[*j] - p).norm() <= 1.0)
+                .collect();
+            assert_eq!(expected, within);
+        }
+        assert!(KdTree::<f64, _>::new(&[] as &[Vector2<f64>])
+            .nearest(&Vector2::zeros())
+            .is_none());
+    }
+
+    #[test]
+    fn k_nearest_in_3d() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(5);
+        let points: Vec<Vector3<f64>> = (0..300)
+            .map(|_| Vector3::from_fn(|_, _| rng.gen_range(-1.0..1.0)))
+            .collect();
+        let tree = KdTree::new(&points);
+        for _ in 0..50 {
+            let p = Vector3::from_fn(|_, _| rng.gen_range(-1.0..1.0));
+            let mut expected: Vec<(usize, f64)> = points
+                .iter()
+                .enumerate()
+                .map(|(i, q)| (i, (q - p).norm_squared()))
+                .collect();
+            expected.sort_by(|a, b| a.1.total_cmp(&b.1));
+            expected.truncate(5);
+            assert_eq!(tree.nearest_k(&p, 5), expected);
+        }
+        assert!(tree.nearest_k(&Vector3::zeros(), 0).is_empty());
+        assert_eq!(tree.nearest_k(&Vector3::zeros(), 400).len(), 300);
+    }
+}
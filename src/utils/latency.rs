@@ -0,0 +1,204 @@
+use std::error::Error;
+use std::time::{Duration, Instant};
+
+/// Latency distribution of a module
+#[derive(Debug, Clone, PartialEq)]
+pub struct LatencyReport {
+    pub module: String,
+    pub count: usize,
+    pub mean: Duration,
+    /// Standard deviation of the latency
+    pub jitter: Duration,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+    pub budget: Option<Duration>,
+    /// Number of samples above the budget
+    pub over_budget: usize,
+}
+
+#[derive(Debug, Default)]
+struct Samples {
+    module: String,
+    durations: Vec<Duration>,
+    budget: Option<Duration>,
+}
+
+/// Records the latency of the modules of the loop (estimate, plan, control, ...) to check their
+/// real-time budgets on the target hardware. `tick` measures the period of the loop itself, its
+/// jitter is reported under the module "period"
+#[derive(Debug, Default)]
+pub struct LatencyMonitor {
+    modules: Vec<Samples>,
+    last_tick: Option<Instant>,
+}
+
+/// Nearest rank percentile of sorted durations
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+impl LatencyMonitor {
+    pub fn new() -> LatencyMonitor {
+        LatencyMonitor::default()
+    }
+
+    fn samples(&mut self, module: &str) -> &mut Samples {
+        let i = match self.modules.iter().position(|s| s.module == module) {
+            Some(i) => i,
+            None => {
+                self.modules.push(Samples {
+                    module: module.to_string(),
+                    ..Default::default()
+                });
+                self.modules.len() - 1
+            }
+        };
+        &mut self.modules[i]
+    }
+
+    /// Runs `f` and records its duration for `module`
+    pub fn measure<R>(&mut self, module: &str, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.record(module, start.elapsed());
+        result
+    }
+
+    pub fn record(&mut self, module: &str, duration: Duration) {
+        self.samples(module).durations.push(duration);
+    }
+
+    pub fn set_budget(&mut self, module: &str, budget: Duration) {
+        self.samples(module).budget = Some(budget);
+    }
+
+    /// Start of an iteration of the loop
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(last) = self.last_tick.replace(now) {
+            self.record("period", now - last);
+        }
+    }
+
+    /// Forgets the samples, e.g. after a warm up, the budgets are kept
+    pub fn clear(&mut self) {
+        for samples in self.modules.iter_mut() {
+            samples.durations.clear();
+        }
+        self.last_tick = None;
+    }
+
+    /// One report per module with samples, in the order of their first record
+    pub fn report(&self) -> Vec<LatencyReport> {
+        self.modules
+            .iter()
+            .filter(|s| !s.durations.is_empty())
+            .map(|s| {
+                let mut sorted = s.durations.clone();
+                sorted.sort();
+                let count = sorted.len();
+                let seconds: Vec<f64> = sorted.iter().map(Duration::as_secs_f64).collect();
+                let mean = seconds.iter().sum::<f64>() / count as f64;
+                let variance =
+                    seconds.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / count as f64;
+                LatencyReport {
+                    module: s.module.clone(),
+                    count,
+                    mean: Duration::from_secs_f64(mean),
+                    jitter: Duration::from_secs_f64(variance.sqrt()),
+                    p50: percentile(&sorted, 50.0),
+                    p90: percentile(&sorted, 90.0),
+                    p99: percentile(&sorted, 99.0),
+                    max: sorted[count - 1],
+                    budget: s.budget,
+                    over_budget: s
+                        .budget
+                        .map_or(0, |b| sorted.iter().filter(|d| **d > b).count()),
+                }
+            })
+            .collect()
+    }
+
+    /// True if no module went over its budget
+    pub fn within_budgets(&self) -> bool {
+        self.report().iter().all(|r| r.over_budget == 0)
+    }
+
+    /// Writes the reports as CSV, the durations in microseconds
+    pub fn write_csv<W: std::io::Write>(&self, writer: W) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_writer(writer);
+        writer.write_record([
+            "module",
+            "count",
+            "mean_us",
+            "jitter_us",
+            "p50_us",
+            "p90_us",
+            "p99_us",
+            "max_us",
+            "budget_us",
+            "over_budget",
+        ])?;
+        let us = |d: Duration| format!("{:.3}", d.as_secs_f64() * 1e6);
+        for r in self.report() {
+            writer.write_record([
+                r.module.clone(),
+                r.count.to_string(),
+                us(r.mean),
+                us(r.jitter),
+                us(r.p50),
+                us(r.p90),
+                us(r.p99),
+                us(r.max),
+                r.budget.map(us).unwrap_or_default(),
+                r.over_budget.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_and_budgets() {
+        let mut monitor = LatencyMonitor::new();
+        monitor.set_budget("control", Duration::from_micros(95));
+        for i in 1..=100 {
+            monitor.record("control", Duration::from_micros(i));
+        }
+        let value = monitor.measure("estimate", || 42);
+        assert_eq!(42, value);
+
+        let report = monitor.report();
+        assert_eq!(2, report.len());
+        let control = &report[0];
+        assert_eq!(100, control.count);
+        assert_eq!(Duration::from_micros(50), control.p50);
+        assert_eq!(Duration::from_micros(90), control.p90);
+        assert_eq!(Duration::from_micros(99), control.p99);
+        assert_eq!(Duration::from_micros(100), control.max);
+        approx::assert_abs_diff_eq!(50.5e-6, control.mean.as_secs_f64(), epsilon = 1e-9);
+        assert_eq!(5, control.over_budget);
+        assert!(!monitor.within_budgets());
+
+        let mut csv = Vec::new();
+        monitor.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert_eq!(3, csv.lines().count());
+        assert!(csv
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("control,100,50.500,"));
+
+        monitor.clear();
+        assert!(monitor.report().is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use nalgebra::{
+    allocator::Allocator, DefaultAllocator, Dim, Isometry2, Matrix2, OMatrix, OVector, RealField,
+    Vector2,
+};
+
+use crate::utils::state::GaussianState;
+
+/// Confidence ellipse of a 2D gaussian
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CovarianceEllipse<T> {
+    pub semi_major: T,
+    pub semi_minor: T,
+    /// Angle of the major axis from the x axis, in ]-pi/2, pi/2]
+    pub orientation: T,
+}
+
+/// Ellipse containing the samples with the probability `confidence`, its semi-axes are the
+/// square roots of the eigenvalues of `cov` scaled by the chi-square quantile with 2 degrees of
+/// freedom
+pub fn covariance_ellipse<T: RealField + Copy>(
+    cov: &Matrix2<T>,
+    confidence: f64,
+) -> CovarianceEllipse<T> {
+    let two = T::from_f64(2.0).unwrap();
+    let (a, b, c) = (cov.m11, (cov.m12 + cov.m21) / two, cov.m22);
+    let center = (a + c) / two;
+    let radius = (((a - c) / two).powi(2) + b * b).sqrt();
+    let scale = T::from_f64(chi_square_quantile(2, confidence).sqrt()).unwrap();
+    CovarianceEllipse {
+        semi_major: (center + radius).max(T::zero()).sqrt() * scale,
+        semi_minor: (center - radius).max(T::zero()).sqrt() * scale,
+        orientation: (two * b).atan2(a - c) / two,
+    }
+}
+
+/// Normalized estimation error squared of `state` against the ground truth, chi-square
+/// distributed with the dimension of the state as degrees of freedom for a consistent filter.
+/// None if the covariance is not positive definite. The angles must be normalized by the caller
+pub fn nees<T: RealField + Copy, D: Dim>(
+    state: &GaussianState<T, D>,
+    truth: &OVector<T, D>,
+) -> Option<T>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    mahalanobis_squared(&(&state.x - truth), &state.cov)
+}
+
+/// Normalized innovation squared, chi-square distributed with the dimension of the measurement
+/// as degrees of freedom for a consistent filter. `s` is the covariance of the innovation
+pub fn nis<T: RealField + Copy, Z: Dim>(
+    innovation: &OVector<T, Z>,
+    s: &OMatrix<T, Z, Z>,
+) -> Option<T>
+where
+    DefaultAllocator: Allocator<T, Z> + Allocator<T, Z, Z>,
+{
+    mahalanobis_squared(innovation, s)
+}
+
+fn mahalanobis_squared<T: RealField + Copy, D: Dim>(
+    error: &OVector<T, D>,
+    cov: &OMatrix<T, D, D>,
+) -> Option<T>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    let cholesky = cov.clone().cholesky()?;
+    Some(error.dot(&cholesky.solve(error)))
+}
+
+/// Two-sided interval containing the average of `samples` NEES (or NIS) with `dof` degrees of
+/// freedom with the probability `confidence`, the average of a consistent filter is inside
+///
+/// Estimation with Applications to Tracking and Navigation, Bar-Shalom p. 234
+pub fn consistency_bounds(dof: usize, samples: usize, confidence: f64) -> (f64, f64) {
+    // the sum of the samples is chi-square distributed with dof * samples degrees of freedom
+    let samples = samples.max(1);
+    let alpha = 1.0 - confidence;
+    let lower = chi_square_quantile(dof * samples, alpha / 2.0);
+    let upper = chi_square_quantile(dof * samples, 1.0 - alpha / 2.0);
+    (lower / samples as f64, upper / samples as f64)
+}
+
+/// Whether the average of the NEES (or NIS) is inside the `consistency_bounds`
+pub fn is_consistent(values: &[f64], dof: usize, confidence: f64) -> bool {
+    if values.is_empty() {
+        return true;
+    }
+    let average = values.iter().sum::<f64>() / values.len() as f64;
+    let (lower, upper) = consistency_bounds(dof, values.len(), confidence);
+    lower <= average && average <= upper
+}
+
+/// Probability for a chi-square variable with `dof` degrees of freedom to be below `x`
+pub fn chi_square_cdf(dof: usize, x: f64) -> f64 {
+    if dof == 0 {
+        return 1.0;
+    }
+    regularized_gamma_p(dof as f64 / 2.0, x / 2.0)
+}
+
+/// Value below which a chi-square variable with `dof` degrees of freedom is with the
+/// probability `p`, e.g. 5.99 for 2 degrees of freedom at 95 %
+pub fn chi_square_quantile(dof: usize, p: f64) -> f64 {
+    if dof == 0 || p <= 0.0 {
+        return 0.0;
+    }
+    if p >= 1.0 {
+        return f64::INFINITY;
+    }
+    let mut upper = dof as f64 + 1.0;
+    while chi_square_cdf(dof, upper) < p {
+        upper *= 2.0;
+    }
+    let mut lower = 0.0;
+    for _ in 0..200 {
+        let middle = (lower + upper) / 2.0;
+        if chi_square_cdf(dof, middle) < p {
+            lower = middle;
+        } else {
+            upper = middle;
+        }
+        if upper - lower <= 1e-12 * upper {
+            break;
+        }
+    }
+    (lower + upper) / 2.0
+}
+
+/// Lanczos approximation (g = 7), only used for a >= 0.5
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    let x = x - 1.0;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |acc, (i, c)| {
+            acc + c / (x + i as f64 + 1.0)
+        });
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// Lower regularized incomplete gamma function, with the series for x < a + 1 and the continued
+/// fraction of the upper one otherwise
+///
+/// Numerical Recipes in C, 6.2
+fn regularized_gamma_p(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let prefactor = (-x + a * x.ln() - ln_gamma(a)).exp();
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..1000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        (sum * prefactor).min(1.0)
+    } else {
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / TINY;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..1000 {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < TINY {
+                d = TINY;
+            }
+            c = b + an / c;
+            if c.abs() < TINY {
+                c = TINY;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        (1.0 - prefactor * h).max(0.0)
+    }
+}
+
+/// Rigid transform minimizing the squared distances between `transform * source` and `target`
+///
+/// Source : Least-Squares Estimation of Transformation Parameters Between Two Point Patterns, Umeyama
+pub fn align_se2(source: &[Vector2<f64>], target: &[Vector2<f64>]) -> Isometry2<f64> {
+    let n = source.len().min(target.len());
+    if n == 0 {
+        return Isometry2::identity();
+    }
+    let mean_source = source[..n].iter().sum::<Vector2<f64>>() / n as f64;
+    let mean_target = target[..n].iter().sum::<Vector2<f64>>() / n as f64;
+    let h = source[..n]
+        .iter()
+        .zip(&target[..n])
+        .fold(Matrix2::zeros(), |h, (s, t)| {
+            h + (s - mean_source) * (t - mean_target).transpose()
+        });
+    let angle = f64::atan2(h.m12 - h.m21, h.m11 + h.m22);
+    let rotation = nalgebra::Rotation2::new(angle);
+    Isometry2::new(mean_target - rotation * mean_source, angle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+    use nalgebra::{Rotation2, Vector2};
+
+    #[test]
+    fn chi_square_quantiles() {
+        assert_relative_eq!(
+            chi_square_quantile(1, 0.95),
+            3.841458820694124,
+            epsilon = 1e-8
+        );
+        assert_relative_eq!(
+            chi_square_quantile(2, 0.95),
+            5.991464547107979,
+            epsilon = 1e-8
+        );
+        assert_relative_eq!(
+            chi_square_quantile(3, 0.95),
+            7.814727903251178,
+            epsilon = 1e-8
+        );
+        assert_relative_eq!(chi_square_quantile(100, 0.025), 74.22192747, epsilon = 1e-6);
+        assert_relative_eq!(chi_square_quantile(100, 0.975), 129.5611972, epsilon = 1e-6);
+        // closed form with 2 degrees of freedom
+        assert_relative_eq!(
+            chi_square_cdf(2, 3.0),
+            1.0 - (-1.5_f64).exp(),
+            epsilon = 1e-12
+        );
+    }
+
+    #[test]
+    fn ellipse_axes_and_orientation() {
+        let rotation = Rotation2::new(0.5);
+        let cov = rotation.matrix() * Matrix2::new(4.0, 0.0, 0.0, 1.0) * rotation.transpose();
+        let ellipse = covariance_ellipse(&cov, 0.95);
+        let scale = chi_square_quantile(2, 0.95).sqrt();
+        assert_relative_eq!(ellipse.semi_major, 2.0 * scale, epsilon = 1e-9);
+        assert_relative_eq!(ellipse.semi_minor, scale, epsilon = 1e-9);
+        assert_relative_eq!(ellipse.orientation, 0.5, epsilon = 1e-9);
+    }
+
+    #[test]
+    fn nees_and_bounds() {
+        let state = GaussianState {
+            x: Vector2::new(2.0, 1.0),
+            cov: Matrix2::new(4.0, 0.0, 0.0, 1.0),
+        };
+        assert_relative_eq!(nees(&state, &Vector2::zeros()).unwrap(), 2.0);
+        assert_eq!(nis(&Vector2::new(1.0, 0.0), &Matrix2::zeros()), None);
+
+        let (lower, upper) = consistency_bounds(2, 50, 0.95);
+        assert!(lower < 2.0 && 2.0 < upper);
+        assert!(is_consistent(&[1.5, 2.5, 2.0, 1.8], 2, 0.95));
+        assert!(!is_consistent(&[20.0; 10], 2, 0.95));
+    }
+}
@@ -1,5 +1,18 @@
+pub mod frames;
+pub mod interpolation;
+pub mod kdtree;
+pub mod latency;
+pub mod metrics;
 pub mod mvn;
+#[cfg(feature = "serde-serialize")]
+pub mod persistence;
+#[cfg(feature = "viz")]
 pub mod plot;
+#[cfg(feature = "viz")]
+pub mod recorder;
+pub mod rng;
+#[cfg(feature = "simd")]
+pub mod simd;
 pub mod state;
 
 pub fn deg2rad(x: f64) -> f64 {
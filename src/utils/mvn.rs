@@ -27,6 +27,9 @@ impl std::fmt::Display for Error {
     }
 }
 
+/// The covariance may be semi-definite, the density is then over the subspace it spans with the
+/// pseudo-inverse and the pseudo-determinant of the covariance
+#[derive(Debug, Clone)]
 pub struct MultiVariateNormal<T: RealField, D: Dim>
 where
     DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
@@ -37,16 +40,52 @@ where
     factor: T,
 }
 
+impl<T: RealField, D: Dim> MultiVariateNormal<T, D>
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    pub fn mean(&self) -> &OVector<T, D> {
+        &self.mean
+    }
+
+    /// Same covariance around another mean, its factorization is reused
+    pub fn with_mean(&self, mean: &OVector<T, D>) -> Self {
+        MultiVariateNormal {
+            mean: mean.clone(),
+            ..self.clone()
+        }
+    }
+
+    /// Inverse of the covariance
+    pub fn precision(&self) -> &OMatrix<T, D, D> {
+        &self.precision
+    }
+
+    /// Log of the normalization factor of the density
+    pub fn log_factor(&self) -> T {
+        self.factor.clone().ln()
+    }
+}
+
 impl<T: RealField, D: Dim> MultiVariateNormal<T, D>
 where
     rand_distr::StandardNormal: Distribution<T>,
     DefaultAllocator: Allocator<T, D> + Allocator<T, D, D> + Allocator<T, Const<1>, D>,
 {
     pub fn new(mean: &OVector<T, D>, covariance: &OMatrix<T, D, D>) -> Result<Self, Error> {
-        let Some(covariance_cholesky) = covariance.clone().cholesky() else {
-            return Err(Error {
-                error_type: ErrorType::CovarianceNotSemiDefinitePositive,
-            });
+        let scale = covariance
+            .diagonal()
+            .iter()
+            .fold(T::zero(), |acc, c| acc.max(c.clone().abs()));
+        let tolerance = singular_tolerance(scale, mean.len());
+        // the cholesky succeeds with zero pivots on some singular covariances
+        let Some(covariance_cholesky) = covariance.clone().cholesky().filter(|cholesky| {
+            let pivots = cholesky.l_dirty().diagonal();
+            pivots
+                .iter()
+                .all(|l| l.clone() * l.clone() > tolerance.clone())
+        }) else {
+            return Self::new_semi_definite(mean, covariance);
         };
         let det = covariance_cholesky.determinant();
         let precision = covariance_cholesky.inverse();
@@ -61,6 +100,55 @@ where
         Ok(mvn)
     }
 
+    /// Zero mean distribution, for the noises of the models
+    pub fn zero_mean(covariance: &OMatrix<T, D, D>) -> Result<Self, Error> {
+        let shape = covariance.shape_generic();
+        Self::new(&OVector::zeros_generic(shape.0, U1), covariance)
+    }
+
+    /// From the eigendecomposition when the cholesky fails, the eigenvalues under the tolerance
+    /// are zeroed and the negative ones above it are an error
+    fn new_semi_definite(
+        mean: &OVector<T, D>,
+        covariance: &OMatrix<T, D, D>,
+    ) -> Result<Self, Error> {
+        let (eigenvalues, eigenvectors) = symmetric_eigen(covariance.clone());
+        let scale = eigenvalues
+            .iter()
+            .fold(T::zero(), |acc, l| acc.max(l.clone().abs()));
+        let tolerance = singular_tolerance(scale, eigenvalues.len());
+        if eigenvalues.iter().any(|l| *l < -tolerance.clone()) {
+            return Err(Error {
+                error_type: ErrorType::CovarianceNotSemiDefinitePositive,
+            });
+        }
+
+        let mut rank = 0;
+        let mut pseudo_det = T::one();
+        let mut inverse = eigenvalues.clone();
+        let mut sqrt = eigenvalues;
+        for (inv, s) in inverse.iter_mut().zip(sqrt.iter_mut()) {
+            if *s > tolerance {
+                rank += 1;
+                pseudo_det *= s.clone();
+                *inv = T::one() / s.clone();
+                *s = s.clone().sqrt();
+            } else {
+                *inv = T::zero();
+                *s = T::zero();
+            }
+        }
+        let precision = &eigenvectors * OMatrix::from_diagonal(&inverse) * eigenvectors.transpose();
+        let lower = &eigenvectors * OMatrix::from_diagonal(&sqrt);
+        let factor = T::one() / (T::two_pi().powi(rank) * pseudo_det).sqrt();
+        Ok(MultiVariateNormal {
+            mean: mean.clone(),
+            precision,
+            lower,
+            factor,
+        })
+    }
+
     /// Probability density function
     pub fn pdf(&self, x: &OVector<T, D>) -> T {
         let dx = &self.mean - x;
@@ -69,20 +157,110 @@ where
         T::exp(neg_half * interior) * self.factor.clone()
     }
 
+    /// Log of the probability density function, finite far from the mean where `pdf` underflows
+    pub fn log_pdf(&self, x: &OVector<T, D>) -> T {
+        let dx = &self.mean - x;
+        let neg_half = T::from_f32(-0.5).unwrap();
+        let interior = (&dx.transpose() * &self.precision * dx).x.clone();
+        neg_half * interior + self.log_factor()
+    }
+
     pub fn sample(&self) -> OVector<T, D> {
+        self.sample_with_rng(&mut rand::thread_rng())
+    }
+
+    /// Same as `sample` with the given generator, for reproducible draws
+    pub fn sample_with_rng<R: rand::Rng + ?Sized>(&self, rng: &mut R) -> OVector<T, D> {
         // https://juanitorduz.github.io/multivariate_normal/
-        let mut rng = rand::thread_rng();
         let dim = self.mean.shape_generic().0;
-        let u = OVector::<T, D>::from_distribution_generic(
-            dim,
-            U1,
-            &rand_distr::StandardNormal,
-            &mut rng,
-        );
+        let u =
+            OVector::<T, D>::from_distribution_generic(dim, U1, &rand_distr::StandardNormal, rng);
         &self.mean + &self.lower * u
     }
 }
 
+/// Symmetric with no eigenvalue under minus the tolerance, the covariances accepted by
+/// `MultiVariateNormal::new`, e.g. to validate a covariance before building a filter
+pub(crate) fn is_semi_definite<T: RealField, D: Dim>(covariance: &OMatrix<T, D, D>) -> bool
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    if !covariance.is_square() {
+        return false;
+    }
+    let (eigenvalues, _) = symmetric_eigen(covariance.clone());
+    let scale = eigenvalues
+        .iter()
+        .fold(T::zero(), |acc, l| acc.max(l.clone().abs()));
+    let tolerance = singular_tolerance(scale, eigenvalues.len());
+    let symmetric = covariance
+        .iter()
+        .zip(covariance.transpose().iter())
+        .all(|(a, b)| (a.clone() - b.clone()).abs() <= tolerance);
+    symmetric && eigenvalues.iter().all(|l| *l >= -tolerance.clone())
+}
+
+/// Eigenvalues (or squared cholesky pivots) under it are zero
+fn singular_tolerance<T: RealField>(scale: T, dim: usize) -> T {
+    scale * T::default_epsilon() * T::from_usize(dim.max(1) * 8).unwrap()
+}
+
+/// Eigenvalues and eigenvectors (in columns) of a symmetric matrix with cyclic Jacobi rotations,
+/// without the dimension bounds of `SymmetricEigen` so it works for any `Dim`
+fn symmetric_eigen<T: RealField, D: Dim>(
+    mut a: OMatrix<T, D, D>,
+) -> (OVector<T, D>, OMatrix<T, D, D>)
+where
+    DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,
+{
+    let shape = a.shape_generic();
+    let n = shape.0.value();
+    let mut v = OMatrix::identity_generic(shape.0, shape.1);
+    let two = T::one() + T::one();
+    for _ in 0..100 {
+        let off_diagonal = (0..n)
+            .flat_map(|p| (p + 1..n).map(move |q| (p, q)))
+            .fold(T::zero(), |acc, (p, q)| acc + a[(p, q)].clone().powi(2));
+        if off_diagonal <= T::default_epsilon() * T::default_epsilon() * a.norm_squared() {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[(p, q)] == T::zero() {
+                    continue;
+                }
+                // rotation zeroing a[(p, q)]
+                let theta =
+                    (a[(q, q)].clone() - a[(p, p)].clone()) / (two.clone() * a[(p, q)].clone());
+                let sign = if theta >= T::zero() {
+                    T::one()
+                } else {
+                    -T::one()
+                };
+                let t = sign / (theta.clone().abs() + (theta.clone() * theta + T::one()).sqrt());
+                let c = T::one() / (t.clone() * t.clone() + T::one()).sqrt();
+                let s = t * c.clone();
+                for k in 0..n {
+                    let (kp, kq) = (a[(k, p)].clone(), a[(k, q)].clone());
+                    a[(k, p)] = c.clone() * kp.clone() - s.clone() * kq.clone();
+                    a[(k, q)] = s.clone() * kp + c.clone() * kq;
+                }
+                for k in 0..n {
+                    let (pk, qk) = (a[(p, k)].clone(), a[(q, k)].clone());
+                    a[(p, k)] = c.clone() * pk.clone() - s.clone() * qk.clone();
+                    a[(q, k)] = s.clone() * pk + c.clone() * qk;
+                }
+                for k in 0..n {
+                    let (kp, kq) = (v[(k, p)].clone(), v[(k, q)].clone());
+                    v[(k, p)] = c.clone() * kp.clone() - s.clone() * kq.clone();
+                    v[(k, q)] = s.clone() * kp + c.clone() * kq;
+                }
+            }
+        }
+    }
+    (a.diagonal(), v)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,4 +285,62 @@ mod tests {
         assert_relative_eq!(mvn.pdf(&x1), 0.09653235, epsilon = epsilon);
         assert_relative_eq!(mvn.pdf(&x2), 0.09653235, epsilon = epsilon);
     }
+
+    #[test]
+    fn test_log_density() {
+        let mu = na::Vector2::<f64>::new(1.0, -1.0);
+        let cov = na::Matrix2::<f64>::new(2.0, 0.5, 0.5, 1.0);
+        let mvn = MultiVariateNormal::new(&mu, &cov).unwrap();
+
+        let x = na::Vector2::<f64>::new(0.5, 0.0);
+        assert_relative_eq!(mvn.log_pdf(&x), mvn.pdf(&x).ln(), epsilon = 1e-10);
+
+        // the density underflows but not its log
+        let far = na::Vector2::<f64>::new(100.0, 0.0);
+        assert_eq!(mvn.pdf(&far), 0.0);
+        assert!(mvn.log_pdf(&far).is_finite());
+    }
+
+    #[test]
+    fn test_semi_definite_covariance() {
+        // x and y are the same variable
+        let mu = na::Vector2::<f64>::new(1.0, 1.0);
+        let cov = na::Matrix2::<f64>::new(1.0, 1.0, 1.0, 1.0);
+        let mvn = MultiVariateNormal::new(&mu, &cov).unwrap();
+
+        // 1D normal along the diagonal, the variance along it is 2
+        let x = na::Vector2::<f64>::new(2.0, 2.0);
+        let expected = (-0.5_f64).exp() / (2.0 * std::f64::consts::PI * 2.0).sqrt();
+        assert_relative_eq!(mvn.pdf(&x), expected, epsilon = 1e-10);
+
+        let sample = mvn.sample();
+        assert_relative_eq!(sample.x, sample.y, epsilon = 1e-10);
+
+        let not_semi_definite = na::Matrix2::<f64>::new(1.0, 2.0, 2.0, 1.0);
+        assert!(MultiVariateNormal::new(&mu, &not_semi_definite).is_err());
+    }
+
+    #[test]
+    fn test_symmetric_eigen() {
+        let m = na::Matrix3::<f64>::new(4.0, 1.0, -2.0, 1.0, 2.0, 0.5, -2.0, 0.5, 3.0);
+        let (eigenvalues, eigenvectors) = symmetric_eigen(m);
+        let reconstructed =
+            eigenvectors * na::Matrix3::from_diagonal(&eigenvalues) * eigenvectors.transpose();
+        assert_relative_eq!(reconstructed, m, epsilon = 1e-10);
+        assert_relative_eq!(
+            eigenvectors.transpose() * eigenvectors,
+            na::Matrix3::identity(),
+            epsilon = 1e-10
+        );
+    }
+
+    #[test]
+    fn test_with_mean() {
+        let cov = na::Matrix2::<f64>::new(2.0, 0.5, 0.5, 1.0);
+        let mvn = MultiVariateNormal::zero_mean(&cov).unwrap();
+        let mu = na::Vector2::<f64>::new(1.0, -1.0);
+        let moved = mvn.with_mean(&mu);
+        let x = na::Vector2::<f64>::new(0.5, 0.0);
+        assert_relative_eq!(moved.pdf(&x), mvn.pdf(&(x - mu)), epsilon = 1e-12);
+    }
 }
@@ -0,0 +1,51 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+use std::io::{Read, Write};
+
+/// Writes `value` as JSON
+pub fn save<W: Write, V: Serialize + ?Sized>(writer: W, value: &V) -> Result<(), Box<dyn Error>> {
+    serde_json::to_writer(writer, value)?;
+    Ok(())
+}
+
+/// Reads a value written by `save`
+pub fn load<R: Read, V: DeserializeOwned>(reader: R) -> Result<V, Box<dyn Error>> {
+    Ok(serde_json::from_reader(reader)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::localization::ParticleFilterBuilder;
+    use crate::models::measurement::SimpleProblemMeasurementModel;
+    use crate::models::motion::SimpleProblemMotionModel;
+    use crate::utils::state::GaussianState;
+    use nalgebra::{Const, Matrix2, Matrix4, Vector4};
+
+    #[test]
+    fn particle_filter_round_trip() {
+        let build = || {
+            ParticleFilterBuilder::<f64, Const<4>, Const<2>, Const<2>>::new()
+                .motion_noise(Matrix4::identity())
+                .measurement_noise(Matrix2::identity())
+                .measurement_model(SimpleProblemMeasurementModel::new())
+                .motion_model(SimpleProblemMotionModel::new())
+                .initial_state(GaussianState {
+                    x: Vector4::new(0., 0., 0., 0.),
+                    cov: Matrix4::identity(),
+                })
+                .num_particules(10)
+                .build()
+                .unwrap()
+        };
+        let saved = build();
+        let mut buffer = Vec::new();
+        saved.save_state(&mut buffer).unwrap();
+
+        let mut restored = build();
+        restored.load_state(buffer.as_slice()).unwrap();
+        assert_eq!(saved.particules, restored.particules);
+
+        assert!(restored.load_state("[]".as_bytes()).is_err());
+        assert_eq!(saved.particules, restored.particules);
+    }
+}
@@ -0,0 +1,198 @@
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, Matrix2, OVector, Vector2};
+use plotters::prelude::*;
+use std::error::Error;
+use std::path::Path;
+
+use crate::utils::plot::ellipse_series;
+use crate::utils::state::GaussianState;
+
+/// Everything recorded between two calls to `FilterRecorder::next_frame`
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    pub time: f64,
+    pub particules: Vec<(f64, f64)>,
+    pub estimate: Option<(f64, f64)>,
+    pub ellipses: Vec<Vec<(f64, f64)>>,
+    pub landmarks: Vec<(f64, f64)>,
+    pub ground_truth: Option<(f64, f64)>,
+}
+
+/// Records the belief of a filter over time and draws it as SVG.
+///
+/// The frames are only written as SVG files, nothing is streamed to rerun.
+///
+/// Only the first two components of the states are used, they are taken as (x, y)
+#[derive(Debug, Default)]
+pub struct FilterRecorder {
+    pub frames: Vec<Frame>,
+    current: Frame,
+}
+
+impl FilterRecorder {
+    pub fn new() -> FilterRecorder {
+        FilterRecorder::default()
+    }
+
+    pub fn record_particules<S: Dim>(&mut self, particules: &[OVector<f64, S>])
+    where
+        DefaultAllocator: Allocator<f64, S>,
+    {
+        self.current
+            .particules
+            .extend(particules.iter().map(|p| (p[0], p[1])));
+    }
+
+    /// Mean and 1 sigma ellipse of the estimate
+    pub fn record_estimate<S: Dim>(&mut self, state: &GaussianState<f64, S>)
+    where
+        DefaultAllocator: Allocator<f64, S> + Allocator<f64, S, S>,
+    {
+        let xy = Vector2::new(state.x[0], state.x[1]);
+        self.current.estimate = Some((xy.x, xy.y));
+        self.record_ellipse(xy, state.cov.fixed_view::<2, 2>(0, 0).clone_owned());
+    }
+
+    pub fn record_landmark(&mut self, xy: Vector2<f64>, cov: Option<Matrix2<f64>>) {
+        self.current.landmarks.push((xy.x, xy.y));
+        if let Some(cov) = cov {
+            self.record_ellipse(xy, cov);
+        }
+    }
+
+    pub fn record_ground_truth(&mut self, xy: Vector2<f64>) {
+        self.current.ground_truth = Some((xy.x, xy.y));
+    }
+
+    /// Closes the current frame and starts a new one at `time`
+    pub fn next_frame(&mut self, time: f64) {
+        let frame = std::mem::take(&mut self.current);
+        self.frames.push(frame);
+        self.current.time = time;
+    }
+
+    fn record_ellipse(&mut self, xy: Vector2<f64>, cov: Matrix2<f64>) {
+        if let Some(ellipse) = ellipse_series(xy, cov) {
+            self.current.ellipses.push(ellipse);
+        }
+    }
+
+    /// Draws the estimated and true trajectories of all the frames,
+    /// with the particules, ellipses and landmarks of the frame `index`
+    pub fn save_svg<P: AsRef<Path>>(
+        &self,
+        path: P,
+        index: usize,
+        name: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let frame = self
+            .frames
+            .get(index)
+            .ok_or_else(|| format!("no frame {index}, {} recorded", self.frames.len()))?;
+
+        let estimates: Vec<(f64, f64)> = self.frames.iter().filter_map(|f| f.estimate).collect();
+        let ground_truth: Vec<(f64, f64)> =
+            self.frames.iter().filter_map(|f| f.ground_truth).collect();
+        let (min, max) = estimates
+            .iter()
+            .chain(ground_truth.iter())
+            .chain(frame.particules.iter())
+            .chain(frame.landmarks.iter())
+            .chain(frame.ellipses.iter().flatten())
+            .fold(
+                (
+                    (f64::INFINITY, f64::INFINITY),
+                    (f64::NEG_INFINITY, f64::NEG_INFINITY),
+                ),
+                |(min, max), p| {
+                    (
+                        (min.0.min(p.0), min.1.min(p.1)),
+                        (max.0.max(p.0), max.1.max(p.1)),
+                    )
+                },
+            );
+        if !min.0.is_finite() {
+            return Err("nothing to draw".into());
+        }
+
+        let root = SVGBackend::new(path.as_ref(), (1024, 768)).into_drawing_area();
+        root.fill(&WHITE)?;
+        let mut chart = ChartBuilder::on(&root)
+            .margin(10)
+            .caption(name, ("sans-serif", 40))
+            .x_label_area_size(30)
+            .y_label_area_size(30)
+            .build_cartesian_2d(min.0 - 1.0..max.0 + 1.0, min.1 - 1.0..max.1 + 1.0)?;
+        chart.configure_mesh().draw()?;
+
+        chart
+            .draw_series(
+                frame
+                    .particules
+                    .iter()
+                    .map(|p| Circle::new(*p, 1, BLACK.mix(0.5).filled())),
+            )?
+            .label("Particules")
+            .legend(|(x, y)| Circle::new((x, y), 3, BLACK.filled()));
+        chart
+            .draw_series(std::iter::once(PathElement::new(ground_truth, BLUE)))?
+            .label("Ground truth")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+        chart
+            .draw_series(std::iter::once(PathElement::new(estimates, GREEN)))?
+            .label("Estimates")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], GREEN));
+        chart.draw_series(
+            frame
+                .ellipses
+                .iter()
+                .map(|e| PathElement::new(e.clone(), GREEN)),
+        )?;
+        chart
+            .draw_series(
+                frame
+                    .landmarks
+                    .iter()
+                    .map(|lm| Cross::new(*lm, 5, RED.filled())),
+            )?
+            .label("Landmarks")
+            .legend(|(x, y)| Cross::new((x, y), 5, RED.filled()));
+
+        chart
+            .configure_series_labels()
+            .position(SeriesLabelPosition::LowerRight)
+            .border_style(BLACK)
+            .draw()?;
+        root.present()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix3, Vector3};
+
+    #[test]
+    fn record_and_draw() {
+        let mut recorder = FilterRecorder::new();
+        for i in 0..5 {
+            let t = i as f64;
+            let x = Vector3::new(t, t * t, 0.0);
+            recorder.record_particules(&[x, x.add_scalar(0.1)]);
+            recorder.record_estimate(&GaussianState {
+                x,
+                cov: Matrix3::identity(),
+            });
+            recorder.record_ground_truth(Vector2::new(t, t * t + 0.5));
+            recorder.record_landmark(Vector2::new(2.0, 2.0), Some(Matrix2::identity() * 0.1));
+            recorder.next_frame(t + 1.0);
+        }
+        assert_eq!(5, recorder.frames.len());
+        assert_eq!(2, recorder.frames[4].ellipses.len());
+
+        let path = std::env::temp_dir().join("filter_recorder.svg");
+        recorder.save_svg(&path, 4, "recorder").unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("<svg"));
+        assert!(recorder.save_svg(&path, 5, "recorder").is_err());
+    }
+}
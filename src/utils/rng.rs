@@ -0,0 +1,116 @@
+use rand::RngCore;
+
+const M0: u32 = 0xD251_1F53;
+const M1: u32 = 0xCD9E_8D57;
+const W0: u32 = 0x9E37_79B9;
+const W1: u32 = 0xBB67_AE85;
+
+/// Philox4x32-10 counter based generator, each (key, counter) pair gives 4 independent words
+/// so any draw can be recomputed without replaying the previous ones.
+///
+/// Source : Parallel Random Numbers: As Easy as 1, 2, 3, Salmon et al.
+#[derive(Debug, Clone)]
+pub struct Philox {
+    key: [u32; 2],
+    counter: [u32; 4],
+    buffer: [u32; 4],
+    index: usize,
+}
+
+impl Philox {
+    /// Stream `stream` of the generator seeded with `seed`
+    pub fn new(seed: u64, stream: u64) -> Philox {
+        Philox::for_particule(seed, stream, 0)
+    }
+
+    /// Stream of the particule `index` at the filter step `step`. The counter is
+    /// (block, step, index), a stream is 2^32 blocks of 4 words long
+    pub fn for_particule(seed: u64, index: u64, step: u64) -> Philox {
+        Philox {
+            key: [seed as u32, (seed >> 32) as u32],
+            counter: [0, step as u32, index as u32, (index >> 32) as u32],
+            buffer: [0; 4],
+            index: 4,
+        }
+    }
+
+    /// Philox4x32 block function with 10 rounds
+    pub fn block(counter: [u32; 4], key: [u32; 2]) -> [u32; 4] {
+        let mut c = counter;
+        let mut k = key;
+        for _ in 0..10 {
+            let p0 = M0 as u64 * c[0] as u64;
+            let p1 = M1 as u64 * c[2] as u64;
+            c = [
+                (p1 >> 32) as u32 ^ c[1] ^ k[0],
+                p1 as u32,
+                (p0 >> 32) as u32 ^ c[3] ^ k[1],
+                p0 as u32,
+            ];
+            k = [k[0].wrapping_add(W0), k[1].wrapping_add(W1)];
+        }
+        c
+    }
+
+    fn refill(&mut self) {
+        self.buffer = Philox::block(self.counter, self.key);
+        self.index = 0;
+        self.counter[0] = self.counter[0].wrapping_add(1);
+    }
+}
+
+impl RngCore for Philox {
+    fn next_u32(&mut self) -> u32 {
+        if self.index == 4 {
+            self.refill();
+        }
+        self.index += 1;
+        self.buffer[self.index - 1]
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let low = self.next_u32() as u64;
+        let high = self.next_u32() as u64;
+        (high << 32) | low
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+
+    #[test]
+    fn philox_known_answers() {
+        // Random123 kat_vectors
+        assert_eq!(
+            [0x6627e8d5, 0xe169c58d, 0xbc57ac4c, 0x9b00dbd8],
+            Philox::block([0; 4], [0; 2])
+        );
+        assert_eq!(
+            [0x408f276d, 0x41c83b0e, 0xa20bc7c6, 0x6d5451fd],
+            Philox::block([u32::MAX; 4], [u32::MAX; 2])
+        );
+
+        let a: Vec<f64> = (0..10)
+            .map(|_| Philox::for_particule(42, 7, 3).gen())
+            .collect();
+        assert!(a.iter().all(|x| *x == a[0]));
+        let b: f64 = Philox::for_particule(42, 8, 3).gen();
+        let c: f64 = Philox::for_particule(42, 7, 4).gen();
+        assert_ne!(a[0], b);
+        assert_ne!(a[0], c);
+    }
+}
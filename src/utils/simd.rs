@@ -0,0 +1,80 @@
+use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OVector};
+use wide::f32x8;
+
+use crate::utils::mvn::MultiVariateNormal;
+
+const LANES: usize = 8;
+
+/// Log density of `mvn` at each of the `xs`. Same result as the log of
+/// `MultiVariateNormal::pdf` up to the f32 rounding, without the underflow of the density far
+/// from the mean
+pub fn log_pdf_batch<D: Dim>(mvn: &MultiVariateNormal<f32, D>, xs: &[OVector<f32, D>]) -> Vec<f32>
+where
+    DefaultAllocator: Allocator<f32, D> + Allocator<f32, D, D>,
+{
+    let residuals: Vec<f32> = xs
+        .iter()
+        .flat_map(|x| (mvn.mean() - x).iter().copied().collect::<Vec<_>>())
+        .collect();
+    log_pdf_residuals(mvn.precision().as_slice(), mvn.log_factor(), &residuals)
+}
+
+/// Log density of the residuals to the mean, stored one after the other in `residuals`, 8 at a
+/// time. `precision` is the column major inverse of the covariance
+pub(crate) fn log_pdf_residuals(precision: &[f32], log_factor: f32, residuals: &[f32]) -> Vec<f32> {
+    let dim = (precision.len() as f64).sqrt() as usize;
+    let log_factor = f32x8::splat(log_factor);
+    let neg_half = f32x8::splat(-0.5);
+
+    let mut log_pdfs = Vec::with_capacity(residuals.len() / dim);
+    let mut dx = vec![f32x8::ZERO; dim];
+    for chunk in residuals.chunks(LANES * dim) {
+        let n = chunk.len() / dim;
+        // dx[i] holds the i-th coordinate of the residuals of the chunk
+        for (i, lanes) in dx.iter_mut().enumerate() {
+            let mut coordinates = [0.0; LANES];
+            for (k, c) in coordinates.iter_mut().take(n).enumerate() {
+                *c = chunk[k * dim + i];
+            }
+            *lanes = f32x8::from(coordinates);
+        }
+        let mut interior = f32x8::ZERO;
+        for i in 0..dim {
+            let mut row = f32x8::ZERO;
+            for j in 0..dim {
+                row = f32x8::splat(precision[i + j * dim]).mul_add(dx[j], row);
+            }
+            interior = dx[i].mul_add(row, interior);
+        }
+        let log_pdf = neg_half.mul_add(interior, log_factor).to_array();
+        log_pdfs.extend_from_slice(&log_pdf[..n]);
+    }
+    log_pdfs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nalgebra::{Matrix3, Vector3};
+
+    #[test]
+    fn same_density_as_the_scalar_pdf() {
+        let mean = Vector3::new(1.0, -2.0, 0.5);
+        let cov = Matrix3::new(2.0, 0.3, 0.1, 0.3, 1.0, -0.2, 0.1, -0.2, 0.5);
+        let mvn = MultiVariateNormal::new(&mean, &cov).unwrap();
+        // not a multiple of the lanes
+        let xs: Vec<_> = (0..21)
+            .map(|i| mean + Vector3::new(0.1, -0.05, 0.02) * i as f32)
+            .collect();
+        let log_pdfs = log_pdf_batch(&mvn, &xs);
+        assert_eq!(xs.len(), log_pdfs.len());
+        for (x, log_pdf) in xs.iter().zip(log_pdfs) {
+            approx::assert_relative_eq!(mvn.pdf(x).ln(), log_pdf, epsilon = 1e-4);
+        }
+
+        // the scalar density underflows
+        let far = vec![mean + Vector3::new(30.0, 0.0, 0.0)];
+        assert_eq!(0.0, mvn.pdf(&far[0]));
+        assert!(log_pdf_batch(&mvn, &far)[0].is_finite());
+    }
+}
@@ -1,6 +1,14 @@
 use nalgebra::{allocator::Allocator, DefaultAllocator, Dim, OMatrix, OVector, RealField};
 
 #[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde-serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(bound(
+        serialize = "OVector<T, D>: serde::Serialize, OMatrix<T, D, D>: serde::Serialize",
+        deserialize = "OVector<T, D>: serde::Deserialize<'de>, OMatrix<T, D, D>: serde::Deserialize<'de>"
+    ))
+)]
 pub struct GaussianState<T: RealField, D: Dim>
 where
     DefaultAllocator: Allocator<T, D> + Allocator<T, D, D>,